@@ -0,0 +1,148 @@
+use std::time::{Duration, Instant};
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use ironboyadvance_arm7tdmi::{
+    CpuState,
+    cpu::Arm7tdmiCpu,
+    memory::{DebugMemoryAccess, MemoryAccess, MemoryInterface},
+};
+
+const ITERATIONS: u32 = 2_000;
+const LOOP_LENGTH: u32 = 5;
+const MEMORY_MASK: u32 = 0xFFFF;
+
+// Both loops count their iterations in r0
+// add r0, r0, #1; eor r1, r1, r0; ldr r2, [r3]; str r2, [r3, #4]; b 0
+const ARM_LOOP: [u32; 5] = [0xE2800001, 0xE0211000, 0xE5932000, 0xE5832004, 0xEAFFFFFA];
+// adds r0, r0, r1; eors r2, r1; ldr r2, [r3]; str r2, [r3, #4]; b 0
+const THUMB_LOOP: [u16; 5] = [0x1840, 0x404A, 0x681A, 0x605A, 0xE7FA];
+
+/// Zero wait state memory mirrored across the whole address space, so only the cpu is measured.
+/// Like the system bus it leaves aligning addresses to the cpu
+struct FlatBus {
+    memory: Vec<u8>,
+}
+
+impl FlatBus {
+    fn read(&self, address: u32, width: u32) -> u32 {
+        (0..width).fold(0, |value, byte| {
+            value | (self.memory[((address + byte) & MEMORY_MASK) as usize] as u32) << (byte * 8)
+        })
+    }
+
+    fn write(&mut self, address: u32, value: u32, width: u32) {
+        for byte in 0..width {
+            self.memory[((address + byte) & MEMORY_MASK) as usize] = (value >> (byte * 8)) as u8;
+        }
+    }
+}
+
+impl MemoryInterface for FlatBus {
+    fn load_8(&mut self, address: u32, _access_pattern: MemoryAccess) -> u32 {
+        self.read(address, 1)
+    }
+
+    fn load_16(&mut self, address: u32, _access_pattern: MemoryAccess) -> u32 {
+        self.read(address, 2)
+    }
+
+    fn load_32(&mut self, address: u32, _access_pattern: MemoryAccess) -> u32 {
+        self.read(address, 4)
+    }
+
+    fn store_8(&mut self, address: u32, value: u8, _access_pattern: MemoryAccess) {
+        self.write(address, value as u32, 1);
+    }
+
+    fn store_16(&mut self, address: u32, value: u16, _access_pattern: MemoryAccess) {
+        self.write(address, value as u32, 2);
+    }
+
+    fn store_32(&mut self, address: u32, value: u32, _access_pattern: MemoryAccess) {
+        self.write(address, value, 4);
+    }
+
+    fn idle_cycle(&mut self) {}
+}
+
+impl DebugMemoryAccess for FlatBus {
+    fn debug_read_8(&self, address: u32) -> u8 {
+        self.read(address, 1) as u8
+    }
+
+    fn debug_write_8(&mut self, address: u32, value: u8) {
+        self.write(address, value as u32, 1);
+    }
+}
+
+fn cpu(state: CpuState) -> Arm7tdmiCpu<FlatBus> {
+    let mut bus = FlatBus {
+        memory: vec![0; MEMORY_MASK as usize + 1],
+    };
+    match state {
+        CpuState::Arm => ARM_LOOP
+            .iter()
+            .enumerate()
+            .for_each(|(index, opcode)| bus.write(index as u32 * 4, *opcode, 4)),
+        CpuState::Thumb => THUMB_LOOP
+            .iter()
+            .enumerate()
+            .for_each(|(index, opcode)| bus.write(index as u32 * 2, *opcode as u32, 2)),
+    }
+    let mut cpu = Arm7tdmiCpu::new(bus, true);
+    let mut cpsr = cpu.cpsr();
+    cpsr.set_state(state);
+    cpu.set_cpsr(cpsr);
+    let mut registers = cpu.general_registers();
+    registers[1] = 1;
+    registers[3] = 0x1000;
+    cpu.set_general_registers(registers);
+    cpu
+}
+
+// Blocks run several instructions per step and compiled loops several iterations, so progress is measured
+// by the loop counter and the time scaled to `ITERATIONS` from the iterations that actually ran
+fn run(cpu: &mut Arm7tdmiCpu<FlatBus>, step: fn(&mut Arm7tdmiCpu<FlatBus>), iterations: u64) -> Duration {
+    let mut elapsed = Duration::ZERO;
+    for _ in 0..iterations {
+        let start = cpu.general_registers()[0];
+        let now = Instant::now();
+        while cpu.general_registers()[0].wrapping_sub(start) < ITERATIONS {
+            step(cpu);
+        }
+        let ran = cpu.general_registers()[0].wrapping_sub(start);
+        elapsed += now.elapsed().mul_f64(ITERATIONS as f64 / ran as f64);
+    }
+    elapsed
+}
+
+fn instructions(c: &mut Criterion) {
+    for (name, state) in [("arm", CpuState::Arm), ("thumb", CpuState::Thumb)] {
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Elements((ITERATIONS * LOOP_LENGTH) as u64));
+
+        let mut interpreter = cpu(state);
+        group.bench_function("interpreter", |b| {
+            b.iter_custom(|iterations| run(&mut interpreter, Arm7tdmiCpu::cycle, iterations))
+        });
+
+        let mut cached = cpu(state);
+        cached.set_block_cache(true);
+        group.bench_function("block_cache", |b| {
+            b.iter_custom(|iterations| run(&mut cached, Arm7tdmiCpu::cycle_cached, iterations))
+        });
+
+        #[cfg(feature = "jit")]
+        {
+            let mut compiled = cpu(state);
+            compiled.set_jit(true);
+            group.bench_function("jit", |b| {
+                b.iter_custom(|iterations| run(&mut compiled, Arm7tdmiCpu::cycle_cached, iterations))
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, instructions);
+criterion_main!(benches);
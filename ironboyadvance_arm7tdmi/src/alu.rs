@@ -50,7 +50,7 @@ pub fn adc<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, set_flags: bool, operan
     let result = operand1 as u64 + operand2 as u64 + cpu.cpsr().carry() as u64;
     if set_flags {
         cpu.set_negative((result >> 31) & 0b1 != 0);
-        cpu.set_zero(result == 0);
+        cpu.set_zero(result as u32 == 0);
         cpu.set_carry(result >> 32 != 0);
         cpu.set_overflow((!(operand1 ^ operand2) & (operand1 ^ result as u32)) >> 31 != 0);
     }
@@ -129,14 +129,73 @@ pub fn mvn<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, set_flags: bool, operan
     result
 }
 
-pub fn multiplier_array_cycles(multiplier: u32) -> usize {
-    if multiplier & 0xFF == multiplier {
-        1
-    } else if multiplier & 0xFFFF == multiplier {
-        2
-    } else if multiplier & 0xFFFFFF == multiplier {
-        3
-    } else {
-        4
+/// Internal cycles the multiplier array takes, 8 bits of `multiplier` each. It stops early once the bits
+/// left are all zeros, or all ones for the signed multiplies
+pub fn multiplier_array_cycles(multiplier: u32, signed: bool) -> usize {
+    (1..4)
+        .find(|cycle| {
+            let rest = multiplier as i32 >> (8 * cycle);
+            rest == 0 || (signed && rest == -1)
+        })
+        .unwrap_or(4)
+}
+
+/// Carry flag left by a multiply, bit 31 or for long multiplies bit 63 of the carry vector of the carry
+/// save adders after the last booth step. `accumulator` is the value added by the accumulating forms.
+pub fn multiply_carry(multiplicand: u32, multiplier: u32, accumulator: u64, long: bool, signed: bool) -> bool {
+    let extend = |value: u32| match signed {
+        true => value as i32 as i64 as u64,
+        false => value as u64,
+    };
+    // The hardware negates by inverting and adds the 1 in later, an odd multiplicand inverts the same upper
+    // bits without the 1 carrying into them
+    let multiplicand = extend(multiplicand) | 1;
+    let multiplier = extend(multiplier);
+    // The multiplier bits below `bits` sign extended, the value of the booth digits recoded from them
+    let recoded = |bits: u32| match bits {
+        64.. => multiplier,
+        _ => ((multiplier << (64 - bits)) as i64 >> (64 - bits)) as u64,
+    };
+
+    let mut booth = recoded(1);
+    let mut carry = multiplicand.wrapping_mul(booth);
+    let mut partial = accumulator;
+    // What the sum and carry vectors add up to, which gives the carry vector without tracking each bit
+    let mut total = carry.wrapping_add(accumulator);
+    let mut bits = 1;
+    for _ in 0..multiplier_array_cycles(multiplier as u32, signed) {
+        // Four booth digits every cycle
+        for _ in 0..4 {
+            bits += 2;
+            let next = recoded(bits);
+            let addend = multiplicand.wrapping_mul(next.wrapping_sub(booth));
+            booth = next;
+            partial ^= carry ^ addend;
+            total = total.wrapping_add(addend);
+            carry = total.wrapping_sub(partial);
+        }
+    }
+    match long {
+        true => carry >> 63 != 0,
+        false => (carry >> 31) & 1 != 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multiplier_timing() {
+        assert_eq!(multiplier_array_cycles(0x0000_00FF, false), 1);
+        assert_eq!(multiplier_array_cycles(0xFFFF_FF00, true), 1);
+        assert_eq!(multiplier_array_cycles(0xFFFF_FF00, false), 4);
+        assert_eq!(multiplier_array_cycles(0xFFFF_0000, true), 2);
+        assert_eq!(multiplier_array_cycles(0x0012_3456, true), 3);
+        assert_eq!(multiplier_array_cycles(0x8000_0000, true), 4);
+
+        // Nothing to add, nothing carried
+        assert!(!multiply_carry(0x1234_5678, 0, 0, false, true));
+        assert!(!multiply_carry(0x1234_5678, 0, 0xFFFF_FFFF_FFFF_FFFF, true, false));
     }
 }
@@ -1,212 +1,276 @@
-use bitvec::field::BitField;
-
-use crate::{CpuMode, DataProcessingOpcode, Register, barrel_shifter::ShiftBy, cpu::Arm7tdmiCpu, memory::MemoryInterface};
+use crate::{
+    DataProcessingOpcode, Register,
+    barrel_shifter::{ShiftBy, ShiftType},
+    bits::Bits,
+    disassembler::DisassemblyOptions,
+};
 
 use super::ArmInstruction;
 
-pub fn disassemble_branch_exchange(instruction: &ArmInstruction) -> String {
+fn immediate(options: &DisassemblyOptions, value: u32) -> String {
+    match options.is_gnu() {
+        true => format!("#{value}"),
+        false => format!("0x{value:08X}"),
+    }
+}
+
+fn shifted_register(
+    options: &DisassemblyOptions,
+    rm: Register,
+    shift_type: ShiftType,
+    shift: ShiftBy,
+    amount: String,
+) -> String {
+    match options.is_gnu() {
+        false => format!("{rm},{shift_type} {amount}"),
+        true => {
+            let shift_type = shift_type.to_string().to_lowercase();
+            match (shift, shift_type.as_str(), amount.as_str()) {
+                (ShiftBy::Immediate, "lsl", "#0") => rm.to_string(),
+                (ShiftBy::Immediate, "lsr" | "asr", "#0") => format!("{rm}, {shift_type} #32"),
+                (ShiftBy::Immediate, "ror", "#0") => format!("{rm}, rrx"),
+                _ => format!("{rm}, {shift_type} {amount}"),
+            }
+        }
+    }
+}
+
+fn pc_relative(options: &DisassemblyOptions, instruction: &ArmInstruction, offset: u32) -> String {
+    let base = instruction.executed_pc.wrapping_add(8);
+    let address = match instruction.add() {
+        true => base.wrapping_add(offset),
+        false => base.wrapping_sub(offset),
+    };
+    match options.is_gnu() {
+        true => {
+            let sign = if instruction.add() { "" } else { "-" };
+            format!("[pc, #{sign}{offset}]\t; {}", options.address(address))
+        }
+        false => format!("[{}]", options.address(address)),
+    }
+}
+
+fn is_pc_relative(instruction: &ArmInstruction) -> bool {
+    instruction.rn() == Register::R15 && instruction.pre_index() && !instruction.write_back()
+}
+
+pub fn disassemble_branch_exchange(instruction: &ArmInstruction, options: &DisassemblyOptions) -> String {
     let cond = instruction.cond();
     let rn = instruction.rn();
-    format!("BX{cond} {rn}")
+    options.instruction(&format!("BX{cond}"), &[rn.to_string()])
 }
 
-pub fn disassemble_branch_and_branch_link(instruction: &ArmInstruction) -> String {
+pub fn disassemble_branch_and_branch_link(instruction: &ArmInstruction, options: &DisassemblyOptions) -> String {
     let cond = instruction.cond();
     let link = if instruction.link() { "L" } else { "" };
-    let expression = instruction.offset();
-    format!("B{link}{cond} 0x{expression:08X}")
+    let offset = ((instruction.offset() << 8) as i32 >> 6) as u32;
+    let target = instruction.executed_pc.wrapping_add(8).wrapping_add(offset);
+    options.instruction(&format!("B{link}{cond}"), &[options.address(target)])
 }
 
-pub fn disassemble_data_processing(instruction: &ArmInstruction) -> String {
+pub fn disassemble_data_processing(instruction: &ArmInstruction, options: &DisassemblyOptions) -> String {
     use DataProcessingOpcode::*;
     let cond = instruction.cond();
     let opcode = instruction.opcode();
     let s = if instruction.sets_flags() { "S" } else { "" };
-    let rd = instruction.rd();
-    let rn = instruction.rn();
+    let rd = instruction.rd().to_string();
+    let rn = instruction.rn().to_string();
     let operand_2 = match instruction.is_immediate() {
         true => {
             let rotate = 2 * instruction.rotate();
-            let immediate = instruction.immediate();
-            format!("0x{:08X}", immediate.rotate_right(rotate))
+            immediate(options, instruction.immediate().rotate_right(rotate))
         }
         false => {
             let rm = instruction.rm();
             let shift_type = instruction.shift_type();
             match instruction.shift_by() {
                 ShiftBy::Register => {
-                    format!("{},{} {}", rm, shift_type, instruction.rs())
-                }
-                ShiftBy::Immediate => {
-                    format!("{},{} #{}", rm, shift_type, instruction.shift_amount())
+                    shifted_register(options, rm, shift_type, ShiftBy::Register, instruction.rs().to_string())
                 }
+                ShiftBy::Immediate => shifted_register(
+                    options,
+                    rm,
+                    shift_type,
+                    ShiftBy::Immediate,
+                    format!("#{}", instruction.shift_amount()),
+                ),
             }
         }
     };
 
     match opcode {
-        MOV | MVN => format!("{opcode}{cond}{s} {rd},{operand_2}"),
-        CMP | CMN | TEQ | TST => format!("{opcode}{cond} {rn},{operand_2}"),
-        _ => format!("{opcode}{cond}{s} {rd},{rn},{operand_2}"),
+        MOV | MVN => options.instruction(&format!("{opcode}{cond}{s}"), &[rd, operand_2]),
+        CMP | CMN | TEQ | TST => options.instruction(&format!("{opcode}{cond}"), &[rn, operand_2]),
+        _ => options.instruction(&format!("{opcode}{cond}{s}"), &[rd, rn, operand_2]),
     }
 }
 
-pub fn disassemble_psr_transfer<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instruction: &ArmInstruction) -> String {
+pub fn disassemble_psr_transfer(instruction: &ArmInstruction, options: &DisassemblyOptions) -> String {
     let cond = instruction.cond();
-    let is_spsr = instruction.is_spsr();
-    let psr = match is_spsr {
-        false => "CPSR",
-        true => match cpu.cpsr().mode() {
-            CpuMode::User | CpuMode::System => "CPSR",
-            CpuMode::Fiq => "SPSR_fiq",
-            CpuMode::Supervisor => "SPSR_svc",
-            CpuMode::Abort => "SPSR_abt",
-            CpuMode::Irq => "SPSR_irq",
-            CpuMode::Undefined => "SPSR_und",
-            CpuMode::Invalid => panic!("invalid mode"),
-        },
-    };
+    let psr = if instruction.is_spsr() { "SPSR" } else { "CPSR" };
 
-    match instruction.bits[16..=21].load::<u8>() == 0xF {
-        true => {
-            let rd = instruction.rd() as usize;
-            return format!("MRS{} {},{}", cond, rd, psr);
-        }
+    match instruction.value.bits(16..=21) == 0xF {
+        true => options.instruction(&format!("MRS{cond}"), &[instruction.rd().to_string(), psr.to_string()]),
         false => {
             let operand = match instruction.is_immediate() {
-                false => format!("{}", instruction.rm()),
+                false => instruction.rm().to_string(),
                 true => {
                     let rotate = 2 * instruction.rotate();
-                    let immediate = instruction.immediate();
-                    let expression = immediate.rotate_right(rotate);
-                    format!("0x{:08X}", expression)
+                    immediate(options, instruction.immediate().rotate_right(rotate))
                 }
             };
 
-            match is_spsr {
-                false => {
-                    if cpu.cpsr().mode() == CpuMode::User {
-                        return format!("MSR{} {}_flg,{}", cond, psr, operand);
-                    }
-                    format!("MSR{} {}_all,{}", cond, psr, operand)
-                }
-                true => format!("MSR{} {},{}", cond, psr, operand),
-            }
+            // Field mask bits 16 to 19 select the c, x, s and f bytes of the PSR
+            let mask = instruction.value.bits(16..=19) as u8;
+            let fields = match (options.is_gnu(), mask) {
+                (false, 0b1000) => String::from("flg"),
+                (false, 0b1001) => String::from("all"),
+                _ => [(0b1000, 'f'), (0b0100, 's'), (0b0010, 'x'), (0b0001, 'c')]
+                    .into_iter()
+                    .filter(|(bit, _)| mask & bit != 0)
+                    .map(|(_, field)| field)
+                    .collect(),
+            };
+            options.instruction(&format!("MSR{cond}"), &[format!("{psr}_{fields}"), operand])
         }
     }
 }
 
-pub fn disassemble_multiply(instruction: &ArmInstruction) -> String {
+pub fn disassemble_multiply(instruction: &ArmInstruction, options: &DisassemblyOptions) -> String {
     let cond = instruction.cond();
     let s = if instruction.sets_flags() { "S" } else { "" };
-    let rd = instruction.rd();
-    let rm = instruction.rm();
-    let rs = instruction.rs();
-    let rn = instruction.rn();
+    let rd = instruction.rd().to_string();
+    let rm = instruction.rm().to_string();
+    let rs = instruction.rs().to_string();
+    let rn = instruction.rn().to_string();
     match instruction.accumulate() {
-        true => format!("MLA{}{} {},{},{},{}", cond, s, rd, rm, rs, rn),
-        false => format!("MUL{}{} {},{},{}", cond, s, rd, rm, rs),
+        true => options.instruction(&format!("MLA{cond}{s}"), &[rd, rm, rs, rn]),
+        false => options.instruction(&format!("MUL{cond}{s}"), &[rd, rm, rs]),
     }
 }
 
-pub fn disassemble_multiply_long(instruction: &ArmInstruction) -> String {
+pub fn disassemble_multiply_long(instruction: &ArmInstruction, options: &DisassemblyOptions) -> String {
     let cond = instruction.cond();
     let s = if instruction.sets_flags() { "S" } else { "" };
-    let rd_hi = instruction.rd_hi();
-    let rd_lo = instruction.rd_lo();
-    let rm = instruction.rm();
-    let rs = instruction.rs();
-    let unsigned = instruction.unsigned();
-    let accumulate = instruction.accumulate();
-    match (unsigned, accumulate) {
-        (true, false) => format!("UMULL{}{} {},{},{},{}", cond, s, rd_lo, rd_hi, rm, rs),
-        (true, true) => format!("UMLAL{}{} {},{},{},{}", cond, s, rd_lo, rd_hi, rm, rs),
-        (false, false) => format!("SMULL{}{} {},{},{},{}", cond, s, rd_lo, rd_hi, rm, rs),
-        (false, true) => format!("SMLAL{}{} {},{},{},{}", cond, s, rd_lo, rd_hi, rm, rs),
-    }
+    let operands = [instruction.rd_lo(), instruction.rd_hi(), instruction.rm(), instruction.rs()].map(|r| r.to_string());
+    let mnemonic = match (instruction.signed_long(), instruction.accumulate()) {
+        (false, false) => "UMULL",
+        (false, true) => "UMLAL",
+        (true, false) => "SMULL",
+        (true, true) => "SMLAL",
+    };
+    options.instruction(&format!("{mnemonic}{cond}{s}"), &operands)
 }
 
-pub fn disassemble_single_data_transfer(instruction: &ArmInstruction) -> String {
+pub fn disassemble_single_data_transfer(instruction: &ArmInstruction, options: &DisassemblyOptions) -> String {
     let cond = instruction.cond();
     let pre_index = instruction.pre_index();
-    let t = if pre_index { "" } else { "T" };
+    let t = if !pre_index && instruction.write_back() { "T" } else { "" };
     let add = if instruction.add() { "+" } else { "-" };
     let byte = if instruction.byte() { "B" } else { "" };
     let rn = instruction.rn();
     let rd = instruction.rd();
     let immediate = instruction.immediate();
-    let address = match rd as usize == 15 {
-        true => format!("#{:08X}", immediate),
+    let address = match instruction.is_immediate() && is_pc_relative(instruction) {
+        true => pc_relative(options, instruction, immediate),
         false => {
             let offset = match instruction.is_immediate() {
-                true => match immediate {
-                    0 => "".into(),
-                    _ => format!(",#{}{}", add, immediate),
+                true => match (immediate, options.is_gnu()) {
+                    (0, _) => "".into(),
+                    (_, false) => format!(",#{add}{immediate}"),
+                    (_, true) => format!(", #{}{immediate}", add.trim_start_matches('+')),
                 },
                 false => {
                     let rm = instruction.rm();
                     let shift_type = instruction.shift_type();
-                    format!(",{}{},{} #{}", add, rm, shift_type, instruction.shift_amount())
+                    let amount = format!("#{}", instruction.shift_amount());
+                    match options.is_gnu() {
+                        false => format!(
+                            ",{add}{}",
+                            shifted_register(options, rm, shift_type, ShiftBy::Immediate, amount)
+                        ),
+                        true => format!(
+                            ", {}{}",
+                            add.trim_start_matches('+'),
+                            shifted_register(options, rm, shift_type, ShiftBy::Immediate, amount)
+                        ),
+                    }
                 }
             };
 
-            let write_back = if instruction.write_back() && offset != "" { "!" } else { "" };
+            let write_back = if instruction.write_back() && !offset.is_empty() {
+                "!"
+            } else {
+                ""
+            };
             match pre_index {
-                true => format!("[{}{}]{}", rn, offset, write_back),
-                false => format!("[{}]{}", rn, offset),
+                true => format!("[{rn}{offset}]{write_back}"),
+                false => format!("[{rn}]{offset}"),
             }
         }
     };
 
-    match instruction.load() {
-        true => format!("LDR{}{}{} {},{}", cond, byte, t, rd, address),
-        false => format!("STR{}{}{} {},{}", cond, byte, t, rd, address),
-    }
+    let mnemonic = match (instruction.load(), options.is_gnu()) {
+        (true, false) => format!("LDR{cond}{byte}{t}"),
+        (false, false) => format!("STR{cond}{byte}{t}"),
+        (true, true) => format!("LDR{byte}{t}{cond}"),
+        (false, true) => format!("STR{byte}{t}{cond}"),
+    };
+    options.instruction(&mnemonic, &[rd.to_string(), address])
 }
 
-pub fn disassemble_halfword_and_signed_data_transfer(instruction: &ArmInstruction) -> String {
+pub fn disassemble_halfword_and_signed_data_transfer(instruction: &ArmInstruction, options: &DisassemblyOptions) -> String {
     let cond = instruction.cond();
     let pre_index = instruction.pre_index();
     let add = if instruction.add() { "+" } else { "-" };
     let rn = instruction.rn();
     let rd = instruction.rd();
     let immediate = instruction.immediate_hi() << 4 | instruction.immediate_lo();
-    let address = match rd as usize == 15 {
-        true => format!("#{:08X}", immediate),
+    let address = match instruction.is_immediate() && is_pc_relative(instruction) {
+        true => pc_relative(options, instruction, immediate),
         false => {
-            let rm = instruction.rm();
+            let sign = match options.is_gnu() {
+                true => add.trim_start_matches('+'),
+                false => add,
+            };
+            let separator = if options.is_gnu() { ", " } else { "," };
             let offset = match instruction.is_immediate() {
                 true => match immediate {
                     0 => "".into(),
-                    _ => format!(",#{}{}", add, immediate),
+                    _ => format!("{separator}#{sign}{immediate}"),
                 },
-                false => format!(",{}{}", add, rm),
+                false => format!("{separator}{sign}{}", instruction.rm()),
             };
 
-            let write_back = if instruction.write_back() && offset != "" { "!" } else { "" };
+            let write_back = if instruction.write_back() && !offset.is_empty() {
+                "!"
+            } else {
+                ""
+            };
             match pre_index {
-                true => format!("[{}{}]{}", rn, offset, write_back),
-                false => format!("[{}]{}", rn, offset),
+                true => format!("[{rn}{offset}]{write_back}"),
+                false => format!("[{rn}]{offset}"),
             }
         }
     };
 
-    let s = instruction.signed();
-    let h = instruction.halfword();
-    let sh = match (s, h) {
+    let sh = match (instruction.signed(), instruction.halfword()) {
         (false, false) => "",
         (false, true) => "H",
         (true, false) => "SB",
         (true, true) => "SH",
     };
 
-    match instruction.load() {
-        true => format!("LDR{}{} {},{}", cond, sh, rd, address),
-        false => format!("STR{}{} {},{}", cond, sh, rd, address),
-    }
+    let mnemonic = match (instruction.load(), options.is_gnu()) {
+        (true, false) => format!("LDR{cond}{sh}"),
+        (false, false) => format!("STR{cond}{sh}"),
+        (true, true) => format!("LDR{sh}{cond}"),
+        (false, true) => format!("STR{sh}{cond}"),
+    };
+    options.instruction(&mnemonic, &[rd.to_string(), address])
 }
 
-pub fn disassemble_block_data_transfer(instruction: &ArmInstruction) -> String {
+pub fn disassemble_block_data_transfer(instruction: &ArmInstruction, options: &DisassemblyOptions) -> String {
     let cond = instruction.cond();
     let pre_index = instruction.pre_index();
     let add = instruction.add();
@@ -217,9 +281,31 @@ pub fn disassemble_block_data_transfer(instruction: &ArmInstruction) -> String {
     let register_list = instruction
         .register_list()
         .iter()
-        .map(|register| Register::from(*register as u32).to_string())
-        .collect::<Vec<String>>()
-        .join(",");
+        .map(|register| Register::from(register as u32).to_string())
+        .collect::<Vec<String>>();
+    let register_list = format!("{}{load_psr_force_user}", options.register_list(&register_list, '(', ')'));
+
+    if options.is_gnu() {
+        // objdump prints full descending stack accesses through sp as push and pop
+        let stack = rn == Register::R13 && instruction.write_back() && load_psr_force_user.is_empty();
+        return match (load, pre_index, add) {
+            (true, false, true) if stack => options.instruction(&format!("POP{cond}"), &[register_list]),
+            (false, true, false) if stack => options.instruction(&format!("PUSH{cond}"), &[register_list]),
+            _ => {
+                let mode = match (pre_index, add) {
+                    (false, true) => "IA",
+                    (true, true) => "IB",
+                    (false, false) => "DA",
+                    (true, false) => "DB",
+                };
+                let mnemonic = if load { "LDM" } else { "STM" };
+                options.instruction(
+                    &format!("{mnemonic}{mode}{cond}"),
+                    &[format!("{rn}{write_back}"), register_list],
+                )
+            }
+        };
+    }
 
     let mnemonic = match (load, pre_index, add) {
         (true, true, true) => match rn == Register::R13 {
@@ -256,24 +342,122 @@ pub fn disassemble_block_data_transfer(instruction: &ArmInstruction) -> String {
         },
     };
 
-    format!("{} {}{},({}){}", mnemonic, rn, write_back, register_list, load_psr_force_user)
+    options.instruction(&mnemonic, &[format!("{rn}{write_back}"), register_list])
 }
 
-pub fn disassemble_single_data_swap(instruction: &ArmInstruction) -> String {
+pub fn disassemble_single_data_swap(instruction: &ArmInstruction, options: &DisassemblyOptions) -> String {
     let cond = instruction.cond();
     let byte = if instruction.byte() { "B" } else { "" };
-    let rd = instruction.rd();
-    let rm = instruction.rm();
+    let rd = instruction.rd().to_string();
+    let rm = instruction.rm().to_string();
     let rn = instruction.rn();
-    format!("SWP{}{} {},{},[{}]", cond, byte, rd, rm, rn)
+    let mnemonic = match options.is_gnu() {
+        true => format!("SWP{byte}{cond}"),
+        false => format!("SWP{cond}{byte}"),
+    };
+    options.instruction(&mnemonic, &[rd, rm, format!("[{rn}]")])
 }
 
-pub fn disassemble_software_interrupt(instruction: &ArmInstruction) -> String {
+pub fn disassemble_software_interrupt(instruction: &ArmInstruction, options: &DisassemblyOptions) -> String {
     let cond = instruction.cond();
     let comment = instruction.comment();
-    format!("SWI{} 0x{:08X}", cond, comment)
+    match options.is_gnu() {
+        true => options.instruction(&format!("SWI{cond}"), &[format!("0x{comment:06x}")]),
+        false => options.instruction(&format!("SWI{cond}"), &[format!("0x{comment:08X}")]),
+    }
 }
 
-pub fn disassemble_undefined(_instruction: &ArmInstruction) -> String {
-    format!("Undefined")
+// GNU numbers coprocessors and their registers bare, `p15` and `c1` in the ARM reference
+fn coprocessor_operands(options: &DisassemblyOptions, instruction: &ArmInstruction) -> (String, String) {
+    match options.is_gnu() {
+        true => (instruction.cp_num().to_string(), String::from("cr")),
+        false => (format!("p{}", instruction.cp_num()), String::from("c")),
+    }
+}
+
+pub fn disassemble_coprocessor_data_transfer(instruction: &ArmInstruction, options: &DisassemblyOptions) -> String {
+    let cond = instruction.cond();
+    let long = if instruction.long() { "L" } else { "" };
+    let (cp_num, cr) = coprocessor_operands(options, instruction);
+    let crd = format!("{cr}{}", instruction.crd());
+    let rn = instruction.rn();
+    let immediate = instruction.immediate();
+    let offset = immediate * 4;
+    let add = if instruction.add() { "+" } else { "-" };
+    let offset = match options.is_gnu() {
+        false => format!(",#{add}{offset}"),
+        true => format!(", #{}{offset}", add.trim_start_matches('+')),
+    };
+    let separator = if options.is_gnu() { ", " } else { "," };
+    let write_back = if instruction.write_back() { "!" } else { "" };
+    let address = match (instruction.pre_index(), instruction.write_back()) {
+        (true, _) if immediate == 0 && instruction.add() => format!("[{rn}]{write_back}"),
+        (true, _) => format!("[{rn}{offset}]{write_back}"),
+        (false, true) => format!("[{rn}]{offset}"),
+        // Unindexed, the offset is left to the coprocessor
+        (false, false) => format!("[{rn}]{separator}{{{immediate}}}"),
+    };
+    let mnemonic = match (instruction.load(), options.is_gnu()) {
+        (true, false) => format!("LDC{cond}{long}"),
+        (false, false) => format!("STC{cond}{long}"),
+        (true, true) => format!("LDC{long}{cond}"),
+        (false, true) => format!("STC{long}{cond}"),
+    };
+    options.instruction(&mnemonic, &[cp_num, crd, address])
+}
+
+pub fn disassemble_coprocessor_data_operation(instruction: &ArmInstruction, options: &DisassemblyOptions) -> String {
+    let cond = instruction.cond();
+    let (cp_num, cr) = coprocessor_operands(options, instruction);
+    let info = match options.is_gnu() {
+        true => format!("{{{}}}", instruction.cp_info()),
+        false => instruction.cp_info().to_string(),
+    };
+    options.instruction(
+        &format!("CDP{cond}"),
+        &[
+            cp_num,
+            instruction.cp_opcode().to_string(),
+            format!("{cr}{}", instruction.crd()),
+            format!("{cr}{}", instruction.crn()),
+            format!("{cr}{}", instruction.crm()),
+            info,
+        ],
+    )
+}
+
+pub fn disassemble_coprocessor_register_transfer(instruction: &ArmInstruction, options: &DisassemblyOptions) -> String {
+    let cond = instruction.cond();
+    let (cp_num, cr) = coprocessor_operands(options, instruction);
+    let rd = match (instruction.rd(), instruction.load() && options.is_gnu()) {
+        // `MRC` to the pc only sets the flags
+        (Register::R15, true) => String::from("APSR_nzcv"),
+        (rd, _) => rd.to_string(),
+    };
+    let info = match options.is_gnu() {
+        true => format!("{{{}}}", instruction.cp_info()),
+        false => instruction.cp_info().to_string(),
+    };
+    let mnemonic = match instruction.load() {
+        true => format!("MRC{cond}"),
+        false => format!("MCR{cond}"),
+    };
+    options.instruction(
+        &mnemonic,
+        &[
+            cp_num,
+            instruction.cp_opcode().to_string(),
+            rd,
+            format!("{cr}{}", instruction.crn()),
+            format!("{cr}{}", instruction.crm()),
+            info,
+        ],
+    )
+}
+
+pub fn disassemble_undefined(instruction: &ArmInstruction, options: &DisassemblyOptions) -> String {
+    match options.is_gnu() {
+        true => format!(".word 0x{:08x}", instruction.value),
+        false => String::from("Undefined"),
+    }
 }
@@ -1,9 +1,8 @@
-use bitvec::field::BitField;
-
 use crate::{
     CpuAction, CpuMode, CpuState, DataProcessingOpcode, Exception,
     alu::*,
     barrel_shifter::*,
+    bits::Bits,
     cpu::{Arm7tdmiCpu, LR, PC},
     memory::{MemoryAccess, MemoryInterface},
     psr::ProgramStatusRegister,
@@ -35,7 +34,7 @@ pub fn execute_branch_and_branch_link<I: MemoryInterface>(
 }
 
 pub fn execute_data_processing<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instruction: &ArmInstruction) -> CpuAction {
-    let mut cpu_action = CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::Sequential);
+    let mut cpu_action = CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL);
     let rn = instruction.rn() as usize;
     let mut operand1 = cpu.register(rn);
     let mut carry = cpu.cpsr().carry();
@@ -59,7 +58,7 @@ pub fn execute_data_processing<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, ins
                     if rm == PC {
                         rm_value += 4;
                     }
-                    cpu_action = CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential);
+                    cpu_action = CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL);
                     cpu.idle_cycle();
                     cpu.register(instruction.rs() as usize) & 0xFF
                 }
@@ -113,7 +112,7 @@ pub fn execute_data_processing<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, ins
 
 pub fn execute_psr_transfer<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instruction: &ArmInstruction) -> CpuAction {
     let is_spsr = instruction.is_spsr();
-    match instruction.bits[16..=21].load::<u8>() == 0xF {
+    match instruction.value.bits(16..=21) == 0xF {
         true => {
             let rd = instruction.rd() as usize;
             let psr = match is_spsr {
@@ -124,16 +123,16 @@ pub fn execute_psr_transfer<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instru
         }
         false => {
             let mut mask = 0u32;
-            if instruction.bits[19] {
+            if instruction.value.bit(19) {
                 mask |= 0xFF000000;
             }
-            if instruction.bits[18] {
+            if instruction.value.bit(18) {
                 mask |= 0xFF0000;
             }
-            if instruction.bits[17] {
+            if instruction.value.bit(17) {
                 mask |= 0xFF00;
             }
-            if instruction.bits[16] {
+            if instruction.value.bit(16) {
                 mask |= 0xFF;
             }
 
@@ -171,7 +170,7 @@ pub fn execute_psr_transfer<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instru
             }
         }
     }
-    CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::Sequential)
+    CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL)
 }
 
 pub fn execute_multiply<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instruction: &ArmInstruction) -> CpuAction {
@@ -189,24 +188,24 @@ pub fn execute_multiply<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instructio
         operand2 += 4
     }
 
-    let mut result = operand1.wrapping_mul(operand2);
-    let multiplier_cycles = multiplier_array_cycles(operand2);
-    for _ in 0..multiplier_cycles {
+    for _ in 0..multiplier_array_cycles(operand2, true) {
         cpu.idle_cycle();
     }
 
+    let mut accumulator = 0;
     if instruction.accumulate() {
-        let mut accumulator = cpu.register(rn);
+        accumulator = cpu.register(rn);
         if rn == PC {
             accumulator += 4
         }
-        result = result.wrapping_add(accumulator);
         cpu.idle_cycle();
     };
+    let result = operand1.wrapping_mul(operand2).wrapping_add(accumulator);
 
     if instruction.sets_flags() {
         cpu.set_negative(result >> 31 != 0);
         cpu.set_zero(result == 0);
+        cpu.set_carry(multiply_carry(operand1, operand2, accumulator as u64, false, true));
     }
 
     cpu.set_register(rd, result);
@@ -215,7 +214,7 @@ pub fn execute_multiply<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instructio
             cpu.pipeline_flush();
             CpuAction::PipelineFlush
         }
-        false => CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential),
+        false => CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL),
     }
 }
 
@@ -234,17 +233,18 @@ pub fn execute_multiply_long<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instr
         operand2 += 4
     }
 
-    let unsigned = instruction.unsigned();
-    let mut result = match unsigned {
+    let signed = instruction.signed_long();
+    let mut result = match signed {
         true => (operand1 as i32 as i64).wrapping_mul(operand2 as i32 as i64) as u64,
         false => (operand1 as u64).wrapping_mul(operand2 as u64),
     };
 
-    let multiplier_cycles = multiplier_array_cycles(operand2);
-    for _ in 0..multiplier_cycles {
+    // One more cycle than the short multiplies for the high half
+    for _ in 0..=multiplier_array_cycles(operand2, signed) {
         cpu.idle_cycle();
     }
 
+    let mut accumulator = 0;
     if instruction.accumulate() {
         let mut accumulator_lo = cpu.register(rd_lo) as u64;
         if rd_lo == PC {
@@ -254,7 +254,8 @@ pub fn execute_multiply_long<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instr
         if rd_hi == PC {
             accumulator_hi += 4
         }
-        result = result.wrapping_add(accumulator_hi << 32 | accumulator_lo);
+        accumulator = accumulator_hi << 32 | accumulator_lo;
+        result = result.wrapping_add(accumulator);
         cpu.idle_cycle();
     };
 
@@ -263,6 +264,7 @@ pub fn execute_multiply_long<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instr
     if instruction.sets_flags() {
         cpu.set_negative(result_hi >> 31 != 0);
         cpu.set_zero(result == 0);
+        cpu.set_carry(multiply_carry(operand1, operand2, accumulator, true, signed));
     }
 
     cpu.set_register(rd_lo, result_lo);
@@ -272,7 +274,7 @@ pub fn execute_multiply_long<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instr
             cpu.pipeline_flush();
             CpuAction::PipelineFlush
         }
-        false => CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential),
+        false => CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL),
     }
 }
 
@@ -314,8 +316,8 @@ pub fn execute_single_data_transfer<I: MemoryInterface>(
     match load {
         true => {
             let value = match byte {
-                true => cpu.load_8(address, MemoryAccess::NonSequential as u8),
-                false => cpu.load_rotated_32(address, MemoryAccess::NonSequential as u8),
+                true => cpu.load_8(address, MemoryAccess::NONSEQUENTIAL),
+                false => cpu.load_rotated_32(address, MemoryAccess::NONSEQUENTIAL),
             };
             if write_back || !pre_index {
                 if rn != rd && rn == PC {
@@ -324,7 +326,7 @@ pub fn execute_single_data_transfer<I: MemoryInterface>(
                 cpu.set_register(rn, cpu.register(rn).wrapping_add(offset));
             }
             cpu.idle_cycle();
-            cpu.set_register(rd, value);
+            cpu.set_loaded_register(rd, value);
         }
         false => {
             let mut value = cpu.register(rd);
@@ -332,8 +334,8 @@ pub fn execute_single_data_transfer<I: MemoryInterface>(
                 value += 4;
             }
             match byte {
-                true => cpu.store_8(address, value as u8, MemoryAccess::NonSequential as u8),
-                false => cpu.store_32(address, value, MemoryAccess::NonSequential as u8),
+                true => cpu.store_8(address, value as u8, MemoryAccess::NONSEQUENTIAL),
+                false => cpu.store_32(address, value, MemoryAccess::NONSEQUENTIAL),
             };
             if write_back || !pre_index {
                 if rn == PC {
@@ -344,12 +346,12 @@ pub fn execute_single_data_transfer<I: MemoryInterface>(
         }
     }
 
-    match load && rd == PC {
+    match load && rd == PC && !cpu.data_aborted() {
         true => {
             cpu.pipeline_flush();
             CpuAction::PipelineFlush
         }
-        false => CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential),
+        false => CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL),
     }
 }
 
@@ -383,7 +385,7 @@ pub fn execute_halfword_and_signed_data_transfer<I: MemoryInterface>(
         true => match (s, h) {
             (false, false) => {}
             (false, true) => {
-                let value = cpu.load_rotated_16(address, MemoryAccess::NonSequential as u8);
+                let value = cpu.load_rotated_16(address, MemoryAccess::NONSEQUENTIAL);
                 if write_back || !pre_index {
                     if rn != rd && rn == PC {
                         cpu.pipeline_flush();
@@ -391,10 +393,10 @@ pub fn execute_halfword_and_signed_data_transfer<I: MemoryInterface>(
                     cpu.set_register(rn, cpu.register(rn).wrapping_add(offset));
                 }
                 cpu.idle_cycle();
-                cpu.set_register(rd, value);
+                cpu.set_loaded_register(rd, value);
             }
             (true, false) => {
-                let value = cpu.load_signed_8(address, MemoryAccess::NonSequential as u8);
+                let value = cpu.load_signed_8(address, MemoryAccess::NONSEQUENTIAL);
                 if write_back || !pre_index {
                     if rn != rd && rn == PC {
                         cpu.pipeline_flush();
@@ -402,10 +404,10 @@ pub fn execute_halfword_and_signed_data_transfer<I: MemoryInterface>(
                     cpu.set_register(rn, cpu.register(rn).wrapping_add(offset));
                 }
                 cpu.idle_cycle();
-                cpu.set_register(rd, value);
+                cpu.set_loaded_register(rd, value);
             }
             (true, true) => {
-                let value = cpu.load_signed_16(address, MemoryAccess::NonSequential as u8);
+                let value = cpu.load_signed_16(address, MemoryAccess::NONSEQUENTIAL);
                 if write_back || !pre_index {
                     if rn != rd && rn == PC {
                         cpu.pipeline_flush();
@@ -413,7 +415,7 @@ pub fn execute_halfword_and_signed_data_transfer<I: MemoryInterface>(
                     cpu.set_register(rn, cpu.register(rn).wrapping_add(offset));
                 }
                 cpu.idle_cycle();
-                cpu.set_register(rd, value);
+                cpu.set_loaded_register(rd, value);
             }
         },
         false => {
@@ -424,7 +426,7 @@ pub fn execute_halfword_and_signed_data_transfer<I: MemoryInterface>(
             match (s, h) {
                 (false, false) => {}
                 (false, true) => {
-                    cpu.store_16(address, value as u16, MemoryAccess::NonSequential as u8);
+                    cpu.store_16(address, value as u16, MemoryAccess::NONSEQUENTIAL);
                     if write_back || !pre_index {
                         if rn == PC {
                             cpu.pipeline_flush();
@@ -454,12 +456,12 @@ pub fn execute_halfword_and_signed_data_transfer<I: MemoryInterface>(
         }
     }
 
-    match load && rd == PC {
+    match load && rd == PC && !cpu.data_aborted() {
         true => {
             cpu.pipeline_flush();
             CpuAction::PipelineFlush
         }
-        false => CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential),
+        false => CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL),
     }
 }
 
@@ -468,15 +470,16 @@ pub fn execute_block_data_transfer<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>,
     let rn = instruction.rn() as usize;
     let mut address = cpu.register(rn);
 
-    let mut transfer_pc = register_list.contains(&PC);
+    let mut transfer_pc = register_list.contains(PC);
     let transfer_bytes = if !register_list.is_empty() {
         register_list.len() as u32 * 4
     } else {
-        register_list.push(PC);
+        register_list.insert(PC);
         transfer_pc = true;
         64
     };
 
+    let base = address;
     let load = instruction.load();
     let load_psr_force_user = instruction.load_psr_force_user();
     let mode = cpu.cpsr().mode();
@@ -497,8 +500,8 @@ pub fn execute_block_data_transfer<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>,
     }
 
     let write_back = instruction.write_back();
-    let mut memory_access = MemoryAccess::NonSequential;
-    let mut action = CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential);
+    let mut memory_access = MemoryAccess::NONSEQUENTIAL;
+    let mut action = CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL);
     match load {
         true => {
             for (i, register) in register_list.iter().enumerate() {
@@ -506,7 +509,7 @@ pub fn execute_block_data_transfer<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>,
                     address += 4
                 }
 
-                let value = cpu.load_32(address, memory_access as u8);
+                let value = cpu.load_32(address, memory_access);
                 if write_back && i == 0 {
                     if rn == PC {
                         base_address += 4;
@@ -516,17 +519,21 @@ pub fn execute_block_data_transfer<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>,
                     }
                     cpu.set_register(rn, base_address);
                 }
-                cpu.set_register(*register, value);
+                cpu.set_loaded_register(register, value);
 
                 if !pre_index {
                     address += 4
                 }
 
-                memory_access = MemoryAccess::Sequential;
+                memory_access = MemoryAccess::SEQUENTIAL;
             }
 
             cpu.idle_cycle();
-            if transfer_pc {
+            // Loads stop overwriting registers at the abort, the base keeps its written back or original value
+            if cpu.data_aborted() && register_list.contains(rn) {
+                cpu.set_register(rn, if write_back { base_address } else { base });
+            }
+            if transfer_pc && !cpu.data_aborted() {
                 if load_psr_force_user {
                     cpu.set_cpsr(cpu.spsr());
                 }
@@ -541,15 +548,15 @@ pub fn execute_block_data_transfer<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>,
                     address += 4
                 }
 
-                let mut value = cpu.register(*register);
-                if *register == PC {
+                let mut value = cpu.register(register);
+                if register == PC {
                     match write_back && rn == PC {
                         true => value -= 4,
                         false => value += 4,
                     }
                 }
 
-                cpu.store_32(address, value, memory_access as u8);
+                cpu.store_32(address, value, memory_access);
                 if write_back && i == 0 {
                     if rn == PC {
                         base_address += 4;
@@ -562,7 +569,7 @@ pub fn execute_block_data_transfer<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>,
                     address += 4
                 }
 
-                memory_access = MemoryAccess::Sequential;
+                memory_access = MemoryAccess::SEQUENTIAL;
             }
         }
     }
@@ -588,23 +595,28 @@ pub fn execute_single_data_swap<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, in
     let value: u32;
     match instruction.byte() {
         true => {
-            value = cpu.load_8(address, MemoryAccess::NonSequential as u8);
-            cpu.store_8(address, source as u8, MemoryAccess::NonSequential | MemoryAccess::Lock);
+            value = cpu.load_8(address, MemoryAccess::NONSEQUENTIAL);
+            // An aborted swap has no effect
+            if !cpu.data_aborted() {
+                cpu.store_8(address, source as u8, MemoryAccess::NONSEQUENTIAL | MemoryAccess::LOCK);
+            }
         }
         false => {
-            value = cpu.load_rotated_32(address, MemoryAccess::NonSequential as u8);
-            cpu.store_32(address, source, MemoryAccess::NonSequential | MemoryAccess::Lock);
+            value = cpu.load_rotated_32(address, MemoryAccess::NONSEQUENTIAL);
+            if !cpu.data_aborted() {
+                cpu.store_32(address, source, MemoryAccess::NONSEQUENTIAL | MemoryAccess::LOCK);
+            }
         }
     };
 
     cpu.idle_cycle();
-    cpu.set_register(rd, value);
-    match rd == PC {
+    cpu.set_loaded_register(rd, value);
+    match rd == PC && !cpu.data_aborted() {
         true => {
             cpu.pipeline_flush();
             CpuAction::PipelineFlush
         }
-        false => CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential),
+        false => CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL),
     }
 }
 
@@ -613,7 +625,111 @@ pub fn execute_software_interrupt<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>,
     CpuAction::PipelineFlush
 }
 
+pub fn execute_coprocessor_data_transfer<I: MemoryInterface>(
+    cpu: &mut Arm7tdmiCpu<I>,
+    instruction: &ArmInstruction,
+) -> CpuAction {
+    let opcode = instruction.value;
+    let Some(length) = cpu.coprocessor().and_then(|coprocessor| coprocessor.transfer_length(opcode)) else {
+        return execute_undefined(cpu, instruction);
+    };
+
+    let rn = instruction.rn() as usize;
+    let base = cpu.register(rn);
+    let offset = instruction.immediate() * 4;
+    let offset_base = match instruction.add() {
+        true => base.wrapping_add(offset),
+        false => base.wrapping_sub(offset),
+    };
+    let mut address = match instruction.pre_index() {
+        true => offset_base,
+        false => base,
+    };
+
+    let mut memory_access = MemoryAccess::NONSEQUENTIAL;
+    for index in 0..length {
+        match instruction.load() {
+            true => {
+                let value = cpu.load_32(address & !0x3, memory_access);
+                if let Some(coprocessor) = cpu.coprocessor() {
+                    coprocessor.load_word(opcode, index, value);
+                }
+            }
+            false => {
+                let value = cpu
+                    .coprocessor()
+                    .map_or(0, |coprocessor| coprocessor.store_word(opcode, index));
+                cpu.store_32(address & !0x3, value, memory_access);
+            }
+        }
+        address = address.wrapping_add(4);
+        memory_access = MemoryAccess::SEQUENTIAL;
+    }
+
+    if instruction.write_back() {
+        cpu.set_register(rn, offset_base);
+    }
+    CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL)
+}
+
+pub fn execute_coprocessor_data_operation<I: MemoryInterface>(
+    cpu: &mut Arm7tdmiCpu<I>,
+    instruction: &ArmInstruction,
+) -> CpuAction {
+    let opcode = instruction.value;
+    match cpu
+        .coprocessor()
+        .is_some_and(|coprocessor| coprocessor.data_operation(opcode))
+    {
+        true => CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL),
+        false => execute_undefined(cpu, instruction),
+    }
+}
+
+pub fn execute_coprocessor_register_transfer<I: MemoryInterface>(
+    cpu: &mut Arm7tdmiCpu<I>,
+    instruction: &ArmInstruction,
+) -> CpuAction {
+    let opcode = instruction.value;
+    let rd = instruction.rd() as usize;
+    match instruction.load() {
+        // MRC
+        true => {
+            let Some(value) = cpu.coprocessor().and_then(|coprocessor| coprocessor.read_register(opcode)) else {
+                return execute_undefined(cpu, instruction);
+            };
+            cpu.idle_cycle();
+            cpu.idle_cycle();
+            match rd == PC {
+                // Only the flags are written
+                true => {
+                    let cpsr = (cpu.cpsr().into_bits() & 0x0FFF_FFFF) | (value & 0xF000_0000);
+                    cpu.set_cpsr(ProgramStatusRegister::from_bits(cpsr));
+                }
+                false => cpu.set_register(rd, value),
+            }
+        }
+        // MCR
+        false => {
+            let mut value = cpu.register(rd);
+            if rd == PC {
+                value = value.wrapping_add(4);
+            }
+            if !cpu
+                .coprocessor()
+                .is_some_and(|coprocessor| coprocessor.write_register(opcode, value))
+            {
+                return execute_undefined(cpu, instruction);
+            }
+            cpu.idle_cycle();
+        }
+    }
+    CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL)
+}
+
+// Also taken by coprocessor instructions nothing answered, the GBA has no coprocessors
 pub fn execute_undefined<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, _instruction: &ArmInstruction) -> CpuAction {
+    cpu.idle_cycle();
     cpu.exeception(Exception::Undefined);
     CpuAction::PipelineFlush
 }
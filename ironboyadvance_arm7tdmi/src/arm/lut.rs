@@ -1,7 +1,7 @@
 use ArmInstructionKind::*;
-use bitvec::{field::BitField, order::Lsb0, view::BitView};
 
 use super::ArmInstructionKind;
+use crate::bits::Bits;
 
 pub fn generate_arm_lut() -> [ArmInstructionKind; 4096] {
     let mut arm_lut = [Undefined; 4096];
@@ -13,13 +13,12 @@ pub fn generate_arm_lut() -> [ArmInstructionKind; 4096] {
 
 fn decode_arm(instruction: u32) -> ArmInstructionKind {
     let pattern = instruction & 0x0FFFFFFF;
-    let bits = pattern.view_bits::<Lsb0>();
-    let set_flags = bits[20];
-    let opcode = bits[21..=24].load::<u32>();
+    let set_flags = pattern.bit(20);
+    let opcode = pattern.bits(21..=24);
     let test_opcode = (0b1000..=0b1011).contains(&opcode);
-    match bits[26..=27].load::<u32>() {
+    match pattern.bits(26..=27) {
         0b00 => {
-            if bits[25] {
+            if pattern.bit(25) {
                 match !set_flags && test_opcode {
                     true => PsrTransfer,
                     false => DataProcessing,
@@ -27,7 +26,7 @@ fn decode_arm(instruction: u32) -> ArmInstructionKind {
             } else if pattern & 0x0FF000F0 == 0x01200010 {
                 BranchAndExchange
             } else if pattern & 0x010000F0 == 0x00000090 {
-                match bits[23] {
+                match pattern.bit(23) {
                     true => MultiplyLong,
                     false => Multiply,
                 }
@@ -46,19 +45,15 @@ fn decode_arm(instruction: u32) -> ArmInstructionKind {
             true => Undefined,
             false => SingleDataTransfer,
         },
-        0b10 => match bits[25] {
+        0b10 => match pattern.bit(25) {
             true => BranchAndBranchWithLink,
             false => BlockDataTransfer,
         },
-        0b11 => match bits[25] {
-            true => match bits[24] {
-                true => SoftwareInterrupt,
-                //CoprocessorDataOperation
-                //CoprocessorRegisterTransfer
-                false => Undefined,
-            },
-            //CoprocessorDataTransfer
-            false => Undefined,
+        0b11 => match (pattern.bit(25), pattern.bit(24), pattern.bit(4)) {
+            (true, true, _) => SoftwareInterrupt,
+            (true, false, false) => CoprocessorDataOperation,
+            (true, false, true) => CoprocessorRegisterTransfer,
+            (false, _, _) => CoprocessorDataTransfer,
         },
         _ => Undefined,
     }
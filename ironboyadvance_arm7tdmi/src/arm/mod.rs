@@ -1,5 +1,4 @@
 use ArmInstructionKind::*;
-use bitvec::{field::BitField, order::Lsb0, vec::BitVec, view::BitView};
 use core::fmt;
 use disassembler::*;
 use execute::*;
@@ -7,7 +6,9 @@ use execute::*;
 use crate::{
     Condition, CpuAction, DataProcessingOpcode,
     barrel_shifter::{ShiftBy, ShiftType},
+    bits::{Bits, RegisterList},
     cpu::Arm7tdmiCpu,
+    disassembler::DisassemblyOptions,
     memory::MemoryInterface,
 };
 
@@ -31,15 +32,15 @@ pub enum ArmInstructionKind {
     BlockDataTransfer,
     BranchAndBranchWithLink,
     SoftwareInterrupt,
-    //CoprocessorDataTransfer,
-    //CoprocessorDataOperation,
-    //CoprocessorRegisterTransfer,
+    CoprocessorDataTransfer,
+    CoprocessorDataOperation,
+    CoprocessorRegisterTransfer,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct ArmInstruction {
     kind: ArmInstructionKind,
-    bits: BitVec<u32>,
+    value: u32,
     executed_pc: u32,
 }
 
@@ -48,11 +49,7 @@ impl fmt::Display for ArmInstruction {
         write!(
             f,
             "ArmInstruction: kind: {:?}, bits: {} -> (0x{:08X}), executed_pc:{} -> (0x{:08X})",
-            self.kind,
-            self.bits.load::<u32>(),
-            self.bits.load::<u32>(),
-            self.executed_pc,
-            self.executed_pc
+            self.kind, self.value, self.value, self.executed_pc, self.executed_pc
         )
     }
 }
@@ -60,20 +57,23 @@ impl fmt::Display for ArmInstruction {
 impl Instruction for ArmInstruction {
     type Size = u32;
 
-    fn disassemble<I: MemoryInterface>(&self, cpu: &mut Arm7tdmiCpu<I>) -> String {
+    fn disassemble(&self, options: &DisassemblyOptions) -> String {
         match self.kind {
-            BranchAndExchange => disassemble_branch_exchange(self),
-            BranchAndBranchWithLink => disassemble_branch_and_branch_link(self),
-            DataProcessing => disassemble_data_processing(self),
-            PsrTransfer => disassemble_psr_transfer(cpu, self),
-            Multiply => disassemble_multiply(self),
-            MultiplyLong => disassemble_multiply_long(self),
-            SingleDataTransfer => disassemble_single_data_transfer(self),
-            HalfwordAndSignedDataTransfer => disassemble_halfword_and_signed_data_transfer(self),
-            BlockDataTransfer => disassemble_block_data_transfer(self),
-            SingleDataSwap => disassemble_single_data_swap(self),
-            SoftwareInterrupt => disassemble_software_interrupt(self),
-            Undefined => disassemble_undefined(self),
+            BranchAndExchange => disassemble_branch_exchange(self, options),
+            BranchAndBranchWithLink => disassemble_branch_and_branch_link(self, options),
+            DataProcessing => disassemble_data_processing(self, options),
+            PsrTransfer => disassemble_psr_transfer(self, options),
+            Multiply => disassemble_multiply(self, options),
+            MultiplyLong => disassemble_multiply_long(self, options),
+            SingleDataTransfer => disassemble_single_data_transfer(self, options),
+            HalfwordAndSignedDataTransfer => disassemble_halfword_and_signed_data_transfer(self, options),
+            BlockDataTransfer => disassemble_block_data_transfer(self, options),
+            SingleDataSwap => disassemble_single_data_swap(self, options),
+            SoftwareInterrupt => disassemble_software_interrupt(self, options),
+            CoprocessorDataTransfer => disassemble_coprocessor_data_transfer(self, options),
+            CoprocessorDataOperation => disassemble_coprocessor_data_operation(self, options),
+            CoprocessorRegisterTransfer => disassemble_coprocessor_register_transfer(self, options),
+            Undefined => disassemble_undefined(self, options),
         }
     }
 
@@ -90,12 +90,15 @@ impl Instruction for ArmInstruction {
             BlockDataTransfer => execute_block_data_transfer(cpu, self),
             SingleDataSwap => execute_single_data_swap(cpu, self),
             SoftwareInterrupt => execute_software_interrupt(cpu, self),
+            CoprocessorDataTransfer => execute_coprocessor_data_transfer(cpu, self),
+            CoprocessorDataOperation => execute_coprocessor_data_operation(cpu, self),
+            CoprocessorRegisterTransfer => execute_coprocessor_register_transfer(cpu, self),
             Undefined => execute_undefined(cpu, self),
         }
     }
 
     fn value(&self) -> u32 {
-        self.bits.load::<u32>()
+        self.value
     }
 }
 
@@ -103,42 +106,52 @@ impl ArmInstruction {
     pub fn new(kind: ArmInstructionKind, instruction: u32, executed_pc: u32) -> ArmInstruction {
         ArmInstruction {
             kind,
-            bits: instruction.view_bits::<Lsb0>().to_bitvec(),
+            value: instruction,
             executed_pc,
         }
     }
 
+    pub fn kind(&self) -> ArmInstructionKind {
+        self.kind
+    }
+
     pub fn cond(&self) -> Condition {
-        self.bits[28..=31].load::<u32>().into()
+        self.value.bits(28..=31).into()
     }
 
     pub fn rn(&self) -> Register {
         match self.kind {
-            BranchAndExchange => self.bits[0..=3].load::<u32>().into(),
-            DataProcessing | SingleDataTransfer | HalfwordAndSignedDataTransfer | BlockDataTransfer | SingleDataSwap => {
-                self.bits[16..=19].load::<u32>().into()
-            }
-            Multiply => self.bits[12..=15].load::<u32>().into(),
+            BranchAndExchange => self.value.bits(0..=3).into(),
+            DataProcessing
+            | SingleDataTransfer
+            | HalfwordAndSignedDataTransfer
+            | BlockDataTransfer
+            | SingleDataSwap
+            | CoprocessorDataTransfer => self.value.bits(16..=19).into(),
+            Multiply => self.value.bits(12..=15).into(),
             _ => unimplemented!(),
         }
     }
 
     pub fn rd(&self) -> Register {
         match self.kind {
-            PsrTransfer | DataProcessing | SingleDataTransfer | HalfwordAndSignedDataTransfer | SingleDataSwap => {
-                self.bits[12..=15].load::<u32>().into()
-            }
-            Multiply => self.bits[16..=19].load::<u32>().into(),
+            PsrTransfer
+            | DataProcessing
+            | SingleDataTransfer
+            | HalfwordAndSignedDataTransfer
+            | SingleDataSwap
+            | CoprocessorRegisterTransfer => self.value.bits(12..=15).into(),
+            Multiply => self.value.bits(16..=19).into(),
             _ => unimplemented!(),
         }
     }
 
     pub fn rd_hi(&self) -> Register {
-        self.bits[16..=19].load::<u32>().into()
+        self.value.bits(16..=19).into()
     }
 
     pub fn rd_lo(&self) -> Register {
-        self.bits[12..=15].load::<u32>().into()
+        self.value.bits(12..=15).into()
     }
 
     pub fn rm(&self) -> Register {
@@ -149,161 +162,199 @@ impl ArmInstruction {
             | MultiplyLong
             | SingleDataTransfer
             | HalfwordAndSignedDataTransfer
-            | SingleDataSwap => self.bits[0..=3].load::<u32>().into(),
+            | SingleDataSwap => self.value.bits(0..=3).into(),
             _ => unimplemented!(),
         }
     }
 
     pub fn rs(&self) -> Register {
         match self.kind {
-            DataProcessing | Multiply | MultiplyLong | SingleDataTransfer => self.bits[8..=11].load::<u32>().into(),
+            DataProcessing | Multiply | MultiplyLong | SingleDataTransfer => self.value.bits(8..=11).into(),
             _ => unimplemented!(),
         }
     }
 
     pub fn link(&self) -> bool {
-        self.bits[24]
+        self.value.bit(24)
     }
 
     pub fn offset(&self) -> u32 {
         match self.kind {
-            BranchAndBranchWithLink => self.bits[0..=23].load(),
+            BranchAndBranchWithLink => self.value.bits(0..=23),
             _ => unimplemented!(),
         }
     }
 
     pub fn is_immediate(&self) -> bool {
         match self.kind {
-            PsrTransfer | DataProcessing => self.bits[25],
-            SingleDataTransfer => !self.bits[25],
-            HalfwordAndSignedDataTransfer => self.bits[22],
+            PsrTransfer | DataProcessing => self.value.bit(25),
+            SingleDataTransfer => !self.value.bit(25),
+            HalfwordAndSignedDataTransfer => self.value.bit(22),
             _ => unimplemented!(),
         }
     }
 
     pub fn opcode(&self) -> DataProcessingOpcode {
-        self.bits[21..=24].load::<u32>().into()
+        self.value.bits(21..=24).into()
     }
 
     pub fn sets_flags(&self) -> bool {
         match self.kind {
-            DataProcessing | Multiply | MultiplyLong => self.bits[20],
+            DataProcessing | Multiply | MultiplyLong => self.value.bit(20),
             _ => unimplemented!(),
         }
     }
 
     pub fn shift_by(&self) -> ShiftBy {
-        match self.bits[4] {
+        match self.value.bit(4) {
             true => ShiftBy::Register,
             false => ShiftBy::Immediate,
         }
     }
 
     pub fn shift_amount(&self) -> u32 {
-        self.bits[7..=11].load()
+        self.value.bits(7..=11)
     }
 
     pub fn shift_type(&self) -> ShiftType {
-        self.bits[5..=6].load::<u32>().into()
+        self.value.bits(5..=6).into()
     }
 
     pub fn rotate(&self) -> u32 {
         match self.kind {
-            PsrTransfer | DataProcessing => self.bits[8..=11].load(),
+            PsrTransfer | DataProcessing => self.value.bits(8..=11),
             _ => unimplemented!(),
         }
     }
 
     pub fn immediate(&self) -> u32 {
         match self.kind {
-            PsrTransfer | DataProcessing => self.bits[0..=7].load(),
-            SingleDataTransfer => self.bits[0..=11].load(),
+            PsrTransfer | DataProcessing => self.value.bits(0..=7),
+            SingleDataTransfer => self.value.bits(0..=11),
+            CoprocessorDataTransfer => self.value.bits(0..=7),
             _ => unimplemented!(),
         }
     }
 
     pub fn immediate_hi(&self) -> u32 {
-        self.bits[8..=11].load()
+        self.value.bits(8..=11)
     }
 
     pub fn immediate_lo(&self) -> u32 {
-        self.bits[0..=3].load()
+        self.value.bits(0..=3)
     }
 
     pub fn is_spsr(&self) -> bool {
-        self.bits[22]
+        self.value.bit(22)
     }
 
     pub fn accumulate(&self) -> bool {
         match self.kind {
-            Multiply | MultiplyLong => self.bits[21],
+            Multiply | MultiplyLong => self.value.bit(21),
             _ => unimplemented!(),
         }
     }
 
-    pub fn unsigned(&self) -> bool {
-        self.bits[22]
+    /// The `U` bit of the long multiplies, set for `SMULL` and `SMLAL`
+    pub fn signed_long(&self) -> bool {
+        self.value.bit(22)
     }
 
     pub fn pre_index(&self) -> bool {
         match self.kind {
-            SingleDataTransfer | HalfwordAndSignedDataTransfer | BlockDataTransfer => self.bits[24],
+            SingleDataTransfer | HalfwordAndSignedDataTransfer | BlockDataTransfer | CoprocessorDataTransfer => {
+                self.value.bit(24)
+            }
             _ => unimplemented!(),
         }
     }
 
     pub fn add(&self) -> bool {
         match self.kind {
-            SingleDataTransfer | HalfwordAndSignedDataTransfer | BlockDataTransfer => self.bits[23],
+            SingleDataTransfer | HalfwordAndSignedDataTransfer | BlockDataTransfer | CoprocessorDataTransfer => {
+                self.value.bit(23)
+            }
             _ => unimplemented!(),
         }
     }
 
     pub fn byte(&self) -> bool {
         match self.kind {
-            SingleDataTransfer | SingleDataSwap => self.bits[22],
+            SingleDataTransfer | SingleDataSwap => self.value.bit(22),
             _ => unimplemented!(),
         }
     }
 
     pub fn write_back(&self) -> bool {
         match self.kind {
-            SingleDataTransfer | HalfwordAndSignedDataTransfer | BlockDataTransfer => self.bits[21],
+            SingleDataTransfer | HalfwordAndSignedDataTransfer | BlockDataTransfer | CoprocessorDataTransfer => {
+                self.value.bit(21)
+            }
             _ => unimplemented!(),
         }
     }
 
     pub fn load(&self) -> bool {
         match self.kind {
-            SingleDataTransfer | HalfwordAndSignedDataTransfer | BlockDataTransfer => self.bits[20],
+            SingleDataTransfer
+            | HalfwordAndSignedDataTransfer
+            | BlockDataTransfer
+            | CoprocessorDataTransfer
+            | CoprocessorRegisterTransfer => self.value.bit(20),
             _ => unimplemented!(),
         }
     }
 
     pub fn signed(&self) -> bool {
-        self.bits[6]
+        self.value.bit(6)
     }
 
     pub fn halfword(&self) -> bool {
-        self.bits[5]
+        self.value.bit(5)
     }
 
     pub fn load_psr_force_user(&self) -> bool {
-        self.bits[22]
+        self.value.bit(22)
     }
 
-    pub fn register_list(&self) -> Vec<usize> {
-        self.bits[0..=15]
-            .iter()
-            .enumerate()
-            .filter_map(|(i, b)| match b.as_ref() {
-                true => Some(i),
-                false => None,
-            })
-            .collect()
+    pub fn register_list(&self) -> RegisterList {
+        RegisterList::new(self.value.bits(0..=15) as u16)
     }
 
     pub fn comment(&self) -> u32 {
-        self.bits[0..=23].load()
+        self.value.bits(0..=23)
+    }
+
+    pub fn cp_num(&self) -> u32 {
+        self.value.bits(8..=11)
+    }
+
+    pub fn cp_opcode(&self) -> u32 {
+        match self.kind {
+            CoprocessorDataOperation => self.value.bits(20..=23),
+            CoprocessorRegisterTransfer => self.value.bits(21..=23),
+            _ => unimplemented!(),
+        }
+    }
+
+    pub fn cp_info(&self) -> u32 {
+        self.value.bits(5..=7)
+    }
+
+    pub fn crn(&self) -> u32 {
+        self.value.bits(16..=19)
+    }
+
+    pub fn crd(&self) -> u32 {
+        self.value.bits(12..=15)
+    }
+
+    pub fn crm(&self) -> u32 {
+        self.value.bits(0..=3)
+    }
+
+    /// The `L` of `LDCL`/`STCL`, a transfer length only the coprocessor knows the meaning of
+    pub fn long(&self) -> bool {
+        self.value.bit(22)
     }
 }
@@ -0,0 +1,1073 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{CpuState, disassembler::SymbolTable};
+
+const CONDITIONS: [&str; 15] = [
+    "EQ", "NE", "CS", "CC", "MI", "PL", "VS", "VC", "HI", "LS", "GE", "LT", "GT", "LE", "AL",
+];
+const DATA_PROCESSING: [&str; 16] = [
+    "AND", "EOR", "SUB", "RSB", "ADD", "ADC", "SBC", "RSC", "TST", "TEQ", "CMP", "CMN", "ORR", "MOV", "BIC", "MVN",
+];
+const ALU_OPERATIONS: [&str; 16] = [
+    "AND", "EOR", "LSL", "LSR", "ASR", "ADC", "SBC", "ROR", "TST", "NEG", "CMP", "CMN", "ORR", "MUL", "BIC", "MVN",
+];
+const SHIFTS: [&str; 4] = ["LSL", "LSR", "ASR", "ROR"];
+const AL: u32 = 0b1110;
+
+/// Why a line could not be assembled, `line` counts from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblyError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssemblyError {}
+
+/// Assembles one ARM instruction located at `pc`, in either style the disassembler prints
+pub fn assemble_arm(source: &str, pc: u32) -> Result<u32, AssemblyError> {
+    let labels = HashMap::new();
+    Assembler::new(pc, &labels, None)
+        .arm(&clean(source))
+        .map_err(|message| AssemblyError { line: 1, message })
+}
+
+/// Assembles one THUMB instruction located at `pc`, a `BL` takes two halfwords
+pub fn assemble_thumb(source: &str, pc: u32) -> Result<Vec<u16>, AssemblyError> {
+    let labels = HashMap::new();
+    Assembler::new(pc, &labels, None)
+        .thumb(&clean(source))
+        .map_err(|message| AssemblyError { line: 1, message })
+}
+
+/// Assembles a program placed at `origin`, see `assemble_with`
+pub fn assemble(source: &str, state: CpuState, origin: u32) -> Result<Vec<u8>, AssemblyError> {
+    assemble_with(source, state, origin, None)
+}
+
+/// Assembles one instruction per line, each may be preceded by `label:`. `.arm` and `.thumb` switch the
+/// instruction set, `.word` and `.hword` emit data. Operands name labels of the program, then `symbols`.
+pub fn assemble_with(
+    source: &str,
+    state: CpuState,
+    origin: u32,
+    symbols: Option<&SymbolTable>,
+) -> Result<Vec<u8>, AssemblyError> {
+    let (statements, labels) = layout(source, state, origin)?;
+    let mut bytes = Vec::new();
+    for statement in statements {
+        let assembler = Assembler::new(statement.address, &labels, symbols);
+        let error = |message| AssemblyError {
+            line: statement.line,
+            message,
+        };
+        match statement.state {
+            CpuState::Arm => bytes.extend(assembler.arm(&statement.text).map_err(error)?.to_le_bytes()),
+            CpuState::Thumb => {
+                for halfword in assembler.thumb(&statement.text).map_err(error)? {
+                    bytes.extend(halfword.to_le_bytes());
+                }
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+struct Statement {
+    line: usize,
+    address: u32,
+    state: CpuState,
+    text: String,
+}
+
+// First pass, places every statement so labels are known before anything is encoded
+fn layout(source: &str, mut state: CpuState, origin: u32) -> Result<(Vec<Statement>, HashMap<String, u32>), AssemblyError> {
+    let mut statements = Vec::new();
+    let mut labels = HashMap::new();
+    let mut address = origin;
+    for (index, source_line) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut text = clean(source_line);
+        while let Some((label, rest)) = text.split_once(':').filter(|(label, _)| is_identifier(label)) {
+            if labels.insert(label.to_string(), address).is_some() {
+                let message = format!("`{label}` is defined twice");
+                return Err(AssemblyError { line, message });
+            }
+            text = rest.trim().to_string();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, _) = split_instruction(&text);
+        let size = match (mnemonic.as_str(), state) {
+            (".ARM", _) => {
+                // ARM code is word aligned
+                if !address.is_multiple_of(4) {
+                    let text = String::from(".hword 0");
+                    let state = CpuState::Thumb;
+                    statements.push(Statement {
+                        line,
+                        address,
+                        state,
+                        text,
+                    });
+                    address = address.wrapping_add(2);
+                }
+                state = CpuState::Arm;
+                continue;
+            }
+            (".THUMB", _) => {
+                state = CpuState::Thumb;
+                continue;
+            }
+            (_, CpuState::Arm) | (".WORD" | "BL", CpuState::Thumb) => 4,
+            (_, CpuState::Thumb) => 2,
+        };
+        statements.push(Statement {
+            line,
+            address,
+            state,
+            text,
+        });
+        address = address.wrapping_add(size);
+    }
+    Ok((statements, labels))
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+// Drops comments and the `<symbol>` annotations of the disassembler
+fn clean(line: &str) -> String {
+    let end = [";", "@", "//"]
+        .iter()
+        .filter_map(|comment| line.find(comment))
+        .min()
+        .unwrap_or(line.len());
+    let mut text = String::new();
+    let mut depth = 0;
+    for c in line[..end].chars() {
+        match c {
+            '<' => depth += 1,
+            '>' if depth > 0 => depth -= 1,
+            _ if depth == 0 => text.push(c),
+            _ => {}
+        }
+    }
+    text.trim().to_string()
+}
+
+fn split_instruction(text: &str) -> (String, Vec<String>) {
+    let (mnemonic, operands) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    (mnemonic.to_ascii_uppercase(), split_operands(operands))
+}
+
+// Splits on the commas outside of addresses and register lists
+fn split_operands(text: &str) -> Vec<String> {
+    let mut operands = Vec::new();
+    let mut operand = String::new();
+    let mut depth = 0;
+    for c in text.chars() {
+        match c {
+            '[' | '{' | '(' => depth += 1,
+            ']' | '}' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                operands.push(operand.trim().to_string());
+                operand.clear();
+                continue;
+            }
+            _ => {}
+        }
+        operand.push(c);
+    }
+    if !operand.trim().is_empty() || !operands.is_empty() {
+        operands.push(operand.trim().to_string());
+    }
+    operands
+}
+
+fn operand_count(operands: &[String], count: usize) -> Result<(), String> {
+    match operands.len() == count {
+        true => Ok(()),
+        false => Err(format!("expected {count} operands, found {}", operands.len())),
+    }
+}
+
+fn condition(name: &str) -> Option<u32> {
+    match name {
+        "" => Some(AL),
+        "HS" => Some(0b0010),
+        "LO" => Some(0b0011),
+        _ => CONDITIONS.iter().position(|condition| *condition == name).map(|i| i as u32),
+    }
+}
+
+fn arm_suffixes(base: &str) -> Option<&'static [&'static str]> {
+    match base {
+        "B" => Some(&["", "L"]),
+        "LDR" | "STR" => Some(&["", "B", "T", "BT", "H", "SB", "SH"]),
+        "LDM" | "STM" => Some(&["", "IA", "IB", "DA", "DB", "FD", "ED", "FA", "EA"]),
+        "SWP" => Some(&["", "B"]),
+        "LDC" | "STC" => Some(&["", "L"]),
+        "MUL" | "MLA" | "UMULL" | "UMLAL" | "SMULL" | "SMLAL" => Some(&["", "S"]),
+        "BX" | "MRS" | "MSR" | "PUSH" | "POP" | "SWI" | "CDP" | "MRC" | "MCR" => Some(&[""]),
+        _ if DATA_PROCESSING.contains(&base) => Some(&["", "S"]),
+        _ => None,
+    }
+}
+
+/// Splits an ARM mnemonic into its base, condition and suffix, the condition can come before the
+/// suffix as the ARM reference writes it or after it like GNU
+fn split_arm_mnemonic(mnemonic: &str) -> Option<(&str, u32, &'static str)> {
+    (1..=mnemonic.len()).find_map(|length| {
+        let base = mnemonic.get(..length)?;
+        let rest = &mnemonic[length..];
+        let (condition, suffix) = arm_suffixes(base)?.iter().find_map(|suffix| {
+            [rest.strip_prefix(suffix), rest.strip_suffix(suffix)]
+                .into_iter()
+                .flatten()
+                .find_map(condition)
+                .map(|condition| (condition, *suffix))
+        })?;
+        Some((base, condition, suffix))
+    })
+}
+
+fn register(text: &str) -> Result<u32, String> {
+    let name = text.trim().to_ascii_lowercase();
+    match name.as_str() {
+        "sp" => Ok(13),
+        "lr" => Ok(14),
+        "pc" => Ok(15),
+        _ => name
+            .strip_prefix('r')
+            .and_then(|number| number.parse::<u32>().ok())
+            .filter(|number| *number < 16)
+            .ok_or_else(|| format!("expected a register, found `{text}`")),
+    }
+}
+
+fn low_register(register: u32) -> Result<u16, String> {
+    match register < 8 {
+        true => Ok(register as u16),
+        false => Err(format!("r{register} is not a low register")),
+    }
+}
+
+/// Sign and magnitude of `#-4`, `0x10` and the like
+fn number(text: &str) -> Option<(bool, u32)> {
+    let text = text.trim();
+    let text = text.strip_prefix('#').unwrap_or(text);
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let magnitude = match digits.get(..2) {
+        Some("0x" | "0X") => u32::from_str_radix(&digits[2..], 16),
+        Some("0b" | "0B") => u32::from_str_radix(&digits[2..], 2),
+        _ => digits.parse::<u32>(),
+    };
+    match digits.starts_with(['+', '-']) {
+        true => None,
+        false => magnitude.ok().map(|magnitude| (negative, magnitude)),
+    }
+}
+
+fn immediate(text: &str) -> Result<u32, String> {
+    match number(text) {
+        Some((false, value)) => Ok(value),
+        Some((true, value)) => Ok(value.wrapping_neg()),
+        None => Err(format!("expected a number, found `{text}`")),
+    }
+}
+
+/// `value` divided by `scale`, which it has to be a multiple of
+fn scaled(value: u32, scale: u32, limit: u32) -> Result<u32, String> {
+    match value {
+        value if value.is_multiple_of(scale) && value <= limit => Ok(value / scale),
+        value if !value.is_multiple_of(scale) => Err(format!("{value} is not a multiple of {scale}")),
+        value => Err(format!("{value} is out of range, the limit is {limit}")),
+    }
+}
+
+/// Rotated 8 bit immediate of the data processing and `MSR` instructions
+fn rotated_immediate(value: u32) -> Result<u32, String> {
+    (0..16)
+        .find(|rotate| value.rotate_left(2 * rotate) <= 0xFF)
+        .map(|rotate| 1 << 25 | rotate << 8 | value.rotate_left(2 * rotate))
+        .ok_or_else(|| format!("0x{value:X} can not be encoded as a rotated immediate"))
+}
+
+/// Shift of an ARM register operand, `LSR #32` and `rrx` are encoded as shifts by 0
+fn shifted_register(rm: u32, shift: &str, by_register: bool) -> Result<u32, String> {
+    let shift = shift.trim();
+    let (kind, amount) = shift.split_once(char::is_whitespace).unwrap_or((shift, ""));
+    let kind = kind.to_ascii_uppercase();
+    if kind == "RRX" && amount.is_empty() {
+        return Ok(0b11 << 5 | rm);
+    }
+    let shift_type = SHIFTS
+        .iter()
+        .position(|name| *name == kind)
+        .ok_or_else(|| format!("unknown shift `{shift}`"))? as u32;
+    if let Ok(rs) = register(amount) {
+        return match by_register {
+            true => Ok(rs << 8 | shift_type << 5 | 1 << 4 | rm),
+            false => Err(String::from("the offset can only be shifted by an immediate")),
+        };
+    }
+    let amount = match (shift_type, immediate(amount)?) {
+        (1 | 2, 32) => 0,
+        (_, amount @ 0..=31) => amount,
+        (_, amount) => return Err(format!("shift amount {amount} is out of range")),
+    };
+    Ok(amount << 7 | shift_type << 5 | rm)
+}
+
+/// The registers of `{r0-r3,lr}` or `(r0,r1)` and whether `^` follows
+fn register_list(text: &str) -> Result<(u32, bool), String> {
+    let text = text.trim();
+    let (list, user) = match text.strip_suffix('^') {
+        Some(list) => (list.trim_end(), true),
+        None => (text, false),
+    };
+    let registers = list
+        .strip_prefix('{')
+        .and_then(|list| list.strip_suffix('}'))
+        .or_else(|| list.strip_prefix('(').and_then(|list| list.strip_suffix(')')))
+        .ok_or_else(|| format!("expected a register list, found `{text}`"))?;
+    let mut bits = 0;
+    for item in registers.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let (first, last) = item.split_once('-').unwrap_or((item, item));
+        let (first, last) = (register(first)?, register(last)?);
+        if first > last {
+            return Err(format!("invalid register range `{item}`"));
+        }
+        (first..=last).for_each(|register| bits |= 1 << register);
+    }
+    Ok((bits, user))
+}
+
+fn coprocessor_register(text: &str) -> Result<u32, String> {
+    let name = text.trim().to_ascii_lowercase();
+    name.strip_prefix("cr")
+        .or_else(|| name.strip_prefix('c'))
+        .and_then(|number| number.parse::<u32>().ok())
+        .filter(|number| *number < 16)
+        .ok_or_else(|| format!("expected a coprocessor register, found `{text}`"))
+}
+
+// Coprocessor numbers read `p15` or `15`, the information field `3` or `{3}`
+fn coprocessor_field(text: &str, limit: u32) -> Result<u32, String> {
+    let text = text.trim();
+    let field = text.strip_prefix(['p', 'P']).unwrap_or(text);
+    let field = field
+        .strip_prefix('{')
+        .and_then(|field| field.strip_suffix('}'))
+        .unwrap_or(field);
+    match immediate(field)? {
+        value if value <= limit => Ok(value),
+        value => Err(format!("{value} is out of range, the limit is {limit}")),
+    }
+}
+
+enum Offset {
+    Immediate {
+        subtract: bool,
+        value: u32,
+    },
+    Register {
+        subtract: bool,
+        rm: u32,
+        shift: Option<String>,
+    },
+    /// The `{option}` of an unindexed `LDC` or `STC`
+    Unindexed(u32),
+}
+
+struct Address {
+    rn: u32,
+    pre_index: bool,
+    write_back: bool,
+    offset: Offset,
+}
+
+struct Assembler<'a> {
+    pc: u32,
+    labels: &'a HashMap<String, u32>,
+    symbols: Option<&'a SymbolTable>,
+}
+
+impl<'a> Assembler<'a> {
+    fn new(pc: u32, labels: &'a HashMap<String, u32>, symbols: Option<&'a SymbolTable>) -> Self {
+        Assembler { pc, labels, symbols }
+    }
+
+    /// A number or the address of a label
+    fn value(&self, text: &str) -> Result<u32, String> {
+        if number(text).is_some() {
+            return immediate(text);
+        }
+        let name = text.trim();
+        self.labels
+            .get(name)
+            .copied()
+            .or_else(|| self.symbols.and_then(|symbols| symbols.address_of(name)))
+            .ok_or_else(|| format!("unknown label `{name}`"))
+    }
+
+    /// Offset from the pipelined pc to `target`, divided by `1 << shift` and truncated to `bits`
+    fn relative(&self, target: u32, pipeline: u32, shift: u32, bits: u32) -> Result<u32, String> {
+        let offset = target.wrapping_sub(self.pc.wrapping_add(pipeline)) as i32;
+        let scaled = offset >> shift;
+        if scaled << shift != offset {
+            return Err(format!("0x{target:08X} is not aligned"));
+        }
+        if scaled < -(1 << (bits - 1)) || scaled >= 1 << (bits - 1) {
+            return Err(format!("0x{target:08X} is out of range"));
+        }
+        Ok(scaled as u32 & ((1 << bits) - 1))
+    }
+
+    /// Parses `[rn,offset]!`, `[rn],offset` or the absolute `[address]` of a pc relative transfer, which is
+    /// made relative to `base`
+    fn address(&self, operands: &[String], base: u32) -> Result<Address, String> {
+        let (first, post) = operands.split_first().ok_or("expected an address")?;
+        let (inner, after) = first
+            .strip_prefix('[')
+            .and_then(|address| address.split_once(']'))
+            .ok_or_else(|| format!("expected an address, found `{first}`"))?;
+        let write_back = match after.trim() {
+            "" => false,
+            "!" => true,
+            after => return Err(format!("unexpected `{after}` after the address")),
+        };
+        let inner = split_operands(inner);
+        if let [target] = inner.as_slice()
+            && register(target).is_err()
+        {
+            if write_back || !post.is_empty() {
+                return Err(String::from("an absolute address can not be indexed"));
+            }
+            let offset = self.value(target)?.wrapping_sub(base) as i32;
+            return Ok(Address {
+                rn: 15,
+                pre_index: true,
+                write_back: false,
+                offset: Offset::Immediate {
+                    subtract: offset < 0,
+                    value: offset.unsigned_abs(),
+                },
+            });
+        }
+
+        let (rn, pre) = inner.split_first().ok_or("expected a base register")?;
+        let (pre_index, offset) = match (pre.is_empty(), post.is_empty()) {
+            (_, true) => (true, pre),
+            (true, false) if !write_back => (false, post),
+            _ => return Err(String::from("an address is either pre or post indexed")),
+        };
+        let offset = match offset {
+            [] => Offset::Immediate {
+                subtract: false,
+                value: 0,
+            },
+            [option] if option.starts_with('{') => Offset::Unindexed(coprocessor_field(option, 0xFF)?),
+            [value] if value.starts_with('#') => {
+                let (subtract, value) = number(value).ok_or_else(|| format!("expected a number, found `{value}`"))?;
+                Offset::Immediate { subtract, value }
+            }
+            [rm, shift @ ..] if shift.len() < 2 => {
+                let (subtract, rm) = match rm.strip_prefix('-') {
+                    Some(rm) => (true, rm),
+                    None => (false, rm.strip_prefix('+').unwrap_or(rm)),
+                };
+                Offset::Register {
+                    subtract,
+                    rm: register(rm)?,
+                    shift: shift.first().cloned(),
+                }
+            }
+            _ => return Err(String::from("too many offset operands")),
+        };
+        Ok(Address {
+            rn: register(rn)?,
+            pre_index,
+            write_back,
+            offset,
+        })
+    }
+
+    fn arm(&self, text: &str) -> Result<u32, String> {
+        let (mnemonic, operands) = split_instruction(text);
+        if mnemonic == ".WORD" {
+            operand_count(&operands, 1)?;
+            return self.value(&operands[0]);
+        }
+        let (base, condition, suffix) =
+            split_arm_mnemonic(&mnemonic).ok_or_else(|| format!("unknown instruction `{mnemonic}`"))?;
+        let set_flags = (suffix == "S") as u32;
+        let opcode = match base {
+            "B" => {
+                operand_count(&operands, 1)?;
+                let offset = self.relative(self.value(&operands[0])?, 8, 2, 24)?;
+                0x0A00_0000 | ((suffix == "L") as u32) << 24 | offset
+            }
+            "BX" => {
+                operand_count(&operands, 1)?;
+                0x012F_FF10 | register(&operands[0])?
+            }
+            "MUL" | "MLA" => {
+                let accumulate = base == "MLA";
+                operand_count(&operands, if accumulate { 4 } else { 3 })?;
+                let rn = if accumulate { register(&operands[3])? } else { 0 };
+                let [rd, rm, rs] = [0, 1, 2].map(|i| register(&operands[i]));
+                (accumulate as u32) << 21 | set_flags << 20 | rd? << 16 | rn << 12 | rs? << 8 | 0x90 | rm?
+            }
+            "UMULL" | "UMLAL" | "SMULL" | "SMLAL" => {
+                operand_count(&operands, 4)?;
+                let signed = base.starts_with('S') as u32;
+                let accumulate = base.ends_with("LAL") as u32;
+                let [rd_lo, rd_hi, rm, rs] = [0, 1, 2, 3].map(|i| register(&operands[i]));
+                0x0080_0090
+                    | signed << 22
+                    | accumulate << 21
+                    | set_flags << 20
+                    | rd_hi? << 16
+                    | rd_lo? << 12
+                    | rs? << 8
+                    | rm?
+            }
+            "MRS" => {
+                operand_count(&operands, 2)?;
+                let (spsr, _) = psr(&operands[1])?;
+                0x010F_0000 | spsr << 22 | register(&operands[0])? << 12
+            }
+            "MSR" => {
+                operand_count(&operands, 2)?;
+                let (spsr, fields) = psr(&operands[0])?;
+                let mask = field_mask(fields)?;
+                let operand = match register(&operands[1]) {
+                    Ok(rm) => rm,
+                    Err(_) => rotated_immediate(self.value(&operands[1])?)?,
+                };
+                0x0120_F000 | spsr << 22 | mask << 16 | operand
+            }
+            "LDR" | "STR" => self.arm_transfer(&operands, base == "LDR", suffix)?,
+            "LDM" | "STM" => block_transfer(&operands, base == "LDM", suffix)?,
+            "PUSH" | "POP" => {
+                operand_count(&operands, 1)?;
+                let operands = [String::from("sp!"), operands[0].clone()];
+                match base {
+                    "PUSH" => block_transfer(&operands, false, "DB")?,
+                    _ => block_transfer(&operands, true, "IA")?,
+                }
+            }
+            "SWP" => {
+                operand_count(&operands, 3)?;
+                let rn = operands[2]
+                    .strip_prefix('[')
+                    .and_then(|rn| rn.strip_suffix(']'))
+                    .ok_or_else(|| format!("expected `[rn]`, found `{}`", operands[2]))?;
+                let byte = (suffix == "B") as u32;
+                let [rd, rm] = [0, 1].map(|i| register(&operands[i]));
+                0x0100_0090 | byte << 22 | register(rn)? << 16 | rd? << 12 | rm?
+            }
+            "SWI" => {
+                operand_count(&operands, 1)?;
+                0x0F00_0000 | scaled(immediate(&operands[0])?, 1, 0xFF_FFFF)?
+            }
+            "CDP" => {
+                operand_count(&operands, 6)?;
+                let [crd, crn, crm] = [2, 3, 4].map(|i| coprocessor_register(&operands[i]));
+                0x0E00_0000
+                    | coprocessor_field(&operands[1], 0xF)? << 20
+                    | crn? << 16
+                    | crd? << 12
+                    | coprocessor_field(&operands[0], 0xF)? << 8
+                    | coprocessor_field(&operands[5], 0x7)? << 5
+                    | crm?
+            }
+            "MRC" | "MCR" => {
+                operand_count(&operands, 6)?;
+                let load = base == "MRC";
+                // Only the flags are written when the destination is the pc
+                let rd = match operands[2].eq_ignore_ascii_case("APSR_nzcv") {
+                    true => 15,
+                    false => register(&operands[2])?,
+                };
+                let [crn, crm] = [3, 4].map(|i| coprocessor_register(&operands[i]));
+                0x0E00_0010
+                    | coprocessor_field(&operands[1], 0x7)? << 21
+                    | (load as u32) << 20
+                    | crn? << 16
+                    | rd << 12
+                    | coprocessor_field(&operands[0], 0xF)? << 8
+                    | coprocessor_field(&operands[5], 0x7)? << 5
+                    | crm?
+            }
+            "LDC" | "STC" => self.coprocessor_transfer(&operands, base == "LDC", suffix == "L")?,
+            _ => data_processing(base, &operands, set_flags, |text| self.value(text))?,
+        };
+        Ok(condition << 28 | opcode)
+    }
+
+    fn arm_transfer(&self, operands: &[String], load: bool, suffix: &str) -> Result<u32, String> {
+        let (rd, address) = operands.split_first().ok_or("expected a register and an address")?;
+        let rd = register(rd)?;
+        let mut address = self.address(address, self.pc.wrapping_add(8))?;
+        // `T` forces a user mode access, encoded as write back on a post indexed transfer
+        let user = suffix.ends_with('T');
+        if user && !address.write_back && matches!(address.offset, Offset::Immediate { value: 0, .. }) {
+            address.pre_index = false;
+        }
+        let common = (address.pre_index as u32) << 24 | (load as u32) << 20 | address.rn << 16 | rd << 12;
+        let signed_halfword = match suffix {
+            "H" => 0b01,
+            "SB" => 0b10,
+            "SH" => 0b11,
+            _ => {
+                if user && address.pre_index {
+                    return Err(String::from("`T` transfers are post indexed"));
+                }
+                let offset = match address.offset {
+                    Offset::Immediate { subtract, value } if value <= 0xFFF => (!subtract as u32) << 23 | value,
+                    Offset::Register { subtract, rm, shift } => {
+                        let rm = match shift {
+                            Some(shift) => shifted_register(rm, &shift, false)?,
+                            None => rm,
+                        };
+                        1 << 25 | (!subtract as u32) << 23 | rm
+                    }
+                    _ => return Err(String::from("the offset is out of range")),
+                };
+                let byte = suffix.starts_with('B') as u32;
+                return Ok(0x0400_0000 | common | byte << 22 | ((address.write_back || user) as u32) << 21 | offset);
+            }
+        };
+        let offset = match address.offset {
+            Offset::Immediate { subtract, value } if value <= 0xFF => {
+                (!subtract as u32) << 23 | 1 << 22 | (value & 0xF0) << 4 | value & 0xF
+            }
+            Offset::Register {
+                subtract,
+                rm,
+                shift: None,
+            } => (!subtract as u32) << 23 | rm,
+            _ => return Err(String::from("the offset is out of range")),
+        };
+        Ok(common | offset | (address.write_back as u32) << 21 | 0x90 | signed_halfword << 5)
+    }
+
+    fn coprocessor_transfer(&self, operands: &[String], load: bool, long: bool) -> Result<u32, String> {
+        if operands.len() < 3 {
+            return Err(format!("expected 3 operands, found {}", operands.len()));
+        }
+        let address = self.address(&operands[2..], self.pc.wrapping_add(8))?;
+        let (pre_index, add, write_back, offset) = match address.offset {
+            Offset::Unindexed(option) if !address.pre_index => (false, true, false, option),
+            Offset::Immediate { subtract, value } if value.is_multiple_of(4) && value <= 0x3FC => (
+                address.pre_index,
+                !subtract,
+                address.write_back || !address.pre_index,
+                value / 4,
+            ),
+            _ => return Err(String::from("expected a word aligned immediate offset up to 1020")),
+        };
+        Ok(0x0C00_0000
+            | (pre_index as u32) << 24
+            | (add as u32) << 23
+            | (long as u32) << 22
+            | (write_back as u32) << 21
+            | (load as u32) << 20
+            | address.rn << 16
+            | coprocessor_register(&operands[1])? << 12
+            | coprocessor_field(&operands[0], 0xF)? << 8
+            | offset)
+    }
+
+    fn thumb(&self, text: &str) -> Result<Vec<u16>, String> {
+        let (mnemonic, operands) = split_instruction(text);
+        let target = || {
+            operand_count(&operands, 1)?;
+            self.value(&operands[0])
+        };
+        let opcode = match mnemonic.as_str() {
+            ".HWORD" => match target()? {
+                value if value <= 0xFFFF => value as u16,
+                value => return Err(format!("0x{value:X} does not fit in a halfword")),
+            },
+            ".WORD" => {
+                let value = target()?;
+                return Ok(vec![value as u16, (value >> 16) as u16]);
+            }
+            "BL" => {
+                let offset = self.relative(target()?, 4, 1, 22)?;
+                return Ok(vec![0xF000 | (offset >> 11) as u16, 0xF800 | (offset & 0x7FF) as u16]);
+            }
+            // The halves of a `BL` as the disassembler prints them on their own
+            "BL(HI)" => 0xF000 | self.relative(target()?, 4, 12, 11)? as u16,
+            "BL(LO)" => {
+                operand_count(&operands, 1)?;
+                let offset = operands[0]
+                    .get(..3)
+                    .filter(|lr| lr.eq_ignore_ascii_case("lr+"))
+                    .map(|_| &operands[0][3..])
+                    .ok_or_else(|| format!("expected `lr+#offset`, found `{}`", operands[0]))?;
+                0xF800 | scaled(immediate(offset)?, 2, 0xFFE)? as u16
+            }
+            "B" => 0xE000 | self.relative(target()?, 4, 1, 11)? as u16,
+            "BX" => {
+                operand_count(&operands, 1)?;
+                0x4700 | (register(&operands[0])? as u16) << 3
+            }
+            "SWI" => {
+                operand_count(&operands, 1)?;
+                0xDF00 | scaled(immediate(&operands[0])?, 1, 0xFF)? as u16
+            }
+            "LSL" | "LSR" | "ASR" if operands.len() == 3 => {
+                let shift_type = SHIFTS.iter().position(|name| *name == mnemonic).unwrap_or_default() as u16;
+                let amount = match (shift_type, immediate(&operands[2])?) {
+                    (1 | 2, 32) => 0,
+                    (_, amount @ 0..=31) => amount as u16,
+                    (_, amount) => return Err(format!("shift amount {amount} is out of range")),
+                };
+                let [rd, rs] = [0, 1].map(|i| register(&operands[i]).and_then(low_register));
+                shift_type << 11 | amount << 6 | rs? << 3 | rd?
+            }
+            "ADD" | "SUB" => self.thumb_add_subtract(&operands, mnemonic == "SUB")?,
+            "MOV" | "CMP" => {
+                operand_count(&operands, 2)?;
+                let compare = mnemonic == "CMP";
+                let rd = register(&operands[0])?;
+                match register(&operands[1]) {
+                    // Only compares between low registers have an ALU form
+                    Ok(rs) if compare && rd < 8 && rs < 8 => 0x4280 | (rs << 3 | rd) as u16,
+                    Ok(rs) => hi_register_operation(if compare { 0b01 } else { 0b10 }, rd, rs),
+                    Err(_) => {
+                        let value = scaled(immediate(&operands[1])?, 1, 0xFF)? as u16;
+                        0x2000 | (compare as u16) << 11 | low_register(rd)? << 8 | value
+                    }
+                }
+            }
+            "LDR" | "STR" | "LDRB" | "STRB" | "LDRH" | "STRH" | "LDSB" | "LDSH" | "LDRSB" | "LDRSH" => {
+                self.thumb_transfer(&mnemonic, &operands)?
+            }
+            "PUSH" | "POP" => {
+                operand_count(&operands, 1)?;
+                let load = mnemonic == "POP";
+                // `lr` can be pushed and `pc` popped besides the low registers
+                let extra = if load { 1 << 15 } else { 1 << 14 };
+                let (list, user) = register_list(&operands[0])?;
+                if user || list & !(0xFF | extra) != 0 {
+                    return Err(format!("{mnemonic} can not transfer `{}`", operands[0]));
+                }
+                0xB400 | (load as u16) << 11 | ((list & extra != 0) as u16) << 8 | list as u16 & 0xFF
+            }
+            "LDMIA" | "STMIA" => {
+                operand_count(&operands, 2)?;
+                let rb = operands[0]
+                    .strip_suffix('!')
+                    .ok_or_else(|| format!("{mnemonic} always writes back, expected `{}!`", operands[0]))?;
+                let (list, user) = register_list(&operands[1])?;
+                if user || list > 0xFF {
+                    return Err(format!("{mnemonic} only transfers low registers"));
+                }
+                let load = (mnemonic == "LDMIA") as u16;
+                0xC000 | load << 11 | low_register(register(rb)?)? << 8 | list as u16
+            }
+            _ => {
+                if let Some(opcode) = ALU_OPERATIONS.iter().position(|name| *name == mnemonic) {
+                    operand_count(&operands, 2)?;
+                    let [rd, rs] = [0, 1].map(|i| register(&operands[i]).and_then(low_register));
+                    0x4000 | (opcode as u16) << 6 | rs? << 3 | rd?
+                } else if let Some(condition) = mnemonic.strip_prefix('B').and_then(condition) {
+                    0xD000 | (condition as u16) << 8 | self.relative(target()?, 4, 1, 8)? as u16
+                } else {
+                    return Err(format!("unknown instruction `{mnemonic}`"));
+                }
+            }
+        };
+        Ok(vec![opcode])
+    }
+
+    fn thumb_add_subtract(&self, operands: &[String], subtract: bool) -> Result<u16, String> {
+        let is_register = |text: &str| register(text).is_ok();
+        let opcode = match operands {
+            [rd, base, offset] if !subtract && matches!(register(base), Ok(13 | 15)) => {
+                let sp = (register(base)? == 13) as u16;
+                0xA000 | sp << 11 | low_register(register(rd)?)? << 8 | scaled(immediate(offset)?, 4, 1020)? as u16
+            }
+            [rd, rs, operand] => {
+                let (rd, rs) = (low_register(register(rd)?)?, low_register(register(rs)?)?);
+                let operand = match register(operand) {
+                    Ok(rn) => low_register(rn)?,
+                    Err(_) => 1 << 4 | scaled(immediate(operand)?, 1, 7)? as u16,
+                };
+                0x1800 | (subtract as u16) << 9 | operand << 6 | rs << 3 | rd
+            }
+            [rd, offset] if register(rd) == Ok(13) && !is_register(offset) => {
+                let (negative, value) = number(offset).ok_or_else(|| format!("expected a number, found `{offset}`"))?;
+                let value = scaled(value, 4, 508)? as u16;
+                0xB000 | ((negative != subtract) as u16) << 7 | value
+            }
+            [rd, offset] if !is_register(offset) => {
+                0x3000
+                    | (subtract as u16) << 11
+                    | low_register(register(rd)?)? << 8
+                    | scaled(immediate(offset)?, 1, 0xFF)? as u16
+            }
+            [rd, rs] if !subtract => hi_register_operation(0b00, register(rd)?, register(rs)?),
+            _ => return Err(format!("invalid operands for {}", if subtract { "SUB" } else { "ADD" })),
+        };
+        Ok(opcode)
+    }
+
+    fn thumb_transfer(&self, mnemonic: &str, operands: &[String]) -> Result<u16, String> {
+        let (rd, address) = operands.split_first().ok_or("expected a register and an address")?;
+        let rd = low_register(register(rd)?)?;
+        // The pc is word aligned before the offset is added
+        let address = self.address(address, self.pc.wrapping_add(4) & !0x3)?;
+        if !address.pre_index || address.write_back {
+            return Err(String::from("THUMB transfers are pre indexed without write back"));
+        }
+        let load = mnemonic.starts_with("LD") as u16;
+        let opcode = match (mnemonic, address.rn, address.offset) {
+            ("LDR", 15, Offset::Immediate { subtract: false, value }) => 0x4800 | rd << 8 | scaled(value, 4, 1020)? as u16,
+            ("LDR" | "STR", 13, Offset::Immediate { subtract: false, value }) => {
+                0x9000 | load << 11 | rd << 8 | scaled(value, 4, 1020)? as u16
+            }
+            (
+                _,
+                rb,
+                Offset::Register {
+                    subtract: false,
+                    rm,
+                    shift: None,
+                },
+            ) => {
+                let opcode = match mnemonic {
+                    "STR" => 0x5000,
+                    "STRH" => 0x5200,
+                    "STRB" => 0x5400,
+                    "LDSB" | "LDRSB" => 0x5600,
+                    "LDR" => 0x5800,
+                    "LDRH" => 0x5A00,
+                    "LDRB" => 0x5C00,
+                    _ => 0x5E00,
+                };
+                opcode | low_register(rm)? << 6 | low_register(rb)? << 3 | rd
+            }
+            (_, rb, Offset::Immediate { subtract: false, value }) => {
+                let opcode = match mnemonic {
+                    "STR" | "LDR" => 0x6000 | scaled(value, 4, 124)? << 6,
+                    "STRB" | "LDRB" => 0x7000 | scaled(value, 1, 31)? << 6,
+                    "STRH" | "LDRH" => 0x8000 | scaled(value, 2, 62)? << 6,
+                    _ => return Err(format!("{mnemonic} only takes a register offset")),
+                };
+                opcode as u16 | load << 11 | low_register(rb)? << 3 | rd
+            }
+            _ => return Err(format!("invalid address for {mnemonic}")),
+        };
+        Ok(opcode)
+    }
+}
+
+fn psr(text: &str) -> Result<(u32, Option<&str>), String> {
+    let (name, fields) = match text.split_once('_') {
+        Some((name, fields)) => (name, Some(fields)),
+        None => (text, None),
+    };
+    match name.to_ascii_uppercase().as_str() {
+        "CPSR" => Ok((0, fields)),
+        "SPSR" => Ok((1, fields)),
+        _ => Err(format!("expected CPSR or SPSR, found `{text}`")),
+    }
+}
+
+// Bits 16 to 19 of `MSR`, written `flg`, `all` or as the letters of the c, x, s and f bytes
+fn field_mask(fields: Option<&str>) -> Result<u32, String> {
+    let Some(fields) = fields else {
+        return Ok(0b1001);
+    };
+    match fields.to_ascii_lowercase().as_str() {
+        "flg" => Ok(0b1000),
+        "all" => Ok(0b1001),
+        "ctl" => Ok(0b0001),
+        letters => letters.chars().try_fold(0, |mask, field| match field {
+            'f' => Ok(mask | 0b1000),
+            's' => Ok(mask | 0b0100),
+            'x' => Ok(mask | 0b0010),
+            'c' => Ok(mask | 0b0001),
+            _ => Err(format!("unknown PSR field `{field}`")),
+        }),
+    }
+}
+
+fn data_processing(
+    base: &str,
+    operands: &[String],
+    set_flags: u32,
+    value: impl Fn(&str) -> Result<u32, String>,
+) -> Result<u32, String> {
+    let opcode = DATA_PROCESSING.iter().position(|name| *name == base).unwrap_or_default() as u32;
+    let required = match opcode {
+        0b1000..=0b1011 | 0b1101 | 0b1111 => 2,
+        _ => 3,
+    };
+    if operands.len() < required || operands.len() > required + 1 {
+        return Err(format!("expected {required} operands, found {}", operands.len()));
+    }
+    // The tests always set the flags, without S they would be PSR transfers
+    let set_flags = match opcode {
+        0b1000..=0b1011 => 1,
+        _ => set_flags,
+    };
+    let (rd, rn) = match opcode {
+        0b1000..=0b1011 => (0, register(&operands[0])?),
+        0b1101 | 0b1111 => (register(&operands[0])?, 0),
+        _ => (register(&operands[0])?, register(&operands[1])?),
+    };
+    let operand_2 = match &operands[required - 1..] {
+        [operand] => match register(operand) {
+            Ok(rm) => rm,
+            Err(_) => rotated_immediate(value(operand)?)?,
+        },
+        [rm, shift] => shifted_register(register(rm)?, shift, true)?,
+        _ => unreachable!(),
+    };
+    Ok(opcode << 21 | set_flags << 20 | rn << 16 | rd << 12 | operand_2)
+}
+
+fn block_transfer(operands: &[String], load: bool, mode: &str) -> Result<u32, String> {
+    operand_count(operands, 2)?;
+    let (rn, write_back) = match operands[0].strip_suffix('!') {
+        Some(rn) => (rn, true),
+        None => (operands[0].as_str(), false),
+    };
+    let (list, user) = register_list(&operands[1])?;
+    // Stack names depend on the direction, a full descending stack is `STMFD` and `LDMFD`
+    let mode = match (load, mode) {
+        (true, "ED") | (false, "FA") => "IB",
+        (true, "FD") | (false, "EA") => "IA",
+        (true, "EA") | (false, "FD") => "DB",
+        (true, "FA") | (false, "ED") => "DA",
+        (_, mode) => mode,
+    };
+    let (pre_index, add) = match mode {
+        "IB" => (true, true),
+        "DB" => (true, false),
+        "DA" => (false, false),
+        _ => (false, true),
+    };
+    Ok(0x0800_0000
+        | (pre_index as u32) << 24
+        | (add as u32) << 23
+        | (user as u32) << 22
+        | (write_back as u32) << 21
+        | (load as u32) << 20
+        | register(rn)? << 16
+        | list)
+}
+
+fn hi_register_operation(opcode: u16, rd: u32, rs: u32) -> u16 {
+    0x4400 | opcode << 8 | ((rd & 0x8) as u16) << 4 | (rs as u16) << 3 | (rd & 0x7) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disassembler::{DisassemblyOptions, DisassemblyStyle, disassemble_arm_with, disassemble_thumb_with};
+
+    #[test]
+    fn disassembly_round_trips() {
+        for style in [DisassemblyStyle::Native, DisassemblyStyle::Gnu] {
+            let options = DisassemblyOptions { style, symbols: None };
+            let pc = 0x0800_0100;
+            for opcode in 0..=u16::MAX {
+                let text = disassemble_thumb_with(opcode, pc, &options);
+                if text == "Undefined" {
+                    continue;
+                }
+                let assembled = assemble_thumb(&text, pc).unwrap_or_else(|error| panic!("{text}: {error}"));
+                assert_eq!(disassemble_thumb_with(assembled[0], pc, &options), text, "{opcode:04X}");
+            }
+
+            let mut seed = 0x1234_5678u32;
+            for _ in 0..100_000 {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                let text = disassemble_arm_with(seed, pc, &options);
+                if text == "Undefined" {
+                    continue;
+                }
+                let assembled = assemble_arm(&text, pc).unwrap_or_else(|error| panic!("{text}: {error}"));
+                assert_eq!(disassemble_arm_with(assembled, pc, &options), text, "{seed:08X}");
+            }
+        }
+    }
+
+    #[test]
+    fn programs() {
+        let source = "
+            start:  mov r0, #0x04000000     ; display control
+                    ldr r1, =0 @ unsupported
+        ";
+        let error = assemble(source, CpuState::Arm, 0x0800_0000).unwrap_err();
+        assert_eq!(error.line, 3);
+
+        let source = "
+            .arm
+            start:
+                adr: add r0, pc, #1
+                ldmfd sp!, {r0-r2, lr}^
+                blne thumb
+                .word start
+            .thumb
+            thumb:
+                bl start
+                beq thumb
+                push {r4-r7, lr}
+                ldr r0, [r1, #4]
+                .hword 0xBEEF
+            .arm
+                swi 0x123456
+        ";
+        let bytes = assemble(source, CpuState::Arm, 0x0800_0000).unwrap();
+        let words = |range: std::ops::Range<usize>| {
+            bytes[range]
+                .chunks(4)
+                .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+                .collect::<Vec<u32>>()
+        };
+        let halfwords = |range: std::ops::Range<usize>| {
+            bytes[range]
+                .chunks(2)
+                .map(|halfword| u16::from_le_bytes(halfword.try_into().unwrap()))
+                .collect::<Vec<u16>>()
+        };
+        assert_eq!(words(0..16), [0xE28F0001, 0xE8FD4007, 0x1B000000, 0x08000000]);
+        assert_eq!(halfwords(16..28), [0xF7FF, 0xFFF6, 0xD0FC, 0xB5F0, 0x6848, 0xBEEF]);
+        assert_eq!(words(28..32), [0xEF123456]);
+        assert_eq!(bytes.len(), 32);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x0800_0000, "rom");
+        let bytes = assemble_with("b rom", CpuState::Arm, 0x0800_0008, Some(&symbols)).unwrap();
+        assert_eq!(bytes, 0xEAFFFFFCu32.to_le_bytes());
+        assert_eq!(
+            assemble_arm("MOVEQS r0,r1,LSL r2", 0),
+            assemble_arm("moveqs r0, r1, lsl r2", 0)
+        );
+        assert_eq!(assemble_arm("umull r0, r1, r2, r3", 0), Ok(0xE0810392));
+        assert!(assemble_arm("mov r0, #0x101", 0).is_err());
+    }
+}
@@ -0,0 +1,88 @@
+use std::ops::RangeInclusive;
+
+/// Field extraction on opcodes, numbering bits from the least significant like the ARM reference
+pub trait Bits: Copy {
+    fn bit(self, index: u32) -> bool;
+
+    /// Bits `start..=end`, shifted down to bit 0
+    fn bits(self, range: RangeInclusive<u32>) -> Self;
+}
+
+macro_rules! impl_bits {
+    ($type:ty) => {
+        impl Bits for $type {
+            #[inline]
+            fn bit(self, index: u32) -> bool {
+                (self >> index) & 0b1 != 0
+            }
+
+            #[inline]
+            fn bits(self, range: RangeInclusive<u32>) -> Self {
+                let (start, end) = (*range.start(), *range.end());
+                let width = end - start + 1;
+                match width >= <$type>::BITS {
+                    true => self >> start,
+                    false => (self >> start) & ((1 << width) - 1),
+                }
+            }
+        }
+    };
+}
+
+impl_bits!(u16);
+impl_bits!(u32);
+
+/// Registers transferred by a block transfer, bit n set for rn
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RegisterList(u16);
+
+impl RegisterList {
+    pub fn new(mask: u16) -> Self {
+        RegisterList(mask)
+    }
+
+    pub fn contains(&self, register: usize) -> bool {
+        self.0.bit(register as u32)
+    }
+
+    pub fn insert(&mut self, register: usize) {
+        self.0 |= 1 << register;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    /// Registers from the lowest, the order they are transferred in
+    pub fn iter(&self) -> impl Iterator<Item = usize> + use<> {
+        let mask = self.0;
+        (0..16).filter(move |register| mask.bit(*register as u32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_fields() {
+        let opcode: u32 = 0xE3A0_1C02; // mov r1, #0x200
+        assert_eq!(opcode.bits(28..=31), 0xE);
+        assert_eq!(opcode.bits(12..=15), 1);
+        assert_eq!(opcode.bits(0..=31), opcode);
+        assert!(opcode.bit(25));
+        assert!(!opcode.bit(20));
+        assert_eq!(0xB5F0u16.bits(0..=7), 0xF0);
+
+        let mut list = RegisterList::new(0b1000_0000_0001_0010);
+        assert_eq!(list.iter().collect::<Vec<usize>>(), vec![1, 4, 15]);
+        assert_eq!(list.len(), 3);
+        assert!(list.contains(15) && !list.contains(0));
+        list.insert(0);
+        assert_eq!(list.iter().next(), Some(0));
+    }
+}
@@ -0,0 +1,336 @@
+use std::{collections::HashMap, rc::Rc};
+
+use crate::{CpuState, arm::ArmInstruction, cpu::Instruction, thumb::ThumbInstruction};
+
+pub(crate) const MAX_BLOCK_INSTRUCTIONS: usize = 64;
+const PAGE_SHIFT: u32 = 8;
+
+pub(crate) enum BlockInstructions {
+    Arm(Vec<ArmInstruction>),
+    Thumb(Vec<ThumbInstruction>),
+}
+
+/// Straight line code decoded once, ending at the first instruction that may branch
+pub(crate) struct Block {
+    pub(crate) start: u32,
+    pub(crate) instructions: BlockInstructions,
+    #[cfg(feature = "jit")]
+    pub(crate) code: crate::jit::BlockCode,
+}
+
+impl Block {
+    pub(crate) fn new(start: u32, instructions: BlockInstructions) -> Self {
+        Block {
+            start,
+            instructions,
+            #[cfg(feature = "jit")]
+            code: Default::default(),
+        }
+    }
+
+    pub(crate) fn state(&self) -> CpuState {
+        match self.instructions {
+            BlockInstructions::Arm(_) => CpuState::Arm,
+            BlockInstructions::Thumb(_) => CpuState::Thumb,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match &self.instructions {
+            BlockInstructions::Arm(instructions) => instructions.len(),
+            BlockInstructions::Thumb(instructions) => instructions.len(),
+        }
+    }
+
+    pub(crate) fn width(&self) -> u32 {
+        match self.state() {
+            CpuState::Arm => 4,
+            CpuState::Thumb => 2,
+        }
+    }
+
+    fn end(&self) -> u32 {
+        self.start.wrapping_add(self.len() as u32 * self.width())
+    }
+
+    /// Whether the opcodes in the pipeline are the first ones of the block, it may still hold opcodes
+    /// fetched before a write replaced them
+    pub(crate) fn matches_pipeline(&self, pipeline: [u32; 2]) -> bool {
+        match &self.instructions {
+            BlockInstructions::Arm(instructions) => instructions
+                .iter()
+                .zip(pipeline)
+                .all(|(instruction, opcode)| instruction.value() == opcode),
+            BlockInstructions::Thumb(instructions) => instructions
+                .iter()
+                .zip(pipeline)
+                .all(|(instruction, opcode)| instruction.value() as u32 == opcode & 0xFFFF),
+        }
+    }
+}
+
+// Aligned addresses leave bit 0 free for the state
+fn key(address: u32, state: CpuState) -> u32 {
+    match state {
+        CpuState::Arm => address,
+        CpuState::Thumb => address | 1,
+    }
+}
+
+// The cpu can only rewrite code in EWRAM and IWRAM, everything else it runs from is the read only BIOS or ROM
+fn is_writable(address: u32) -> bool {
+    matches!(address >> 24, 0x02 | 0x03)
+}
+
+/// Whether blocks are decoded at `address`, only where writes to them are tracked or impossible
+pub(crate) fn is_cacheable(address: u32) -> bool {
+    matches!(address >> 24, 0x00 | 0x02 | 0x03 | 0x08..=0x0D)
+}
+
+// Bit of a page of EWRAM or IWRAM in `code_pages`
+fn page_bit(page: u32) -> usize {
+    ((page >> 16) & 0x1) as usize * 0x1_0000 + (page & 0xFFFF) as usize
+}
+
+/// Decoded blocks keyed by start address and state, dropped when the memory they came from is written
+#[derive(Default)]
+pub struct BlockCache {
+    blocks: HashMap<u32, Rc<Block>>,
+    // Blocks overlapping each 256 byte page of writable memory, so a write only checks its neighbours
+    pages: HashMap<u32, Vec<u32>>,
+    // One bit for every page in `pages`, most stores go to pages without code and stop here
+    code_pages: Vec<u64>,
+    // Raised when a write drops a block, the block being executed stops at the next instruction
+    pub(crate) invalidated: bool,
+    // The block run last, loops run the same block again without hashing its address
+    last: Option<Rc<Block>>,
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        BlockCache::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.pages.clear();
+        self.code_pages.clear();
+        self.last = None;
+        self.invalidated = true;
+    }
+
+    pub(crate) fn get(&mut self, address: u32, state: CpuState) -> Option<Rc<Block>> {
+        if let Some(last) = &self.last
+            && last.start == address
+            && last.state() == state
+        {
+            return Some(Rc::clone(last));
+        }
+        let block = self.blocks.get(&key(address, state)).cloned();
+        self.last = block.clone();
+        block
+    }
+
+    pub(crate) fn insert(&mut self, block: Block) -> Rc<Block> {
+        let key = key(block.start, block.state());
+        if is_writable(block.start) {
+            let last = block.end().wrapping_sub(1);
+            self.code_pages.resize(0x2_0000 / 64, 0);
+            for page in (block.start >> PAGE_SHIFT)..=(last >> PAGE_SHIFT) {
+                self.pages.entry(page).or_default().push(key);
+                self.code_pages[page_bit(page) / 64] |= 1 << (page_bit(page) % 64);
+            }
+        }
+        let block = Rc::new(block);
+        self.blocks.insert(key, Rc::clone(&block));
+        block
+    }
+
+    fn has_code(&self, page: u32) -> bool {
+        self.code_pages
+            .get(page_bit(page) / 64)
+            .is_some_and(|bits| bits & (1 << (page_bit(page) % 64)) != 0)
+    }
+
+    /// Drops every block decoded from the `length` bytes the cpu wrote at `address`
+    pub(crate) fn invalidate(&mut self, address: u32, length: u32) {
+        let last = address.wrapping_add(length - 1);
+        if !is_writable(address) || !(self.has_code(address >> PAGE_SHIFT) || self.has_code(last >> PAGE_SHIFT)) {
+            return;
+        }
+        for page in [address >> PAGE_SHIFT, last >> PAGE_SHIFT] {
+            let Some(keys) = self.pages.get_mut(&page) else {
+                continue;
+            };
+            keys.retain(|key| match self.blocks.get(key) {
+                Some(block) if block.start <= last && address < block.end() => {
+                    self.blocks.remove(key);
+                    self.last = None;
+                    self.invalidated = true;
+                    false
+                }
+                Some(_) => true,
+                // Removed through another page
+                None => false,
+            });
+            if keys.is_empty() {
+                self.pages.remove(&page);
+                self.code_pages[page_bit(page) / 64] &= !(1 << (page_bit(page) % 64));
+            }
+        }
+    }
+
+    /// Like `invalidate` for debugger writes, which can patch the ROM too
+    pub(crate) fn invalidate_debug(&mut self, address: u32, length: u32) {
+        match is_writable(address) {
+            true => self.invalidate(address, length),
+            false => self.clear(),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::{
+        CpuState,
+        assembler::assemble,
+        cpu::Arm7tdmiCpu,
+        memory::{DebugMemoryAccess, MemoryAccess, MemoryInterface},
+    };
+
+    const MAX_STEPS: usize = 100_000;
+
+    /// The first 4 KiB of every region, zero wait states
+    pub(crate) struct RamBus {
+        memory: Vec<u8>,
+        // Accesses and idle cycles
+        cycles: u64,
+    }
+
+    impl RamBus {
+        fn index(address: u32) -> usize {
+            ((address >> 12) & 0xF000 | address & 0xFFF) as usize
+        }
+    }
+
+    impl MemoryInterface for RamBus {
+        fn load_8(&mut self, address: u32, _access_pattern: MemoryAccess) -> u32 {
+            self.cycles += 1;
+            self.debug_read_8(address) as u32
+        }
+
+        fn load_16(&mut self, address: u32, _access_pattern: MemoryAccess) -> u32 {
+            self.cycles += 1;
+            self.debug_read_16(address) as u32
+        }
+
+        fn load_32(&mut self, address: u32, _access_pattern: MemoryAccess) -> u32 {
+            self.cycles += 1;
+            self.debug_read_32(address)
+        }
+
+        fn store_8(&mut self, address: u32, value: u8, _access_pattern: MemoryAccess) {
+            self.cycles += 1;
+            self.debug_write_8(address, value);
+        }
+
+        fn store_16(&mut self, address: u32, value: u16, _access_pattern: MemoryAccess) {
+            self.cycles += 1;
+            self.debug_write_16(address, value);
+        }
+
+        fn store_32(&mut self, address: u32, value: u32, _access_pattern: MemoryAccess) {
+            self.cycles += 1;
+            self.debug_write_32(address, value);
+        }
+
+        fn idle_cycle(&mut self) {
+            self.cycles += 1;
+        }
+
+        fn cycle_count(&self) -> u64 {
+            self.cycles
+        }
+    }
+
+    impl DebugMemoryAccess for RamBus {
+        fn debug_read_8(&self, address: u32) -> u8 {
+            self.memory[Self::index(address)]
+        }
+
+        fn debug_write_8(&mut self, address: u32, value: u8) {
+            self.memory[Self::index(address)] = value;
+        }
+    }
+
+    /// Cpu about to run `source`, assembled at the start of ROM
+    pub(crate) fn program_cpu(source: &str) -> Arm7tdmiCpu<RamBus> {
+        let mut bus = RamBus {
+            memory: vec![0; 0x10000],
+            cycles: 0,
+        };
+        let program = assemble(source, CpuState::Arm, 0x0800_0000).unwrap();
+        for (offset, byte) in program.into_iter().enumerate() {
+            bus.debug_write_8(0x0800_0000 + offset as u32, byte);
+        }
+        let mut cpu = Arm7tdmiCpu::new(bus, true);
+        cpu.debug_pipeline_flush();
+        cpu
+    }
+
+    /// Runs until the program reaches the branch to itself it ends with
+    pub(crate) fn run(cpu: &mut Arm7tdmiCpu<RamBus>, cycle: fn(&mut Arm7tdmiCpu<RamBus>)) {
+        for _ in 0..MAX_STEPS {
+            let idle = match cpu.cpsr().state() {
+                CpuState::Arm => 0xEAFFFFFE,
+                CpuState::Thumb => 0xE7FE,
+            };
+            if cpu.next_opcode() == idle {
+                return;
+            }
+            cycle(cpu);
+        }
+        panic!("program did not finish");
+    }
+
+    // Copies `add r2, r2, #1` to IWRAM and calls it, then patches it to add 16 and calls it again
+    pub(crate) const SELF_MODIFYING_PROGRAM: &str = "
+                mov r0, #0x03000000
+                ldr r1, [add_one]
+                ldr r3, [return]
+                stmia r0, {r1, r3}
+                mov lr, pc
+                bx r0
+                ldr r1, [add_sixteen]
+                str r1, [r0]
+                mov lr, pc
+                bx r0
+        done:   b done
+        add_one:
+                add r2, r2, #1
+        return: bx lr
+        add_sixteen:
+                add r2, r2, #16
+    ";
+
+    #[test]
+    fn self_modifying_code() {
+        let mut cached = program_cpu(SELF_MODIFYING_PROGRAM);
+        cached.set_block_cache(true);
+        run(&mut cached, Arm7tdmiCpu::cycle_cached);
+        assert_eq!(cached.register(2), 17);
+        assert!(!cached.block_cache().unwrap().is_empty());
+
+        let mut interpreted = program_cpu(SELF_MODIFYING_PROGRAM);
+        run(&mut interpreted, Arm7tdmiCpu::cycle);
+        assert_eq!(cached.snapshot(), interpreted.snapshot());
+    }
+}
@@ -0,0 +1,157 @@
+use crate::Exception;
+
+// Code that uses `BL` as a long jump never returns, the oldest frames are dropped instead of growing forever
+const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameKind {
+    Call,
+    Exception(Exception),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Entry point of the called function, or the exception vector
+    pub function: u32,
+    /// Address of the call, or of the instruction the exception interrupted
+    pub call_site: u32,
+    pub return_address: u32,
+    pub kind: FrameKind,
+}
+
+/// What a taken branch or exception does to the call stack
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StackChange {
+    Call(Frame),
+    /// Unwinds every frame from `depth` up, more than one when frames returned without being seen
+    Return {
+        depth: usize,
+    },
+    /// A branch from the first instruction of a function, like the `_call_via_rX` thunks
+    TailCall {
+        function: u32,
+    },
+    None,
+}
+
+/// Shadow call stack rebuilt from the branches the cpu takes, outermost frame first
+#[derive(Debug, Default, Clone)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+    // Counts pushes, the depth alone does not change once the stack is full
+    pushes: u64,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        CallStack::default()
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    pub(crate) fn pushes(&self) -> u64 {
+        self.pushes
+    }
+
+    /// Classifies a branch taken by the instruction at `address`, with `link` being lr after it executed
+    pub(crate) fn classify(&self, address: u32, width: u32, target: u32, link: u32) -> StackChange {
+        let next = address.wrapping_add(width);
+        // `BL`, and `BX`/`MOV pc` after `mov lr, pc`, leave lr pointing just past the branch
+        if link & !0x1 == next {
+            return StackChange::Call(Frame {
+                function: target,
+                call_site: address,
+                return_address: next,
+                kind: FrameKind::Call,
+            });
+        }
+        if let Some(depth) = self.frames.iter().rposition(|frame| frame.return_address == target) {
+            return StackChange::Return { depth };
+        }
+        match self.frames.last() {
+            Some(frame) if frame.kind == FrameKind::Call && frame.function == address => {
+                StackChange::TailCall { function: target }
+            }
+            _ => StackChange::None,
+        }
+    }
+
+    pub(crate) fn apply(&mut self, change: StackChange) {
+        match change {
+            StackChange::Call(frame) => {
+                if self.frames.len() == MAX_DEPTH {
+                    self.frames.remove(0);
+                }
+                self.frames.push(frame);
+                self.pushes += 1;
+            }
+            StackChange::Return { depth } => self.frames.truncate(depth),
+            StackChange::TailCall { function } => {
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.function = function;
+                }
+            }
+            StackChange::None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_tail_calls_and_returns() {
+        let mut stack = CallStack::new();
+        // bl 0x08000100
+        let change = stack.classify(0x0800_0000, 4, 0x0800_0100, 0x0800_0004);
+        assert_eq!(
+            change,
+            StackChange::Call(Frame {
+                function: 0x0800_0100,
+                call_site: 0x0800_0000,
+                return_address: 0x0800_0004,
+                kind: FrameKind::Call,
+            })
+        );
+        stack.apply(change);
+        // Second half of a THUMB bl, lr has bit 0 set
+        stack.apply(stack.classify(0x0800_0104, 2, 0x0800_0200, 0x0800_0107));
+        assert_eq!(stack.depth(), 2);
+
+        // b from the first instruction of a function
+        let change = stack.classify(0x0800_0200, 2, 0x0800_0300, 0x0800_0107);
+        assert_eq!(change, StackChange::TailCall { function: 0x0800_0300 });
+        stack.apply(change);
+        assert_eq!(stack.frames()[1].function, 0x0800_0300);
+        assert_eq!(stack.classify(0x0800_0310, 2, 0x0800_0320, 0x0800_0107), StackChange::None);
+
+        // Returning to the outermost caller unwinds the frame that never returned too
+        let change = stack.classify(0x0800_0320, 2, 0x0800_0004, 0x0800_0107);
+        assert_eq!(change, StackChange::Return { depth: 0 });
+        stack.apply(change);
+        assert_eq!(stack.depth(), 0);
+    }
+
+    #[test]
+    fn oldest_frames_are_dropped() {
+        let mut stack = CallStack::new();
+        for call in 0..MAX_DEPTH as u32 + 10 {
+            let address = 0x0800_0000 + call * 8;
+            stack.apply(stack.classify(address, 4, address + 8, address + 4));
+        }
+        assert_eq!(stack.depth(), MAX_DEPTH);
+        assert_eq!(stack.pushes(), MAX_DEPTH as u64 + 10);
+        assert_eq!(stack.frames()[0].call_site, 0x0800_0000 + 10 * 8);
+    }
+}
@@ -0,0 +1,110 @@
+/// Hardware answering the coprocessor instructions addressed to it. Like on the real bus it sees the
+/// whole opcode and decodes the coprocessor number and registers itself, ignoring instructions for
+/// other coprocessors. Anything left unanswered takes the undefined instruction trap, which is all
+/// that happens on the GBA, it has no coprocessors.
+pub trait Coprocessor {
+    /// `CDP`, false if the operation is not accepted
+    fn data_operation(&mut self, _opcode: u32) -> bool {
+        false
+    }
+
+    /// `MRC`, the value of the coprocessor register
+    fn read_register(&mut self, _opcode: u32) -> Option<u32> {
+        None
+    }
+
+    /// `MCR`, false if the write is not accepted
+    fn write_register(&mut self, _opcode: u32, _value: u32) -> bool {
+        false
+    }
+
+    /// `LDC` and `STC`, how many words the transfer moves
+    fn transfer_length(&mut self, _opcode: u32) -> Option<u32> {
+        None
+    }
+
+    /// Word `index` read from memory by an `LDC`
+    fn load_word(&mut self, _opcode: u32, _index: u32, _value: u32) {}
+
+    /// Word `index` to be written to memory by an `STC`
+    fn store_word(&mut self, _opcode: u32, _index: u32) -> u32 {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+    use crate::{
+        CpuMode,
+        assembler::assemble_arm,
+        block_cache::tests::{RamBus, program_cpu},
+        cpu::{Arm7tdmiCpu, LR, PC},
+        memory::DebugMemoryAccess,
+    };
+
+    struct SystemControl;
+
+    impl Coprocessor for SystemControl {
+        fn read_register(&mut self, opcode: u32) -> Option<u32> {
+            (opcode >> 8 & 0xF == 15).then_some(0x4107_0000)
+        }
+    }
+
+    // Keeps the last value written to any of its registers
+    struct Latch(Rc<Cell<Option<u32>>>);
+
+    impl Coprocessor for Latch {
+        fn write_register(&mut self, _opcode: u32, value: u32) -> bool {
+            self.0.set(Some(value));
+            true
+        }
+    }
+
+    // Executes the instruction `source` from 0x08000000
+    fn run(source: &str, coprocessor: Option<Box<dyn Coprocessor>>) -> Arm7tdmiCpu<RamBus> {
+        run_at(0x0800_0000, source, coprocessor)
+    }
+
+    fn run_at(address: u32, source: &str, coprocessor: Option<Box<dyn Coprocessor>>) -> Arm7tdmiCpu<RamBus> {
+        let mut cpu = program_cpu("");
+        cpu.debug_write_32(address, assemble_arm(source, address).unwrap());
+        cpu.set_coprocessor(coprocessor);
+        cpu.set_pc(address);
+        cpu.debug_pipeline_flush();
+        cpu.cycle();
+        cpu
+    }
+
+    #[test]
+    fn unanswered_instructions_are_undefined() {
+        let cpu = run("mrc p15, 0, r0, c0, c0, 0", Some(Box::new(SystemControl)));
+        assert_eq!(cpu.general_registers()[0], 0x4107_0000);
+        assert_eq!(cpu.cpsr().mode(), CpuMode::System);
+
+        let cpu = run("mrc p14, 0, r0, c0, c0, 0", Some(Box::new(SystemControl)));
+        assert_eq!(cpu.cpsr().mode(), CpuMode::Undefined);
+        assert_eq!(cpu.register(LR), 0x0800_0004);
+        assert_eq!(cpu.register(PC), 0x04 + 8);
+
+        let cpu = run("cdp p1, 2, c3, c4, c5, 3", None);
+        assert_eq!(cpu.cpsr().mode(), CpuMode::Undefined);
+    }
+
+    #[test]
+    fn mcr_writes_the_pc_plus_12() {
+        let written = Rc::new(Cell::new(None));
+        run("mcr p15, 0, pc, c1, c0, 0", Some(Box::new(Latch(Rc::clone(&written)))));
+        assert_eq!(written.get(), Some(0x0800_000C));
+
+        // Wraps at the top of the address space
+        run_at(
+            0xFFFF_FFF4,
+            "mcr p15, 0, pc, c1, c0, 0",
+            Some(Box::new(Latch(Rc::clone(&written)))),
+        );
+        assert_eq!(written.get(), Some(0));
+    }
+}
@@ -1,22 +1,47 @@
-use ironboyadvance_utils::get_set;
+use std::rc::Rc;
+
+use ironboyadvance_utils::{
+    get_set,
+    state::{SaveState, StateError, StateReader, StateWriter},
+};
 
 use crate::{
     Condition, CpuAction, Exception,
     arm::{ArmInstructionKind, lut::generate_arm_lut},
-    memory::{MemoryAccess, MemoryInterface},
+    block_cache::{Block, BlockCache, BlockInstructions, MAX_BLOCK_INSTRUCTIONS, is_cacheable},
+    call_stack::{CallStack, Frame, FrameKind, StackChange},
+    coprocessor::Coprocessor,
+    disassembler::{
+        DisassemblyOptions, Flow, SymbolTable, analyze_arm, analyze_thumb, disassemble_arm_with, disassemble_thumb_pair,
+    },
+    memory::{DebugMemoryAccess, MemoryAccess, MemoryInterface},
+    profiler::Profiler,
+    snapshot::{CpuSnapshot, StepInfo},
     thumb::{ThumbInstruction, ThumbInstructionKind, lut::generate_thumb_lut},
+    trace::{TraceRecord, TraceSink},
 };
 
+#[cfg(feature = "jit")]
+use crate::jit::Jit;
+
 use super::{CpuMode, CpuState, arm::ArmInstruction, psr::ProgramStatusRegister};
 
 pub const SP: usize = 13;
 pub const LR: usize = 14;
 pub const PC: usize = 15;
 
+fn arm_lut_index(opcode: u32) -> usize {
+    (((opcode >> 16) & 0x0FF0) | ((opcode >> 4) & 0x000F)) as usize
+}
+
+fn thumb_lut_index(opcode: u16) -> usize {
+    (opcode >> 6) as usize
+}
+
 pub trait Instruction {
     type Size;
     fn execute<I: MemoryInterface>(&self, cpu: &mut Arm7tdmiCpu<I>) -> CpuAction;
-    fn disassemble<I: MemoryInterface>(&self, cpu: &mut Arm7tdmiCpu<I>) -> String;
+    fn disassemble(&self, options: &DisassemblyOptions) -> String;
     fn value(&self) -> Self::Size;
 }
 
@@ -30,40 +55,284 @@ pub struct Arm7tdmiCpu<I: MemoryInterface> {
     spsrs: [ProgramStatusRegister; 5],
     cpsr: ProgramStatusRegister,
     pipeline: [u32; 2],
+    // Fetches that aborted, the prefetch abort is taken once the instruction reaches execute
+    pipeline_aborts: [bool; 2],
+    // Raised by aborted accesses until the fetch or the instruction that made them handles them
+    fetch_aborted: bool,
+    data_aborted: bool,
+    // Interrupt inputs, levels driven by the host and sampled between instructions
+    irq_line: bool,
+    fiq_line: bool,
+    // Accesses and internal cycles made, and what happened since the start of the current `step`
+    cycles: u64,
+    flushed: bool,
+    entered_exception: Option<Exception>,
     bus: I, // May need to make this shared TODO:make getter
-    next_memory_access: u8,
+    next_memory_access: MemoryAccess,
     arm_lut: [ArmInstructionKind; 4096],
     thumb_lut: [ThumbInstructionKind; 1024],
-    pub(super) dissassembled_instruction: String,
+    trace_sink: Option<Box<dyn TraceSink>>,
+    // Names branch targets in traces and disassembly
+    symbols: SymbolTable,
+    // Only maintained while enabled, it costs a lookup on every taken branch
+    call_stack: Option<CallStack>,
+    profiler: Option<Profiler>,
+    block_cache: Option<BlockCache>,
+    coprocessor: Option<Box<dyn Coprocessor>>,
+    // Declared after the block cache, blocks holding compiled code are dropped first
+    #[cfg(feature = "jit")]
+    jit: Option<Jit>,
 }
 
 impl<I: MemoryInterface> MemoryInterface for Arm7tdmiCpu<I> {
-    fn load_8(&mut self, address: u32, access: u8) -> u32 {
-        self.bus.load_8(address, access)
+    fn load_8(&mut self, address: u32, access: MemoryAccess) -> u32 {
+        let value = self.bus.load_8(address, access);
+        self.cycles += 1;
+        self.check_abort(access);
+        value
     }
 
-    fn load_16(&mut self, address: u32, access: u8) -> u32 {
-        self.bus.load_16(address, access)
+    fn load_16(&mut self, address: u32, access: MemoryAccess) -> u32 {
+        let value = self.bus.load_16(address, access);
+        self.cycles += 1;
+        self.check_abort(access);
+        value
     }
 
-    fn load_32(&mut self, address: u32, access: u8) -> u32 {
-        self.bus.load_32(address, access)
+    fn load_32(&mut self, address: u32, access: MemoryAccess) -> u32 {
+        let value = self.bus.load_32(address, access);
+        self.cycles += 1;
+        self.check_abort(access);
+        value
     }
 
-    fn store_8(&mut self, address: u32, value: u8, access: u8) {
+    fn store_8(&mut self, address: u32, value: u8, access: MemoryAccess) {
+        self.invalidate_code(address, 1);
         self.bus.store_8(address, value, access);
+        self.cycles += 1;
+        self.check_abort(access);
     }
 
-    fn store_16(&mut self, address: u32, value: u16, access: u8) {
+    fn store_16(&mut self, address: u32, value: u16, access: MemoryAccess) {
+        self.invalidate_code(address & !0x1, 2);
         self.bus.store_16(address, value, access);
+        self.cycles += 1;
+        self.check_abort(access);
     }
 
-    fn store_32(&mut self, address: u32, value: u32, access: u8) {
+    fn store_32(&mut self, address: u32, value: u32, access: MemoryAccess) {
+        self.invalidate_code(address & !0x3, 4);
         self.bus.store_32(address, value, access);
+        self.cycles += 1;
+        self.check_abort(access);
     }
 
     fn idle_cycle(&mut self) {
         self.bus.idle_cycle();
+        self.cycles += 1;
+    }
+
+    fn cycle_count(&self) -> u64 {
+        self.bus.cycle_count()
+    }
+
+    fn yield_requested(&self) -> bool {
+        self.bus.yield_requested()
+    }
+}
+
+impl<I: MemoryInterface + DebugMemoryAccess> DebugMemoryAccess for Arm7tdmiCpu<I> {
+    fn debug_read_8(&self, address: u32) -> u8 {
+        self.bus.debug_read_8(address)
+    }
+
+    fn debug_write_8(&mut self, address: u32, value: u8) {
+        if let Some(block_cache) = self.block_cache.as_mut() {
+            block_cache.invalidate_debug(address, 1);
+        }
+        self.bus.debug_write_8(address, value);
+    }
+}
+
+impl<I: MemoryInterface + DebugMemoryAccess> Arm7tdmiCpu<I> {
+    /// Disassembles the instruction stored at `address` without touching the bus timing
+    pub fn disassemble_at(&self, address: u32, state: CpuState) -> String {
+        let options = self.disassembly_options();
+        match state {
+            CpuState::Arm => disassemble_arm_with(self.bus.debug_read_32(address & !0x3), address & !0x3, &options),
+            CpuState::Thumb => {
+                let address = address & !0x1;
+                let first = self.bus.debug_read_16(address);
+                let second = self.bus.debug_read_16(address.wrapping_add(2));
+                disassemble_thumb_pair(first, second, address, &options)
+            }
+        }
+    }
+
+    /// Like `cycle`, but runs the block of instructions decoded once into the block cache that starts at the
+    /// next instruction, when the cache is enabled. The block stops early, between two instructions, once the
+    /// bus asks to yield or an interrupt is pending.
+    pub fn cycle_cached(&mut self) {
+        if self.block_cache.is_none() {
+            return self.cycle();
+        }
+        if self.take_interrupt() {
+            return;
+        }
+        let state = self.cpsr.state();
+        let address = self.next_instruction_address();
+        if self.prefetch_aborted() || !is_cacheable(address) {
+            return self.cycle();
+        }
+        let block = self.cached_block(address, state);
+        // Checked once on entry, writes made while the block runs invalidate it instead
+        if !block.matches_pipeline(self.pipeline) {
+            return self.cycle();
+        }
+        if let Some(cache) = self.block_cache.as_mut() {
+            cache.invalidated = false;
+        }
+        #[cfg(feature = "jit")]
+        if self.run_compiled(&block) {
+            return;
+        }
+        match &block.instructions {
+            BlockInstructions::Arm(instructions) => {
+                for (index, instruction) in instructions.iter().enumerate() {
+                    if index > 0 && self.block_interrupted() {
+                        break;
+                    }
+                    let (pc, cpsr) = (self.general_registers[PC], self.cpsr);
+                    self.fetch(pc);
+                    self.execute_arm(instruction);
+                    if self.left_block(pc.wrapping_add(4), cpsr) {
+                        break;
+                    }
+                }
+            }
+            BlockInstructions::Thumb(instructions) => {
+                for (index, instruction) in instructions.iter().enumerate() {
+                    if index > 0 && self.block_interrupted() {
+                        break;
+                    }
+                    let (pc, cpsr) = (self.general_registers[PC], self.cpsr);
+                    self.fetch(pc & !0x1);
+                    self.execute_thumb(instruction);
+                    if self.left_block(pc.wrapping_add(2), cpsr) {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    // Runs the compiled code of a block, compiling it once it is hot
+    #[cfg(feature = "jit")]
+    fn run_compiled(&mut self, block: &Block) -> bool {
+        // Compiled code neither traces nor tracks calls
+        if self.jit.is_none() || self.trace_sink.is_some() || self.call_stack.is_some() {
+            return false;
+        }
+        let compiled = match block.code.compiled.get() {
+            Some(compiled) => *compiled,
+            None if block.code.is_hot() => {
+                let jit = self.jit.as_mut().expect("the jit is checked at the start of run_compiled");
+                if jit.is_full() {
+                    if let Some(cache) = self.block_cache.as_mut() {
+                        cache.clear();
+                    }
+                    *jit = match Jit::new() {
+                        Some(jit) => jit,
+                        None => return false,
+                    };
+                }
+                let compiled = jit.compile::<I>(block, self.cpsr.mode());
+                *block.code.compiled.get_or_init(|| compiled)
+            }
+            None => None,
+        };
+        match compiled.filter(|compiled| compiled.mode == self.cpsr.mode()) {
+            // A write to the block raises `invalidated`, which makes the compiled code return
+            Some(compiled) => {
+                unsafe { compiled.run(self) };
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn cached_block(&mut self, address: u32, state: CpuState) -> Rc<Block> {
+        if let Some(block) = self.block_cache.as_mut().and_then(|cache| cache.get(address, state)) {
+            return block;
+        }
+        let block = self.decode_block(address, state);
+        self.block_cache.get_or_insert_with(BlockCache::new).insert(block)
+    }
+
+    // Reads ahead without touching the bus timing, the block ends at anything that may write the pc
+    fn decode_block(&self, start: u32, state: CpuState) -> Block {
+        let mut address = start;
+        let instructions = match state {
+            CpuState::Arm => {
+                let mut instructions = Vec::new();
+                while instructions.len() < MAX_BLOCK_INSTRUCTIONS {
+                    let opcode = self.bus.debug_read_32(address);
+                    let instruction = ArmInstruction::new(self.arm_lut[arm_lut_index(opcode)], opcode, address);
+                    let ends = instruction.kind() == ArmInstructionKind::SoftwareInterrupt
+                        || analyze_arm(opcode, address).flow != Flow::Next;
+                    instructions.push(instruction);
+                    if ends {
+                        break;
+                    }
+                    address = address.wrapping_add(4);
+                    if address >> 24 != start >> 24 {
+                        break;
+                    }
+                }
+                BlockInstructions::Arm(instructions)
+            }
+            CpuState::Thumb => {
+                let mut instructions = Vec::new();
+                while instructions.len() < MAX_BLOCK_INSTRUCTIONS {
+                    let opcode = self.bus.debug_read_16(address);
+                    let kind = self.thumb_lut[thumb_lut_index(opcode)];
+                    // Only the second half of `BL` branches
+                    let ends = kind == ThumbInstructionKind::SoftwareInterrupt
+                        || (kind == ThumbInstructionKind::LongBranchWithLink && opcode & 0x0800 != 0)
+                        || analyze_thumb(opcode, address).flow != Flow::Next;
+                    instructions.push(ThumbInstruction::new(kind, opcode, address));
+                    if ends {
+                        break;
+                    }
+                    address = address.wrapping_add(2);
+                    if address >> 24 != start >> 24 {
+                        break;
+                    }
+                }
+                BlockInstructions::Thumb(instructions)
+            }
+        };
+        Block::new(start, instructions)
+    }
+
+    /// Refills the pipeline from the current pc like `pipeline_flush`, but without spending any cycles
+    pub fn debug_pipeline_flush(&mut self) {
+        let pc = self.general_registers[PC];
+        match self.cpsr.state() {
+            CpuState::Arm => {
+                self.pipeline = [self.bus.debug_read_32(pc), self.bus.debug_read_32(pc.wrapping_add(4))];
+                self.general_registers[PC] = pc.wrapping_add(8);
+            }
+            CpuState::Thumb => {
+                self.pipeline = [
+                    self.bus.debug_read_16(pc) as u32,
+                    self.bus.debug_read_16(pc.wrapping_add(2)) as u32,
+                ];
+                self.general_registers[PC] = pc.wrapping_add(4);
+            }
+        }
+        self.pipeline_aborts = [false; 2];
+        self.next_memory_access = MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL;
     }
 }
 
@@ -79,11 +348,26 @@ impl<I: MemoryInterface> Arm7tdmiCpu<I> {
             spsrs: [ProgramStatusRegister::from_bits(0x13); 5],
             cpsr: ProgramStatusRegister::from_bits(0x13),
             pipeline: [0; 2],
+            pipeline_aborts: [false; 2],
+            fetch_aborted: false,
+            data_aborted: false,
+            irq_line: false,
+            fiq_line: false,
+            cycles: 0,
+            flushed: false,
+            entered_exception: None,
             bus,
-            next_memory_access: MemoryAccess::Instruction | MemoryAccess::NonSequential,
+            next_memory_access: MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL,
             arm_lut: [ArmInstructionKind::Undefined; 4096],
             thumb_lut: [ThumbInstructionKind::Undefined; 1024],
-            dissassembled_instruction: String::new(),
+            trace_sink: None,
+            symbols: SymbolTable::new(),
+            call_stack: None,
+            profiler: None,
+            block_cache: None,
+            coprocessor: None,
+            #[cfg(feature = "jit")]
+            jit: None,
         };
 
         cpu.arm_lut = generate_arm_lut();
@@ -119,59 +403,440 @@ impl<I: MemoryInterface> Arm7tdmiCpu<I> {
     get_set!(spsrs, set_spsrs, [ProgramStatusRegister; 5]);
     get_set!(cpsr, set_cpsr, ProgramStatusRegister);
     get_set!(pipeline, set_pipeline, [u32; 2]);
+    get_set!(next_memory_access, set_next_memory_access, MemoryAccess);
 
     pub fn cycle(&mut self) {
+        if self.take_interrupt() {
+            return;
+        }
         let pc = self.general_registers[PC] & !0x1;
-
+        let aborted = self.pipeline_aborts[0];
+        let opcode = self.fetch(pc);
+        if aborted {
+            return self.exeception(Exception::AbortPrefetch);
+        }
         match self.cpsr.state() {
             CpuState::Arm => {
-                let instruction = self.pipeline[0];
-                self.pipeline[0] = self.pipeline[1];
-                self.pipeline[1] = self.load_32(pc, self.next_memory_access);
-                let lut_index = ((instruction >> 16) & 0x0FF0) | ((instruction >> 4) & 0x000F);
-                let instruction = ArmInstruction::new(self.arm_lut[lut_index as usize], instruction, pc.saturating_sub(8));
-                self.dissassembled_instruction = instruction.disassemble(self);
-
-                println!("{}", instruction);
-                println!("{}", self.dissassembled_instruction);
-
-                let condition = instruction.cond();
-                if condition != Condition::AL && !self.is_condition_met(condition) {
-                    self.advance_pc_arm();
-                    self.next_memory_access = MemoryAccess::Instruction | MemoryAccess::Sequential;
-                    return;
-                }
-                match instruction.execute(self) {
-                    CpuAction::Advance(memory_access) => {
-                        self.advance_pc_arm();
-                        self.next_memory_access = memory_access;
-                    }
-                    CpuAction::PipelineFlush => {}
-                };
+                let kind = self.arm_lut[arm_lut_index(opcode)];
+                self.execute_arm(&ArmInstruction::new(kind, opcode, pc.saturating_sub(8)));
             }
             CpuState::Thumb => {
-                let instruction = self.pipeline[0];
-                self.pipeline[0] = self.pipeline[1];
-                self.pipeline[1] = self.load_32(pc, self.next_memory_access);
-                let lut_index = (instruction) as u16 >> 6;
-                let instruction =
-                    ThumbInstruction::new(self.thumb_lut[lut_index as usize], instruction as u16, pc.saturating_sub(4));
-                self.dissassembled_instruction = instruction.disassemble(self);
-
-                println!("{}", instruction);
-                println!("{}", self.dissassembled_instruction);
-
-                match instruction.execute(self) {
-                    CpuAction::Advance(memory_access) => {
-                        self.advance_pc_thumb();
-                        self.next_memory_access = memory_access;
-                    }
-                    CpuAction::PipelineFlush => {}
-                };
+                let kind = self.thumb_lut[thumb_lut_index(opcode as u16)];
+                self.execute_thumb(&ThumbInstruction::new(kind, opcode as u16, pc.saturating_sub(4)));
             }
         }
     }
 
+    /// Like `cycle`, reporting what the instruction did
+    pub fn step(&mut self) -> StepInfo {
+        let address = self.next_instruction_address();
+        let cycles = self.cycles;
+        self.flushed = false;
+        self.entered_exception = None;
+        self.cycle();
+        StepInfo {
+            address,
+            cycles: (self.cycles - cycles) as u32,
+            branched: self.flushed,
+            exception: self.entered_exception,
+        }
+    }
+
+    pub fn snapshot(&self) -> CpuSnapshot {
+        CpuSnapshot {
+            general_registers: self.general_registers,
+            banked_registers_fiq: self.banked_registers_fiq,
+            banked_registers_svc: self.banked_registers_svc,
+            banked_registers_abt: self.banked_registers_abt,
+            banked_registers_irq: self.banked_registers_irq,
+            banked_registers_und: self.banked_registers_und,
+            spsrs: self.spsrs.map(|spsr| spsr.into_bits()),
+            cpsr: self.cpsr.into_bits(),
+            pipeline: self.pipeline,
+            pipeline_aborts: self.pipeline_aborts,
+            next_memory_access: self.next_memory_access,
+            irq_line: self.irq_line,
+            fiq_line: self.fiq_line,
+        }
+    }
+
+    /// Puts back the state of `snapshot`, the bus and the memory it holds are left alone
+    pub fn restore(&mut self, snapshot: &CpuSnapshot) {
+        self.general_registers = snapshot.general_registers;
+        self.banked_registers_fiq = snapshot.banked_registers_fiq;
+        self.banked_registers_svc = snapshot.banked_registers_svc;
+        self.banked_registers_abt = snapshot.banked_registers_abt;
+        self.banked_registers_irq = snapshot.banked_registers_irq;
+        self.banked_registers_und = snapshot.banked_registers_und;
+        self.spsrs = snapshot.spsrs.map(ProgramStatusRegister::from_bits);
+        self.cpsr = ProgramStatusRegister::from_bits(snapshot.cpsr);
+        self.pipeline = snapshot.pipeline;
+        self.pipeline_aborts = snapshot.pipeline_aborts;
+        self.fetch_aborted = false;
+        self.data_aborted = false;
+        self.next_memory_access = snapshot.next_memory_access;
+        self.irq_line = snapshot.irq_line;
+        self.fiq_line = snapshot.fiq_line;
+        // The frames belong to the abandoned timeline
+        if let Some(call_stack) = self.call_stack.as_mut() {
+            call_stack.clear();
+        }
+    }
+
+    // Moves the pipeline along, returning the opcode that leaves it to be executed
+    pub(crate) fn fetch(&mut self, pc: u32) -> u32 {
+        let opcode = self.pipeline[0];
+        self.pipeline[0] = self.pipeline[1];
+        self.pipeline[1] = match self.cpsr.state() {
+            CpuState::Arm => self.load_32(pc, self.next_memory_access),
+            CpuState::Thumb => self.load_16(pc, self.next_memory_access),
+        };
+        self.pipeline_aborts = [self.pipeline_aborts[1], std::mem::take(&mut self.fetch_aborted)];
+        opcode
+    }
+
+    // Instruction fetches are marked and abort once executed, data accesses when the instruction completes
+    fn check_abort(&mut self, access: MemoryAccess) {
+        if self.bus.take_abort() {
+            match access.contains(MemoryAccess::INSTRUCTION) {
+                true => self.fetch_aborted = true,
+                false => self.data_aborted = true,
+            }
+        }
+    }
+
+    /// Asserts or releases the IRQ input, the interrupt is taken at an instruction boundary while it is
+    /// asserted and not masked by the cpsr
+    pub fn set_irq_line(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
+
+    /// Asserts or releases the FIQ input, which has priority over IRQ
+    pub fn set_fiq_line(&mut self, asserted: bool) {
+        self.fiq_line = asserted;
+    }
+
+    pub fn irq_line(&self) -> bool {
+        self.irq_line
+    }
+
+    pub fn fiq_line(&self) -> bool {
+        self.fiq_line
+    }
+
+    /// The interrupt taken instead of the next instruction, if any
+    pub fn pending_interrupt(&self) -> Option<Exception> {
+        if self.fiq_line && !self.cpsr.fiq_disable() {
+            Some(Exception::Fiq)
+        } else if self.irq_line && !self.cpsr.irq_disable() {
+            Some(Exception::Irq)
+        } else {
+            None
+        }
+    }
+
+    // Enters the handler of a pending interrupt, the instruction in execute is left for it to return to
+    fn take_interrupt(&mut self) -> bool {
+        match self.pending_interrupt() {
+            Some(exception) => {
+                self.exeception(exception);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the fetch of the next instruction to execute aborted
+    pub fn prefetch_aborted(&self) -> bool {
+        self.pipeline_aborts[0]
+    }
+
+    /// Whether a data access of the executing instruction aborted
+    pub fn data_aborted(&self) -> bool {
+        self.data_aborted
+    }
+
+    /// Writes a value loaded from memory, unless a data access of the instruction aborted
+    pub fn set_loaded_register(&mut self, index: usize, value: u32) {
+        if !self.data_aborted {
+            self.set_register(index, value);
+        }
+    }
+
+    pub(crate) fn execute_arm(&mut self, instruction: &ArmInstruction) {
+        let address = self.general_registers[PC].wrapping_sub(8) & !0x1;
+        if self.trace_sink.is_some() {
+            let disassembly = instruction.disassemble(&self.disassembly_options());
+            self.trace(address, instruction.value(), disassembly);
+        }
+
+        let condition = instruction.cond();
+        if condition != Condition::AL && !self.is_condition_met(condition) {
+            self.advance_pc_arm();
+            self.next_memory_access = MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL;
+            return;
+        }
+        let pushes = self.call_stack.as_ref().map(CallStack::pushes);
+        let action = instruction.execute(self);
+        if std::mem::take(&mut self.data_aborted) {
+            return self.exeception(Exception::AbortData);
+        }
+        match action {
+            CpuAction::Advance(memory_access) => {
+                self.advance_pc_arm();
+                self.next_memory_access = memory_access;
+            }
+            CpuAction::PipelineFlush => self.track_branch(address, 4, pushes),
+        };
+    }
+
+    pub(crate) fn execute_thumb(&mut self, instruction: &ThumbInstruction) {
+        let address = self.general_registers[PC].wrapping_sub(4) & !0x1;
+        if self.trace_sink.is_some() {
+            let disassembly = instruction.disassemble(&self.disassembly_options());
+            self.trace(address, instruction.value() as u32, disassembly);
+        }
+
+        let pushes = self.call_stack.as_ref().map(CallStack::pushes);
+        let action = instruction.execute(self);
+        if std::mem::take(&mut self.data_aborted) {
+            return self.exeception(Exception::AbortData);
+        }
+        match action {
+            CpuAction::Advance(memory_access) => {
+                self.advance_pc_thumb();
+                self.next_memory_access = memory_access;
+            }
+            CpuAction::PipelineFlush => self.track_branch(address, 2, pushes),
+        };
+    }
+
+    /// Decodes instructions once into blocks and reuses them, for `cycle_cached`
+    pub fn set_block_cache(&mut self, enabled: bool) {
+        self.block_cache = enabled.then(BlockCache::new);
+    }
+
+    pub fn block_cache(&self) -> Option<&BlockCache> {
+        self.block_cache.as_ref()
+    }
+
+    /// Compiles hot blocks of the block cache to native code, enabling the cache if needed.
+    /// Does nothing on hosts cranelift cannot generate code for.
+    #[cfg(feature = "jit")]
+    pub fn set_jit(&mut self, enabled: bool) {
+        // Compiled code is freed with the jit, blocks must not outlive it
+        if let Some(cache) = self.block_cache.as_mut() {
+            cache.clear();
+        }
+        self.jit = None;
+        if enabled {
+            self.jit = Jit::new();
+            self.block_cache.get_or_insert_with(BlockCache::new);
+        }
+    }
+
+    #[cfg(feature = "jit")]
+    pub fn is_jit_enabled(&self) -> bool {
+        self.jit.is_some()
+    }
+
+    /// Like `cycle`, but compiles the next instruction on its own and runs the native code, for the
+    /// single step tests whose bus cannot be read ahead
+    #[cfg(all(test, feature = "jit"))]
+    pub(crate) fn cycle_compiled(&mut self) {
+        if self.take_interrupt() {
+            return;
+        }
+        if self.prefetch_aborted() {
+            return self.cycle();
+        }
+        let address = self.next_instruction_address();
+        let opcode = self.next_opcode();
+        let instructions = match self.cpsr.state() {
+            CpuState::Arm => {
+                let kind = self.arm_lut[arm_lut_index(opcode)];
+                BlockInstructions::Arm(vec![ArmInstruction::new(kind, opcode, address)])
+            }
+            CpuState::Thumb => {
+                let kind = self.thumb_lut[thumb_lut_index(opcode as u16)];
+                BlockInstructions::Thumb(vec![ThumbInstruction::new(kind, opcode as u16, address)])
+            }
+        };
+        let block = Block::new(address, instructions);
+        let mode = self.cpsr.mode();
+        let Some(jit) = self.jit.as_mut() else {
+            return self.cycle();
+        };
+        if jit.is_full() {
+            *jit = match Jit::new() {
+                Some(jit) => jit,
+                None => return self.cycle(),
+            };
+        }
+        match jit.compile::<I>(&block, mode) {
+            // The block outlives its run, the code that points into it is never called again
+            Some(compiled) => unsafe {
+                compiled.run(self);
+            },
+            None => self.cycle(),
+        }
+    }
+
+    // True when the instruction just run left the block, branched or wrote to it
+    pub(crate) fn left_block(&self, next: u32, cpsr: ProgramStatusRegister) -> bool {
+        // The mode and state bits, compared without decoding them
+        self.general_registers[PC] != next
+            || (self.cpsr.into_bits() ^ cpsr.into_bits()) & 0x3F != 0
+            || self.block_cache.as_ref().is_none_or(|cache| cache.invalidated)
+    }
+
+    // True when the rest of the block has to wait, checked between two instructions of a block
+    pub(crate) fn block_interrupted(&self) -> bool {
+        self.bus.yield_requested() || self.prefetch_aborted() || self.pending_interrupt().is_some()
+    }
+
+    // Compiled code reads and writes the registers it uses in place, banked for `mode`
+    #[cfg(feature = "jit")]
+    pub(crate) fn register_offset(index: usize, mode: CpuMode) -> usize {
+        use std::mem::offset_of;
+        let (offset, bank_index) = match (index, mode) {
+            (8..=14, CpuMode::Fiq) => (offset_of!(Self, banked_registers_fiq), index - 8),
+            (13 | 14, CpuMode::Irq) => (offset_of!(Self, banked_registers_irq), index - 13),
+            (13 | 14, CpuMode::Supervisor) => (offset_of!(Self, banked_registers_svc), index - 13),
+            (13 | 14, CpuMode::Abort) => (offset_of!(Self, banked_registers_abt), index - 13),
+            (13 | 14, CpuMode::Undefined) => (offset_of!(Self, banked_registers_und), index - 13),
+            _ => (offset_of!(Self, general_registers), index),
+        };
+        offset + bank_index * size_of::<u32>()
+    }
+
+    #[cfg(feature = "jit")]
+    pub(crate) fn cpsr_offset() -> usize {
+        std::mem::offset_of!(Self, cpsr)
+    }
+
+    #[cfg(feature = "jit")]
+    pub(crate) fn next_memory_access_offset() -> usize {
+        std::mem::offset_of!(Self, next_memory_access)
+    }
+
+    // Called for every write the cpu makes, so self-modifying code is decoded again
+    fn invalidate_code(&mut self, address: u32, length: u32) {
+        if let Some(block_cache) = self.block_cache.as_mut() {
+            block_cache.invalidate(address, length);
+        }
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    fn disassembly_options(&self) -> DisassemblyOptions<'_> {
+        DisassemblyOptions {
+            symbols: Some(&self.symbols),
+            ..DisassemblyOptions::default()
+        }
+    }
+
+    /// Starts or stops maintaining the shadow call stack, stopping it also stops the profiler
+    pub fn set_call_stack_tracking(&mut self, enabled: bool) {
+        match enabled {
+            true => {
+                self.call_stack.get_or_insert_with(CallStack::new);
+            }
+            false => {
+                self.call_stack = None;
+                self.profiler = None;
+            }
+        }
+    }
+
+    pub fn call_stack(&self) -> Option<&CallStack> {
+        self.call_stack.as_ref()
+    }
+
+    /// Attributes cycles to functions from now on, tracking the call stack if it was not already
+    pub fn start_profiling(&mut self) {
+        self.set_call_stack_tracking(true);
+        self.profiler = Some(Profiler::new(self.bus.cycle_count()));
+    }
+
+    pub fn is_profiling(&self) -> bool {
+        self.profiler.is_some()
+    }
+
+    /// Ends the profile, counting the cycles spent since the last call or return
+    pub fn stop_profiling(&mut self) -> Option<Profiler> {
+        let mut profiler = self.profiler.take()?;
+        if let Some(call_stack) = self.call_stack.as_ref() {
+            profiler.sample(call_stack, self.bus.cycle_count());
+        }
+        Some(profiler)
+    }
+
+    // `pushes` is taken before the instruction executed, an exception it raised has pushed its own frame
+    fn track_branch(&mut self, address: u32, width: u32, pushes: Option<u64>) {
+        let (Some(call_stack), Some(pushes)) = (self.call_stack.as_ref(), pushes) else {
+            return;
+        };
+        if call_stack.pushes() != pushes {
+            return;
+        }
+        let change = call_stack.classify(address, width, self.next_instruction_address(), self.register(LR));
+        self.update_call_stack(change);
+    }
+
+    fn update_call_stack(&mut self, change: StackChange) {
+        let Some(call_stack) = self.call_stack.as_mut() else {
+            return;
+        };
+        if change == StackChange::None {
+            return;
+        }
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(call_stack, change, self.bus.cycle_count());
+        }
+        call_stack.apply(change);
+    }
+
+    /// Attaches hardware answering coprocessor instructions, without one they are undefined like on the GBA
+    pub fn set_coprocessor(&mut self, coprocessor: Option<Box<dyn Coprocessor>>) {
+        self.coprocessor = coprocessor;
+    }
+
+    pub(crate) fn coprocessor(&mut self) -> Option<&mut (dyn Coprocessor + 'static)> {
+        self.coprocessor.as_deref_mut()
+    }
+
+    pub fn set_trace_sink(&mut self, trace_sink: Option<Box<dyn TraceSink>>) {
+        if let Some(mut previous) = std::mem::replace(&mut self.trace_sink, trace_sink) {
+            previous.flush();
+        }
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.trace_sink.is_some()
+    }
+
+    fn trace(&mut self, pc: u32, opcode: u32, disassembly: String) {
+        let record = TraceRecord {
+            pc,
+            state: self.cpsr.state(),
+            opcode,
+            disassembly,
+            registers: std::array::from_fn(|i| self.register(i)),
+            cpsr: self.cpsr.into_bits(),
+            cycles: self.bus.cycle_count(),
+        };
+        if let Some(trace_sink) = self.trace_sink.as_mut() {
+            trace_sink.trace(&record);
+        }
+    }
+
     pub fn is_condition_met(&self, condition: Condition) -> bool {
         use Condition::*;
         match condition {
@@ -229,6 +894,21 @@ impl<I: MemoryInterface> Arm7tdmiCpu<I> {
         self.general_registers[PC]
     }
 
+    /// Address of the instruction the next `cycle` executes, the pc runs ahead because of the pipeline
+    pub fn next_instruction_address(&self) -> u32 {
+        match self.cpsr.state() {
+            CpuState::Arm => self.general_registers[PC].wrapping_sub(8),
+            CpuState::Thumb => self.general_registers[PC].wrapping_sub(4),
+        }
+    }
+
+    pub fn next_opcode(&self) -> u32 {
+        match self.cpsr.state() {
+            CpuState::Arm => self.pipeline[0],
+            CpuState::Thumb => self.pipeline[0] & 0xFFFF,
+        }
+    }
+
     pub fn set_pc(&mut self, value: u32) {
         self.general_registers[PC] = value;
     }
@@ -242,32 +922,37 @@ impl<I: MemoryInterface> Arm7tdmiCpu<I> {
     }
 
     pub fn pipeline_flush(&mut self) {
+        self.flushed = true;
         match self.cpsr.state() {
             CpuState::Arm => {
                 self.pipeline[0] = self.load_32(
                     self.general_registers[PC],
-                    MemoryAccess::Instruction | MemoryAccess::NonSequential,
+                    MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL,
                 );
+                self.pipeline_aborts[0] = std::mem::take(&mut self.fetch_aborted);
                 self.advance_pc_arm();
                 self.pipeline[1] = self.load_32(
                     self.general_registers[PC],
-                    MemoryAccess::Instruction | MemoryAccess::Sequential,
+                    MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL,
                 );
+                self.pipeline_aborts[1] = std::mem::take(&mut self.fetch_aborted);
                 self.advance_pc_arm();
-                self.next_memory_access = MemoryAccess::Instruction | MemoryAccess::Sequential;
+                self.next_memory_access = MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL;
             }
             CpuState::Thumb => {
                 self.pipeline[0] = self.load_16(
                     self.general_registers[PC],
-                    MemoryAccess::Instruction | MemoryAccess::Sequential,
+                    MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL,
                 );
+                self.pipeline_aborts[0] = std::mem::take(&mut self.fetch_aborted);
                 self.advance_pc_thumb();
                 self.pipeline[1] = self.load_16(
                     self.general_registers[PC],
-                    MemoryAccess::Instruction | MemoryAccess::Sequential,
+                    MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL,
                 );
+                self.pipeline_aborts[1] = std::mem::take(&mut self.fetch_aborted);
                 self.advance_pc_thumb();
-                self.next_memory_access = MemoryAccess::Instruction | MemoryAccess::Sequential;
+                self.next_memory_access = MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL;
             }
         }
     }
@@ -357,10 +1042,13 @@ impl<I: MemoryInterface> Arm7tdmiCpu<I> {
     }
 
     pub fn exeception(&mut self, exception: Exception) {
+        self.entered_exception = Some(exception);
+        let interrupted = self.next_instruction_address();
         let (mode, disable_irq, disable_fiq) = match exception {
             Exception::Reset => (CpuMode::Supervisor, true, true),
             Exception::Undefined => (CpuMode::Undefined, true, false),
             Exception::SoftwareInterrupt => (CpuMode::Supervisor, true, false),
+            Exception::AbortPrefetch | Exception::AbortData => (CpuMode::Abort, true, false),
             Exception::Irq => (CpuMode::Irq, true, false),
             Exception::Fiq => (CpuMode::Fiq, true, true),
         };
@@ -374,11 +1062,29 @@ impl<I: MemoryInterface> Arm7tdmiCpu<I> {
             self.cpsr.set_fiq_disable(true);
         }
 
-        let return_pc = match self.cpsr.state() {
-            CpuState::Arm => self.pc() - 4,
-            CpuState::Thumb => self.pc() - 2,
+        // Aborts are taken while the aborted instruction executes and interrupts before the next one
+        // executes, lr points past it the same in both states
+        let return_pc = match (exception, self.cpsr.state()) {
+            (Exception::Irq | Exception::Fiq, _) => interrupted.wrapping_add(4),
+            (Exception::AbortPrefetch, CpuState::Thumb) | (Exception::AbortData, CpuState::Arm) => self.pc(),
+            (Exception::AbortData, CpuState::Thumb) => self.pc() + 4,
+            (_, CpuState::Arm) => self.pc() - 4,
+            (_, CpuState::Thumb) => self.pc() - 2,
         };
         self.set_register(LR, return_pc);
+        // Interrupt and prefetch abort handlers return with `subs pc, lr, #4`, data abort handlers with
+        // `subs pc, lr, #8` to retry the instruction, the others with `movs pc, lr`
+        let return_address = match exception {
+            Exception::Irq | Exception::Fiq | Exception::AbortPrefetch => return_pc.wrapping_sub(4),
+            Exception::AbortData => return_pc.wrapping_sub(8),
+            _ => return_pc,
+        };
+        self.update_call_stack(StackChange::Call(Frame {
+            function: exception as u32,
+            call_site: interrupted,
+            return_address,
+            kind: FrameKind::Exception(exception),
+        }));
         self.set_state(CpuState::Arm);
         self.set_pc(exception as u32);
         self.pipeline_flush();
@@ -387,10 +1093,217 @@ impl<I: MemoryInterface> Arm7tdmiCpu<I> {
     pub fn reset(&mut self) {
         self.exeception(Exception::Reset);
     }
+}
+
+impl<I: MemoryInterface + SaveState> SaveState for Arm7tdmiCpu<I> {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.general_registers.iter().for_each(|r| writer.write_u32(*r));
+        self.banked_registers_fiq.iter().for_each(|r| writer.write_u32(*r));
+        self.banked_registers_svc.iter().for_each(|r| writer.write_u32(*r));
+        self.banked_registers_abt.iter().for_each(|r| writer.write_u32(*r));
+        self.banked_registers_irq.iter().for_each(|r| writer.write_u32(*r));
+        self.banked_registers_und.iter().for_each(|r| writer.write_u32(*r));
+        self.spsrs.iter().for_each(|spsr| writer.write_u32(spsr.into_bits()));
+        writer.write_u32(self.cpsr.into_bits());
+        self.pipeline.iter().for_each(|p| writer.write_u32(*p));
+        writer.write_u8(self.next_memory_access.bits());
+        self.bus.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        for register in self
+            .general_registers
+            .iter_mut()
+            .chain(self.banked_registers_fiq.iter_mut())
+            .chain(self.banked_registers_svc.iter_mut())
+            .chain(self.banked_registers_abt.iter_mut())
+            .chain(self.banked_registers_irq.iter_mut())
+            .chain(self.banked_registers_und.iter_mut())
+        {
+            *register = reader.read_u32()?;
+        }
+        for spsr in self.spsrs.iter_mut() {
+            *spsr = ProgramStatusRegister::from_bits(reader.read_u32()?);
+        }
+        self.cpsr = ProgramStatusRegister::from_bits(reader.read_u32()?);
+        for pipeline in self.pipeline.iter_mut() {
+            *pipeline = reader.read_u32()?;
+        }
+        self.next_memory_access = MemoryAccess::from_bits_retain(reader.read_u8()?);
+        // The frames belong to the abandoned timeline
+        if let Some(call_stack) = self.call_stack.as_mut() {
+            call_stack.clear();
+        }
+        if let Some(block_cache) = self.block_cache.as_mut() {
+            block_cache.clear();
+        }
+        self.bus.load_state(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::assemble_arm;
+
+    // Mirrored every 256 bytes, accesses from `abort_from` up abort
+    struct AbortingBus {
+        memory: [u8; 0x100],
+        abort_from: u32,
+        aborted: bool,
+    }
+
+    impl AbortingBus {
+        fn access(&mut self, address: u32) -> usize {
+            self.aborted |= address >= self.abort_from;
+            address as usize & 0xFF
+        }
+    }
+
+    impl MemoryInterface for AbortingBus {
+        fn load_8(&mut self, address: u32, _access_pattern: MemoryAccess) -> u32 {
+            self.memory[self.access(address)] as u32
+        }
+
+        fn load_16(&mut self, address: u32, access_pattern: MemoryAccess) -> u32 {
+            self.load_8(address, access_pattern) | self.load_8(address + 1, access_pattern) << 8
+        }
+
+        fn load_32(&mut self, address: u32, access_pattern: MemoryAccess) -> u32 {
+            self.load_16(address, access_pattern) | self.load_16(address + 2, access_pattern) << 16
+        }
+
+        fn store_8(&mut self, address: u32, value: u8, _access_pattern: MemoryAccess) {
+            let index = self.access(address);
+            self.memory[index] = value;
+        }
+
+        fn store_16(&mut self, address: u32, value: u16, access_pattern: MemoryAccess) {
+            self.store_8(address, value as u8, access_pattern);
+            self.store_8(address + 1, (value >> 8) as u8, access_pattern);
+        }
+
+        fn store_32(&mut self, address: u32, value: u32, access_pattern: MemoryAccess) {
+            self.store_16(address, value as u16, access_pattern);
+            self.store_16(address + 2, (value >> 16) as u16, access_pattern);
+        }
+
+        fn idle_cycle(&mut self) {}
+
+        fn take_abort(&mut self) -> bool {
+            std::mem::take(&mut self.aborted)
+        }
+    }
+
+    // Executes `source` from 0x08000000 with r1 pointing at the first aborting address
+    fn execute(source: &str, abort_from: u32) -> Arm7tdmiCpu<AbortingBus> {
+        let mut memory = [0; 0x100];
+        memory[..4].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+        let bus = AbortingBus {
+            memory,
+            abort_from,
+            aborted: false,
+        };
+        let mut cpu = Arm7tdmiCpu::new(bus, true);
+        cpu.set_register(1, 0x0900_0000);
+        cpu.set_pipeline([assemble_arm(source, 0x0800_0000).unwrap(), 0]);
+        cpu.set_pc(0x0800_0008);
+        cpu.cycle();
+        cpu
+    }
+
+    #[test]
+    fn aborts() {
+        let cpu = execute("ldr r0, [r1, #4]!", 0x0900_0000);
+        assert_eq!(cpu.cpsr().mode(), CpuMode::Abort);
+        assert_eq!(cpu.spsr().mode(), CpuMode::System);
+        assert_eq!(cpu.register(LR), 0x0800_0008);
+        assert_eq!(cpu.register(PC), 0x10 + 8);
+        assert_eq!(cpu.general_registers()[..2], [0, 0x0900_0004]);
+
+        // Aborting on the last load
+        let cpu = execute("ldmia r1!, {r0, r1, r2}", 0x0900_0008);
+        assert_eq!(cpu.cpsr().mode(), CpuMode::Abort);
+        assert_eq!(cpu.general_registers()[..3], [0x1234_5678, 0x0900_000C, 0]);
+
+        // Into aborting memory
+        let mut cpu = execute("bx r1", 0x0900_0000);
+        assert_eq!(cpu.cpsr().mode(), CpuMode::System);
+        cpu.cycle();
+        assert_eq!(cpu.cpsr().mode(), CpuMode::Abort);
+        assert_eq!(cpu.register(LR), 0x0900_0004);
+    }
+
+    #[test]
+    fn interrupt_lines() {
+        let mut cpu = execute("mov r8, #1", 0x1000_0000);
+        cpu.set_irq_line(true);
+        cpu.set_fiq_line(true);
+        cpu.cycle();
+        assert_eq!(cpu.cpsr().mode(), CpuMode::Fiq);
+        assert!(cpu.cpsr().irq_disable() && cpu.cpsr().fiq_disable());
+        assert_eq!(cpu.register(LR), 0x0800_0008);
+        assert_eq!(cpu.register(PC), 0x1C + 8);
+        assert_eq!((cpu.register(8), cpu.general_registers()[8]), (0, 1));
+
+        // Masked by the fiq entry until the handler returns
+        cpu.cycle();
+        assert_eq!(cpu.cpsr().mode(), CpuMode::Fiq);
+        assert_eq!(cpu.pending_interrupt(), None);
+
+        // From thumb lr still points 4 past the interrupted instruction
+        let mut cpu = execute("mov r8, #1", 0x1000_0000);
+        cpu.set_state(CpuState::Thumb);
+        cpu.set_pc(0x0800_0004);
+        cpu.set_irq_line(true);
+        cpu.cycle();
+        assert_eq!(cpu.cpsr().mode(), CpuMode::Irq);
+        assert_eq!(cpu.spsr().state(), CpuState::Thumb);
+        assert_eq!(cpu.register(LR), 0x0800_0004);
+    }
+
+    #[test]
+    fn thumb_undefined_gaps() {
+        for opcode in [0xB100, 0xB800, 0xBF00] {
+            let mut cpu = execute("mov r8, #1", 0x1000_0000);
+            cpu.set_state(CpuState::Thumb);
+            cpu.set_pipeline([opcode, 0]);
+            cpu.set_pc(0x0800_0004);
+            let step = cpu.step();
+            assert_eq!(step.exception, Some(Exception::Undefined), "{opcode:04X}");
+            assert_eq!(cpu.cpsr().mode(), CpuMode::Undefined);
+            assert_eq!(cpu.register(LR), 0x0800_0002);
+        }
+    }
+
+    #[test]
+    fn snapshots_and_steps() {
+        let mut cpu = execute("mov r8, #1", 0x1000_0000);
+        let snapshot = cpu.snapshot();
+
+        // andeq r0, r0, r0 read from memory
+        let step = cpu.step();
+        assert_eq!(
+            (step.address, step.cycles, step.branched, step.exception),
+            (0x0800_0004, 1, false, None)
+        );
+
+        cpu.set_pipeline([assemble_arm("b #0x08000008", 0x0800_0008).unwrap(), 0]);
+        let step = cpu.step();
+        assert_eq!((step.address, step.cycles, step.branched), (0x0800_0008, 3, true));
+        assert_eq!(cpu.next_instruction_address(), 0x0800_0008);
+
+        cpu.set_irq_line(true);
+        let step = cpu.step();
+        assert_eq!((step.cycles, step.branched, step.exception), (2, true, Some(Exception::Irq)));
 
-    pub fn irq(&mut self) {
-        if !self.cpsr.irq_disable() {
-            self.exeception(Exception::Irq);
+        cpu.restore(&snapshot);
+        assert_eq!(cpu.snapshot(), snapshot);
+        assert_eq!(cpu.register(8), 1);
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&snapshot).unwrap();
+            assert_eq!(serde_json::from_str::<CpuSnapshot>(&json).unwrap(), snapshot);
         }
     }
 }
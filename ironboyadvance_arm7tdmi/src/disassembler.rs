@@ -1,10 +1,176 @@
-use std::fmt;
+use std::{collections::BTreeMap, fmt, sync::OnceLock};
 
 use crate::{
     AluOperationsOpcode, Condition, CpuMode, CpuState, DataProcessingOpcode, HiRegOpsBxOpcode, HiRegister, LoRegister,
-    MovCmpAddSubImmediateOpcode, Register, barrel_shifter::ShiftType,
+    MovCmpAddSubImmediateOpcode, Register,
+    arm::{ArmInstruction, ArmInstructionKind, lut::generate_arm_lut},
+    barrel_shifter::ShiftType,
+    cpu::Instruction,
+    thumb::{ThumbInstruction, ThumbInstructionKind, lut::generate_thumb_lut},
 };
 
+static ARM_LUT: OnceLock<[ArmInstructionKind; 4096]> = OnceLock::new();
+static THUMB_LUT: OnceLock<[ThumbInstructionKind; 1024]> = OnceLock::new();
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum DisassemblyStyle {
+    /// Upper case mnemonics as printed by the trace logs
+    #[default]
+    Native,
+    /// Lower case mnemonics and operands laid out like `objdump`
+    Gnu,
+}
+
+/// Names for addresses, used to label branch targets and pc relative loads
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SymbolTable {
+    symbols: BTreeMap<u32, String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    pub fn insert(&mut self, address: u32, name: impl Into<String>) {
+        self.symbols.insert(address, name.into());
+    }
+
+    pub fn get(&self, address: u32) -> Option<&str> {
+        self.symbols.get(&address).map(String::as_str)
+    }
+
+    /// Closest symbol at or below `address` and the distance from it
+    pub fn lookup(&self, address: u32) -> Option<(&str, u32)> {
+        self.symbols
+            .range(..=address)
+            .next_back()
+            .map(|(symbol_address, name)| (name.as_str(), address - symbol_address))
+    }
+
+    pub fn address_of(&self, name: &str) -> Option<u32> {
+        self.symbols
+            .iter()
+            .find(|(_, symbol)| symbol.as_str() == name)
+            .map(|(address, _)| *address)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.symbols.iter().map(|(address, name)| (*address, name.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+impl Extend<(u32, String)> for SymbolTable {
+    fn extend<T: IntoIterator<Item = (u32, String)>>(&mut self, iter: T) {
+        self.symbols.extend(iter);
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct DisassemblyOptions<'a> {
+    pub style: DisassemblyStyle,
+    pub symbols: Option<&'a SymbolTable>,
+}
+
+impl DisassemblyOptions<'_> {
+    pub(crate) fn is_gnu(&self) -> bool {
+        self.style == DisassemblyStyle::Gnu
+    }
+
+    /// Joins a mnemonic and its operands in the selected style
+    pub(crate) fn instruction(&self, mnemonic: &str, operands: &[String]) -> String {
+        let (mnemonic, separator) = match self.style {
+            DisassemblyStyle::Native => (mnemonic.to_string(), ","),
+            DisassemblyStyle::Gnu => (mnemonic.to_lowercase(), ", "),
+        };
+        match operands.is_empty() {
+            true => mnemonic,
+            false => format!("{mnemonic} {}", operands.join(separator)),
+        }
+    }
+
+    pub(crate) fn register_list(&self, registers: &[String], open: char, close: char) -> String {
+        match self.style {
+            DisassemblyStyle::Native => format!("{open}{}{close}", registers.join(",")),
+            DisassemblyStyle::Gnu => format!("{{{}}}", registers.join(", ")),
+        }
+    }
+
+    /// Absolute address, followed by the closest symbol when a table is available
+    pub(crate) fn address(&self, address: u32) -> String {
+        let address_text = match self.style {
+            DisassemblyStyle::Native => format!("0x{address:08X}"),
+            DisassemblyStyle::Gnu => format!("0x{address:x}"),
+        };
+        match self.symbols.and_then(|symbols| symbols.lookup(address)) {
+            Some((name, 0)) => format!("{address_text} <{name}>"),
+            Some((name, offset)) => format!("{address_text} <{name}+0x{offset:x}>"),
+            None => address_text,
+        }
+    }
+}
+
+/// Disassembles an ARM opcode located at `pc` in the current style
+pub fn disassemble_arm(opcode: u32, pc: u32) -> String {
+    disassemble_arm_with(opcode, pc, &DisassemblyOptions::default())
+}
+
+pub fn disassemble_arm_with(opcode: u32, pc: u32, options: &DisassemblyOptions) -> String {
+    decode_arm(opcode, pc).disassemble(options)
+}
+
+// The never condition is unpredictable on the ARM7TDMI, so it is treated like an undefined opcode
+fn decode_arm(opcode: u32, pc: u32) -> ArmInstruction {
+    let kind = match opcode >> 28 {
+        0xF => ArmInstructionKind::Undefined,
+        _ => {
+            let lut = ARM_LUT.get_or_init(generate_arm_lut);
+            lut[(((opcode >> 16) & 0x0FF0) | ((opcode >> 4) & 0x000F)) as usize]
+        }
+    };
+    ArmInstruction::new(kind, opcode, pc)
+}
+
+fn decode_thumb(opcode: u16, pc: u32) -> ThumbInstruction {
+    let lut = THUMB_LUT.get_or_init(generate_thumb_lut);
+    ThumbInstruction::new(lut[(opcode >> 6) as usize], opcode, pc)
+}
+
+/// Disassembles a THUMB opcode located at `pc` in the current style
+pub fn disassemble_thumb(opcode: u16, pc: u32) -> String {
+    disassemble_thumb_with(opcode, pc, &DisassemblyOptions::default())
+}
+
+pub fn disassemble_thumb_with(opcode: u16, pc: u32, options: &DisassemblyOptions) -> String {
+    decode_thumb(opcode, pc).disassemble(options)
+}
+
+/// Target of a THUMB `BL` split over `high` at `pc` and `low` right after it
+pub fn thumb_long_branch_target(high: u16, low: u16, pc: u32) -> Option<u32> {
+    let is_long_branch = |opcode: u16| opcode & 0xF000 == 0xF000;
+    if !is_long_branch(high) || !is_long_branch(low) || high & 0x0800 != 0 || low & 0x0800 == 0 {
+        return None;
+    }
+    let upper = (((high as u32) << 21) as i32 >> 9) as u32;
+    Some(pc.wrapping_add(4).wrapping_add(upper).wrapping_add((low as u32 & 0x7FF) << 1))
+}
+
+/// Disassembles the THUMB opcode `first`, or the whole `BL` when `first` and `second` form one
+pub fn disassemble_thumb_pair(first: u16, second: u16, pc: u32, options: &DisassemblyOptions) -> String {
+    match thumb_long_branch_target(first, second, pc) {
+        Some(target) => options.instruction("BL", &[options.address(target)]),
+        None => disassemble_thumb_with(first, pc, options),
+    }
+}
+
 impl fmt::Display for CpuMode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         use CpuMode::*;
@@ -193,3 +359,221 @@ impl fmt::Display for ShiftType {
         }
     }
 }
+
+/// How an instruction moves the pc, for static analysis
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Flow {
+    /// Falls through to the next instruction
+    Next,
+    /// `B` or `BL` to a known address, the state never changes
+    Branch { target: u32, conditional: bool, link: bool },
+    /// `BX` to whatever `register` holds
+    Exchange { register: usize, conditional: bool },
+    /// Any other write to the pc, such as `POP {pc}` or `MOV pc,lr`
+    Indirect { conditional: bool },
+    /// Takes the undefined instruction trap
+    Undefined,
+}
+
+impl Flow {
+    /// Whether execution can reach the following instruction
+    pub fn falls_through(&self) -> bool {
+        match *self {
+            Flow::Next => true,
+            Flow::Branch { conditional, link, .. } => conditional || link,
+            Flow::Exchange { conditional, .. } | Flow::Indirect { conditional } => conditional,
+            Flow::Undefined => false,
+        }
+    }
+}
+
+/// A register loaded from, or pointed at, an address relative to the pc
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PcRelative {
+    pub register: usize,
+    pub address: u32,
+    /// `LDR` of the word at `address` rather than an `ADR` of the address itself
+    pub load: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InstructionInfo {
+    pub flow: Flow,
+    pub pc_relative: Option<PcRelative>,
+}
+
+pub fn analyze_arm(opcode: u32, pc: u32) -> InstructionInfo {
+    use ArmInstructionKind::*;
+    let instruction = decode_arm(opcode, pc);
+    let conditional = instruction.kind() != Undefined && instruction.cond() != Condition::AL;
+    let base = pc.wrapping_add(8);
+    let mut pc_relative = None;
+    let flow = match instruction.kind() {
+        BranchAndExchange => Flow::Exchange {
+            register: instruction.rn() as usize,
+            conditional,
+        },
+        BranchAndBranchWithLink => Flow::Branch {
+            target: base.wrapping_add(((instruction.offset() << 8) as i32 >> 6) as u32),
+            conditional,
+            link: instruction.link(),
+        },
+        DataProcessing => {
+            let opcode = instruction.opcode();
+            if instruction.rn() == Register::R15 && instruction.is_immediate() {
+                let offset = instruction.immediate().rotate_right(2 * instruction.rotate());
+                let address = match opcode {
+                    DataProcessingOpcode::ADD => Some(base.wrapping_add(offset)),
+                    DataProcessingOpcode::SUB => Some(base.wrapping_sub(offset)),
+                    _ => None,
+                };
+                pc_relative = address.map(|address| PcRelative {
+                    register: instruction.rd() as usize,
+                    address,
+                    load: false,
+                });
+            }
+            let test = matches!(
+                opcode,
+                DataProcessingOpcode::TST
+                    | DataProcessingOpcode::TEQ
+                    | DataProcessingOpcode::CMP
+                    | DataProcessingOpcode::CMN
+            );
+            match instruction.rd() == Register::R15 && !test {
+                true => Flow::Indirect { conditional },
+                false => Flow::Next,
+            }
+        }
+        SingleDataTransfer => {
+            if instruction.rn() == Register::R15
+                && instruction.is_immediate()
+                && instruction.pre_index()
+                && !instruction.write_back()
+                && instruction.load()
+                && !instruction.byte()
+            {
+                let address = match instruction.add() {
+                    true => base.wrapping_add(instruction.immediate()),
+                    false => base.wrapping_sub(instruction.immediate()),
+                };
+                pc_relative = Some(PcRelative {
+                    register: instruction.rd() as usize,
+                    address,
+                    load: true,
+                });
+            }
+            match instruction.load() && instruction.rd() == Register::R15 {
+                true => Flow::Indirect { conditional },
+                false => Flow::Next,
+            }
+        }
+        BlockDataTransfer => match instruction.load() && instruction.register_list().contains(15) {
+            true => Flow::Indirect { conditional },
+            false => Flow::Next,
+        },
+        // Nothing answers coprocessor instructions on the GBA
+        Undefined | CoprocessorDataTransfer | CoprocessorDataOperation | CoprocessorRegisterTransfer => Flow::Undefined,
+        _ => Flow::Next,
+    };
+    InstructionInfo { flow, pc_relative }
+}
+
+/// Analyzes a single THUMB opcode, each half of a `BL` falls through on its own
+pub fn analyze_thumb(opcode: u16, pc: u32) -> InstructionInfo {
+    use ThumbInstructionKind::*;
+    let instruction = decode_thumb(opcode, pc);
+    let base = pc.wrapping_add(4);
+    let mut pc_relative = None;
+    let flow = match instruction.kind() {
+        ConditionalBranch => Flow::Branch {
+            target: base.wrapping_add(((instruction.offset() as u8 as i8 as i32) << 1) as u32),
+            conditional: true,
+            link: false,
+        },
+        UnconditionalBranch => Flow::Branch {
+            target: base.wrapping_add((((instruction.offset() as u32) << 21) as i32 >> 20) as u32),
+            conditional: false,
+            link: false,
+        },
+        HiRegisterOperationsBranchExchange => {
+            let source = instruction.rs() as usize + if instruction.h2() { 8 } else { 0 };
+            let destination = instruction.rd() as usize + if instruction.h1() { 8 } else { 0 };
+            match HiRegOpsBxOpcode::from(instruction.opcode()) {
+                HiRegOpsBxOpcode::BX => Flow::Exchange {
+                    register: source,
+                    conditional: false,
+                },
+                HiRegOpsBxOpcode::ADD | HiRegOpsBxOpcode::MOV if destination == 15 => Flow::Indirect { conditional: false },
+                _ => Flow::Next,
+            }
+        }
+        PcRelativeLoad => {
+            pc_relative = Some(PcRelative {
+                register: instruction.rd() as usize,
+                address: (base & !0x3).wrapping_add(instruction.offset() as u32 * 4),
+                load: true,
+            });
+            Flow::Next
+        }
+        LoadAddress => {
+            if !instruction.sp() {
+                pc_relative = Some(PcRelative {
+                    register: instruction.rd() as usize,
+                    address: (base & !0x3).wrapping_add(instruction.offset() as u32 * 4),
+                    load: false,
+                });
+            }
+            Flow::Next
+        }
+        PushPopRegisters if instruction.load() && instruction.store_lr_load_pc() => Flow::Indirect { conditional: false },
+        Undefined => Flow::Undefined,
+        _ => Flow::Next,
+    };
+    InstructionInfo { flow, pc_relative }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassemble_without_cpu() {
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x080000C0, "start");
+        let gnu = DisassemblyOptions {
+            style: DisassemblyStyle::Gnu,
+            symbols: Some(&symbols),
+        };
+
+        assert_eq!(disassemble_arm(0xEA00002E, 0x08000000), "B 0x080000C0");
+        assert_eq!(disassemble_arm_with(0xEA00002E, 0x08000000, &gnu), "b 0x80000c0 <start>");
+        assert_eq!(disassemble_arm_with(0xEAFFFFFF, 0x080000C0, &gnu), "b 0x80000c4 <start+0x4>");
+        assert_eq!(disassemble_arm(0xE3A00301, 0x08000000), "MOV r0,0x04000000");
+        assert_eq!(disassemble_arm_with(0xE3A00301, 0x08000000, &gnu), "mov r0, #67108864");
+        assert_eq!(disassemble_arm(0xE129F000, 0), "MSR CPSR_all,r0");
+        assert_eq!(disassemble_arm_with(0xE129F000, 0, &gnu), "msr CPSR_fc, r0");
+        assert_eq!(disassemble_arm(0xEE243165, 0), "CDP p1,2,c3,c4,c5,3");
+        assert_eq!(disassemble_arm_with(0xEE243165, 0, &gnu), "cdp 1, 2, cr3, cr4, cr5, {3}");
+        assert_eq!(disassemble_arm(0xEE110F10, 0), "MRC p15,0,r0,c1,c0,0");
+        assert_eq!(disassemble_arm_with(0x0E01FF10, 0, &gnu), "mcreq 15, 0, pc, cr1, cr0, {0}");
+        assert_eq!(disassemble_arm(0xED931204, 0), "LDC p2,c1,[r3,#+16]");
+        assert_eq!(disassemble_arm_with(0xECE31204, 0, &gnu), "stcl 2, cr1, [r3], #16");
+
+        assert_eq!(disassemble_thumb(0xD0FE, 0x08000200), "BEQ 0x08000200");
+        assert_eq!(
+            disassemble_thumb_pair(0xF000, 0xF802, 0x08000100, &DisassemblyOptions::default()),
+            "BL 0x08000108"
+        );
+        assert_eq!(disassemble_thumb_with(0xB500, 0, &gnu), "push {lr}");
+    }
+
+    #[test]
+    fn multiply_long_signedness() {
+        // The U bit, bit 22, is set for the signed multiplies
+        assert_eq!(disassemble_arm(0xE0810392, 0), "UMULL r0,r1,r2,r3");
+        assert_eq!(disassemble_arm(0xE0A10392, 0), "UMLAL r0,r1,r2,r3");
+        assert_eq!(disassemble_arm(0xE0C10392, 0), "SMULL r0,r1,r2,r3");
+        assert_eq!(disassemble_arm(0x10F10392, 0), "SMLALNES r0,r1,r2,r3");
+    }
+}
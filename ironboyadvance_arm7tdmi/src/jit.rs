@@ -0,0 +1,1041 @@
+use std::{
+    cell::{Cell, OnceCell},
+    marker::PhantomData,
+};
+
+use cranelift_codegen::{
+    ir::{AbiParam, Block as IrBlock, InstBuilder, MemFlags, SigRef, Signature, Type, Value, condcodes::IntCC, types},
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{Module, default_libcall_names};
+
+use crate::{
+    AluOperationsOpcode, Condition, CpuMode, DataProcessingOpcode, MovCmpAddSubImmediateOpcode,
+    alu::{multiplier_array_cycles, multiply_carry},
+    arm::{ArmInstruction, ArmInstructionKind},
+    barrel_shifter::{ShiftBy, ShiftType},
+    block_cache::{Block, BlockInstructions},
+    cpu::{Arm7tdmiCpu, Instruction, PC},
+    memory::{MemoryAccess, MemoryInterface},
+    psr::ProgramStatusRegister,
+    thumb::{ThumbInstruction, ThumbInstructionKind},
+};
+
+// Blocks run this many times through the interpreter before they are compiled
+const HOT_THRESHOLD: u32 = 64;
+// Code of invalidated blocks is never freed on its own, the module is rebuilt after this many functions
+const MAX_FUNCTIONS: usize = 4096;
+// Blocks that branch back to their start run again without returning, up to this many times per call
+const MAX_PASSES: u32 = 64;
+
+const N: u32 = 1 << 31;
+const Z: u32 = 1 << 30;
+const C: u32 = 1 << 29;
+const V: u32 = 1 << 28;
+
+type BlockFunction<I> = unsafe extern "C-unwind" fn(*mut Arm7tdmiCpu<I>) -> u32;
+
+/// Native code of a block, only valid while the `Jit` that compiled it is alive
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct CompiledBlock {
+    function: *const u8,
+    // Banked registers are addressed directly, so the code only runs in the mode it was compiled for
+    pub(crate) mode: CpuMode,
+}
+
+impl CompiledBlock {
+    /// Runs instructions until the block ends or one of them leaves it, returning how many ran in the last pass
+    ///
+    /// # Safety
+    /// The block must have been compiled for the same bus type by a `Jit` that is still alive
+    pub(crate) unsafe fn run<I: MemoryInterface>(&self, cpu: &mut Arm7tdmiCpu<I>) -> usize {
+        let function = unsafe { std::mem::transmute::<*const u8, BlockFunction<I>>(self.function) };
+        unsafe { function(cpu) as usize }
+    }
+}
+
+/// Compilation state kept next to the decoded instructions of a block
+#[derive(Default)]
+pub(crate) struct BlockCode {
+    entries: Cell<u32>,
+    // `None` once compiling failed, the block stays interpreted
+    pub(crate) compiled: OnceCell<Option<CompiledBlock>>,
+}
+
+impl BlockCode {
+    /// Counts an entry into the block, true once it ran often enough to be compiled
+    pub(crate) fn is_hot(&self) -> bool {
+        let entries = self.entries.get() + 1;
+        self.entries.set(entries);
+        entries >= HOT_THRESHOLD
+    }
+}
+
+/// Translates blocks to native code with cranelift.
+/// Data processing and multiplies that do not write the pc are translated, every other instruction calls
+/// into the interpreter, and every instruction fetches through the bus so the timing matches the interpreter.
+pub(crate) struct Jit {
+    // Only taken when the module is dropped, freeing its code
+    module: Option<JITModule>,
+    context: cranelift_codegen::Context,
+    builder_context: FunctionBuilderContext,
+    functions: usize,
+}
+
+impl Jit {
+    /// `None` when cranelift cannot generate code for the host
+    pub(crate) fn new() -> Option<Self> {
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").ok()?;
+        let isa = cranelift_native::builder().ok()?.finish(settings::Flags::new(flags)).ok()?;
+        let module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+        Some(Jit {
+            context: module.make_context(),
+            module: Some(module),
+            builder_context: FunctionBuilderContext::new(),
+            functions: 0,
+        })
+    }
+
+    /// True once the module should be rebuilt, after dropping every block compiled into it
+    pub(crate) fn is_full(&self) -> bool {
+        self.functions >= MAX_FUNCTIONS
+    }
+
+    pub(crate) fn compile<I: MemoryInterface>(&mut self, block: &Block, mode: CpuMode) -> Option<CompiledBlock> {
+        if mode == CpuMode::Invalid {
+            return None;
+        }
+        let module = self.module.as_mut()?;
+        let pointer = module.target_config().pointer_type();
+        let mut signature = module.make_signature();
+        signature.params.push(AbiParam::new(pointer));
+        signature.returns.push(AbiParam::new(types::I32));
+        self.context.func.signature = signature;
+
+        let builder = FunctionBuilder::new(&mut self.context.func, &mut self.builder_context);
+        let mut emitter = Emitter::<I>::new(builder, module, pointer, mode, block);
+        let executed = match &block.instructions {
+            BlockInstructions::Arm(instructions) => {
+                for (index, instruction) in instructions.iter().enumerate() {
+                    emitter.arm(index, instruction, block.start.wrapping_add(index as u32 * 4));
+                }
+                instructions.len()
+            }
+            BlockInstructions::Thumb(instructions) => {
+                for (index, instruction) in instructions.iter().enumerate() {
+                    emitter.thumb(index, instruction);
+                }
+                instructions.len()
+            }
+        };
+        emitter.finish(executed);
+
+        let id = module.declare_anonymous_function(&self.context.func.signature).ok();
+        let defined = id.filter(|id| module.define_function(*id, &mut self.context).is_ok());
+        module.clear_context(&mut self.context);
+        let id = defined?;
+        module.finalize_definitions().ok()?;
+        self.functions += 1;
+        Some(CompiledBlock {
+            function: module.get_finalized_function(id),
+            mode,
+        })
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // Blocks are dropped before their compiler, nothing can call into the code anymore
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+// Where the result of a shift leaves the carry flag
+#[derive(Copy, Clone)]
+enum Carry {
+    Unchanged,
+    Constant(bool),
+    Value(Value),
+}
+
+struct Emitter<'a, I: MemoryInterface> {
+    builder: FunctionBuilder<'a>,
+    cpu: Value,
+    pointer: Type,
+    mode: CpuMode,
+    // Where the first instruction starts, with whether the block needs checking and the passes so far
+    top: IrBlock,
+    length: usize,
+    // The pc and the mode and state bits of the cpsr once the block branched back to its start
+    loop_pc: u32,
+    loop_cpsr: u32,
+    begin: SigRef,
+    execute: SigRef,
+    idle: SigRef,
+    multiply: SigRef,
+    loops: SigRef,
+    bus: PhantomData<I>,
+}
+
+impl<'a, I: MemoryInterface> Emitter<'a, I> {
+    fn new(mut builder: FunctionBuilder<'a>, module: &JITModule, pointer: Type, mode: CpuMode, block: &Block) -> Self {
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+        let cpu = builder.block_params(entry)[0];
+        // Sealed once the branch back to it is known
+        let top = builder.create_block();
+        builder.append_block_param(top, types::I32);
+        builder.append_block_param(top, types::I32);
+        let zero = builder.ins().iconst(types::I32, 0);
+        builder.ins().jump(top, &[zero, zero]);
+        builder.switch_to_block(top);
+
+        let helper = |params: &[Type]| {
+            let mut signature: Signature = module.make_signature();
+            signature.params.push(AbiParam::new(pointer));
+            signature.params.extend(params.iter().map(|param| AbiParam::new(*param)));
+            signature.returns.push(AbiParam::new(types::I32));
+            signature
+        };
+        let begin = builder.import_signature(helper(&[types::I32]));
+        let execute = builder.import_signature(helper(&[pointer]));
+        let idle = builder.import_signature(helper(&[]));
+        let multiply = builder.import_signature(helper(&[types::I32; 5]));
+        let loops = builder.import_signature(helper(&[types::I32; 2]));
+        Emitter {
+            builder,
+            cpu,
+            pointer,
+            mode,
+            top,
+            length: block.len(),
+            loop_pc: block.start.wrapping_add(2 * block.width()),
+            loop_cpsr: mode as u32 | (block.state() as u32) << 5,
+            begin,
+            execute,
+            idle,
+            multiply,
+            loops,
+            bus: PhantomData,
+        }
+    }
+
+    fn finish(mut self, executed: usize) {
+        let executed = self.builder.ins().iconst(types::I32, executed as i64);
+        self.builder.ins().return_(&[executed]);
+        self.builder.seal_block(self.top);
+        self.builder.finalize();
+    }
+
+    fn call(&mut self, signature: SigRef, function: *const (), arguments: &[Value]) -> Value {
+        let callee = self.builder.ins().iconst(self.pointer, function as i64);
+        let mut values = vec![self.cpu];
+        values.extend_from_slice(arguments);
+        let call = self.builder.ins().call_indirect(signature, callee, &values);
+        self.builder.inst_results(call)[0]
+    }
+
+    // Returns from the block with `executed` instructions run when `condition` is non zero
+    fn exit_if(&mut self, condition: Value, executed: usize) {
+        let exit = self.builder.create_block();
+        let next = self.builder.create_block();
+        self.builder.ins().brif(condition, exit, &[], next, &[]);
+        self.builder.switch_to_block(exit);
+        self.builder.seal_block(exit);
+        let executed = self.builder.ins().iconst(types::I32, executed as i64);
+        self.builder.ins().return_(&[executed]);
+        self.builder.switch_to_block(next);
+        self.builder.seal_block(next);
+    }
+
+    // The entry was already checked by `cycle_cached`, later instructions and passes check for a reason to return first
+    fn begin(&mut self, index: usize) {
+        let check = match index {
+            0 => self.builder.block_params(self.top)[0],
+            _ => self.builder.ins().iconst(types::I32, 1),
+        };
+        let stop = self.call(self.begin, begin::<I> as *const (), &[check]);
+        self.exit_if(stop, index);
+    }
+
+    fn interpret(&mut self, index: usize, function: *const (), instruction: usize) {
+        let instruction = self.builder.ins().iconst(self.pointer, instruction as i64);
+        let stop = self.call(self.execute, function, &[instruction]);
+        match index + 1 == self.length {
+            true => self.loop_if(stop, index + 1),
+            false => self.exit_if(stop, index + 1),
+        }
+    }
+
+    // Like `exit_if` for the last instruction, which runs the block again when it branched back to the start
+    fn loop_if(&mut self, condition: Value, executed: usize) {
+        let branched = self.builder.create_block();
+        let exit = self.builder.create_block();
+        let next = self.builder.create_block();
+        self.builder.ins().brif(condition, branched, &[], next, &[]);
+        self.builder.switch_to_block(branched);
+        self.builder.seal_block(branched);
+        let pc = self.builder.ins().iconst(types::I32, self.loop_pc as i64);
+        let cpsr = self.builder.ins().iconst(types::I32, self.loop_cpsr as i64);
+        let looped = self.call(self.loops, loops::<I> as *const (), &[pc, cpsr]);
+        let passes = self.builder.block_params(self.top)[1];
+        let passes = self.builder.ins().iadd_imm(passes, 1);
+        let room = self
+            .builder
+            .ins()
+            .icmp_imm(IntCC::UnsignedLessThan, passes, MAX_PASSES as i64);
+        let room = self.builder.ins().uextend(types::I32, room);
+        let again = self.builder.ins().band(looped, room);
+        let check = self.builder.ins().iconst(types::I32, 1);
+        self.builder.ins().brif(again, self.top, &[check, passes], exit, &[]);
+        self.builder.switch_to_block(exit);
+        self.builder.seal_block(exit);
+        let executed = self.builder.ins().iconst(types::I32, executed as i64);
+        self.builder.ins().return_(&[executed]);
+        self.builder.switch_to_block(next);
+        self.builder.seal_block(next);
+    }
+
+    fn arm(&mut self, index: usize, instruction: &ArmInstruction, address: u32) {
+        self.begin(index);
+        // The condition field 0xF is unpredictable, the interpreter decides what it does
+        let translated = instruction.value() >> 28 != 0xF
+            && match instruction.kind() {
+                ArmInstructionKind::DataProcessing => self.arm_data_processing(instruction, address),
+                ArmInstructionKind::Multiply => self.arm_multiply(instruction),
+                _ => false,
+            };
+        if !translated {
+            let instruction = instruction as *const ArmInstruction as usize;
+            self.interpret(index, execute_arm::<I> as *const (), instruction);
+        }
+    }
+
+    fn thumb(&mut self, index: usize, instruction: &ThumbInstruction) {
+        self.begin(index);
+        let translated = match instruction.kind() {
+            ThumbInstructionKind::MoveShiftedRegister => self.thumb_move_shifted_register(instruction),
+            ThumbInstructionKind::AddSubtract => self.thumb_add_subtract(instruction),
+            ThumbInstructionKind::MoveCompareAddSubtractImmediate => self.thumb_immediate(instruction),
+            ThumbInstructionKind::AluOperations => self.thumb_alu_operations(instruction),
+            _ => false,
+        };
+        if !translated {
+            let instruction = instruction as *const ThumbInstruction as usize;
+            self.interpret(index, execute_thumb::<I> as *const (), instruction);
+        }
+    }
+
+    fn load(&mut self, offset: usize) -> Value {
+        self.builder
+            .ins()
+            .load(types::I32, MemFlags::trusted(), self.cpu, offset as i32)
+    }
+
+    fn store(&mut self, offset: usize, value: Value) {
+        self.builder.ins().store(MemFlags::trusted(), value, self.cpu, offset as i32);
+    }
+
+    // The pc reads as the address of the instruction plus 8, or plus 12 once a shift by a register took a cycle
+    fn register(&mut self, index: usize, pc: u32) -> Value {
+        match index {
+            PC => self.builder.ins().iconst(types::I32, pc as i64),
+            _ => self.load(Arm7tdmiCpu::<I>::register_offset(index, self.mode)),
+        }
+    }
+
+    fn set_register(&mut self, index: usize, value: Value) {
+        self.store(Arm7tdmiCpu::<I>::register_offset(index, self.mode), value);
+    }
+
+    fn cpsr_bit(&mut self, bit: u32) -> Value {
+        let cpsr = self.load(Arm7tdmiCpu::<I>::cpsr_offset());
+        let shifted = self.builder.ins().ushr_imm(cpsr, bit.trailing_zeros() as i64);
+        self.builder.ins().band_imm(shifted, 1)
+    }
+
+    // Same as `advance_pc_*` followed by a fetch with `access`, the action of every translated instruction
+    fn advance(&mut self, width: u32, access: MemoryAccess) {
+        let pc = self.load(Arm7tdmiCpu::<I>::register_offset(PC, self.mode));
+        let pc = self.builder.ins().iadd_imm(pc, width as i64);
+        self.set_register(PC, pc);
+        let access = self.builder.ins().iconst(types::I8, access.bits() as i64);
+        self.builder.ins().store(
+            MemFlags::trusted(),
+            access,
+            self.cpu,
+            Arm7tdmiCpu::<I>::next_memory_access_offset() as i32,
+        );
+    }
+
+    fn set_flags(&mut self, result: Value, carry: Carry, overflow: Option<Value>) {
+        let negative = self.builder.ins().ushr_imm(result, 31);
+        let negative = self.builder.ins().ishl_imm(negative, 31);
+        let zero = self.builder.ins().icmp_imm(IntCC::Equal, result, 0);
+        let zero = self.builder.ins().uextend(types::I32, zero);
+        let zero = self.builder.ins().ishl_imm(zero, 30);
+        let mut flags = self.builder.ins().bor(negative, zero);
+        let mut mask = N | Z;
+        match carry {
+            Carry::Unchanged => {}
+            Carry::Constant(carry) => {
+                mask |= C;
+                if carry {
+                    flags = self.builder.ins().bor_imm(flags, C as i64);
+                }
+            }
+            Carry::Value(carry) => {
+                mask |= C;
+                let carry = self.builder.ins().ishl_imm(carry, 29);
+                flags = self.builder.ins().bor(flags, carry);
+            }
+        }
+        if let Some(overflow) = overflow {
+            mask |= V;
+            let overflow = self.builder.ins().ishl_imm(overflow, 28);
+            flags = self.builder.ins().bor(flags, overflow);
+        }
+        let cpsr = self.load(Arm7tdmiCpu::<I>::cpsr_offset());
+        let cpsr = self.builder.ins().band_imm(cpsr, !mask as i64);
+        let cpsr = self.builder.ins().bor(cpsr, flags);
+        self.store(Arm7tdmiCpu::<I>::cpsr_offset(), cpsr);
+    }
+
+    fn flag(&mut self, condition: IntCC, a: Value, b: Value) -> Value {
+        let flag = self.builder.ins().icmp(condition, a, b);
+        self.builder.ins().uextend(types::I32, flag)
+    }
+
+    // Mirrors `alu::add` and `alu::sub`, or `alu::adc` and `alu::sbc` when the carry is added in.
+    // The carry of a subtraction is set when it does not borrow
+    fn arithmetic(&mut self, subtract: bool, operand1: Value, operand2: Value, carry_in: bool, set_flags: bool) -> Value {
+        let (result, carry) = match carry_in {
+            true => self.arithmetic_with_carry(subtract, operand1, operand2),
+            false => {
+                let result = match subtract {
+                    true => self.builder.ins().isub(operand1, operand2),
+                    false => self.builder.ins().iadd(operand1, operand2),
+                };
+                (result, None)
+            }
+        };
+        if set_flags {
+            let carry = match (carry, subtract) {
+                (Some(carry), _) => carry,
+                (None, true) => self.flag(IntCC::UnsignedGreaterThanOrEqual, operand1, operand2),
+                (None, false) => self.flag(IntCC::UnsignedLessThan, result, operand1),
+            };
+            let operands = self.builder.ins().bxor(operand1, operand2);
+            let operands = match subtract {
+                true => operands,
+                false => self.builder.ins().bnot(operands),
+            };
+            let changed = self.builder.ins().bxor(operand1, result);
+            let overflow = self.builder.ins().band(operands, changed);
+            let overflow = self.builder.ins().ushr_imm(overflow, 31);
+            self.set_flags(result, Carry::Value(carry), Some(overflow));
+        }
+        result
+    }
+
+    // Adds in the carry flag, or subtracts its inverse, in 64 bits so the carry out is the bit above the result
+    fn arithmetic_with_carry(&mut self, subtract: bool, operand1: Value, operand2: Value) -> (Value, Option<Value>) {
+        let carry_in = self.cpsr_bit(C);
+        let ins = self.builder.ins();
+        let wide1 = ins.uextend(types::I64, operand1);
+        let wide2 = self.builder.ins().uextend(types::I64, operand2);
+        match subtract {
+            true => {
+                let borrow = self.builder.ins().bxor_imm(carry_in, 1);
+                let difference = self.builder.ins().isub(operand1, operand2);
+                let result = self.builder.ins().isub(difference, borrow);
+                let borrow = self.builder.ins().uextend(types::I64, borrow);
+                let subtrahend = self.builder.ins().iadd(wide2, borrow);
+                let carry = self.flag(IntCC::UnsignedGreaterThanOrEqual, wide1, subtrahend);
+                (result, Some(carry))
+            }
+            false => {
+                let carry_in = self.builder.ins().uextend(types::I64, carry_in);
+                let sum = self.builder.ins().iadd(wide1, wide2);
+                let sum = self.builder.ins().iadd(sum, carry_in);
+                let result = self.builder.ins().ireduce(types::I32, sum);
+                let carry = self.builder.ins().ushr_imm(sum, 32);
+                (result, Some(self.builder.ins().ireduce(types::I32, carry)))
+            }
+        }
+    }
+
+    // Mirrors `barrel_shifter` for shifts by an immediate, where LSR #0 and ASR #0 mean 32 and ROR #0 is RRX
+    fn shift_immediate(&mut self, shift: ShiftType, value: Value, amount: u32) -> (Value, Carry) {
+        let ins = self.builder.ins();
+        match (shift, amount) {
+            (ShiftType::LSL, 0) => (value, Carry::Unchanged),
+            (ShiftType::LSL, _) => {
+                let carry = ins.ushr_imm(value, 32 - amount as i64);
+                let carry = self.builder.ins().band_imm(carry, 1);
+                (self.builder.ins().ishl_imm(value, amount as i64), Carry::Value(carry))
+            }
+            (ShiftType::LSR, 0) => (
+                ins.iconst(types::I32, 0),
+                Carry::Value(self.builder.ins().ushr_imm(value, 31)),
+            ),
+            (ShiftType::ASR, 0) => {
+                let carry = ins.ushr_imm(value, 31);
+                (self.builder.ins().sshr_imm(value, 31), Carry::Value(carry))
+            }
+            (ShiftType::LSR | ShiftType::ASR, _) => {
+                let carry = ins.ushr_imm(value, amount as i64 - 1);
+                let carry = self.builder.ins().band_imm(carry, 1);
+                let result = match shift {
+                    ShiftType::LSR => self.builder.ins().ushr_imm(value, amount as i64),
+                    _ => self.builder.ins().sshr_imm(value, amount as i64),
+                };
+                (result, Carry::Value(carry))
+            }
+            (ShiftType::ROR, 0) => {
+                let carry = ins.band_imm(value, 1);
+                let carry_in = self.cpsr_bit(C);
+                let carry_in = self.builder.ins().ishl_imm(carry_in, 31);
+                let shifted = self.builder.ins().ushr_imm(value, 1);
+                (self.builder.ins().bor(shifted, carry_in), Carry::Value(carry))
+            }
+            (ShiftType::ROR, _) => {
+                let result = ins.rotr_imm(value, amount as i64);
+                (result, Carry::Value(self.builder.ins().ushr_imm(result, 31)))
+            }
+        }
+    }
+
+    // Mirrors `barrel_shifter` for shifts by the bottom byte of a register, which leave the carry alone when it is 0.
+    // The value is shifted in 64 bits with the amount clamped, so the carry is the bit next to the result
+    fn shift_register(&mut self, shift: ShiftType, value: Value, amount: Value) -> (Value, Carry) {
+        let amount = self.builder.ins().band_imm(amount, 0xFF);
+        let (result, carry) = match shift {
+            ShiftType::LSL => {
+                let limit = self.builder.ins().iconst(types::I32, 33);
+                let clamped = self.builder.ins().umin(amount, limit);
+                let clamped = self.builder.ins().uextend(types::I64, clamped);
+                let wide = self.builder.ins().uextend(types::I64, value);
+                let shifted = self.builder.ins().ishl(wide, clamped);
+                let carry = self.builder.ins().ushr_imm(shifted, 32);
+                let carry = self.builder.ins().band_imm(carry, 1);
+                let carry = self.builder.ins().ireduce(types::I32, carry);
+                (self.builder.ins().ireduce(types::I32, shifted), carry)
+            }
+            ShiftType::LSR | ShiftType::ASR => {
+                let (limit, extended) = match shift {
+                    ShiftType::LSR => (33, self.builder.ins().uextend(types::I64, value)),
+                    _ => (32, self.builder.ins().sextend(types::I64, value)),
+                };
+                let limit = self.builder.ins().iconst(types::I32, limit);
+                let clamped = self.builder.ins().umin(amount, limit);
+                let clamped = self.builder.ins().uextend(types::I64, clamped);
+                // The bit shifted out last ends up below the result
+                let wide = self.builder.ins().ishl_imm(extended, 1);
+                let shifted = match shift {
+                    ShiftType::LSR => self.builder.ins().ushr(wide, clamped),
+                    _ => self.builder.ins().sshr(wide, clamped),
+                };
+                let carry = self.builder.ins().band_imm(shifted, 1);
+                let carry = self.builder.ins().ireduce(types::I32, carry);
+                let result = self.builder.ins().ushr_imm(shifted, 1);
+                (self.builder.ins().ireduce(types::I32, result), carry)
+            }
+            ShiftType::ROR => {
+                let result = self.builder.ins().rotr(value, amount);
+                (result, self.builder.ins().ushr_imm(result, 31))
+            }
+        };
+        let carry_in = self.cpsr_bit(C);
+        let unshifted = self.builder.ins().icmp_imm(IntCC::Equal, amount, 0);
+        let carry = self.builder.ins().select(unshifted, carry_in, carry);
+        (result, Carry::Value(carry))
+    }
+
+    // Mirrors `Arm7tdmiCpu::is_condition_met`, as 0 or 1
+    fn condition(&mut self, condition: Condition) -> Value {
+        use Condition::*;
+        let flag = |emitter: &mut Self, bit| emitter.cpsr_bit(bit);
+        match condition {
+            EQ | NE => {
+                let zero = flag(self, Z);
+                match condition {
+                    EQ => zero,
+                    _ => self.builder.ins().bxor_imm(zero, 1),
+                }
+            }
+            CS | CC => {
+                let carry = flag(self, C);
+                match condition {
+                    CS => carry,
+                    _ => self.builder.ins().bxor_imm(carry, 1),
+                }
+            }
+            MI | PL => {
+                let negative = flag(self, N);
+                match condition {
+                    MI => negative,
+                    _ => self.builder.ins().bxor_imm(negative, 1),
+                }
+            }
+            VS | VC => {
+                let overflow = flag(self, V);
+                match condition {
+                    VS => overflow,
+                    _ => self.builder.ins().bxor_imm(overflow, 1),
+                }
+            }
+            HI | LS => {
+                let carry = flag(self, C);
+                let zero = flag(self, Z);
+                let not_zero = self.builder.ins().bxor_imm(zero, 1);
+                let higher = self.builder.ins().band(carry, not_zero);
+                match condition {
+                    HI => higher,
+                    _ => self.builder.ins().bxor_imm(higher, 1),
+                }
+            }
+            GE | LT | GT | LE => {
+                let negative = flag(self, N);
+                let overflow = flag(self, V);
+                let less = self.builder.ins().bxor(negative, overflow);
+                let less_or_equal = match condition {
+                    GT | LE => {
+                        let zero = flag(self, Z);
+                        self.builder.ins().bor(less, zero)
+                    }
+                    _ => less,
+                };
+                match condition {
+                    LT | LE => less_or_equal,
+                    _ => self.builder.ins().bxor_imm(less_or_equal, 1),
+                }
+            }
+            AL => self.builder.ins().iconst(types::I32, 1),
+        }
+    }
+
+    // Runs what follows only when `condition` is met, returns the block skipped instructions continue in
+    fn begin_condition(&mut self, condition: Condition) -> Option<IrBlock> {
+        if condition == Condition::AL {
+            return None;
+        }
+        let met = self.condition(condition);
+        let run = self.builder.create_block();
+        let skip = self.builder.create_block();
+        self.builder.ins().brif(met, run, &[], skip, &[]);
+        self.builder.switch_to_block(run);
+        self.builder.seal_block(run);
+        Some(skip)
+    }
+
+    // Advances past an instruction that ran with `access` for the next fetch, skipped instructions fetch sequentially
+    fn end_condition(&mut self, skip: Option<IrBlock>, access: MemoryAccess) {
+        self.advance(4, access);
+        let Some(skip) = skip else {
+            return;
+        };
+        let done = self.builder.create_block();
+        self.builder.ins().jump(done, &[]);
+        self.builder.switch_to_block(skip);
+        self.builder.seal_block(skip);
+        self.advance(4, MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL);
+        self.builder.ins().jump(done, &[]);
+        self.builder.switch_to_block(done);
+        self.builder.seal_block(done);
+    }
+
+    fn idle_cycle(&mut self) {
+        self.call(self.idle, idle::<I> as *const (), &[]);
+    }
+
+    // Mirrors `execute_data_processing` without the cases that write the pc
+    fn arm_data_processing(&mut self, instruction: &ArmInstruction, address: u32) -> bool {
+        use DataProcessingOpcode::*;
+        if instruction.rd() as usize == PC {
+            return false;
+        }
+        let skip = self.begin_condition(instruction.cond());
+        let register_shift = !instruction.is_immediate() && matches!(instruction.shift_by(), ShiftBy::Register);
+        let (pc, access) = match register_shift {
+            true => {
+                self.idle_cycle();
+                (
+                    address.wrapping_add(12),
+                    MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL,
+                )
+            }
+            false => (address.wrapping_add(8), MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL),
+        };
+
+        let operand1 = self.register(instruction.rn() as usize, pc);
+        let (operand2, carry) = match instruction.is_immediate() {
+            true => {
+                let rotate = 2 * instruction.rotate();
+                let value = instruction.immediate().rotate_right(rotate);
+                let carry = match rotate {
+                    0 => Carry::Unchanged,
+                    _ => Carry::Constant(value >> 31 != 0),
+                };
+                (self.builder.ins().iconst(types::I32, value as i64), carry)
+            }
+            false => {
+                let value = self.register(instruction.rm() as usize, pc);
+                match register_shift {
+                    true => {
+                        // The shift amount is read before the pc moves on
+                        let amount = self.register(instruction.rs() as usize, address.wrapping_add(8));
+                        self.shift_register(instruction.shift_type(), value, amount)
+                    }
+                    false => self.shift_immediate(instruction.shift_type(), value, instruction.shift_amount()),
+                }
+            }
+        };
+
+        let set_flags = instruction.sets_flags();
+        let opcode = instruction.opcode();
+        let result = match opcode {
+            SUB | CMP => self.arithmetic(true, operand1, operand2, false, set_flags),
+            RSB => self.arithmetic(true, operand2, operand1, false, set_flags),
+            ADD | CMN => self.arithmetic(false, operand1, operand2, false, set_flags),
+            ADC => self.arithmetic(false, operand1, operand2, true, set_flags),
+            SBC => self.arithmetic(true, operand1, operand2, true, set_flags),
+            RSC => self.arithmetic(true, operand2, operand1, true, set_flags),
+            _ => {
+                let ins = self.builder.ins();
+                let result = match opcode {
+                    AND | TST => ins.band(operand1, operand2),
+                    EOR | TEQ => ins.bxor(operand1, operand2),
+                    ORR => ins.bor(operand1, operand2),
+                    BIC => ins.band_not(operand1, operand2),
+                    MOV => operand2,
+                    _ => ins.bnot(operand2),
+                };
+                if set_flags {
+                    self.set_flags(result, carry, None);
+                }
+                result
+            }
+        };
+        if !matches!(opcode, TST | TEQ | CMP | CMN) {
+            self.set_register(instruction.rd() as usize, result);
+        }
+        self.end_condition(skip, access);
+        true
+    }
+
+    // Mirrors `execute_multiply`, the idle cycles and the carry flag come from `multiply`
+    fn arm_multiply(&mut self, instruction: &ArmInstruction) -> bool {
+        let registers = [instruction.rd(), instruction.rm(), instruction.rs(), instruction.rn()];
+        if registers.iter().any(|register| *register as usize == PC) {
+            return false;
+        }
+        let skip = self.begin_condition(instruction.cond());
+        let operand1 = self.register(instruction.rm() as usize, 0);
+        let operand2 = self.register(instruction.rs() as usize, 0);
+        let accumulate = instruction.accumulate();
+        let accumulator = match accumulate {
+            true => self.register(instruction.rn() as usize, 0),
+            false => self.builder.ins().iconst(types::I32, 0),
+        };
+        let carry = self.multiply(operand1, operand2, accumulator, accumulate, instruction.sets_flags());
+        let product = self.builder.ins().imul(operand1, operand2);
+        let result = self.builder.ins().iadd(product, accumulator);
+        if instruction.sets_flags() {
+            self.set_flags(result, Carry::Value(carry), None);
+        }
+        self.set_register(instruction.rd() as usize, result);
+        self.end_condition(skip, MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL);
+        true
+    }
+
+    fn multiply(
+        &mut self,
+        multiplicand: Value,
+        multiplier: Value,
+        accumulator: Value,
+        accumulate: bool,
+        set_flags: bool,
+    ) -> Value {
+        let accumulate = self.builder.ins().iconst(types::I32, accumulate as i64);
+        let set_flags = self.builder.ins().iconst(types::I32, set_flags as i64);
+        let arguments = [multiplicand, multiplier, accumulator, accumulate, set_flags];
+        self.call(self.multiply, multiply::<I> as *const (), &arguments)
+    }
+
+    fn thumb_register(&mut self, index: usize) -> Value {
+        self.load(Arm7tdmiCpu::<I>::register_offset(index, self.mode))
+    }
+
+    fn thumb_move_shifted_register(&mut self, instruction: &ThumbInstruction) -> bool {
+        let shift = match instruction.opcode() {
+            0b00 => ShiftType::LSL,
+            0b01 => ShiftType::LSR,
+            0b10 => ShiftType::ASR,
+            _ => return false,
+        };
+        let value = self.thumb_register(instruction.rs() as usize);
+        let (result, carry) = self.shift_immediate(shift, value, instruction.offset() as u32);
+        self.set_flags(result, carry, None);
+        self.set_register(instruction.rd() as usize, result);
+        self.advance(2, MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL);
+        true
+    }
+
+    fn thumb_add_subtract(&mut self, instruction: &ThumbInstruction) -> bool {
+        let operand1 = self.thumb_register(instruction.rs() as usize);
+        let operand2 = match instruction.is_immediate() {
+            true => self.builder.ins().iconst(types::I32, instruction.offset() as i64),
+            false => self.thumb_register(instruction.rn() as usize),
+        };
+        let result = self.arithmetic(instruction.opcode() != 0, operand1, operand2, false, true);
+        self.set_register(instruction.rd() as usize, result);
+        self.advance(2, MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL);
+        true
+    }
+
+    fn thumb_immediate(&mut self, instruction: &ThumbInstruction) -> bool {
+        use MovCmpAddSubImmediateOpcode::*;
+        let rd = instruction.rd() as usize;
+        let offset = self.builder.ins().iconst(types::I32, instruction.offset() as i64);
+        let opcode: MovCmpAddSubImmediateOpcode = instruction.opcode().into();
+        let result = match opcode {
+            MOV => {
+                self.set_flags(offset, Carry::Unchanged, None);
+                offset
+            }
+            _ => {
+                let operand1 = self.thumb_register(rd);
+                self.arithmetic(opcode != ADD, operand1, offset, false, true)
+            }
+        };
+        if opcode != CMP {
+            self.set_register(rd, result);
+        }
+        self.advance(2, MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL);
+        true
+    }
+
+    // Mirrors `execute_alu_operations`, shifts by a register and MUL take idle cycles and fetch non sequentially
+    fn thumb_alu_operations(&mut self, instruction: &ThumbInstruction) -> bool {
+        use AluOperationsOpcode::*;
+        let opcode: AluOperationsOpcode = instruction.opcode().into();
+        let rd = instruction.rd() as usize;
+        let operand1 = self.thumb_register(rd);
+        let operand2 = self.thumb_register(instruction.rs() as usize);
+        let mut access = MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL;
+        let result = match opcode {
+            NEG => {
+                let zero = self.builder.ins().iconst(types::I32, 0);
+                self.arithmetic(true, zero, operand2, false, true)
+            }
+            CMP => self.arithmetic(true, operand1, operand2, false, true),
+            CMN => self.arithmetic(false, operand1, operand2, false, true),
+            ADC => self.arithmetic(false, operand1, operand2, true, true),
+            SBC => self.arithmetic(true, operand1, operand2, true, true),
+            LSL | LSR | ASR | ROR => {
+                let shift = match opcode {
+                    LSL => ShiftType::LSL,
+                    LSR => ShiftType::LSR,
+                    ASR => ShiftType::ASR,
+                    _ => ShiftType::ROR,
+                };
+                let (result, carry) = self.shift_register(shift, operand1, operand2);
+                self.idle_cycle();
+                access = MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL;
+                self.set_flags(result, carry, None);
+                result
+            }
+            MUL => {
+                let zero = self.builder.ins().iconst(types::I32, 0);
+                let carry = self.multiply(operand2, operand1, zero, false, true);
+                access = MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL;
+                let result = self.builder.ins().imul(operand1, operand2);
+                self.set_flags(result, Carry::Value(carry), None);
+                result
+            }
+            _ => {
+                let ins = self.builder.ins();
+                let result = match opcode {
+                    AND | TST => ins.band(operand1, operand2),
+                    EOR => ins.bxor(operand1, operand2),
+                    ORR => ins.bor(operand1, operand2),
+                    BIC => ins.band_not(operand1, operand2),
+                    _ => ins.bnot(operand2),
+                };
+                self.set_flags(result, Carry::Unchanged, None);
+                result
+            }
+        };
+        if !matches!(opcode, TST | CMP | CMN) {
+            self.set_register(rd, result);
+        }
+        self.advance(2, access);
+        true
+    }
+}
+
+// Called before every instruction, non zero when the block has to return before running it
+unsafe extern "C-unwind" fn begin<I: MemoryInterface>(cpu: *mut Arm7tdmiCpu<I>, check: u32) -> u32 {
+    let cpu = unsafe { &mut *cpu };
+    // The opcodes were checked against the pipeline on entry, writes since then end the block
+    if check != 0 && cpu.block_interrupted() {
+        return 1;
+    }
+    cpu.fetch(cpu.pc() & !0x1);
+    0
+}
+
+// Non zero when the last instruction branched back to the start of the block, without changing mode or writing to it
+unsafe extern "C-unwind" fn loops<I: MemoryInterface>(cpu: *mut Arm7tdmiCpu<I>, pc: u32, cpsr: u32) -> u32 {
+    let cpu = unsafe { &*cpu };
+    !cpu.left_block(pc, ProgramStatusRegister::from_bits(cpsr)) as u32
+}
+
+unsafe extern "C-unwind" fn idle<I: MemoryInterface>(cpu: *mut Arm7tdmiCpu<I>) -> u32 {
+    unsafe { &mut *cpu }.idle_cycle();
+    0
+}
+
+// Takes the idle cycles of the multiplier array, and of the addition for the accumulating forms, returning the carry
+unsafe extern "C-unwind" fn multiply<I: MemoryInterface>(
+    cpu: *mut Arm7tdmiCpu<I>,
+    multiplicand: u32,
+    multiplier: u32,
+    accumulator: u32,
+    accumulate: u32,
+    set_flags: u32,
+) -> u32 {
+    let cpu = unsafe { &mut *cpu };
+    for _ in 0..multiplier_array_cycles(multiplier, true) + accumulate as usize {
+        cpu.idle_cycle();
+    }
+    (set_flags != 0 && multiply_carry(multiplicand, multiplier, accumulator as u64, false, true)) as u32
+}
+
+unsafe extern "C-unwind" fn execute_arm<I: MemoryInterface>(
+    cpu: *mut Arm7tdmiCpu<I>,
+    instruction: *const ArmInstruction,
+) -> u32 {
+    let (cpu, instruction) = unsafe { (&mut *cpu, &*instruction) };
+    let (pc, cpsr) = (cpu.pc(), cpu.cpsr());
+    cpu.execute_arm(instruction);
+    cpu.left_block(pc.wrapping_add(4), cpsr) as u32
+}
+
+unsafe extern "C-unwind" fn execute_thumb<I: MemoryInterface>(
+    cpu: *mut Arm7tdmiCpu<I>,
+    instruction: *const ThumbInstruction,
+) -> u32 {
+    let (cpu, instruction) = unsafe { (&mut *cpu, &*instruction) };
+    let (pc, cpsr) = (cpu.pc(), cpu.cpsr());
+    cpu.execute_thumb(instruction);
+    cpu.left_block(pc.wrapping_add(2), cpsr) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        block_cache::tests::{SELF_MODIFYING_PROGRAM, program_cpu, run},
+        cpu::Arm7tdmiCpu,
+        memory::MemoryInterface,
+    };
+
+    // Loops through data processing and multiplies in both states, so the blocks get hot enough to compile
+    const ALU_PROGRAM: &str = "
+                mov r0, #0
+                mvn r1, #0xFF
+        arm:    adds r2, r1, r0, lsl #3
+                eors r3, r2, r1, ror #7
+                rsbs r4, r3, #0x1000
+                bics r5, r4, r2, lsr #32
+                movs r6, r5, asr #32
+                orrs r7, r6, r1, rrx
+                subs r8, r7, r2
+                cmp r0, #0x40
+                addlt r9, r9, r8
+                addge r10, r10, r8, asr #4
+                teq r1, r2
+                mvnne r11, r11
+                adcs r9, r9, r0, lsl r0
+                adc r13, r13, r9
+                sbcs r10, r10, r1, lsr r0
+                adc r13, r13, r10
+                rscs r11, r11, r3, asr r0
+                adc r13, r13, r11
+                movs r12, r4, ror r0
+                adc r13, r13, r12
+                movs r12, r3, lsl r1
+                adc r13, r13, r12
+                eor r12, r0, #0x80000000
+                movs r12, r12, asr r0
+                adc r13, r13, r12
+                add r12, r0, pc, lsl r1
+                adc r13, r13, r12
+                muls r12, r4, r0
+                adc r13, r13, r12
+                mlas r14, r4, r1, r13
+                adc r13, r13, r14
+                mulne r12, r1, r4
+                add r0, r0, #1
+                cmp r0, #0xFF
+                bls arm
+                add r12, pc, #1
+                bx r12
+        .thumb
+                mov r0, #200
+        thumb:  lsl r1, r0, #24
+                lsr r2, r1, #32
+                asr r3, r1, #3
+                add r4, r1, r3
+                sub r5, r4, #7
+                neg r6, r5
+                and r6, r4
+                eor r6, r1
+                orr r7, r6
+                bic r7, r3
+                mvn r2, r7
+                tst r2, r4
+                cmn r2, r5
+                lsl r1, r0
+                adc r7, r1
+                lsr r2, r0
+                adc r7, r2
+                asr r3, r0
+                adc r7, r3
+                ror r4, r0
+                adc r7, r4
+                adc r5, r1
+                sbc r6, r2
+                adc r7, r6
+                mul r5, r6
+                adc r7, r5
+                sub r0, #1
+                bne thumb
+        done:   b done
+    ";
+
+    #[test]
+    fn matches_interpreter() {
+        let mut compiled = program_cpu(ALU_PROGRAM);
+        compiled.set_jit(true);
+        run(&mut compiled, Arm7tdmiCpu::cycle_cached);
+        let mut interpreted = program_cpu(ALU_PROGRAM);
+        run(&mut interpreted, Arm7tdmiCpu::cycle);
+        assert_eq!(compiled.snapshot(), interpreted.snapshot());
+        assert_eq!(compiled.cycle_count(), interpreted.cycle_count());
+        assert_eq!(compiled.general_registers()[0], 0);
+
+        let mut compiled = program_cpu(SELF_MODIFYING_PROGRAM);
+        compiled.set_jit(true);
+        run(&mut compiled, Arm7tdmiCpu::cycle_cached);
+        assert_eq!(compiled.register(2), 17);
+    }
+}
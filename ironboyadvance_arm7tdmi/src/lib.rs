@@ -1,18 +1,28 @@
 mod alu;
 mod arm;
+pub mod assembler;
 mod barrel_shifter;
+mod bits;
+pub mod block_cache;
+pub mod call_stack;
+pub mod coprocessor;
 pub mod cpu;
-mod disassembler;
+pub mod disassembler;
+#[cfg(feature = "jit")]
+mod jit;
 pub mod memory;
-mod psr;
+pub mod profiler;
+pub mod psr;
+pub mod snapshot;
 mod tests;
 mod thumb;
+pub mod trace;
 
 pub const CPU_CLOCK_SPEED: u32 = 16777216;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum CpuAction {
-    Advance(u8),
+    Advance(memory::MemoryAccess),
     PipelineFlush,
 }
 
@@ -345,12 +355,13 @@ impl From<u16> for HiRegOpsBxOpcode {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Exception {
     Reset = 0x00,
     Undefined = 0x04,
     SoftwareInterrupt = 0x08,
-    // AbortPrefetch = 0x0C,
-    // AbortData = 0x10,
+    AbortPrefetch = 0x0C,
+    AbortData = 0x10,
     // Reserved = 0x14,
     Irq = 0x18,
     Fiq = 0x1C,
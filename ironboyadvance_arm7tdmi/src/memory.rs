@@ -1,45 +1,21 @@
-use std::ops::BitOr;
+use bitflags::bitflags;
 
 use crate::cpu::Arm7tdmiCpu;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum MemoryAccess {
-    NonSequential = 0b0,
-    Sequential = 0b1,
-    Instruction = 0b10,
-    Dma = 0b100,
-    Lock = 0b1000,
-}
-
-impl BitOr for MemoryAccess {
-    type Output = u8;
-
-    fn bitor(self, rhs: Self) -> Self::Output {
-        self as u8 | rhs as u8
+bitflags! {
+    /// Kind of a bus access, a nonsequential data access when empty
+    #[repr(transparent)]
+    #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct MemoryAccess: u8 {
+        const NONSEQUENTIAL = 0b0;
+        const SEQUENTIAL = 0b1;
+        const INSTRUCTION = 0b10;
+        const DMA = 0b100;
+        const LOCK = 0b1000;
     }
 }
 
-pub fn decompose_access_pattern(access_pattern: u8) -> Vec<MemoryAccess> {
-    let mut decomposition = Vec::new();
-    match access_pattern & MemoryAccess::Sequential as u8 != 0 {
-        true => decomposition.push(MemoryAccess::Sequential),
-        false => decomposition.push(MemoryAccess::NonSequential),
-    };
-
-    if access_pattern & MemoryAccess::Instruction as u8 != 0 {
-        decomposition.push(MemoryAccess::Instruction);
-    }
-
-    if access_pattern & MemoryAccess::Dma as u8 != 0 {
-        decomposition.push(MemoryAccess::Dma);
-    }
-
-    if access_pattern & MemoryAccess::Lock as u8 != 0 {
-        decomposition.push(MemoryAccess::Lock);
-    }
-    decomposition
-}
-
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum MemoryAccessWidth {
     Byte,
@@ -48,19 +24,34 @@ pub enum MemoryAccessWidth {
 }
 
 pub trait MemoryInterface {
-    fn load_8(&mut self, address: u32, access_pattern: u8) -> u32;
+    fn load_8(&mut self, address: u32, access_pattern: MemoryAccess) -> u32;
 
-    fn load_16(&mut self, address: u32, access_pattern: u8) -> u32;
+    fn load_16(&mut self, address: u32, access_pattern: MemoryAccess) -> u32;
 
-    fn load_32(&mut self, address: u32, access_pattern: u8) -> u32;
+    fn load_32(&mut self, address: u32, access_pattern: MemoryAccess) -> u32;
 
-    fn store_8(&mut self, address: u32, value: u8, access_pattern: u8);
+    fn store_8(&mut self, address: u32, value: u8, access_pattern: MemoryAccess);
 
-    fn store_16(&mut self, address: u32, value: u16, access_pattern: u8);
+    fn store_16(&mut self, address: u32, value: u16, access_pattern: MemoryAccess);
 
-    fn store_32(&mut self, address: u32, value: u32, access_pattern: u8);
+    fn store_32(&mut self, address: u32, value: u32, access_pattern: MemoryAccess);
 
     fn idle_cycle(&mut self);
+
+    /// Total cycles elapsed, reported in trace records
+    fn cycle_count(&self) -> u64 {
+        0
+    }
+
+    /// Asks code running several instructions per call, like compiled blocks, to return early
+    fn yield_requested(&self) -> bool {
+        false
+    }
+
+    /// Whether the access just made was aborted, clearing the signal. Nothing on the GBA aborts
+    fn take_abort(&mut self) -> bool {
+        false
+    }
 }
 
 pub trait SystemMemoryAccess {
@@ -95,12 +86,42 @@ pub trait SystemMemoryAccess {
     }
 }
 
+/// Memory access for debugging tools, never advances time or triggers register side effects.
+/// Unmapped addresses read as zero and ignore writes instead of panicking.
+pub trait DebugMemoryAccess {
+    fn debug_read_8(&self, address: u32) -> u8;
+
+    fn debug_read_16(&self, address: u32) -> u16 {
+        let byte1 = self.debug_read_8(address) as u16;
+        let byte2 = self.debug_read_8(address.wrapping_add(1)) as u16;
+        byte2 << 8 | byte1
+    }
+
+    fn debug_read_32(&self, address: u32) -> u32 {
+        let half_word1 = self.debug_read_16(address) as u32;
+        let half_word2 = self.debug_read_16(address.wrapping_add(2)) as u32;
+        half_word2 << 16 | half_word1
+    }
+
+    fn debug_write_8(&mut self, address: u32, value: u8);
+
+    fn debug_write_16(&mut self, address: u32, value: u16) {
+        self.debug_write_8(address, value as u8);
+        self.debug_write_8(address.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    fn debug_write_32(&mut self, address: u32, value: u32) {
+        self.debug_write_16(address, value as u16);
+        self.debug_write_16(address.wrapping_add(2), (value >> 16) as u16);
+    }
+}
+
 impl<I: MemoryInterface> Arm7tdmiCpu<I> {
-    pub fn load_signed_8(&mut self, address: u32, access_pattern: u8) -> u32 {
+    pub fn load_signed_8(&mut self, address: u32, access_pattern: MemoryAccess) -> u32 {
         self.load_8(address, access_pattern) as i8 as i32 as u32
     }
 
-    pub fn load_signed_16(&mut self, address: u32, access_pattern: u8) -> u32 {
+    pub fn load_signed_16(&mut self, address: u32, access_pattern: MemoryAccess) -> u32 {
         match address & 0x1 != 0 {
             true => {
                 let mut value = self.load_8(address, access_pattern);
@@ -111,7 +132,7 @@ impl<I: MemoryInterface> Arm7tdmiCpu<I> {
         }
     }
 
-    pub fn load_rotated_16(&mut self, address: u32, access_pattern: u8) -> u32 {
+    pub fn load_rotated_16(&mut self, address: u32, access_pattern: MemoryAccess) -> u32 {
         let value = self.load_16(address, access_pattern);
         match address & 0x1 != 0 {
             true => value >> 8 | value << 24,
@@ -119,9 +140,39 @@ impl<I: MemoryInterface> Arm7tdmiCpu<I> {
         }
     }
 
-    pub fn load_rotated_32(&mut self, address: u32, access_pattern: u8) -> u32 {
+    pub fn load_rotated_32(&mut self, address: u32, access_pattern: MemoryAccess) -> u32 {
         let value = self.load_32(address, access_pattern);
         let rotation = (address & 0x3) << 3;
         value >> rotation | value.wrapping_shl(32 - rotation)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::DebugMemoryAccess;
+
+    // 16 bytes mirrored across the address space
+    struct Mirrored([u8; 16]);
+
+    impl DebugMemoryAccess for Mirrored {
+        fn debug_read_8(&self, address: u32) -> u8 {
+            self.0[address as usize & 0xF]
+        }
+
+        fn debug_write_8(&mut self, address: u32, value: u8) {
+            self.0[address as usize & 0xF] = value;
+        }
+    }
+
+    #[test]
+    fn debug_access_is_little_endian_and_wraps() {
+        let mut memory = Mirrored(std::array::from_fn(|i| i as u8));
+        assert_eq!(memory.debug_read_16(0x1), 0x0201);
+        assert_eq!(memory.debug_read_32(0x4), 0x0706_0504);
+        // Accesses straddling the end of the address space continue from address 0
+        assert_eq!(memory.debug_read_32(0xFFFF_FFFE), 0x0100_0F0E);
+        memory.debug_write_32(0xFFFF_FFFF, 0xAABB_CCDD);
+        assert_eq!(memory.0[0xF], 0xDD);
+        assert_eq!(memory.0[..3], [0xCC, 0xBB, 0xAA]);
+    }
+}
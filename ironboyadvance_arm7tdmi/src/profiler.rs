@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use crate::{
+    call_stack::{CallStack, StackChange},
+    disassembler::SymbolTable,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct FunctionProfile {
+    pub function: u32,
+    pub calls: u64,
+    /// Cycles spent in the function and everything it called
+    pub inclusive: u64,
+    /// Cycles spent in the function itself
+    pub exclusive: u64,
+}
+
+/// Attributes cycles to the shadow call stack every time it changes
+#[derive(Debug, Default, Clone)]
+pub struct Profiler {
+    // Cycles spent with exactly this stack of functions, outermost first
+    stacks: HashMap<Vec<u32>, u64>,
+    calls: HashMap<u32, u64>,
+    last_cycle: u64,
+}
+
+/// Symbol at `function`, falling back to the exception vectors and then the address
+pub fn function_name(symbols: &SymbolTable, function: u32) -> String {
+    match (symbols.get(function), function) {
+        (Some(name), _) => name.to_string(),
+        (None, 0x00) => String::from("[reset]"),
+        (None, 0x04) => String::from("[undefined]"),
+        (None, 0x08) => String::from("[swi]"),
+        (None, 0x0C) => String::from("[prefetch_abort]"),
+        (None, 0x10) => String::from("[data_abort]"),
+        (None, 0x18) => String::from("[irq]"),
+        (None, 0x1C) => String::from("[fiq]"),
+        (None, _) => format!("sub_{function:08X}"),
+    }
+}
+
+impl Profiler {
+    /// Starts counting from `cycles`, the current cycle count of the bus
+    pub fn new(cycles: u64) -> Self {
+        Profiler {
+            last_cycle: cycles,
+            ..Profiler::default()
+        }
+    }
+
+    /// Called before `change` is applied to `stack`
+    pub(crate) fn record(&mut self, stack: &CallStack, change: StackChange, cycles: u64) {
+        self.sample(stack, cycles);
+        match change {
+            StackChange::Call(frame) => *self.calls.entry(frame.function).or_default() += 1,
+            StackChange::TailCall { function } => *self.calls.entry(function).or_default() += 1,
+            _ => {}
+        }
+    }
+
+    /// Attributes the cycles since the previous sample to the functions on `stack`
+    pub(crate) fn sample(&mut self, stack: &CallStack, cycles: u64) {
+        let elapsed = cycles.saturating_sub(self.last_cycle);
+        self.last_cycle = cycles;
+        if elapsed == 0 {
+            return;
+        }
+        let path = stack.frames().iter().map(|frame| frame.function).collect();
+        *self.stacks.entry(path).or_default() += elapsed;
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.stacks.values().sum()
+    }
+
+    /// Every function seen, the most expensive by exclusive cycles first
+    pub fn functions(&self) -> Vec<FunctionProfile> {
+        let mut functions: HashMap<u32, FunctionProfile> = HashMap::new();
+        for (path, cycles) in &self.stacks {
+            for (index, function) in path.iter().enumerate() {
+                // Recursive functions count once per stack
+                if path[..index].contains(function) {
+                    continue;
+                }
+                let profile = functions.entry(*function).or_insert(FunctionProfile {
+                    function: *function,
+                    calls: self.calls.get(function).copied().unwrap_or(0),
+                    inclusive: 0,
+                    exclusive: 0,
+                });
+                profile.inclusive += cycles;
+            }
+            if let Some(function) = path.last() {
+                functions.get_mut(function).unwrap().exclusive += cycles;
+            }
+        }
+        let mut functions = functions.into_values().collect::<Vec<_>>();
+        functions.sort_by(|a, b| b.exclusive.cmp(&a.exclusive).then(a.function.cmp(&b.function)));
+        functions
+    }
+
+    /// One `outer;inner cycles` line per distinct stack, the format flamegraph.pl and inferno read
+    pub fn folded(&self, symbols: &SymbolTable) -> String {
+        let mut lines = self
+            .stacks
+            .iter()
+            .map(|(path, cycles)| {
+                let names = match path.is_empty() {
+                    true => String::from("[unknown]"),
+                    false => path
+                        .iter()
+                        .map(|function| function_name(symbols, *function))
+                        .collect::<Vec<String>>()
+                        .join(";"),
+                };
+                format!("{names} {cycles}")
+            })
+            .collect::<Vec<String>>();
+        lines.sort();
+        lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folded_stacks_and_totals() {
+        let mut stack = CallStack::new();
+        let mut profiler = Profiler::new(0);
+        let mut branch = |stack: &mut CallStack, address, target, link, cycles| {
+            let change = stack.classify(address, 4, target, link);
+            profiler.record(stack, change, cycles);
+            stack.apply(change);
+        };
+        branch(&mut stack, 0x0800_0000, 0x0800_0100, 0x0800_0004, 10); // bl main
+        branch(&mut stack, 0x0800_0104, 0x0800_0200, 0x0800_0108, 30); // bl _call_via_r3
+        branch(&mut stack, 0x0800_0200, 0x0800_0300, 0x0800_0108, 30); // bx r3
+        branch(&mut stack, 0x0800_0310, 0x0800_0108, 0x0800_0108, 100); // bx lr
+        assert_eq!(stack.depth(), 1);
+        profiler.sample(&stack, 120);
+
+        let mut symbols = SymbolTable::new();
+        symbols.insert(0x0800_0100, "main");
+        assert_eq!(profiler.folded(&symbols), "[unknown] 10\nmain 40\nmain;sub_08000300 70\n");
+        let functions = profiler.functions();
+        assert_eq!(
+            functions[0],
+            FunctionProfile {
+                function: 0x0800_0300,
+                calls: 1,
+                inclusive: 70,
+                exclusive: 70,
+            }
+        );
+        assert_eq!((functions[1].inclusive, functions[1].exclusive), (110, 40));
+        assert_eq!(profiler.total_cycles(), 120);
+    }
+}
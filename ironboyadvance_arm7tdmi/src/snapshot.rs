@@ -0,0 +1,35 @@
+use crate::{Exception, memory::MemoryAccess};
+
+/// Everything that decides what the core does next, taken with `Arm7tdmiCpu::snapshot` and put back with
+/// `Arm7tdmiCpu::restore`. Program status registers are kept as their raw bits.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuSnapshot {
+    pub general_registers: [u32; 16],
+    pub banked_registers_fiq: [u32; 7],
+    pub banked_registers_svc: [u32; 2],
+    pub banked_registers_abt: [u32; 2],
+    pub banked_registers_irq: [u32; 2],
+    pub banked_registers_und: [u32; 2],
+    pub spsrs: [u32; 5],
+    pub cpsr: u32,
+    pub pipeline: [u32; 2],
+    /// Fetches in the pipeline that aborted
+    pub pipeline_aborts: [bool; 2],
+    /// Access pattern of the next instruction fetch
+    pub next_memory_access: MemoryAccess,
+    pub irq_line: bool,
+    pub fiq_line: bool,
+}
+
+/// What one `Arm7tdmiCpu::step` did
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StepInfo {
+    /// The instruction executed, or the one an exception was entered before
+    pub address: u32,
+    /// Memory accesses and internal cycles, the wait states the bus adds are not included
+    pub cycles: u32,
+    /// The pipeline was refilled, by a taken branch, a write to the pc or an exception
+    pub branched: bool,
+    pub exception: Option<Exception>,
+}
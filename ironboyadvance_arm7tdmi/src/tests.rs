@@ -1,13 +1,22 @@
-//TODO: implement step tests
-
 #[cfg(test)]
 mod tests {
     use serde::Deserialize;
     use serde_repr::Deserialize_repr;
-    use std::fs;
-
-    use crate::memory::{MemoryAccess, MemoryInterface, SystemMemoryAccess, decompose_access_pattern};
-    use crate::{cpu::Arm7tdmiCpu, psr::ProgramStatusRegister};
+    use std::{collections::BTreeMap, fs};
+
+    use crate::memory::{MemoryAccess, MemoryInterface};
+    use crate::{
+        alu::multiply_carry,
+        bits::Bits,
+        cpu::{Arm7tdmiCpu, PC},
+        psr::ProgramStatusRegister,
+    };
+
+    // Limits the run to the files whose name contains it, `SINGLE_STEP_FILTER=thumb_ cargo test single_step`
+    const FILTER_VARIABLE: &str = "SINGLE_STEP_FILTER";
+    // Mismatches printed for each failing file
+    const REPORTED_FAILURES: usize = 3;
+    const TESTS_DIRECTORY: &str = "../external/arm7tdmi/v1";
 
     #[derive(Debug, Deserialize_repr, Clone, Copy, PartialEq, Eq)]
     #[repr(u8)]
@@ -17,7 +26,7 @@ mod tests {
         Write,
     }
 
-    #[derive(Debug, Deserialize_repr, Clone, Copy)]
+    #[derive(Debug, Deserialize_repr, Clone, Copy, PartialEq, Eq)]
     #[repr(u8)]
     enum Size {
         Byte = 1,
@@ -25,18 +34,17 @@ mod tests {
         Word = 4,
     }
 
-    #[derive(Debug)]
-    #[allow(unused)]
-    enum Access {
-        Nonsequential = 0b0,
-        Sequential = 0b1,
-        Code = 0b10,
-        Dma = 0b100,
-        Lock = 0b1000,
+    impl Size {
+        fn mask(self) -> u32 {
+            match self {
+                Size::Byte => 0xFF,
+                Size::HalfWord => 0xFFFF,
+                Size::Word => 0xFFFF_FFFF,
+            }
+        }
     }
 
-    #[derive(Debug, Deserialize, Clone, Copy)]
-    #[allow(unused)]
+    #[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
     pub struct Transaction {
         kind: TransactionKind,
         size: Size,
@@ -47,7 +55,6 @@ mod tests {
     }
 
     #[derive(Debug, Deserialize)]
-    #[allow(non_snake_case, unused)]
     struct State {
         #[serde(rename = "R")]
         r: [u32; 16],
@@ -69,7 +76,6 @@ mod tests {
     }
 
     #[derive(Debug, Deserialize)]
-    #[allow(unused)]
     struct Test {
         #[serde(rename = "initial")]
         initial_state: State,
@@ -77,238 +83,294 @@ mod tests {
         final_state: State,
         transactions: Vec<Transaction>,
         opcode: u32,
-        base_addr: [u32; 1],
     }
 
-    #[allow(unused)]
+    /// Answers reads from the transactions of a test and checks every access against the next one
+    #[derive(Default)]
     pub struct TestBus {
-        data: Vec<u8>,
-        base_address: u32,
-        opcode: u32,
         transactions: Vec<Transaction>,
+        next: usize,
+        // Numbered like the transactions, idle cycles count too
+        cycle: u32,
+        errors: Vec<String>,
     }
 
-    impl MemoryInterface for TestBus {
-        fn load_8(&mut self, _address: u32, access_pattern: u8) -> u32 {
-            let access = decompose_access_pattern(access_pattern);
-            let is_instruction_read = access.contains(&MemoryAccess::Instruction);
-            let mut transaction_index = None;
-            for (i, transaction) in self.transactions.iter().enumerate() {
-                if is_instruction_read && transaction.kind == TransactionKind::InstructionRead {
-                    transaction_index = Some(i);
-                    break;
-                } else if !is_instruction_read && transaction.kind == TransactionKind::GeneralRead {
-                    transaction_index = Some(i);
-                    break;
-                }
-            }
-
-            match transaction_index {
-                Some(index) => {
-                    let transaction = self.transactions.remove(index);
-                    transaction.data
-                }
-                None => panic!("No transaction found"),
+    impl TestBus {
+        pub fn new(transactions: Vec<Transaction>) -> Self {
+            TestBus {
+                cycle: transactions.first().map_or(0, |transaction| transaction.cycle as u32),
+                transactions,
+                next: 0,
+                errors: Vec::new(),
             }
         }
 
-        fn load_16(&mut self, _address: u32, access_pattern: u8) -> u32 {
-            let access = decompose_access_pattern(access_pattern);
-            let is_instruction_read = access.contains(&MemoryAccess::Instruction);
-            let mut transaction_index = None;
-            for (i, transaction) in self.transactions.iter().enumerate() {
-                if is_instruction_read && transaction.kind == TransactionKind::InstructionRead {
-                    transaction_index = Some(i);
-                    break;
-                } else if !is_instruction_read && transaction.kind == TransactionKind::GeneralRead {
-                    transaction_index = Some(i);
-                    break;
-                }
-            }
-
-            match transaction_index {
-                Some(index) => {
-                    let transaction = self.transactions.remove(index);
-                    transaction.data
-                }
-                None => panic!("No transaction found"),
+        // Only N/S and code are compared, the suites do not model dma and lock
+        fn transfer(
+            &mut self,
+            kind: TransactionKind,
+            size: Size,
+            address: u32,
+            access_pattern: MemoryAccess,
+            written: Option<u32>,
+        ) -> u32 {
+            let access = (access_pattern & (MemoryAccess::SEQUENTIAL | MemoryAccess::INSTRUCTION)).bits();
+            let cycle = self.cycle;
+            self.cycle += 1;
+            let Some(expected) = self.transactions.get(self.next).copied() else {
+                self.errors.push(format!("unexpected {kind:?} {size:?} at {address:08X}"));
+                return 0;
+            };
+            self.next += 1;
+            let actual = Transaction {
+                kind,
+                size,
+                addr: address,
+                data: written.unwrap_or(expected.data) & size.mask(),
+                cycle: cycle as u8,
+                access,
+            };
+            let expected = Transaction {
+                data: expected.data & expected.size.mask(),
+                ..expected
+            };
+            if actual != expected {
+                self.errors.push(format!(
+                    "transaction {}: expected {expected:X?}, got {actual:X?}",
+                    self.next - 1
+                ));
             }
+            expected.data
         }
 
-        fn load_32(&mut self, _address: u32, access_pattern: u8) -> u32 {
-            let access = decompose_access_pattern(access_pattern);
-            let is_instruction_read = access.contains(&MemoryAccess::Instruction);
-            let mut transaction_index = None;
-            for (i, transaction) in self.transactions.iter().enumerate() {
-                if is_instruction_read && transaction.kind == TransactionKind::InstructionRead {
-                    transaction_index = Some(i);
-                    break;
-                } else if !is_instruction_read && transaction.kind == TransactionKind::GeneralRead {
-                    transaction_index = Some(i);
-                    break;
-                }
-            }
-
-            match transaction_index {
-                Some(index) => {
-                    let transaction = self.transactions.remove(index);
-                    transaction.data
-                }
-                None => panic!("No transaction found"),
+        fn finish(&mut self) -> Vec<String> {
+            if self.next < self.transactions.len() {
+                let missing = &self.transactions[self.next..];
+                self.errors.push(format!("missing transactions {missing:X?}"));
             }
+            std::mem::take(&mut self.errors)
         }
+    }
 
-        fn store_8(&mut self, _address: u32, value: u8, _access_pattern: u8) {
-            let mut transaction_index = None;
-            for (i, transaction) in self.transactions.iter().enumerate() {
-                if transaction.kind == TransactionKind::Write {
-                    transaction_index = Some(i);
-                    break;
-                }
-            }
-
-            match transaction_index {
-                Some(index) => {
-                    let transaction = self.transactions.remove(index);
-                    assert_eq!(value, transaction.data as u8);
-                }
-                None => panic!("No transaction found"),
-            }
+    impl MemoryInterface for TestBus {
+        fn load_8(&mut self, address: u32, access_pattern: MemoryAccess) -> u32 {
+            self.transfer(read_kind(access_pattern), Size::Byte, address, access_pattern, None)
         }
 
-        fn store_16(&mut self, _address: u32, value: u16, _access_pattern: u8) {
-            let mut transaction_index = None;
-            for (i, transaction) in self.transactions.iter().enumerate() {
-                if transaction.kind == TransactionKind::Write {
-                    transaction_index = Some(i);
-                    break;
-                }
-            }
-
-            match transaction_index {
-                Some(index) => {
-                    let transaction = self.transactions.remove(index);
-                    assert_eq!(value, transaction.data as u16);
-                }
-                None => panic!("No transaction found"),
-            }
+        fn load_16(&mut self, address: u32, access_pattern: MemoryAccess) -> u32 {
+            self.transfer(read_kind(access_pattern), Size::HalfWord, address, access_pattern, None)
         }
 
-        fn store_32(&mut self, _address: u32, value: u32, _access_pattern: u8) {
-            let mut transaction_index = None;
-            for (i, transaction) in self.transactions.iter().enumerate() {
-                if transaction.kind == TransactionKind::Write {
-                    transaction_index = Some(i);
-                    break;
-                }
-            }
+        fn load_32(&mut self, address: u32, access_pattern: MemoryAccess) -> u32 {
+            self.transfer(read_kind(access_pattern), Size::Word, address, access_pattern, None)
+        }
 
-            match transaction_index {
-                Some(index) => {
-                    let transaction = self.transactions.remove(index);
-                    assert_eq!(value, transaction.data);
-                }
-                None => panic!("No transaction found"),
-            }
+        fn store_8(&mut self, address: u32, value: u8, access_pattern: MemoryAccess) {
+            self.transfer(
+                TransactionKind::Write,
+                Size::Byte,
+                address,
+                access_pattern,
+                Some(value as u32),
+            );
         }
 
-        fn idle_cycle(&mut self) {}
-    }
+        fn store_16(&mut self, address: u32, value: u16, access_pattern: MemoryAccess) {
+            self.transfer(
+                TransactionKind::Write,
+                Size::HalfWord,
+                address,
+                access_pattern,
+                Some(value as u32),
+            );
+        }
 
-    impl SystemMemoryAccess for TestBus {
-        fn read_8(&self, address: u32) -> u8 {
-            self.data[address as usize]
+        fn store_32(&mut self, address: u32, value: u32, access_pattern: MemoryAccess) {
+            self.transfer(TransactionKind::Write, Size::Word, address, access_pattern, Some(value));
         }
 
-        fn write_8(&mut self, address: u32, value: u8) {
-            self.data[address as usize] = value
+        fn idle_cycle(&mut self) {
+            self.cycle += 1;
         }
     }
 
-    #[allow(dead_code)]
-    impl TestBus {
-        pub fn new(base_address: u32, opcode: u32, transactions: Vec<Transaction>) -> Self {
-            TestBus {
-                data: vec![0; 0xFFFFFFFF],
-                base_address,
-                opcode,
-                transactions,
-            }
+    fn read_kind(access_pattern: MemoryAccess) -> TransactionKind {
+        match access_pattern.contains(MemoryAccess::INSTRUCTION) {
+            true => TransactionKind::InstructionRead,
+            false => TransactionKind::GeneralRead,
         }
     }
 
-    impl Default for TestBus {
-        fn default() -> Self {
-            Self {
-                data: Default::default(),
-                base_address: Default::default(),
-                opcode: Default::default(),
-                transactions: Default::default(),
+    fn load(cpu: &mut Arm7tdmiCpu<TestBus>, initial_state: &State) {
+        cpu.set_general_registers(initial_state.r);
+        cpu.set_banked_registers_fiq(initial_state.r_fiq);
+        cpu.set_banked_registers_svc(initial_state.r_svc);
+        cpu.set_banked_registers_abt(initial_state.r_abt);
+        cpu.set_banked_registers_irq(initial_state.r_irq);
+        cpu.set_banked_registers_und(initial_state.r_und);
+        cpu.set_cpsr(ProgramStatusRegister::from_bits(initial_state.cpsr));
+        cpu.set_spsrs(initial_state.spsr.map(ProgramStatusRegister::from_bits));
+        cpu.set_pipeline(initial_state.pipeline);
+        // The pipeline was filled by straight line code
+        cpu.set_next_memory_access(MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL);
+    }
+
+    // Executes one test with `step`, returning what did not match
+    fn run(cpu: &mut Arm7tdmiCpu<TestBus>, step: fn(&mut Arm7tdmiCpu<TestBus>), test: Test) -> Vec<String> {
+        let final_state = test.final_state;
+        cpu.set_bus(TestBus::new(test.transactions));
+        load(cpu, &test.initial_state);
+        step(cpu);
+
+        let mut errors = cpu.bus().finish();
+        let mut compare = |name: &str, actual: &[u32], expected: &[u32]| {
+            if actual != expected {
+                errors.push(format!("{name}: expected {expected:08X?}, got {actual:08X?}"));
             }
-        }
+        };
+        compare("r", &cpu.general_registers(), &final_state.r);
+        compare("r_fiq", &cpu.banked_registers_fiq(), &final_state.r_fiq);
+        compare("r_svc", &cpu.banked_registers_svc(), &final_state.r_svc);
+        compare("r_abt", &cpu.banked_registers_abt(), &final_state.r_abt);
+        compare("r_irq", &cpu.banked_registers_irq(), &final_state.r_irq);
+        compare("r_und", &cpu.banked_registers_und(), &final_state.r_und);
+        compare(
+            "spsr",
+            &cpu.spsrs().map(|spsr| spsr.into_bits()),
+            &final_state
+                .spsr
+                .map(|spsr| ProgramStatusRegister::from_bits(spsr).into_bits()),
+        );
+
+        compare(
+            "cpsr",
+            &[cpu.cpsr().into_bits()],
+            &[ProgramStatusRegister::from_bits(final_state.cpsr).into_bits()],
+        );
+        compare("pipeline", &cpu.pipeline(), &final_state.pipeline);
+        errors
     }
 
     #[test]
+    #[ignore = "requires the external/arm7tdmi submodule"]
     fn single_step_tests() {
-        // Skip Coprocessor instructions
-        let skip = ["arm_cdp.json", "arm_mcr_mrc.json", "arm_stc_ldc.json"];
         let mut cpu = Arm7tdmiCpu::new(TestBus::default(), true);
-        let directory = fs::read_dir("../external/arm7tdmi/v1").expect("Unable to access directory");
-        for file in directory {
+        run_files(&mut cpu, Arm7tdmiCpu::cycle);
+    }
+
+    // Every instruction compiled on its own, the bus of the tests cannot be read ahead to decode blocks
+    #[cfg(feature = "jit")]
+    #[test]
+    #[ignore = "requires the external/arm7tdmi submodule"]
+    fn single_step_tests_jit() {
+        let mut cpu = Arm7tdmiCpu::new(TestBus::default(), true);
+        cpu.set_jit(true);
+        assert!(cpu.is_jit_enabled(), "cranelift does not support this host");
+        run_files(&mut cpu, Arm7tdmiCpu::cycle_compiled);
+    }
+
+    // The tests of the files whose name contains `filter`, by file name
+    fn read_files(filter: &str) -> impl Iterator<Item = (String, Vec<Test>)> {
+        let directory = fs::read_dir(TESTS_DIRECTORY).expect("Unable to access directory");
+        directory.filter_map(move |file| {
             let file = file.unwrap().path();
             if file.extension().unwrap().to_str().unwrap() != "json" {
-                continue;
+                return None;
             }
-
-            if skip.contains(&file.file_name().unwrap().to_str().unwrap()) {
-                continue;
+            let name = file.file_stem().unwrap().to_string_lossy().into_owned();
+            if !name.contains(filter) {
+                return None;
             }
+            let test_json = fs::read_to_string(&file).expect("unable to read file");
+            Some((name, serde_json::from_str(&test_json).unwrap()))
+        })
+    }
 
-            let test_json = fs::read_to_string(file).expect("unable to read file");
-            let tests: Vec<Test> = serde_json::from_str(&test_json).unwrap();
-            for test in tests {
-                let test_bus = TestBus::new(test.base_addr[0], test.opcode, test.transactions.clone());
-                cpu.set_bus(test_bus);
-
-                let initial_state = test.initial_state;
-                let final_state = test.final_state;
-
-                cpu.set_general_registers(initial_state.r);
-                cpu.set_banked_registers_fiq(initial_state.r_fiq);
-                cpu.set_banked_registers_svc(initial_state.r_svc);
-                cpu.set_banked_registers_abt(initial_state.r_abt);
-                cpu.set_banked_registers_irq(initial_state.r_irq);
-                cpu.set_banked_registers_und(initial_state.r_und);
-                cpu.set_cpsr(ProgramStatusRegister::from_bits(initial_state.cpsr));
-                cpu.set_spsrs(initial_state.spsr.map(|x| ProgramStatusRegister::from_bits(x)));
-                cpu.set_pipeline(initial_state.pipeline);
-
-                cpu.cycle();
+    fn run_files(cpu: &mut Arm7tdmiCpu<TestBus>, step: fn(&mut Arm7tdmiCpu<TestBus>)) {
+        let filter = std::env::var(FILTER_VARIABLE).unwrap_or_default();
+        // Passed and failed tests of each file
+        let mut results: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+        for (name, tests) in read_files(&filter) {
+            let (mut passed, mut failed) = (0, 0);
+            for (index, test) in tests.into_iter().enumerate() {
+                let opcode = test.opcode;
+                let errors = run(cpu, step, test);
+                if errors.is_empty() {
+                    passed += 1;
+                    continue;
+                }
+                if failed < REPORTED_FAILURES {
+                    println!("{name} #{index} ({opcode:08X}):\n    {}", errors.join("\n    "));
+                }
+                failed += 1;
+            }
+            results.insert(name, (passed, failed));
+        }
 
-                assert_eq!(cpu.general_registers(), final_state.r);
-                assert_eq!(cpu.banked_registers_fiq(), final_state.r_fiq);
-                assert_eq!(cpu.banked_registers_svc(), final_state.r_svc);
-                assert_eq!(cpu.banked_registers_abt(), final_state.r_abt);
-                assert_eq!(cpu.banked_registers_irq(), final_state.r_irq);
-                assert_eq!(cpu.banked_registers_und(), final_state.r_und);
-                assert_eq!(
-                    cpu.spsrs().map(|x| x.into_bits()),
-                    final_state.spsr.map(|x| ProgramStatusRegister::from_bits(x).into_bits())
-                );
+        for (name, (passed, failed)) in &results {
+            println!("{name:<32} {passed:>6} passed {failed:>6} failed");
+        }
+        let failed = results.values().map(|(_, failed)| failed).sum::<usize>();
+        assert!(!results.is_empty(), "no test files matched");
+        assert_eq!(failed, 0, "{failed} single step tests failed");
+    }
 
-                let expected = ProgramStatusRegister::from_bits(final_state.cpsr);
-                let mut actual = cpu.cpsr();
-                // the booth multiplication sets the carry. data sheet says its set to a meaningless value. Will ignore result
-                if ["MUL", "MLA", "MULL", "MLAL"]
-                    .iter()
-                    .any(|s| cpu.dissassembled_instruction.contains(s))
-                {
-                    actual.set_carry(expected.carry());
+    // The carry left by every flag setting multiply of the `arm_mul*` files, checked against `multiply_carry`
+    // on its own so a wrong carry is told apart from the rest of the instruction
+    #[test]
+    #[ignore = "requires the external/arm7tdmi submodule"]
+    fn multiply_carry_vectors() {
+        let mut cpu = Arm7tdmiCpu::new(TestBus::default(), true);
+        // Passed and failed vectors of each instruction
+        let mut results: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+        for (name, tests) in read_files("arm_mul") {
+            for (index, test) in tests.into_iter().enumerate() {
+                let opcode = test.opcode;
+                load(&mut cpu, &test.initial_state);
+                let registers = [0, 8, 12, 16].map(|shift| (opcode >> shift) as usize & 0xF);
+                let sets_flags = opcode.bit(20);
+                // Multiplies reading the pc are unpredictable
+                if !sets_flags || !cpu.is_condition_met(opcode.bits(28..=31).into()) || registers.contains(&PC) {
+                    continue;
+                }
+                let [rm, rs, rn, rd] = registers.map(|register| cpu.register(register));
+                let (long, accumulate) = (opcode.bit(23), opcode.bit(21));
+                let signed = !long || opcode.bit(22);
+                let accumulator = match (accumulate, long) {
+                    (false, _) => 0,
+                    (true, false) => rn as u64,
+                    (true, true) => (rd as u64) << 32 | rn as u64,
+                };
+                let instruction = match (long, signed, accumulate) {
+                    (false, _, false) => "MUL",
+                    (false, _, true) => "MLA",
+                    (true, false, false) => "UMULL",
+                    (true, false, true) => "UMLAL",
+                    (true, true, false) => "SMULL",
+                    (true, true, true) => "SMLAL",
+                };
+                let expected = ProgramStatusRegister::from_bits(test.final_state.cpsr).carry();
+                let actual = multiply_carry(rm, rs, accumulator, long, signed);
+                let (passed, failed) = results.entry(instruction).or_default();
+                match actual == expected {
+                    true => *passed += 1,
+                    false => {
+                        if *failed < REPORTED_FAILURES {
+                            println!("{name} #{index} ({opcode:08X}): expected carry {expected}, got {actual}");
+                        }
+                        *failed += 1;
+                    }
                 }
-                assert_eq!(expected.into_bits(), actual.into_bits());
-
-                assert_eq!(cpu.pipeline(), final_state.pipeline);
             }
         }
+
+        for (instruction, (passed, failed)) in &results {
+            println!("{instruction:<8} {passed:>6} passed {failed:>6} failed");
+        }
+        for instruction in ["MUL", "MLA", "UMULL", "SMLAL"] {
+            assert!(results.contains_key(instruction), "no {instruction} vectors found");
+        }
+        let failed = results.values().map(|(_, failed)| failed).sum::<usize>();
+        assert_eq!(failed, 0, "{failed} multiply carries differ");
     }
 }
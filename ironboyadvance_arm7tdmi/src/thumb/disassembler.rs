@@ -1,45 +1,74 @@
-use crate::{AluOperationsOpcode, HiRegOpsBxOpcode, LoRegister, MovCmpAddSubImmediateOpcode};
+use crate::{
+    AluOperationsOpcode, HiRegOpsBxOpcode, LoRegister, MovCmpAddSubImmediateOpcode, barrel_shifter::ShiftType,
+    disassembler::DisassemblyOptions,
+};
 
 use super::ThumbInstruction;
 
-pub fn disassemble_move_shifted_register(instruction: &ThumbInstruction) -> String {
-    let shift_type = instruction.opcode();
+fn memory(options: &DisassemblyOptions, base: impl ToString, offset: String) -> String {
+    match options.is_gnu() {
+        true => format!("[{}, {offset}]", base.to_string()),
+        false => format!("[{},{offset}]", base.to_string()),
+    }
+}
+
+fn register_list(options: &DisassemblyOptions, instruction: &ThumbInstruction, extra: Option<&str>) -> String {
+    let mut registers = instruction
+        .register_list()
+        .iter()
+        .map(|register| LoRegister::from(register as u16).to_string())
+        .collect::<Vec<String>>();
+    registers.extend(extra.map(String::from));
+    options.register_list(&registers, '{', '}')
+}
+
+fn branch_target(instruction: &ThumbInstruction, offset: u32) -> u32 {
+    instruction.executed_pc.wrapping_add(4).wrapping_add(offset)
+}
+
+pub fn disassemble_move_shifted_register(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
+    let shift_type = ShiftType::from(instruction.opcode());
     let offset5 = instruction.offset();
-    let rs = instruction.rs();
-    let rd = instruction.rd();
-    format!("{} {},{},#{}", shift_type, rd, rs, offset5)
+    let rs = instruction.rs().to_string();
+    let rd = instruction.rd().to_string();
+    options.instruction(&shift_type.to_string(), &[rd, rs, format!("#{offset5}")])
 }
 
-pub fn disassemble_add_subtract(instruction: &ThumbInstruction) -> String {
-    let rs = instruction.rs();
-    let rd = instruction.rd();
-    let is_immediate = instruction.is_immediate();
-    let operand = match is_immediate {
+pub fn disassemble_add_subtract(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
+    let rs = instruction.rs().to_string();
+    let rd = instruction.rd().to_string();
+    let operand = match instruction.is_immediate() {
         true => format!("#{}", instruction.offset()),
-        false => format!("{}", instruction.rn()),
+        false => instruction.rn().to_string(),
     };
     let opcode = match instruction.opcode() != 0 {
         true => "SUB",
         false => "ADD",
     };
-    format!("{} {},{},{}", opcode, rd, rs, operand)
+    options.instruction(opcode, &[rd, rs, operand])
 }
 
-pub fn disassemble_move_compare_add_subtract_immediate(instruction: &ThumbInstruction) -> String {
-    let rd = instruction.rd();
+pub fn disassemble_move_compare_add_subtract_immediate(
+    instruction: &ThumbInstruction,
+    options: &DisassemblyOptions,
+) -> String {
+    let rd = instruction.rd().to_string();
     let offset = instruction.offset();
     let opcode = MovCmpAddSubImmediateOpcode::from(instruction.opcode());
-    format!("{} {},#{}", opcode, rd, offset)
+    options.instruction(&opcode.to_string(), &[rd, format!("#{offset}")])
 }
 
-pub fn disassemble_alu_operations(instruction: &ThumbInstruction) -> String {
-    let rd = instruction.rd();
-    let rs = instruction.rs();
+pub fn disassemble_alu_operations(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
+    let rd = instruction.rd().to_string();
+    let rs = instruction.rs().to_string();
     let opcode = AluOperationsOpcode::from(instruction.opcode());
-    format!("{} {},{}", opcode, rd, rs)
+    options.instruction(&opcode.to_string(), &[rd, rs])
 }
 
-pub fn disassemble_hi_register_operations_branch_exchange(instruction: &ThumbInstruction) -> String {
+pub fn disassemble_hi_register_operations_branch_exchange(
+    instruction: &ThumbInstruction,
+    options: &DisassemblyOptions,
+) -> String {
     let destination = match instruction.h1() {
         true => instruction.hd().to_string(),
         false => instruction.rd().to_string(),
@@ -51,137 +80,145 @@ pub fn disassemble_hi_register_operations_branch_exchange(instruction: &ThumbIns
     };
 
     let opcode = HiRegOpsBxOpcode::from(instruction.opcode());
-    format!("{} {},{}", opcode, destination, source)
+    match opcode {
+        HiRegOpsBxOpcode::BX => options.instruction(&opcode.to_string(), &[source]),
+        _ => options.instruction(&opcode.to_string(), &[destination, source]),
+    }
 }
 
-pub fn disassemble_pc_relative_load(instruction: &ThumbInstruction) -> String {
-    let rd = instruction.rd();
-    let offset = instruction.offset();
-    format!("LDR {},[PC, #{}]", rd, offset)
+pub fn disassemble_pc_relative_load(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
+    let rd = instruction.rd().to_string();
+    let offset = instruction.offset() as u32 * 4;
+    // The pc is word aligned before the offset is added
+    let address = (instruction.executed_pc.wrapping_add(4) & !0x3).wrapping_add(offset);
+    match options.is_gnu() {
+        true => format!(
+            "{}\t; {}",
+            options.instruction("LDR", &[rd, memory(options, "pc", format!("#{offset}"))]),
+            options.address(address)
+        ),
+        false => options.instruction("LDR", &[rd, format!("[{}]", options.address(address))]),
+    }
 }
 
-pub fn disassemble_load_store_register_offset(instruction: &ThumbInstruction) -> String {
+pub fn disassemble_load_store_register_offset(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
     let byte = if instruction.byte() { "B" } else { "" };
-    let ro = instruction.ro();
+    let ro = instruction.ro().to_string();
     let rb = instruction.rb();
-    let rd = instruction.rd();
-
-    match instruction.load() {
-        true => format!("LDR{} {}, [{},{}]", byte, rd, rb, ro),
-        false => format!("STR{} {}, [{},{}]", byte, rd, rb, ro),
-    }
+    let rd = instruction.rd().to_string();
+    let mnemonic = if instruction.load() { "LDR" } else { "STR" };
+    options.instruction(&format!("{mnemonic}{byte}"), &[rd, memory(options, rb, ro)])
 }
 
-pub fn disassemble_load_store_sign_extended_byte_halfword(instruction: &ThumbInstruction) -> String {
-    let ro = instruction.ro();
+pub fn disassemble_load_store_sign_extended_byte_halfword(
+    instruction: &ThumbInstruction,
+    options: &DisassemblyOptions,
+) -> String {
+    let ro = instruction.ro().to_string();
     let rb = instruction.rb();
-    let rd = instruction.rd();
-    let signed = instruction.signed();
-    let halfword = instruction.halfword();
-    match (signed, halfword) {
-        (false, false) => format!("STRH {}, [{},{}]", rd, rb, ro),
-        (false, true) => format!("LDRH {}, [{},{}]", rd, rb, ro),
-        (true, false) => format!("LDSB {}, [{},{}]", rd, rb, ro),
-        (true, true) => format!("LDSH {}, [{},{}]", rd, rb, ro),
-    }
+    let rd = instruction.rd().to_string();
+    let mnemonic = match (instruction.signed(), instruction.halfword(), options.is_gnu()) {
+        (false, false, _) => "STRH",
+        (false, true, _) => "LDRH",
+        (true, false, false) => "LDSB",
+        (true, true, false) => "LDSH",
+        (true, false, true) => "LDRSB",
+        (true, true, true) => "LDRSH",
+    };
+    options.instruction(mnemonic, &[rd, memory(options, rb, ro)])
 }
 
-pub fn disassemble_load_store_immediate_offset(instruction: &ThumbInstruction) -> String {
-    let byte = if instruction.byte() { "B" } else { "" };
-    let offset = instruction.offset();
+pub fn disassemble_load_store_immediate_offset(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
+    let byte = instruction.byte();
+    // Word offsets are stored divided by four
+    let offset = match byte {
+        true => instruction.offset(),
+        false => instruction.offset() * 4,
+    };
     let rb = instruction.rb();
-    let rd = instruction.rd();
-
-    match instruction.load() {
-        true => format!("LDR{} {}, [{},#{}]", byte, rd, rb, offset),
-        false => format!("STR{} {}, [{},#{}]", byte, rd, rb, offset),
-    }
+    let rd = instruction.rd().to_string();
+    let mnemonic = if instruction.load() { "LDR" } else { "STR" };
+    let byte = if byte { "B" } else { "" };
+    options.instruction(&format!("{mnemonic}{byte}"), &[rd, memory(options, rb, format!("#{offset}"))])
 }
 
-pub fn disassemble_load_store_halfword(instruction: &ThumbInstruction) -> String {
-    let offset = instruction.offset();
+pub fn disassemble_load_store_halfword(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
+    let offset = instruction.offset() * 2;
     let rb = instruction.rb();
-    let rd = instruction.rd();
-    match instruction.load() {
-        true => format!("LDRH {}, [{},#{}]", rd, rb, offset),
-        false => format!("STRH {}, [{},#{}]", rd, rb, offset),
-    }
+    let rd = instruction.rd().to_string();
+    let mnemonic = if instruction.load() { "LDRH" } else { "STRH" };
+    options.instruction(mnemonic, &[rd, memory(options, rb, format!("#{offset}"))])
 }
 
-pub fn disassemble_sp_relative_load_store(instruction: &ThumbInstruction) -> String {
-    let offset = instruction.offset();
-    let rd = instruction.rd();
-    match instruction.load() {
-        true => format!("LDR {}, [sp,#{}]", rd, offset),
-        false => format!("STRH {}, [sp,#{}]", rd, offset),
-    }
+pub fn disassemble_sp_relative_load_store(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
+    let offset = instruction.offset() as u32 * 4;
+    let rd = instruction.rd().to_string();
+    let mnemonic = if instruction.load() { "LDR" } else { "STR" };
+    options.instruction(mnemonic, &[rd, memory(options, "sp", format!("#{offset}"))])
 }
 
-pub fn disassemble_load_address(instruction: &ThumbInstruction) -> String {
-    let offset = instruction.offset();
-    let rd = instruction.rd();
+pub fn disassemble_load_address(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
+    let offset = instruction.offset() as u32 * 4;
+    let rd = instruction.rd().to_string();
     let sp = if instruction.sp() { "sp" } else { "pc" };
-    format!("ADD {},{},{}", rd, sp, offset)
+    options.instruction("ADD", &[rd, sp.to_string(), format!("#{offset}")])
 }
 
-pub fn disassemble_add_offset_to_sp(instruction: &ThumbInstruction) -> String {
-    let offset = instruction.offset();
+pub fn disassemble_add_offset_to_sp(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
+    let offset = instruction.offset() as u32 * 4;
     let signed = if instruction.signed() { "-" } else { "" };
-    format!("ADD sp, {}{}", signed, offset)
+    options.instruction("ADD", &[String::from("sp"), format!("#{signed}{offset}")])
 }
 
-pub fn disassemble_push_pop_registers(instruction: &ThumbInstruction) -> String {
+pub fn disassemble_push_pop_registers(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
     let load = instruction.load();
-    let store_lr_load_pc = instruction.store_lr_load_pc();
-    let register_list = instruction
-        .register_list()
-        .iter()
-        .map(|register| LoRegister::from(*register as u16).to_string())
-        .collect::<Vec<String>>()
-        .join(",");
-
-    match (load, store_lr_load_pc) {
-        (false, false) => format!("PUSH {{{}}}", register_list),
-        (false, true) => format!("PUSH {{{},lr}}", register_list),
-        (true, false) => format!("POP {{{}}}", register_list),
-        (true, true) => format!("POP {{{},pc}}", register_list),
-    }
+    let extra = match (load, instruction.store_lr_load_pc()) {
+        (_, false) => None,
+        (false, true) => Some("lr"),
+        (true, true) => Some("pc"),
+    };
+    let mnemonic = if load { "POP" } else { "PUSH" };
+    options.instruction(mnemonic, &[register_list(options, instruction, extra)])
 }
 
-pub fn disassemble_multiple_load_store(instruction: &ThumbInstruction) -> String {
+pub fn disassemble_multiple_load_store(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
     let rb = instruction.rb();
-    let load = instruction.load();
-    let register_list = instruction
-        .register_list()
-        .iter()
-        .map(|register| LoRegister::from(*register as u16).to_string())
-        .collect::<Vec<String>>()
-        .join(",");
-
-    match load {
-        true => format!("LDMIA {}!,{{{}}}", rb, register_list),
-        false => format!("STMIA {}!,{{{}}}", rb, register_list),
-    }
+    let mnemonic = if instruction.load() { "LDMIA" } else { "STMIA" };
+    options.instruction(mnemonic, &[format!("{rb}!"), register_list(options, instruction, None)])
 }
 
-pub fn disassemble_conditional_branch(instruction: &ThumbInstruction) -> String {
+pub fn disassemble_conditional_branch(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
     let cond = instruction.cond();
-    let offset = instruction.offset();
-    format!("B{} #{}", cond, offset)
+    let offset = ((instruction.offset() as u8 as i8 as i32) << 1) as u32;
+    options.instruction(&format!("B{cond}"), &[options.address(branch_target(instruction, offset))])
 }
 
-pub fn disassemble_software_interrupt(instruction: &ThumbInstruction) -> String {
+pub fn disassemble_software_interrupt(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
     let offset = instruction.offset();
-    format!("SWI #{}", offset)
+    options.instruction("SWI", &[format!("#{offset}")])
 }
 
-pub fn disassemble_unconditional_branch(instruction: &ThumbInstruction) -> String {
-    let offset = instruction.offset();
-    format!("B #{}", offset)
+pub fn disassemble_unconditional_branch(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
+    let offset = (((instruction.offset() as u32) << 21) as i32 >> 20) as u32;
+    options.instruction("B", &[options.address(branch_target(instruction, offset))])
 }
 
-pub fn disassemble_long_branch_with_link(instruction: &ThumbInstruction) -> String {
-    let offset = instruction.offset();
-    let high = if instruction.high() { "hi" } else { "lo" };
-    format!("BL #{}({})", offset, high)
+/// Each half of a `BL` only holds part of the offset, `disassemble_thumb_pair` resolves the target
+pub fn disassemble_long_branch_with_link(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
+    let offset = instruction.offset() as u32;
+    match instruction.high() {
+        false => {
+            let upper = ((offset << 21) as i32 >> 9) as u32;
+            let lr = instruction.executed_pc.wrapping_add(4).wrapping_add(upper);
+            options.instruction("BL(hi)", &[options.address(lr)])
+        }
+        true => options.instruction("BL(lo)", &[format!("lr+#0x{:X}", offset << 1)]),
+    }
+}
+
+pub fn disassemble_undefined(instruction: &ThumbInstruction, options: &DisassemblyOptions) -> String {
+    match options.is_gnu() {
+        true => format!(".hword 0x{:04x}", instruction.value),
+        false => String::from("Undefined"),
+    }
 }
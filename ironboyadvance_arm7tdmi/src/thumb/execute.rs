@@ -30,7 +30,7 @@ pub fn execute_move_shifted_register<I: MemoryInterface>(
     cpu.set_carry(carry);
 
     cpu.set_register(rd, result);
-    CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::Sequential)
+    CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL)
 }
 
 pub fn execute_add_subtract<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instruction: &ThumbInstruction) -> CpuAction {
@@ -47,7 +47,7 @@ pub fn execute_add_subtract<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instru
     };
 
     cpu.set_register(rd, result);
-    CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::Sequential)
+    CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL)
 }
 
 pub fn execute_move_compare_add_subtract_immediate<I: MemoryInterface>(
@@ -70,7 +70,7 @@ pub fn execute_move_compare_add_subtract_immediate<I: MemoryInterface>(
         cpu.set_register(rd, result);
     }
 
-    CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::Sequential)
+    CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL)
 }
 
 pub fn execute_alu_operations<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instruction: &ThumbInstruction) -> CpuAction {
@@ -80,7 +80,7 @@ pub fn execute_alu_operations<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, inst
     let mut operand2 = cpu.register(instruction.rs() as usize);
     let mut carry = cpu.cpsr().carry();
     let opcode: AluOperationsOpcode = instruction.opcode().into();
-    let mut access = CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::Sequential);
+    let mut access = CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL);
 
     let result = match opcode {
         AND => and(cpu, true, operand1, operand2, carry),
@@ -89,7 +89,7 @@ pub fn execute_alu_operations<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, inst
             operand2 &= 0xFF;
             let result = lsl(operand1, operand2, &mut carry);
             cpu.idle_cycle();
-            access = CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential);
+            access = CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL);
 
             cpu.set_negative(result >> 31 != 0);
             cpu.set_zero(result == 0);
@@ -100,7 +100,7 @@ pub fn execute_alu_operations<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, inst
             operand2 &= 0xFF;
             let result = lsr(operand1, operand2, &mut carry, false);
             cpu.idle_cycle();
-            access = CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential);
+            access = CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL);
 
             cpu.set_negative(result >> 31 != 0);
             cpu.set_zero(result == 0);
@@ -111,7 +111,7 @@ pub fn execute_alu_operations<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, inst
             operand2 &= 0xFF;
             let result = asr(operand1, operand2, &mut carry, false);
             cpu.idle_cycle();
-            access = CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential);
+            access = CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL);
 
             cpu.set_negative(result >> 31 != 0);
             cpu.set_zero(result == 0);
@@ -124,7 +124,7 @@ pub fn execute_alu_operations<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, inst
             operand2 &= 0xFF;
             let result = ror(operand1, operand2, &mut carry, false);
             cpu.idle_cycle();
-            access = CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential);
+            access = CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL);
 
             cpu.set_negative(result >> 31 != 0);
             cpu.set_zero(result == 0);
@@ -137,15 +137,15 @@ pub fn execute_alu_operations<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, inst
         CMN => cmn(cpu, true, operand1, operand2),
         ORR => orr(cpu, true, operand1, operand2, carry),
         MUL => {
-            let multiplier_cycles = multiplier_array_cycles(operand1);
-            for _ in 0..multiplier_cycles {
+            for _ in 0..multiplier_array_cycles(operand1, true) {
                 cpu.idle_cycle();
             }
-            access = CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential);
+            access = CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL);
 
             let result = operand1.wrapping_mul(operand2);
             cpu.set_negative(result >> 31 != 0);
             cpu.set_zero(result == 0);
+            cpu.set_carry(multiply_carry(operand2, operand1, 0, false, true));
             result
         }
         BIC => bic(cpu, true, operand1, operand2, carry),
@@ -164,7 +164,7 @@ pub fn execute_hi_register_operations_branch_exchange<I: MemoryInterface>(
     instruction: &ThumbInstruction,
 ) -> CpuAction {
     use HiRegOpsBxOpcode::*;
-    let mut action = CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::Sequential);
+    let mut action = CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL);
     let destination = match instruction.h1() {
         true => instruction.hd() as usize + 8,
         false => instruction.rd() as usize,
@@ -216,10 +216,10 @@ pub fn execute_hi_register_operations_branch_exchange<I: MemoryInterface>(
 pub fn execute_pc_relative_load<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instruction: &ThumbInstruction) -> CpuAction {
     let offset = instruction.offset();
     let address = (cpu.register(PC) & !0x2).wrapping_add((offset << 2) as u32);
-    let value = cpu.load_32(address, MemoryAccess::NonSequential as u8);
-    cpu.set_register(instruction.rd() as usize, value);
+    let value = cpu.load_32(address, MemoryAccess::NONSEQUENTIAL);
+    cpu.set_loaded_register(instruction.rd() as usize, value);
     cpu.idle_cycle();
-    CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential)
+    CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL)
 }
 
 pub fn execute_load_store_register_offset<I: MemoryInterface>(
@@ -236,25 +236,25 @@ pub fn execute_load_store_register_offset<I: MemoryInterface>(
     match (load, byte) {
         (false, false) => {
             let value = cpu.register(rd as usize);
-            cpu.store_32(address, value, MemoryAccess::NonSequential as u8);
+            cpu.store_32(address, value, MemoryAccess::NONSEQUENTIAL);
         }
         (false, true) => {
             let value = cpu.register(rd as usize);
-            cpu.store_8(address, value as u8, MemoryAccess::NonSequential as u8);
+            cpu.store_8(address, value as u8, MemoryAccess::NONSEQUENTIAL);
         }
         (true, false) => {
-            let value = cpu.load_rotated_32(address, MemoryAccess::NonSequential as u8);
-            cpu.set_register(rd, value);
+            let value = cpu.load_rotated_32(address, MemoryAccess::NONSEQUENTIAL);
+            cpu.set_loaded_register(rd, value);
             cpu.idle_cycle();
         }
         (true, true) => {
-            let value = cpu.load_8(address, MemoryAccess::NonSequential as u8);
-            cpu.set_register(rd, value);
+            let value = cpu.load_8(address, MemoryAccess::NONSEQUENTIAL);
+            cpu.set_loaded_register(rd, value);
             cpu.idle_cycle();
         }
     }
 
-    CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential)
+    CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL)
 }
 
 pub fn execute_load_store_sign_extended_byte_halfword<I: MemoryInterface>(
@@ -271,26 +271,26 @@ pub fn execute_load_store_sign_extended_byte_halfword<I: MemoryInterface>(
     match (signed, halfword) {
         (false, false) => {
             let value = cpu.register(rd as usize);
-            cpu.store_16(address, value as u16, MemoryAccess::NonSequential as u8);
+            cpu.store_16(address, value as u16, MemoryAccess::NONSEQUENTIAL);
         }
         (false, true) => {
-            let value = cpu.load_rotated_16(address, MemoryAccess::NonSequential as u8);
-            cpu.set_register(rd, value);
+            let value = cpu.load_rotated_16(address, MemoryAccess::NONSEQUENTIAL);
+            cpu.set_loaded_register(rd, value);
             cpu.idle_cycle();
         }
         (true, false) => {
-            let value = cpu.load_signed_8(address, MemoryAccess::NonSequential as u8);
-            cpu.set_register(rd, value);
+            let value = cpu.load_signed_8(address, MemoryAccess::NONSEQUENTIAL);
+            cpu.set_loaded_register(rd, value);
             cpu.idle_cycle();
         }
         (true, true) => {
-            let value = cpu.load_signed_16(address, MemoryAccess::NonSequential as u8);
-            cpu.set_register(rd, value);
+            let value = cpu.load_signed_16(address, MemoryAccess::NONSEQUENTIAL);
+            cpu.set_loaded_register(rd, value);
             cpu.idle_cycle();
         }
     }
 
-    CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential)
+    CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL)
 }
 
 pub fn execute_load_store_immediate_offset<I: MemoryInterface>(
@@ -307,25 +307,25 @@ pub fn execute_load_store_immediate_offset<I: MemoryInterface>(
     match (load, byte) {
         (false, false) => {
             let value = cpu.register(rd as usize);
-            cpu.store_32(address, value, MemoryAccess::NonSequential as u8);
+            cpu.store_32(address, value, MemoryAccess::NONSEQUENTIAL);
         }
         (false, true) => {
             let value = cpu.register(rd as usize);
-            cpu.store_8(address, value as u8, MemoryAccess::NonSequential as u8);
+            cpu.store_8(address, value as u8, MemoryAccess::NONSEQUENTIAL);
         }
         (true, false) => {
-            let value = cpu.load_rotated_32(address, MemoryAccess::NonSequential as u8);
-            cpu.set_register(rd, value);
+            let value = cpu.load_rotated_32(address, MemoryAccess::NONSEQUENTIAL);
+            cpu.set_loaded_register(rd, value);
             cpu.idle_cycle();
         }
         (true, true) => {
-            let value = cpu.load_8(address, MemoryAccess::NonSequential as u8);
-            cpu.set_register(rd, value);
+            let value = cpu.load_8(address, MemoryAccess::NONSEQUENTIAL);
+            cpu.set_loaded_register(rd, value);
             cpu.idle_cycle();
         }
     }
 
-    CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential)
+    CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL)
 }
 
 pub fn execute_load_store_halfword<I: MemoryInterface>(
@@ -338,17 +338,17 @@ pub fn execute_load_store_halfword<I: MemoryInterface>(
     let rd = instruction.rd() as usize;
     match instruction.load() {
         true => {
-            let value = cpu.load_rotated_16(address, MemoryAccess::NonSequential as u8);
-            cpu.set_register(rd, value);
+            let value = cpu.load_rotated_16(address, MemoryAccess::NONSEQUENTIAL);
+            cpu.set_loaded_register(rd, value);
             cpu.idle_cycle();
         }
         false => {
             let value = cpu.register(rd as usize);
-            cpu.store_16(address, value as u16, MemoryAccess::NonSequential as u8);
+            cpu.store_16(address, value as u16, MemoryAccess::NONSEQUENTIAL);
         }
     }
 
-    CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential)
+    CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL)
 }
 
 pub fn execute_sp_relative_load_store<I: MemoryInterface>(
@@ -361,17 +361,17 @@ pub fn execute_sp_relative_load_store<I: MemoryInterface>(
     let rd = instruction.rd() as usize;
     match instruction.load() {
         true => {
-            let value = cpu.load_rotated_32(address, MemoryAccess::NonSequential as u8);
-            cpu.set_register(rd, value);
+            let value = cpu.load_rotated_32(address, MemoryAccess::NONSEQUENTIAL);
+            cpu.set_loaded_register(rd, value);
             cpu.idle_cycle();
         }
         false => {
             let value = cpu.register(rd as usize);
-            cpu.store_32(address, value, MemoryAccess::NonSequential as u8);
+            cpu.store_32(address, value, MemoryAccess::NONSEQUENTIAL);
         }
     }
 
-    CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential)
+    CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL)
 }
 
 pub fn execute_load_address<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instruction: &ThumbInstruction) -> CpuAction {
@@ -382,7 +382,7 @@ pub fn execute_load_address<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instru
         false => (cpu.pc() & !0b10).wrapping_add(offset as u32),
     };
     cpu.set_register(rd, value);
-    CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::Sequential)
+    CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL)
 }
 
 pub fn execute_add_offset_to_sp<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, instruction: &ThumbInstruction) -> CpuAction {
@@ -393,7 +393,7 @@ pub fn execute_add_offset_to_sp<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, in
         false => sp_value.wrapping_add(offset as u32),
     };
     cpu.set_register(SP, value);
-    CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::Sequential)
+    CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL)
 }
 
 pub fn execute_push_pop_registers<I: MemoryInterface>(
@@ -404,30 +404,35 @@ pub fn execute_push_pop_registers<I: MemoryInterface>(
     let register_list = instruction.register_list();
     let store_lr_load_pc = instruction.store_lr_load_pc();
 
-    let mut memory_access = MemoryAccess::NonSequential;
+    let mut memory_access = MemoryAccess::NONSEQUENTIAL;
     match instruction.load() {
         true => {
             if register_list.is_empty() && !store_lr_load_pc {
-                let value = cpu.load_32(address, memory_access as u8);
-                cpu.set_pc(value);
+                let value = cpu.load_32(address, memory_access);
                 cpu.set_register(SP, address + 64);
+                if cpu.data_aborted() {
+                    return CpuAction::PipelineFlush;
+                }
+                cpu.set_pc(value);
                 cpu.pipeline_flush();
                 return CpuAction::PipelineFlush;
             }
 
             for register in register_list.iter() {
-                let value = cpu.load_32(address, memory_access as u8);
-                cpu.set_register(*register, value);
-                memory_access = MemoryAccess::Sequential;
+                let value = cpu.load_32(address, memory_access);
+                cpu.set_loaded_register(register, value);
+                memory_access = MemoryAccess::SEQUENTIAL;
                 address += 4
             }
 
             if store_lr_load_pc {
-                let value = cpu.load_32(address, memory_access as u8);
-                cpu.set_register(PC, value & !0b1);
+                let value = cpu.load_32(address, memory_access);
+                cpu.set_loaded_register(PC, value & !0b1);
                 cpu.set_register(SP, address + 4);
                 cpu.idle_cycle();
-                cpu.pipeline_flush();
+                if !cpu.data_aborted() {
+                    cpu.pipeline_flush();
+                }
                 return CpuAction::PipelineFlush;
             }
 
@@ -439,8 +444,8 @@ pub fn execute_push_pop_registers<I: MemoryInterface>(
                 address -= 64;
                 cpu.set_register(SP, address);
                 let value = cpu.pc() + 2;
-                cpu.store_32(address, value, memory_access as u8);
-                return CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential);
+                cpu.store_32(address, value, memory_access);
+                return CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL);
             }
 
             address -= register_list.len() as u32 * 4;
@@ -450,20 +455,20 @@ pub fn execute_push_pop_registers<I: MemoryInterface>(
             cpu.set_register(SP, address);
 
             for register in register_list.iter() {
-                let value = cpu.register(*register);
-                cpu.store_32(address, value, memory_access as u8);
-                memory_access = MemoryAccess::Sequential;
+                let value = cpu.register(register);
+                cpu.store_32(address, value, memory_access);
+                memory_access = MemoryAccess::SEQUENTIAL;
                 address += 4
             }
 
             if store_lr_load_pc {
                 let value = cpu.register(LR);
-                cpu.store_32(address, value, memory_access as u8);
+                cpu.store_32(address, value, memory_access);
             }
         }
     }
 
-    CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential)
+    CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL)
 }
 
 pub fn execute_multiple_load_store<I: MemoryInterface>(
@@ -474,52 +479,56 @@ pub fn execute_multiple_load_store<I: MemoryInterface>(
     let mut address = cpu.register(rb);
     let register_list = instruction.register_list();
 
-    let mut memory_access = MemoryAccess::NonSequential;
+    let mut memory_access = MemoryAccess::NONSEQUENTIAL;
     match instruction.load() {
         true => {
             if register_list.is_empty() {
-                let value = cpu.load_32(address, memory_access as u8);
-                cpu.set_pc(value);
+                let value = cpu.load_32(address, memory_access);
                 cpu.set_register(rb, address + 64);
+                if cpu.data_aborted() {
+                    return CpuAction::PipelineFlush;
+                }
+                cpu.set_pc(value);
                 cpu.pipeline_flush();
                 return CpuAction::PipelineFlush;
             }
 
             for register in register_list.iter() {
-                let value = cpu.load_32(address, memory_access as u8);
-                cpu.set_register(*register, value);
-                memory_access = MemoryAccess::Sequential;
+                let value = cpu.load_32(address, memory_access);
+                cpu.set_loaded_register(register, value);
+                memory_access = MemoryAccess::SEQUENTIAL;
                 address += 4
             }
 
             cpu.idle_cycle();
-            if !register_list.contains(&rb) {
+            // An abort keeps the written back base even when it is in the list
+            if !register_list.contains(rb) || cpu.data_aborted() {
                 cpu.set_register(rb, address);
             }
         }
         false => {
             if register_list.is_empty() {
                 let value = cpu.pc() + 2;
-                cpu.store_32(address, value, memory_access as u8);
+                cpu.store_32(address, value, memory_access);
                 cpu.set_register(rb, address + 64);
-                return CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential);
+                return CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL);
             }
 
             for (i, register) in register_list.iter().enumerate() {
-                let value = cpu.register(*register);
-                cpu.store_32(address, value, memory_access as u8);
+                let value = cpu.register(register);
+                cpu.store_32(address, value, memory_access);
 
                 if i == 0 {
                     cpu.set_register(rb, address + register_list.len() as u32 * 4);
                 }
 
-                memory_access = MemoryAccess::Sequential;
+                memory_access = MemoryAccess::SEQUENTIAL;
                 address += 4
             }
         }
     }
 
-    CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::NonSequential)
+    CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::NONSEQUENTIAL)
 }
 
 pub fn execute_conditional_branch<I: MemoryInterface>(
@@ -528,7 +537,7 @@ pub fn execute_conditional_branch<I: MemoryInterface>(
 ) -> CpuAction {
     let condition = instruction.cond();
     if !cpu.is_condition_met(condition) {
-        CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::Sequential)
+        CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL)
     } else {
         let offset = (((instruction.offset() as u32) << 24) as i32) >> 23;
         cpu.set_pc(cpu.pc().wrapping_add(offset as u32));
@@ -572,7 +581,13 @@ pub fn execute_long_branch_with_link<I: MemoryInterface>(
         false => {
             offset = (offset << 21) >> 9;
             cpu.set_register(LR, cpu.pc().wrapping_add(offset as u32));
-            CpuAction::Advance(MemoryAccess::Instruction | MemoryAccess::Sequential)
+            CpuAction::Advance(MemoryAccess::INSTRUCTION | MemoryAccess::SEQUENTIAL)
         }
     }
 }
+
+pub fn execute_undefined<I: MemoryInterface>(cpu: &mut Arm7tdmiCpu<I>, _instruction: &ThumbInstruction) -> CpuAction {
+    cpu.idle_cycle();
+    cpu.exeception(Exception::Undefined);
+    CpuAction::PipelineFlush
+}
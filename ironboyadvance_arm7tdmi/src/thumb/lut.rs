@@ -41,7 +41,7 @@ fn decode_thumb(instruction: u16) -> ThumbInstructionKind {
         PushPopRegisters
     } else if instruction & 0xF000 == 0xC000 {
         MultipleLoadStore
-    } else if instruction & 0xFF00 < 0xDF00 {
+    } else if instruction & 0xF000 == 0xD000 && instruction & 0xFF00 != 0xDF00 {
         ConditionalBranch
     } else if instruction & 0xFF00 == 0xDF00 {
         SoftwareInterrupt
@@ -53,3 +53,24 @@ fn decode_thumb(instruction: u16) -> ThumbInstructionKind {
         Undefined
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_undefined_gaps() {
+        // Unallocated miscellaneous encodings, which used to decode as conditional branches
+        for prefix in [0xB100, 0xB200, 0xB300, 0xB600, 0xB700, 0xB800, 0xBA00, 0xBB00, 0xBE00, 0xBF00] {
+            for low in 0..=0xFF {
+                assert_eq!(decode_thumb(prefix | low), Undefined, "{:04X}", prefix | low);
+            }
+        }
+        assert_eq!(decode_thumb(0xB080), AddOffsetToSp);
+        assert_eq!(decode_thumb(0xB5F0), PushPopRegisters);
+        assert_eq!(decode_thumb(0xBDF0), PushPopRegisters);
+        assert_eq!(decode_thumb(0xD0FE), ConditionalBranch);
+        assert_eq!(decode_thumb(0xDDFE), ConditionalBranch);
+        assert_eq!(decode_thumb(0xDF00), SoftwareInterrupt);
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use crate::CpuState;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Address of the executed instruction, not the prefetch PC
+    pub pc: u32,
+    pub state: CpuState,
+    pub opcode: u32,
+    pub disassembly: String,
+    /// Registers as seen by the current mode before the instruction executes
+    pub registers: [u32; 16],
+    pub cpsr: u32,
+    pub cycles: u64,
+}
+
+pub trait TraceSink {
+    fn trace(&mut self, record: &TraceRecord);
+
+    fn flush(&mut self) {}
+}
+
+pub struct TextTraceSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> TextTraceSink<W> {
+    pub fn new(writer: W) -> Self {
+        TextTraceSink { writer }
+    }
+}
+
+/// One line of the text trace format, without the trailing newline
+pub fn format_text_record(record: &TraceRecord) -> String {
+    let opcode = match record.state {
+        CpuState::Arm => format!("{:08X}", record.opcode),
+        CpuState::Thumb => format!("    {:04X}", record.opcode),
+    };
+    let registers = record
+        .registers
+        .iter()
+        .map(|r| format!("{r:08X}"))
+        .collect::<Vec<String>>()
+        .join(" ");
+    format!(
+        "{:>12} {:08X} {:<5} {} {:<32} {} cpsr:{:08X}",
+        record.cycles,
+        record.pc,
+        record.state.to_string(),
+        opcode,
+        record.disassembly,
+        registers,
+        record.cpsr
+    )
+}
+
+impl<W: Write> TraceSink for TextTraceSink<W> {
+    fn trace(&mut self, record: &TraceRecord) {
+        // Tracing must never take the emulator down, a failed write only loses the line
+        let _ = writeln!(self.writer, "{}", format_text_record(record));
+    }
+
+    fn flush(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+pub const BINARY_TRACE_RECORD_BYTES: usize = 85;
+
+/// Fixed size little endian records without disassembly:
+/// pc u32, opcode u32, state u8, cpsr u32, r0-r15 u32, cycles u64
+pub struct BinaryTraceSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> BinaryTraceSink<W> {
+    pub fn new(writer: W) -> Self {
+        BinaryTraceSink { writer }
+    }
+}
+
+impl<W: Write> TraceSink for BinaryTraceSink<W> {
+    fn trace(&mut self, record: &TraceRecord) {
+        let _ = self.writer.write_all(&encode_binary_record(record));
+    }
+
+    fn flush(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+pub fn encode_binary_record(record: &TraceRecord) -> [u8; BINARY_TRACE_RECORD_BYTES] {
+    let mut bytes = [0; BINARY_TRACE_RECORD_BYTES];
+    bytes[0..4].copy_from_slice(&record.pc.to_le_bytes());
+    bytes[4..8].copy_from_slice(&record.opcode.to_le_bytes());
+    bytes[8] = record.state.into_bits();
+    bytes[9..13].copy_from_slice(&record.cpsr.to_le_bytes());
+    for (i, register) in record.registers.iter().enumerate() {
+        bytes[13 + i * 4..17 + i * 4].copy_from_slice(&register.to_le_bytes());
+    }
+    bytes[77..85].copy_from_slice(&record.cycles.to_le_bytes());
+    bytes
+}
+
+pub fn decode_binary_record(bytes: &[u8; BINARY_TRACE_RECORD_BYTES]) -> TraceRecord {
+    let word = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    TraceRecord {
+        pc: word(0),
+        opcode: word(4),
+        state: CpuState::from_bits(bytes[8] & 0x1),
+        cpsr: word(9),
+        registers: std::array::from_fn(|i| word(13 + i * 4)),
+        cycles: u64::from_le_bytes(bytes[77..85].try_into().unwrap()),
+        disassembly: String::new(),
+    }
+}
+
+pub fn read_binary_trace<R: io::Read>(mut reader: R) -> io::Result<Vec<TraceRecord>> {
+    let mut records = Vec::new();
+    let mut bytes = [0; BINARY_TRACE_RECORD_BYTES];
+    loop {
+        match reader.read_exact(&mut bytes) {
+            Ok(()) => records.push(decode_binary_record(&bytes)),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(records),
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Keeps the most recent records in memory, clones share the same buffer so a frontend
+/// can hold one handle while the cpu owns another
+#[derive(Clone)]
+pub struct RingBufferTraceSink {
+    records: Rc<RefCell<VecDeque<TraceRecord>>>,
+    capacity: usize,
+}
+
+impl RingBufferTraceSink {
+    pub fn new(capacity: usize) -> Self {
+        RingBufferTraceSink {
+            records: Rc::new(RefCell::new(VecDeque::with_capacity(capacity))),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn records(&self) -> Vec<TraceRecord> {
+        self.records.borrow().iter().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.borrow().is_empty()
+    }
+
+    pub fn clear(&self) {
+        self.records.borrow_mut().clear();
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl TraceSink for RingBufferTraceSink {
+    fn trace(&mut self, record: &TraceRecord) {
+        let mut records = self.records.borrow_mut();
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CpuState;
+
+    use super::{RingBufferTraceSink, TraceRecord, TraceSink, decode_binary_record, encode_binary_record};
+
+    fn record(pc: u32) -> TraceRecord {
+        TraceRecord {
+            pc,
+            state: CpuState::Thumb,
+            opcode: 0xE7FE,
+            disassembly: String::new(),
+            registers: std::array::from_fn(|i| i as u32 * 0x11111111),
+            cpsr: 0x6000003F,
+            cycles: 0x1_0000_0001,
+        }
+    }
+
+    #[test]
+    fn binary_record_round_trip() {
+        let record = record(0x08000100);
+        assert_eq!(decode_binary_record(&encode_binary_record(&record)), record);
+    }
+
+    #[test]
+    fn ring_buffer_keeps_latest_records() {
+        let ring = RingBufferTraceSink::new(3);
+        let mut sink = ring.clone();
+        (0..5).for_each(|pc| sink.trace(&record(pc)));
+        assert_eq!(ring.records().iter().map(|r| r.pc).collect::<Vec<u32>>(), vec![2, 3, 4]);
+    }
+}
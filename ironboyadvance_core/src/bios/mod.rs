@@ -1,10 +1,12 @@
 use std::path::PathBuf;
 
-use ironboyadvance_arm7tdmi::memory::SystemMemoryAccess;
+use ironboyadvance_arm7tdmi::memory::{DebugMemoryAccess, SystemMemoryAccess};
 use ironboyadvance_utils::read_file;
 
 use crate::GbaError;
 
+const BIOS_BYTES: usize = 16 * 1024;
+
 pub struct Bios {
     data: Box<[u8]>,
 }
@@ -17,12 +19,32 @@ impl Bios {
         };
         Ok(Bios { data: buffer })
     }
+
+    // Stand in for when no BIOS image is available and the boot sequence is skipped
+    pub fn empty() -> Bios {
+        Bios {
+            data: vec![0; BIOS_BYTES].into_boxed_slice(),
+        }
+    }
 }
 
 impl SystemMemoryAccess for Bios {
     fn read_8(&self, address: u32) -> u8 {
-        self.data[address as usize]
+        self.data.get(address as usize).copied().unwrap_or(0)
     }
 
     fn write_8(&mut self, _address: u32, _value: u8) {}
 }
+
+// Unlike the CPU, debuggers are allowed to patch the BIOS
+impl DebugMemoryAccess for Bios {
+    fn debug_read_8(&self, address: u32) -> u8 {
+        self.read_8(address)
+    }
+
+    fn debug_write_8(&mut self, address: u32, value: u8) {
+        if let Some(byte) = self.data.get_mut(address as usize) {
+            *byte = value;
+        }
+    }
+}
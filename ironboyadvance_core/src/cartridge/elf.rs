@@ -0,0 +1,245 @@
+use ironboyadvance_arm7tdmi::disassembler::SymbolTable;
+
+use crate::{
+    GbaError,
+    cartridge::MAX_CARTRIDGE_BYTES,
+    system_bus::{ROM_WS0_LO, SRAM_LO},
+};
+
+const MAGIC: &[u8; 4] = b"\x7FELF";
+const CLASS_32: u8 = 1;
+const LITTLE_ENDIAN: u8 = 1;
+const MACHINE_ARM: u16 = 40;
+const SEGMENT_LOAD: u32 = 1;
+const SECTION_SYMBOL_TABLE: u32 = 2;
+const SYMBOL_ENTRY_BYTES: usize = 16;
+const SYMBOL_OBJECT: u8 = 1;
+const SYMBOL_FUNCTION: u8 = 2;
+const SYMBOL_NO_TYPE: u8 = 0;
+
+pub fn is_elf(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Loadable contents placed at their physical (load) address
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+/// The parts of a 32 bit little endian ARM ELF executable the emulator needs
+pub struct Elf {
+    pub entry: u32,
+    pub segments: Vec<Segment>,
+    pub symbols: SymbolTable,
+}
+
+fn read_16(bytes: &[u8], offset: usize) -> Result<u16, GbaError> {
+    bytes
+        .get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or(GbaError::InvalidElf("truncated file"))
+}
+
+fn read_32(bytes: &[u8], offset: usize) -> Result<u32, GbaError> {
+    bytes
+        .get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or(GbaError::InvalidElf("truncated file"))
+}
+
+fn slice(bytes: &[u8], offset: u32, length: u32) -> Result<&[u8], GbaError> {
+    let start = offset as usize;
+    bytes
+        .get(start..start + length as usize)
+        .ok_or(GbaError::InvalidElf("section outside of file"))
+}
+
+fn string_at(table: &[u8], offset: u32) -> &str {
+    let start = (offset as usize).min(table.len());
+    let end = table[start..].iter().position(|b| *b == 0).map_or(table.len(), |i| start + i);
+    std::str::from_utf8(&table[start..end]).unwrap_or("")
+}
+
+impl Elf {
+    pub fn parse(bytes: &[u8]) -> Result<Elf, GbaError> {
+        if !is_elf(bytes) || bytes.len() < 0x34 {
+            return Err(GbaError::InvalidElf("missing ELF header"));
+        }
+        if bytes[4] != CLASS_32 || bytes[5] != LITTLE_ENDIAN || read_16(bytes, 0x12)? != MACHINE_ARM {
+            return Err(GbaError::InvalidElf("not a 32 bit little endian ARM executable"));
+        }
+
+        let entry = read_32(bytes, 0x18)?;
+        let program_headers = read_32(bytes, 0x1C)? as usize;
+        let section_headers = read_32(bytes, 0x20)? as usize;
+        let program_header_size = read_16(bytes, 0x2A)? as usize;
+        let program_header_count = read_16(bytes, 0x2C)? as usize;
+        let section_header_size = read_16(bytes, 0x2E)? as usize;
+        let section_header_count = read_16(bytes, 0x30)? as usize;
+
+        let mut segments = Vec::new();
+        for index in 0..program_header_count {
+            let header = program_headers + index * program_header_size;
+            let file_size = read_32(bytes, header + 16)?;
+            if read_32(bytes, header)? != SEGMENT_LOAD || file_size == 0 {
+                continue;
+            }
+            segments.push(Segment {
+                address: read_32(bytes, header + 12)?,
+                data: slice(bytes, read_32(bytes, header + 4)?, file_size)?.to_vec(),
+            });
+        }
+
+        let mut symbols = SymbolTable::new();
+        for index in 0..section_header_count {
+            let header = section_headers + index * section_header_size;
+            if read_32(bytes, header + 4)? != SECTION_SYMBOL_TABLE {
+                continue;
+            }
+            let table = slice(bytes, read_32(bytes, header + 16)?, read_32(bytes, header + 20)?)?;
+            let strings_header = section_headers + read_32(bytes, header + 24)? as usize * section_header_size;
+            let strings = slice(
+                bytes,
+                read_32(bytes, strings_header + 16)?,
+                read_32(bytes, strings_header + 20)?,
+            )?;
+
+            for symbol in table.chunks_exact(SYMBOL_ENTRY_BYTES) {
+                let name = string_at(strings, read_32(symbol, 0)?);
+                let value = read_32(symbol, 4)?;
+                let kind = symbol[12] & 0xF;
+                let defined = read_16(symbol, 14)? != 0;
+                // `$a`, `$t` and `$d` only mark where ARM, THUMB and data start
+                if !defined || name.is_empty() || name.starts_with('$') {
+                    continue;
+                }
+                match kind {
+                    // THUMB functions have bit 0 set
+                    SYMBOL_FUNCTION => symbols.insert(value & !0x1, name),
+                    SYMBOL_NO_TYPE | SYMBOL_OBJECT => symbols.insert(value, name),
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(Elf {
+            entry,
+            segments,
+            symbols,
+        })
+    }
+
+    /// Cartridge ROM with every segment loaded into the Game Pak address space copied in place
+    pub fn rom_image(&self) -> Result<Vec<u8>, GbaError> {
+        let mut rom = Vec::new();
+        for segment in &self.segments {
+            let end = segment.address as u64 + segment.data.len() as u64;
+            if segment.address < ROM_WS0_LO || end > SRAM_LO as u64 {
+                continue;
+            }
+            // All three wait state regions mirror the same ROM
+            let offset = ((segment.address - ROM_WS0_LO) & 0x01FF_FFFF) as usize;
+            if offset + segment.data.len() > MAX_CARTRIDGE_BYTES {
+                return Err(GbaError::InvalidElf("segment does not fit in cartridge ROM"));
+            }
+            if rom.len() < offset + segment.data.len() {
+                rom.resize(offset + segment.data.len(), 0);
+            }
+            rom[offset..offset + segment.data.len()].copy_from_slice(&segment.data);
+        }
+        match rom.is_empty() {
+            true => Err(GbaError::InvalidElf("no segment is loaded into cartridge ROM")),
+            false => Ok(rom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ironboyadvance_arm7tdmi::disassembler::{DisassemblyOptions, disassemble_arm_with};
+
+    use super::*;
+
+    // Minimal executable with one ROM segment and a symbol table holding a THUMB function and a byte variable
+    fn build_elf() -> Vec<u8> {
+        let mut elf = vec![0u8; 0x34];
+        elf[..4].copy_from_slice(MAGIC);
+        elf[4] = CLASS_32;
+        elf[5] = LITTLE_ENDIAN;
+        elf[0x12..0x14].copy_from_slice(&MACHINE_ARM.to_le_bytes());
+        elf[0x18..0x1C].copy_from_slice(&0x0800_0000u32.to_le_bytes());
+
+        let program_header = elf.len();
+        let segment = program_header + 32;
+        let symbols = segment + 8;
+        let strings = symbols + 3 * SYMBOL_ENTRY_BYTES;
+        let string_table = b"\0main\0$t\0flag\0";
+        let section_headers = strings + string_table.len();
+
+        let mut header = [0u32; 8];
+        header[0] = SEGMENT_LOAD;
+        header[1] = segment as u32;
+        header[3] = 0x0800_0000;
+        header[4] = 8;
+        elf.extend(header.iter().flat_map(|v| v.to_le_bytes()));
+        elf.extend([0xEA, 0x00, 0x00, 0x2E, 0x70, 0x47, 0x00, 0x00]);
+
+        for (name, value, info) in [
+            (1u32, 0x0800_0005u32, SYMBOL_FUNCTION),
+            (6, 0x0800_0004, SYMBOL_NO_TYPE),
+            (9, 0x0300_0001, SYMBOL_OBJECT),
+        ] {
+            elf.extend(name.to_le_bytes());
+            elf.extend(value.to_le_bytes());
+            elf.extend(0u32.to_le_bytes());
+            elf.extend([info, 0]);
+            elf.extend(1u16.to_le_bytes());
+        }
+        elf.extend(string_table);
+
+        let section = |kind: u32, offset: usize, size: usize, link: u32| {
+            [0, kind, 0, 0, offset as u32, size as u32, link, 0, 0, 0].map(u32::to_le_bytes)
+        };
+        elf.extend([0u8; 40]);
+        elf.extend(section(SECTION_SYMBOL_TABLE, symbols, 3 * SYMBOL_ENTRY_BYTES, 2).concat());
+        elf.extend(section(3, strings, string_table.len(), 0).concat());
+
+        elf[0x1C..0x20].copy_from_slice(&(program_header as u32).to_le_bytes());
+        elf[0x20..0x24].copy_from_slice(&(section_headers as u32).to_le_bytes());
+        elf[0x2A..0x2C].copy_from_slice(&32u16.to_le_bytes());
+        elf[0x2C..0x2E].copy_from_slice(&1u16.to_le_bytes());
+        elf[0x2E..0x30].copy_from_slice(&40u16.to_le_bytes());
+        elf[0x30..0x32].copy_from_slice(&3u16.to_le_bytes());
+        elf
+    }
+
+    #[test]
+    fn parse_segments_and_symbols() {
+        let elf = Elf::parse(&build_elf()).unwrap();
+        assert_eq!(elf.entry, 0x0800_0000);
+        assert_eq!(elf.rom_image().unwrap(), vec![0xEA, 0x00, 0x00, 0x2E, 0x70, 0x47, 0x00, 0x00]);
+        assert_eq!(elf.symbols.get(0x0800_0004), Some("main"));
+        assert_eq!(elf.symbols.get(0x0300_0001), Some("flag"));
+        assert_eq!(elf.symbols.len(), 2);
+        assert!(Elf::parse(b"not an elf").is_err());
+    }
+
+    #[test]
+    fn symbols_name_branch_targets() {
+        let elf = Elf::parse(&build_elf()).unwrap();
+        assert_eq!(elf.symbols.address_of("main"), Some(0x0800_0004));
+        let options = DisassemblyOptions {
+            symbols: Some(&elf.symbols),
+            ..Default::default()
+        };
+        assert_eq!(
+            disassemble_arm_with(0xEBFFFFFF, 0x0800_0000, &options),
+            "BL 0x08000004 <main>"
+        );
+        assert_eq!(
+            disassemble_arm_with(0xEA000000, 0x0800_0000, &options),
+            "B 0x08000008 <main+0x4>"
+        );
+    }
+}
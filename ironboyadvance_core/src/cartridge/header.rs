@@ -1,6 +1,6 @@
 use std::str::from_utf8;
 
-use crate::GbaError;
+use crate::{GbaError, system_bus::ROM_WS0_LO};
 
 pub struct Header {
     rom_entry_point: [u8; 4],
@@ -31,8 +31,6 @@ impl Header {
         let complement_check = bytes[0xBD];
         if complement_check != calculate_checksum(&bytes[0xA0..=0xBC]) {
             return Err(GbaError::CartridgeCheckSumFailure);
-        } else {
-            println!("Checksum passed!")
         }
 
         let game_title = from_utf8(&bytes[0xA0..0xAC]).map_err(|_| GbaError::HeaderParseFailure)?;
@@ -61,6 +59,13 @@ impl Header {
         Ok(header)
     }
 
+    /// Address the branch stored at the start of the ROM jumps to
+    pub fn rom_entry_point(&self) -> u32 {
+        let branch = u32::from_le_bytes(self.rom_entry_point);
+        let offset = ((branch << 8) as i32 >> 6) as u32;
+        ROM_WS0_LO.wrapping_add(8).wrapping_add(offset)
+    }
+
     pub fn game_title(&self) -> String {
         self.game_title.clone()
     }
@@ -1,38 +1,70 @@
 use std::path::PathBuf;
 
+use elf::{Elf, is_elf};
 use header::Header;
-use ironboyadvance_arm7tdmi::memory::SystemMemoryAccess;
-use ironboyadvance_utils::read_file;
+use ironboyadvance_arm7tdmi::{
+    disassembler::SymbolTable,
+    memory::{DebugMemoryAccess, SystemMemoryAccess},
+};
+use ironboyadvance_utils::{
+    fnv1a_hash, read_file,
+    state::{SaveState, StateError, StateReader, StateWriter},
+};
 
 use crate::{
     GbaError,
     system_bus::{ROM_WS0_HI, ROM_WS0_LO, ROM_WS1_HI, ROM_WS1_LO, ROM_WS2_HI, ROM_WS2_LO, SRAM_HI, SRAM_LO},
 };
 
+pub mod elf;
 pub mod header;
 
-const MAX_CARTRIDGE_BYTES: usize = 32 * 1024 * 1024;
+pub(crate) const MAX_CARTRIDGE_BYTES: usize = 32 * 1024 * 1024;
+// Addressed SRAM, which shares the ROM array from its start
+const SRAM_BYTES: usize = 64 * 1024;
 
 pub struct Cartridge {
     header: Header,
     data: Vec<u8>,
+    rom_hash: u64,
+    symbols: SymbolTable,
 }
 
 impl Cartridge {
     pub fn load(path: PathBuf) -> Result<Cartridge, GbaError> {
-        let buffer = match read_file(&path) {
-            Ok(buffer) => buffer.into_boxed_slice(),
+        let mut buffer = match read_file(&path) {
+            Ok(buffer) => buffer,
             Err(_) => return Err(GbaError::FileLoadFailure),
         };
 
-        let header = Header::load(&buffer[0..228])?;
-        println!("{}", header.game_title());
-        println!("{}", header.game_code());
+        // ELF executables are laid out at their load addresses, and keep their symbols for debugging
+        let mut symbols = SymbolTable::new();
+        if is_elf(&buffer) {
+            let elf = Elf::parse(&buffer)?;
+            buffer = elf.rom_image()?;
+            symbols = elf.symbols;
+        }
+
+        let header = Header::load(&buffer)?;
 
+        let rom_hash = fnv1a_hash(&buffer);
         let mut data = vec![0; MAX_CARTRIDGE_BYTES];
         data[..buffer.len()].clone_from_slice(&buffer);
 
-        Ok(Cartridge { header, data })
+        Ok(Cartridge {
+            header,
+            data,
+            rom_hash,
+            symbols,
+        })
+    }
+
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
     }
 }
 
@@ -57,3 +89,30 @@ impl SystemMemoryAccess for Cartridge {
         }
     }
 }
+
+// Goes straight to the ROM and SRAM arrays, bypassing any backup command state machine
+impl DebugMemoryAccess for Cartridge {
+    fn debug_read_8(&self, address: u32) -> u8 {
+        match address & 0xFF000000 {
+            ROM_WS0_LO..=SRAM_HI => self.read_8(address),
+            _ => 0,
+        }
+    }
+
+    fn debug_write_8(&mut self, address: u32, value: u8) {
+        if let ROM_WS0_LO..=SRAM_HI = address & 0xFF000000 {
+            self.write_8(address, value);
+        }
+    }
+}
+
+impl SaveState for Cartridge {
+    // The ROM itself is not part of the machine state, only the bytes written through SRAM are
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.data[..SRAM_BYTES]);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes_into(&mut self.data[..SRAM_BYTES])
+    }
+}
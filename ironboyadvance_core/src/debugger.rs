@@ -0,0 +1,552 @@
+use std::{collections::BTreeMap, ops::RangeInclusive};
+
+use ironboyadvance_arm7tdmi::{
+    Condition, CpuMode, CpuState,
+    cpu::{Arm7tdmiCpu, LR, SP},
+    memory::MemoryInterface,
+};
+
+use crate::{
+    GbaError,
+    system_bus::{
+        BIOS_BASE, IO_REGISTERS_BASE, OAM_BASE, PALETTE_RAM_BASE, ROM_WS0_LO, SRAM_LO, VRAM_BASE, WRAM_BOARD_BASE,
+        WRAM_CHIP_BASE,
+    },
+};
+
+pub type BreakpointId = usize;
+pub type WatchpointId = usize;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    FrameComplete,
+    Breakpoint {
+        id: BreakpointId,
+        address: u32,
+    },
+    /// Raised after the instruction that made the access has completed
+    Watchpoint {
+        id: WatchpointId,
+        address: u32,
+        access: WatchKind,
+        value: u32,
+    },
+    Step,
+    ScanlineReached(u32),
+    FrameReached(u64),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn holds(self, left: u32, right: u32) -> bool {
+        use Comparison::*;
+        match self {
+            Equal => left == right,
+            NotEqual => left != right,
+            Less => left < right,
+            LessOrEqual => left <= right,
+            Greater => left > right,
+            GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Operand {
+    Register(usize),
+    Cpsr,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BreakCondition {
+    State(CpuState),
+    Mode(CpuMode),
+    Compare {
+        operand: Operand,
+        comparison: Comparison,
+        value: u32,
+    },
+}
+
+impl BreakCondition {
+    /// Parses `&&` separated terms such as `r0 == 0x10 && thumb && mode == irq`
+    pub fn parse(expression: &str) -> Result<Vec<BreakCondition>, GbaError> {
+        expression
+            .split("&&")
+            .map(|term| BreakCondition::parse_term(term.trim()))
+            .collect()
+    }
+
+    fn parse_term(term: &str) -> Result<BreakCondition, GbaError> {
+        let invalid = || GbaError::InvalidBreakCondition(term.to_string());
+        match term.to_lowercase().as_str() {
+            "arm" => return Ok(BreakCondition::State(CpuState::Arm)),
+            "thumb" => return Ok(BreakCondition::State(CpuState::Thumb)),
+            _ => {}
+        }
+
+        // Two character operators first so `<=` is not read as `<`
+        let (left, comparison, right) = [
+            ("==", Comparison::Equal),
+            ("!=", Comparison::NotEqual),
+            ("<=", Comparison::LessOrEqual),
+            (">=", Comparison::GreaterOrEqual),
+            ("<", Comparison::Less),
+            (">", Comparison::Greater),
+        ]
+        .iter()
+        .find_map(|(operator, comparison)| {
+            term.split_once(operator)
+                .map(|(left, right)| (left.trim().to_lowercase(), *comparison, right.trim().to_lowercase()))
+        })
+        .ok_or_else(invalid)?;
+
+        if left == "mode" {
+            let mode = match right.as_str() {
+                "usr" | "user" => CpuMode::User,
+                "fiq" => CpuMode::Fiq,
+                "irq" => CpuMode::Irq,
+                "svc" | "supervisor" => CpuMode::Supervisor,
+                "abt" | "abort" => CpuMode::Abort,
+                "und" | "undefined" => CpuMode::Undefined,
+                "sys" | "system" => CpuMode::System,
+                _ => return Err(invalid()),
+            };
+            return match comparison {
+                Comparison::Equal => Ok(BreakCondition::Mode(mode)),
+                _ => Err(invalid()),
+            };
+        }
+
+        let operand = match left.as_str() {
+            "sp" => Operand::Register(SP),
+            "lr" => Operand::Register(LR),
+            "pc" => Operand::Register(15),
+            "cpsr" => Operand::Cpsr,
+            register => match register.strip_prefix('r').and_then(|index| index.parse::<usize>().ok()) {
+                Some(index) if index < 16 => Operand::Register(index),
+                _ => return Err(invalid()),
+            },
+        };
+        let value = match right.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => right.parse::<u32>(),
+        }
+        .map_err(|_| invalid())?;
+
+        Ok(BreakCondition::Compare {
+            operand,
+            comparison,
+            value,
+        })
+    }
+
+    pub fn holds<I: MemoryInterface>(&self, cpu: &Arm7tdmiCpu<I>) -> bool {
+        match *self {
+            BreakCondition::State(state) => cpu.cpsr().state() == state,
+            BreakCondition::Mode(mode) => cpu.cpsr().mode() == mode,
+            BreakCondition::Compare {
+                operand,
+                comparison,
+                value,
+            } => {
+                let operand = match operand {
+                    Operand::Register(index) => cpu.register(index),
+                    Operand::Cpsr => cpu.cpsr().into_bits(),
+                };
+                comparison.holds(operand, value)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub address: u32,
+    pub conditions: Vec<BreakCondition>,
+}
+
+impl Breakpoint {
+    pub fn new(address: u32) -> Self {
+        Breakpoint {
+            address,
+            conditions: Vec::new(),
+        }
+    }
+
+    pub fn conditional(address: u32, expression: &str) -> Result<Self, GbaError> {
+        Ok(Breakpoint {
+            address,
+            conditions: BreakCondition::parse(expression)?,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+impl WatchKind {
+    fn matches(self, access: WatchKind) -> bool {
+        self == WatchKind::Access || self == access
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemoryRegion {
+    Bios,
+    WramBoard,
+    WramChip,
+    IoRegisters,
+    PaletteRam,
+    Vram,
+    Oam,
+    Rom,
+    Sram,
+}
+
+impl MemoryRegion {
+    pub const ALL: [MemoryRegion; 9] = [
+        MemoryRegion::Bios,
+        MemoryRegion::WramBoard,
+        MemoryRegion::WramChip,
+        MemoryRegion::IoRegisters,
+        MemoryRegion::PaletteRam,
+        MemoryRegion::Vram,
+        MemoryRegion::Oam,
+        MemoryRegion::Rom,
+        MemoryRegion::Sram,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MemoryRegion::Bios => "BIOS",
+            MemoryRegion::WramBoard => "EWRAM",
+            MemoryRegion::WramChip => "IWRAM",
+            MemoryRegion::IoRegisters => "IO",
+            MemoryRegion::PaletteRam => "Palette",
+            MemoryRegion::Vram => "VRAM",
+            MemoryRegion::Oam => "OAM",
+            MemoryRegion::Rom => "ROM",
+            MemoryRegion::Sram => "SRAM",
+        }
+    }
+
+    pub fn range(self) -> RangeInclusive<u32> {
+        let (base, length) = match self {
+            MemoryRegion::Bios => (BIOS_BASE, 0x4000),
+            MemoryRegion::WramBoard => (WRAM_BOARD_BASE, 0x40000),
+            MemoryRegion::WramChip => (WRAM_CHIP_BASE, 0x8000),
+            MemoryRegion::IoRegisters => (IO_REGISTERS_BASE, 0x400),
+            MemoryRegion::PaletteRam => (PALETTE_RAM_BASE, 0x400),
+            MemoryRegion::Vram => (VRAM_BASE, 0x18000),
+            MemoryRegion::Oam => (OAM_BASE, 0x400),
+            MemoryRegion::Rom => (ROM_WS0_LO, 0x600_0000),
+            MemoryRegion::Sram => (SRAM_LO, 0x1_0000),
+        };
+        base..=base + (length - 1)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u32>,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub fn new(address: u32, length: u32, kind: WatchKind) -> Self {
+        Watchpoint {
+            range: address..=address.wrapping_add(length.max(1) - 1),
+            kind,
+        }
+    }
+
+    pub fn region(region: MemoryRegion, kind: WatchKind) -> Self {
+        Watchpoint {
+            range: region.range(),
+            kind,
+        }
+    }
+
+    /// True when any byte of the access falls inside the watched range
+    pub fn hit(&self, address: u32, width: u32, access: WatchKind) -> bool {
+        let end = address.wrapping_add(width - 1);
+        self.kind.matches(access) && address <= *self.range.end() && end >= *self.range.start()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BranchKind {
+    Call { return_address: u32 },
+    Return,
+    Other,
+}
+
+/// Classifies the instruction at `address` as a call or return, for stepping over and out of functions
+pub fn branch_kind<I: MemoryInterface>(cpu: &Arm7tdmiCpu<I>, address: u32, opcode: u32) -> BranchKind {
+    let lr = cpu.register(LR) & !0x1;
+    match cpu.cpsr().state() {
+        CpuState::Arm => {
+            let condition = opcode >> 28;
+            if condition == 0xF || !cpu.is_condition_met(Condition::from(condition)) {
+                return BranchKind::Other;
+            }
+            let next = address.wrapping_add(4);
+            match opcode & 0x0FFFFFFF {
+                // BL
+                op if op & 0x0F000000 == 0x0B000000 => BranchKind::Call { return_address: next },
+                // BX lr, or BX after `mov lr, pc`
+                op if op & 0x0FFFFFF0 == 0x012FFF10 => match op & 0xF {
+                    14 => BranchKind::Return,
+                    _ if lr == next => BranchKind::Call { return_address: next },
+                    _ => BranchKind::Other,
+                },
+                // MOV pc, lr and SUBS pc, lr, #imm
+                op if op & 0x0FEFFFFF == 0x01A0F00E || op & 0x0FFFF000 == 0x025EF000 => BranchKind::Return,
+                // LDM sp with pc in the list and LDR pc, [sp], #4
+                op if op & 0x0E1F8000 == 0x081D8000 || op == 0x049DF004 => BranchKind::Return,
+                _ => BranchKind::Other,
+            }
+        }
+        CpuState::Thumb => {
+            let next = address.wrapping_add(2);
+            match opcode & 0xFFFF {
+                // Second half of BL, the first half only sets up lr
+                op if op & 0xF800 == 0xF800 => BranchKind::Call { return_address: next },
+                op if op & 0xFF80 == 0x4700 => match (op >> 3) & 0xF {
+                    14 => BranchKind::Return,
+                    _ if lr == next => BranchKind::Call { return_address: next },
+                    _ => BranchKind::Other,
+                },
+                // POP {.., pc} and MOV pc, lr
+                op if op & 0xFF00 == 0xBD00 || op == 0x46F7 => BranchKind::Return,
+                _ => BranchKind::Other,
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum RunTarget {
+    Step,
+    /// Runs until `address` is reached with the stack unwound to at least `sp`, so recursion does not stop early
+    Return {
+        address: u32,
+        sp: u32,
+    },
+    StepOut {
+        depth: usize,
+    },
+    Scanline {
+        line: u32,
+        previous: u32,
+    },
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: BTreeMap<BreakpointId, Breakpoint>,
+    next_id: usize,
+    target: Option<RunTarget>,
+    // Resuming from a breakpoint must execute the instruction instead of stopping on it again
+    resume_address: Option<u32>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger::default()
+    }
+
+    pub(crate) fn next_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> BreakpointId {
+        let id = self.next_id();
+        self.breakpoints.insert(id, breakpoint);
+        id
+    }
+
+    pub fn remove_breakpoint(&mut self, id: BreakpointId) -> bool {
+        self.breakpoints.remove(&id).is_some()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (BreakpointId, &Breakpoint)> {
+        self.breakpoints.iter().map(|(id, breakpoint)| (*id, breakpoint))
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        !self.breakpoints.is_empty() || self.target.is_some()
+    }
+
+    pub(crate) fn set_target(&mut self, target: Option<RunTarget>) {
+        self.target = target;
+    }
+
+    pub(crate) fn set_resume_address(&mut self, address: Option<u32>) {
+        self.resume_address = address;
+    }
+
+    /// Checked before the instruction at `address` executes
+    pub(crate) fn check_before<I: MemoryInterface>(&mut self, cpu: &Arm7tdmiCpu<I>, address: u32) -> Option<StopReason> {
+        if self.resume_address.take() == Some(address) {
+            return None;
+        }
+
+        if let Some(RunTarget::Return { address: target, sp }) = self.target
+            && address == target
+            && cpu.register(SP) >= sp
+        {
+            return Some(StopReason::Step);
+        }
+
+        self.breakpoints
+            .iter()
+            .find(|(_, b)| b.address == address && b.conditions.iter().all(|c| c.holds(cpu)))
+            .map(|(id, _)| {
+                self.resume_address = Some(address);
+                StopReason::Breakpoint { id: *id, address }
+            })
+    }
+
+    /// Checked after an instruction of kind `branch` executed
+    pub(crate) fn check_after(&mut self, branch: BranchKind) -> Option<StopReason> {
+        match self.target.as_mut()? {
+            RunTarget::Step => Some(StopReason::Step),
+            RunTarget::StepOut { depth } => match (branch, *depth) {
+                (BranchKind::Call { .. }, _) => {
+                    *depth += 1;
+                    None
+                }
+                (BranchKind::Return, 0) => Some(StopReason::Step),
+                (BranchKind::Return, _) => {
+                    *depth -= 1;
+                    None
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub(crate) fn check_scanline(&mut self, scanline: u32) -> Option<StopReason> {
+        match self.target.as_mut()? {
+            RunTarget::Scanline { line, previous } => {
+                let entered = scanline == *line && *previous != *line;
+                *previous = scanline;
+                entered.then_some(StopReason::ScanlineReached(scanline))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ironboyadvance_arm7tdmi::{CpuMode, CpuState};
+
+    use super::{BreakCondition, Breakpoint, Comparison, Operand, StopReason, WatchKind, Watchpoint};
+    use crate::gba::{GameBoyAdvance, tests::program_gba};
+
+    // Calls a function that sets r1, stores it to WRAM and idles, assembled at 0x080000C0
+    const PROGRAM: &str = "
+                mov r0, #1
+                bl function
+                mov r2, #0x03000000
+                str r1, [r2]
+        idle:   b idle
+                .word 0
+        function:
+                mov r1, #2
+                bx lr
+    ";
+
+    fn test_gba(name: &str) -> GameBoyAdvance {
+        program_gba(&format!("debugger_{name}"), PROGRAM)
+    }
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        let mut gba = test_gba("stepping");
+        let id = gba.debugger_mut().add_breakpoint(Breakpoint::new(0x080000D8));
+        assert_eq!(gba.run(), StopReason::Breakpoint { id, address: 0x080000D8 });
+        assert_eq!(gba.cpu().register(0), 1);
+
+        assert_eq!(gba.step_out(), StopReason::Step);
+        assert_eq!(gba.cpu().next_instruction_address(), 0x080000C8);
+        assert_eq!(gba.cpu().register(1), 2);
+
+        assert_eq!(gba.step_instruction(), StopReason::Step);
+        assert_eq!(gba.cpu().next_instruction_address(), 0x080000CC);
+
+        let mut gba = test_gba("step_over");
+        gba.debugger_mut()
+            .add_breakpoint(Breakpoint::conditional(0x080000C4, "r0 == 1 && arm").unwrap());
+        assert!(matches!(gba.run(), StopReason::Breakpoint { .. }));
+        assert_eq!(gba.step_over(), StopReason::Step);
+        assert_eq!(gba.cpu().next_instruction_address(), 0x080000C8);
+    }
+
+    #[test]
+    fn write_watchpoint() {
+        let mut gba = test_gba("watchpoint");
+        let id = gba.add_watchpoint(Watchpoint::new(0x03000000, 4, WatchKind::Write));
+        assert_eq!(
+            gba.run(),
+            StopReason::Watchpoint {
+                id,
+                address: 0x03000000,
+                access: WatchKind::Write,
+                value: 2,
+            }
+        );
+        assert!(gba.remove_watchpoint(id));
+        assert_eq!(gba.run(), StopReason::FrameComplete);
+        assert_eq!(gba.run_to_frame(3), StopReason::FrameReached(3));
+    }
+
+    #[test]
+    fn parse_conditions() {
+        assert_eq!(
+            BreakCondition::parse("r3 >= 0x10 && thumb && mode == irq").unwrap(),
+            vec![
+                BreakCondition::Compare {
+                    operand: Operand::Register(3),
+                    comparison: Comparison::GreaterOrEqual,
+                    value: 0x10,
+                },
+                BreakCondition::State(CpuState::Thumb),
+                BreakCondition::Mode(CpuMode::Irq),
+            ]
+        );
+        assert!(BreakCondition::parse("r16 == 1").is_err());
+        assert!(BreakCondition::parse("lr").is_err());
+    }
+
+    #[test]
+    fn watchpoint_overlap() {
+        let watchpoint = Watchpoint::new(0x03000010, 4, WatchKind::Write);
+        assert!(watchpoint.hit(0x0300000E, 4, WatchKind::Write));
+        assert!(watchpoint.hit(0x03000013, 1, WatchKind::Write));
+        assert!(!watchpoint.hit(0x03000014, 4, WatchKind::Write));
+        assert!(!watchpoint.hit(0x03000010, 4, WatchKind::Read));
+    }
+}
@@ -1,13 +1,33 @@
-use std::{cell::RefCell, path::PathBuf, rc::Rc};
+use std::{
+    cell::RefCell,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
 
-use ironboyadvance_arm7tdmi::cpu::Arm7tdmiCpu;
+use ironboyadvance_arm7tdmi::{
+    CpuState,
+    assembler::assemble_with,
+    call_stack::CallStack,
+    cpu::{Arm7tdmiCpu, SP},
+    disassembler::SymbolTable,
+    memory::DebugMemoryAccess,
+    profiler::Profiler,
+    trace::TraceSink,
+};
+use ironboyadvance_utils::{
+    fnv1a_hash,
+    state::{SaveState, StateError, StateReader, StateWriter},
+};
 
 use crate::{
     GbaError,
     bios::Bios,
     cartridge::Cartridge,
-    ppu::CYCLES_PER_FRAME,
+    debugger::{BranchKind, Debugger, RunTarget, StopReason, Watchpoint, WatchpointId, branch_kind},
+    ppu::{CYCLES_PER_FRAME, CYCLES_PER_SCANLINE, VBLANK_SCANLINES, VDRAW_SCANLINES},
+    rewind::{RewindBuffer, RewindConfig},
     scheduler::{self, Scheduler, event::EventType},
+    symbols::load_symbols,
     system_bus::SystemBus,
     system_control::HaltMode,
 };
@@ -18,68 +38,432 @@ pub struct GameBoyAdvance {
     // sharp_sm83: SharpSm83Cpu<SystemBus>,
     scheduler: Rc<RefCell<Scheduler>>,
     rom_name: String,
+    rom_hash: u64,
+    skip_bios: bool,
+    rewind: Option<RewindBuffer>,
+    debugger: Debugger,
+    frame_count: u64,
+    frame_start: usize,
 }
 
+const SAVE_STATE_MAGIC: &[u8; 4] = b"IBAS";
+const SAVE_STATE_VERSION: u32 = 3;
+
 impl GameBoyAdvance {
-    pub fn new(rom_path: PathBuf, bios_path: PathBuf, show_logs: bool, skip_bios: bool) -> Result<GameBoyAdvance, GbaError> {
+    pub fn new(rom_path: PathBuf, bios_path: PathBuf, skip_bios: bool) -> Result<GameBoyAdvance, GbaError> {
+        let bios = Bios::load(bios_path)?;
+        GameBoyAdvance::with_bios(rom_path, bios, skip_bios)
+    }
+
+    /// Boots straight into the cartridge without a BIOS image, for headless test runs
+    pub fn without_bios(rom_path: PathBuf) -> Result<GameBoyAdvance, GbaError> {
+        GameBoyAdvance::with_bios(rom_path, Bios::empty(), true)
+    }
+
+    fn with_bios(rom_path: PathBuf, bios: Bios, skip_bios: bool) -> Result<GameBoyAdvance, GbaError> {
         let rom_name = rom_path.file_name().unwrap().to_str().unwrap().to_string();
         let scheduler = Rc::new(RefCell::new(Scheduler::new()));
         let cartridge = Cartridge::load(rom_path)?;
-        let bios = Bios::load(bios_path)?;
-        let gba = GameBoyAdvance {
+        let rom_hash = cartridge.rom_hash();
+        let symbols = cartridge.symbols().clone();
+        scheduler
+            .borrow_mut()
+            .schedule_at_timestamp(EventType::FrameComplete, CYCLES_PER_FRAME);
+        let mut gba = GameBoyAdvance {
             arm7tdmi: Arm7tdmiCpu::new(SystemBus::new(cartridge, bios, scheduler.clone()), skip_bios),
             scheduler,
             rom_name,
+            rom_hash,
+            skip_bios,
+            rewind: None,
+            debugger: Debugger::new(),
+            frame_count: 0,
+            frame_start: 0,
         };
+        gba.arm7tdmi.set_symbols(symbols);
         Ok(gba)
     }
 
+    pub fn cpu(&self) -> &Arm7tdmiCpu<SystemBus> {
+        &self.arm7tdmi
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Arm7tdmiCpu<SystemBus> {
+        &mut self.arm7tdmi
+    }
+
+    pub fn set_trace_sink(&mut self, trace_sink: Option<Box<dyn TraceSink>>) {
+        self.arm7tdmi.set_trace_sink(trace_sink);
+    }
+
+    pub fn call_stack(&self) -> Option<&CallStack> {
+        self.arm7tdmi.call_stack()
+    }
+
+    pub fn start_profiling(&mut self) {
+        self.arm7tdmi.start_profiling();
+    }
+
+    pub fn stop_profiling(&mut self) -> Option<Profiler> {
+        self.arm7tdmi.stop_profiling()
+    }
+
+    /// Runs blocks of instructions decoded once, off by default
+    pub fn set_block_cache(&mut self, enabled: bool) {
+        self.arm7tdmi.set_block_cache(enabled);
+    }
+
+    /// Compiles hot blocks to native code, the debugger still steps through the interpreter
+    #[cfg(feature = "jit")]
+    pub fn set_jit(&mut self, enabled: bool) {
+        self.arm7tdmi.set_jit(enabled);
+    }
+
     pub fn cycle(&mut self) {
+        if self.dispatch_interrupts() {
+            self.arm7tdmi.cycle_cached();
+        }
+    }
+
+    // Drives the irq line from the interrupt controller, returns true when the cpu is running and executes an
+    // instruction this cycle
+    fn dispatch_interrupts(&mut self) -> bool {
+        let pending = self.arm7tdmi.bus().interrupt_pending();
+        self.arm7tdmi.set_irq_line(pending);
         match self.arm7tdmi.bus().halt_mode() {
             HaltMode::Stopped => todo!(),
             HaltMode::Halted => {
-                if self.arm7tdmi.bus().interrupt_pending() {
-                    self.arm7tdmi.irq();
-                    self.arm7tdmi.bus().un_halt();
-                } else {
-                    self.scheduler.borrow_mut().update_to_next_event()
+                match pending {
+                    true => self.arm7tdmi.bus().un_halt(),
+                    false => self.scheduler.borrow_mut().update_to_next_event(),
                 }
+                false
             }
-            HaltMode::Running => {
-                if self.arm7tdmi.bus().interrupt_pending() {
-                    self.arm7tdmi.irq();
+            HaltMode::Running => true,
+        }
+    }
+
+    /// Runs until the current frame completes or the debugger stops execution.
+    /// A stopped frame carries on from the same point on the next call.
+    pub fn run(&mut self) -> StopReason {
+        loop {
+            while self.scheduler.borrow().timestamp() <= self.scheduler.borrow().timestamp_of_next_event() {
+                if let Some(reason) = self.debug_cycle() {
+                    self.debugger.set_target(None);
+                    return reason;
                 }
-                self.arm7tdmi.cycle();
             }
+
+            if self.handle_events() {
+                break;
+            }
+        }
+
+        self.arm7tdmi.bus().render_frame();
+        self.frame_count += 1;
+        if let Some(mut rewind) = self.rewind.take() {
+            if rewind.frame_complete() {
+                rewind.push(&self.save_state());
+            }
+            self.rewind = Some(rewind);
         }
+        StopReason::FrameComplete
     }
 
-    pub fn run(&mut self, overshoot: usize) -> usize {
-        let start_time = self.scheduler.borrow().timestamp();
-        let end_time = start_time + CYCLES_PER_FRAME - overshoot;
+    /// Runs to the end of the current frame, carrying on through debugger stops
+    pub fn run_frame(&mut self) {
+        while self.run() != StopReason::FrameComplete {}
+    }
 
-        self.scheduler
-            .borrow_mut()
-            .schedule_at_timestamp(EventType::FrameComplete, end_time);
+    fn debug_cycle(&mut self) -> Option<StopReason> {
+        if !self.debugger.is_active() {
+            self.cycle();
+            return self.arm7tdmi.bus().take_watchpoint_hit();
+        }
 
-        'events: loop {
-            while self.scheduler.borrow().timestamp() <= self.scheduler.borrow().timestamp_of_next_event() {
-                self.cycle();
+        let mode = self.arm7tdmi.cpsr().mode();
+        let mut reason = None;
+        if self.dispatch_interrupts() {
+            if self.arm7tdmi.cpsr().mode() != mode {
+                self.debugger.check_after(BranchKind::Call { return_address: 0 });
             }
 
-            if self.handle_events() {
-                break 'events;
+            let address = self.arm7tdmi.next_instruction_address();
+            if let Some(reason) = self.debugger.check_before(&self.arm7tdmi, address) {
+                return Some(reason);
+            }
+            let branch = branch_kind(&self.arm7tdmi, address, self.arm7tdmi.next_opcode());
+            self.arm7tdmi.cycle();
+            reason = self
+                .arm7tdmi
+                .bus()
+                .take_watchpoint_hit()
+                .or_else(|| self.debugger.check_after(branch));
+        }
+        reason.or_else(|| {
+            let scanline = self.scanline();
+            self.debugger.check_scanline(scanline)
+        })
+    }
+
+    // The target stays set across frames, so frontends can keep calling `run` until it is reached
+    fn run_to_target(&mut self, target: RunTarget) -> StopReason {
+        self.debugger
+            .set_resume_address(Some(self.arm7tdmi.next_instruction_address()));
+        self.debugger.set_target(Some(target));
+        self.run()
+    }
+
+    pub fn step_instruction(&mut self) -> StopReason {
+        self.run_to_target(RunTarget::Step)
+    }
+
+    /// Steps over BL and call style BX instructions, any other instruction is a single step
+    pub fn step_over(&mut self) -> StopReason {
+        let address = self.arm7tdmi.next_instruction_address();
+        let opcode = self.arm7tdmi.next_opcode();
+        let thumb_bl_prefix = self.arm7tdmi.cpsr().state() == CpuState::Thumb && opcode & 0xF800 == 0xF000;
+        let return_address = match branch_kind(&self.arm7tdmi, address, opcode) {
+            _ if thumb_bl_prefix => Some(address.wrapping_add(4)),
+            BranchKind::Call { return_address } => Some(return_address),
+            _ => None,
+        };
+        match return_address {
+            Some(return_address) => self.run_to_target(RunTarget::Return {
+                address: return_address,
+                sp: self.arm7tdmi.register(SP),
+            }),
+            None => self.step_instruction(),
+        }
+    }
+
+    /// Runs until the current function returns to its caller
+    pub fn step_out(&mut self) -> StopReason {
+        self.run_to_target(RunTarget::StepOut { depth: 0 })
+    }
+
+    pub fn run_to_scanline(&mut self, line: u32) -> StopReason {
+        let line = line.min(VDRAW_SCANLINES + VBLANK_SCANLINES - 1);
+        self.run_to_target(RunTarget::Scanline {
+            line,
+            previous: self.scanline(),
+        })
+    }
+
+    pub fn run_to_frame(&mut self, frame: u64) -> StopReason {
+        while self.frame_count < frame {
+            match self.run() {
+                StopReason::FrameComplete => {}
+                reason => return reason,
+            }
+        }
+        StopReason::FrameReached(self.frame_count)
+    }
+
+    pub fn cancel_run_target(&mut self) {
+        self.debugger.set_target(None);
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> WatchpointId {
+        let id = self.debugger.next_id();
+        self.arm7tdmi.bus().add_watchpoint(id, watchpoint);
+        id
+    }
+
+    pub fn remove_watchpoint(&mut self, id: WatchpointId) -> bool {
+        self.arm7tdmi.bus().remove_watchpoint(id)
+    }
+
+    /// Reads memory for debugging tools without spending cycles or touching IO side effects
+    pub fn debug_read(&self, address: u32, length: usize) -> Vec<u8> {
+        (0..length as u32)
+            .map(|i| self.arm7tdmi.debug_read_8(address.wrapping_add(i)))
+            .collect()
+    }
+
+    /// Writes memory for debugging tools, see `debug_read`
+    pub fn debug_write(&mut self, address: u32, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            self.arm7tdmi.debug_write_8(address.wrapping_add(i as u32), *byte);
+        }
+    }
+
+    /// Assembles `source` at `address` over whatever is there, returning the number of bytes written
+    pub fn patch(&mut self, address: u32, source: &str, state: CpuState) -> Result<usize, GbaError> {
+        let bytes = assemble_with(source, state, address, Some(self.symbols()))?;
+        self.debug_write(address, &bytes);
+        Ok(bytes.len())
+    }
+
+    pub fn disassemble_at(&self, address: u32, state: CpuState) -> String {
+        self.arm7tdmi.disassemble_at(address, state)
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        self.arm7tdmi.symbols()
+    }
+
+    /// Adds the symbols of an ELF or `.sym` file to those already known, returning how many were read
+    pub fn load_symbols(&mut self, path: &Path) -> Result<usize, GbaError> {
+        let loaded = load_symbols(path)?;
+        let count = loaded.len();
+        let mut symbols = self.arm7tdmi.symbols().clone();
+        symbols.extend(loaded.iter().map(|(address, name)| (address, name.to_string())));
+        self.arm7tdmi.set_symbols(symbols);
+        Ok(count)
+    }
+
+    /// Address of the symbol called `name`, for setting breakpoints by name
+    pub fn resolve_symbol(&self, name: &str) -> Result<u32, GbaError> {
+        self.symbols()
+            .address_of(name)
+            .ok_or_else(|| GbaError::UnknownSymbol(name.to_string()))
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn scanline(&self) -> u32 {
+        let cycles = self.scheduler.borrow().timestamp().saturating_sub(self.frame_start);
+        (cycles as u32 / CYCLES_PER_SCANLINE).min(VDRAW_SCANLINES + VBLANK_SCANLINES - 1)
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        SAVE_STATE_MAGIC.iter().for_each(|b| writer.write_u8(*b));
+        writer.write_u32(SAVE_STATE_VERSION);
+        self.arm7tdmi.save_state(&mut writer);
+        self.scheduler.borrow().save_state(&mut writer);
+        writer.write_u64(self.frame_count);
+        writer.write_u64(self.frame_start as u64);
+        writer.into_inner()
+    }
+
+    /// Restores a state produced by [`GameBoyAdvance::save_state`], leaving the emulator untouched when it is invalid
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), GbaError> {
+        let mut reader = StateReader::new(state);
+        let mut magic = [0; 4];
+        for byte in magic.iter_mut() {
+            *byte = reader.read_u8().map_err(|_| GbaError::InvalidSaveState)?;
+        }
+        let version = reader.read_u32().map_err(|_| GbaError::InvalidSaveState)?;
+        if &magic != SAVE_STATE_MAGIC || version != SAVE_STATE_VERSION {
+            return Err(GbaError::InvalidSaveState);
+        }
+
+        // Components decode in place, so a failed load is rolled back to the state from before it started
+        let backup = self.save_state();
+        match self.load_components(&mut reader) {
+            Ok((frame_count, frame_start)) => {
+                self.frame_count = frame_count;
+                self.frame_start = frame_start;
+                Ok(())
             }
+            Err(_) => {
+                let mut reader = StateReader::new(&backup[SAVE_STATE_MAGIC.len() + 4..]);
+                self.load_components(&mut reader).map_err(|_| GbaError::InvalidSaveState)?;
+                Err(GbaError::InvalidSaveState)
+            }
+        }
+    }
+
+    fn load_components(&mut self, reader: &mut StateReader) -> Result<(u64, usize), StateError> {
+        self.arm7tdmi.load_state(reader)?;
+        self.scheduler.borrow_mut().load_state(reader)?;
+        let frame_count = reader.read_u64()?;
+        let frame_start = reader.read_u64()? as usize;
+        match reader.is_empty() {
+            true => Ok((frame_count, frame_start)),
+            false => Err(StateError::LengthMismatch),
         }
+    }
+
+    pub fn state_hash(&self) -> u64 {
+        fnv1a_hash(&self.save_state())
+    }
+
+    /// The last completed frame as 240x160 BGR555 pixels
+    pub fn frame(&mut self) -> &[u16] {
+        self.arm7tdmi.bus().frame()
+    }
+
+    /// Hash of the last completed frame
+    pub fn frame_hash(&mut self) -> u64 {
+        self.arm7tdmi.bus().frame_hash()
+    }
+
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    /// Whether the machine booted straight into the cartridge
+    pub fn skip_bios(&self) -> bool {
+        self.skip_bios
+    }
 
-        self.scheduler.borrow().timestamp() - start_time
+    /// True until the first cycle is emulated
+    pub fn is_power_on(&self) -> bool {
+        self.scheduler.borrow().timestamp() == 0
+    }
+
+    pub fn key_input(&mut self) -> u16 {
+        self.arm7tdmi.bus().key_input()
+    }
+
+    /// Sets KEYINPUT, where a cleared bit means the button is held
+    pub fn set_key_input(&mut self, value: u16) {
+        self.arm7tdmi.bus().set_key_input(value);
+    }
+
+    pub fn enable_rewind(&mut self, config: RewindConfig) {
+        self.rewind = Some(RewindBuffer::new(config));
+    }
+
+    pub fn disable_rewind(&mut self) {
+        self.rewind = None;
+    }
+
+    pub fn rewind(&self) -> Option<&RewindBuffer> {
+        self.rewind.as_ref()
+    }
+
+    /// Restores the most recently captured rewind state, returning false when there is nothing left to rewind
+    pub fn rewind_step(&mut self) -> Result<bool, GbaError> {
+        let Some(rewind) = self.rewind.as_mut() else {
+            return Ok(false);
+        };
+        // The newest capture is usually the frame that just finished, which restoring would leave unchanged
+        let mut state = rewind.pop();
+        if state.as_deref().and_then(state_frame) == Some(self.frame_count) {
+            state = rewind.pop();
+        }
+        match state {
+            Some(state) => {
+                self.load_state(&state)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
     }
 
     fn handle_events(&mut self) -> bool {
         let mut scheduler = self.scheduler.borrow_mut();
         while let Some((event, timestamp)) = scheduler.pop() {
             let future_event: Option<(EventType, usize)> = match event {
-                EventType::FrameComplete => return true,
+                EventType::FrameComplete => {
+                    scheduler.schedule_at_timestamp(EventType::FrameComplete, timestamp + CYCLES_PER_FRAME);
+                    self.frame_start = timestamp;
+                    return true;
+                }
                 //TODO: write handlers for events
                 EventType::Timer(_timer_event) => None,
                 EventType::Ppu(_ppu_event) => None,
@@ -93,3 +477,38 @@ impl GameBoyAdvance {
         false
     }
 }
+
+// Frame count of a state produced by `save_state`, which ends with the frame count and frame start
+fn state_frame(state: &[u8]) -> Option<u64> {
+    let start = state.len().checked_sub(16)?;
+    Some(u64::from_le_bytes(state[start..start + 8].try_into().ok()?))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{fs, path::PathBuf};
+
+    use ironboyadvance_arm7tdmi::{
+        CpuState,
+        assembler::{assemble, assemble_arm},
+    };
+
+    use super::GameBoyAdvance;
+
+    const PROGRAM_ADDRESS: u32 = 0x0800_00C0;
+
+    /// Machine booting into `source`, assembled after the cartridge header
+    pub(crate) fn program_gba(name: &str, source: &str) -> GameBoyAdvance {
+        let program = assemble(source, CpuState::Arm, PROGRAM_ADDRESS).unwrap();
+        let mut rom = vec![0u8; 0xC0 + program.len().max(0x40)];
+        let entry = assemble_arm(&format!("b #{PROGRAM_ADDRESS:#010X}"), 0x0800_0000).unwrap();
+        rom[..4].copy_from_slice(&entry.to_le_bytes());
+        rom[0xBD] = 0xE7; // header complement check of an empty header
+        rom[0xC0..0xC0 + program.len()].copy_from_slice(&program);
+        let path = std::env::temp_dir().join(format!("ironboyadvance_{name}.gba"));
+        fs::write(&path, rom).unwrap();
+        let gba = GameBoyAdvance::without_bios(PathBuf::from(&path)).unwrap();
+        fs::remove_file(path).unwrap();
+        gba
+    }
+}
@@ -0,0 +1,693 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+};
+
+use ironboyadvance_arm7tdmi::{CpuMode, CpuState, psr::ProgramStatusRegister};
+
+use crate::{
+    debugger::{Breakpoint, BreakpointId, StopReason, WatchKind, Watchpoint, WatchpointId},
+    gba::GameBoyAdvance,
+};
+
+// r0-r15, cpsr, r8-r14 fiq, r13-r14 svc/abt/irq/und, spsr fiq/svc/abt/irq/und
+const REGISTER_COUNT: usize = 37;
+const BANKED_REGISTER_NAMES: [&str; 20] = [
+    "r8_fiq", "r9_fiq", "r10_fiq", "r11_fiq", "r12_fiq", "sp_fiq", "lr_fiq", "sp_svc", "lr_svc", "sp_abt", "lr_abt",
+    "sp_irq", "lr_irq", "sp_und", "lr_und", "spsr_fiq", "spsr_svc", "spsr_abt", "spsr_irq", "spsr_und",
+];
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+const INTERRUPT: u8 = 0x03;
+
+/// GDB remote serial protocol server, listening on localhost only
+pub struct GdbServer {
+    listener: TcpListener,
+}
+
+impl GdbServer {
+    pub fn bind(port: u16) -> io::Result<GdbServer> {
+        Ok(GdbServer {
+            listener: TcpListener::bind(("127.0.0.1", port))?,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Waits for a debugger to attach and serves it until it detaches or kills the session
+    pub fn serve(&self, gba: &mut GameBoyAdvance) -> io::Result<()> {
+        let (stream, _) = self.listener.accept()?;
+        stream.set_nodelay(true)?;
+        let mut session = Session {
+            stream,
+            gba,
+            no_ack: false,
+            pending: VecDeque::new(),
+            breakpoints: HashMap::new(),
+            watchpoints: HashMap::new(),
+        };
+        let result = session.run();
+        session.clear_breakpoints();
+        result
+    }
+}
+
+enum Packet {
+    Command(String),
+    Interrupt,
+}
+
+type BreakpointKey = (u8, u32, u32);
+
+struct Session<'a> {
+    stream: TcpStream,
+    gba: &'a mut GameBoyAdvance,
+    no_ack: bool,
+    // Bytes read while polling for an interrupt, handed back to the packet reader
+    pending: VecDeque<u8>,
+    breakpoints: HashMap<BreakpointKey, BreakpointId>,
+    watchpoints: HashMap<BreakpointKey, (WatchpointId, WatchKind)>,
+}
+
+impl Session<'_> {
+    fn run(&mut self) -> io::Result<()> {
+        loop {
+            let command = match self.read_packet()? {
+                Some(Packet::Command(command)) => command,
+                Some(Packet::Interrupt) => {
+                    self.send(&format!("S{SIGINT:02x}"))?;
+                    continue;
+                }
+                None => return Ok(()),
+            };
+
+            match command.as_bytes().first() {
+                Some(b'k') => return Ok(()),
+                Some(b'D') => return self.send("OK"),
+                _ => {
+                    let reply = self.handle(&command)?;
+                    self.send(&reply)?;
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, command: &str) -> io::Result<String> {
+        // An empty packet is unsupported like any other unknown command
+        let Some(name) = command.chars().next() else {
+            return Ok(String::new());
+        };
+        let arguments = &command[name.len_utf8()..];
+        let reply = match name {
+            '?' => format!("S{SIGTRAP:02x}"),
+            'g' => (0..REGISTER_COUNT).map(|n| encode_u32(self.read_register(n))).collect(),
+            'G' => self.write_registers(arguments),
+            'p' => match usize::from_str_radix(arguments, 16) {
+                Ok(n) if n < REGISTER_COUNT => encode_u32(self.read_register(n)),
+                _ => String::from("E00"),
+            },
+            'P' => self.write_register(arguments),
+            'm' => self.read_memory(arguments),
+            'M' => self.write_memory(arguments),
+            'c' => self.resume(false, arguments)?,
+            's' => self.resume(true, arguments)?,
+            'Z' => self.insert_breakpoint(arguments),
+            'z' => self.remove_breakpoint(arguments),
+            'H' | 'T' => String::from("OK"),
+            _ => self.handle_query(command),
+        };
+        Ok(reply)
+    }
+
+    fn handle_query(&mut self, command: &str) -> String {
+        if command.starts_with("qSupported") {
+            return String::from("PacketSize=1000;qXfer:features:read+;QStartNoAckMode+;swbreak+;hwbreak+");
+        }
+        if let Some(range) = command.strip_prefix("qXfer:features:read:target.xml:") {
+            return read_xfer(&target_xml(), range);
+        }
+        match command {
+            "QStartNoAckMode" => {
+                self.no_ack = true;
+                String::from("OK")
+            }
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            // Anything else is unsupported, which GDB expects as an empty reply
+            _ => String::new(),
+        }
+    }
+
+    fn read_register(&mut self, n: usize) -> u32 {
+        let cpu = self.gba.cpu();
+        match n {
+            // GDB expects the address of the next instruction, not the prefetch pc
+            15 => cpu.next_instruction_address(),
+            0..=14 => cpu.register(n),
+            16 => cpu.cpsr().into_bits(),
+            17..=23 => cpu.banked_registers_fiq()[n - 17],
+            24 | 25 => cpu.banked_registers_svc()[n - 24],
+            26 | 27 => cpu.banked_registers_abt()[n - 26],
+            28 | 29 => cpu.banked_registers_irq()[n - 28],
+            30 | 31 => cpu.banked_registers_und()[n - 30],
+            _ => cpu.spsrs()[n - 32].into_bits(),
+        }
+    }
+
+    fn set_register(&mut self, n: usize, value: u32) -> bool {
+        let cpu = self.gba.cpu_mut();
+        match n {
+            15 => {
+                let alignment = match cpu.cpsr().state() {
+                    CpuState::Arm => !0x3,
+                    CpuState::Thumb => !0x1,
+                };
+                cpu.set_pc(value & alignment);
+                cpu.debug_pipeline_flush();
+            }
+            0..=14 => cpu.set_register(n, value),
+            16 => {
+                let cpsr = ProgramStatusRegister::from_bits(value);
+                if cpsr.mode() == CpuMode::Invalid {
+                    return false;
+                }
+                let pc = cpu.next_instruction_address();
+                let state_changed = cpsr.state() != cpu.cpsr().state();
+                cpu.set_cpsr(cpsr);
+                if state_changed {
+                    cpu.set_pc(pc);
+                    cpu.debug_pipeline_flush();
+                }
+            }
+            17..=31 => {
+                let mut fiq = cpu.banked_registers_fiq();
+                let mut banked = [
+                    cpu.banked_registers_svc(),
+                    cpu.banked_registers_abt(),
+                    cpu.banked_registers_irq(),
+                    cpu.banked_registers_und(),
+                ];
+                match n {
+                    17..=23 => fiq[n - 17] = value,
+                    _ => banked[(n - 24) / 2][(n - 24) % 2] = value,
+                }
+                cpu.set_banked_registers_fiq(fiq);
+                cpu.set_banked_registers_svc(banked[0]);
+                cpu.set_banked_registers_abt(banked[1]);
+                cpu.set_banked_registers_irq(banked[2]);
+                cpu.set_banked_registers_und(banked[3]);
+            }
+            32..=36 => {
+                let mut spsrs = cpu.spsrs();
+                spsrs[n - 32] = ProgramStatusRegister::from_bits(value);
+                cpu.set_spsrs(spsrs);
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn write_registers(&mut self, arguments: &str) -> String {
+        let values = arguments
+            .as_bytes()
+            .chunks(8)
+            .map(|chunk| std::str::from_utf8(chunk).ok().and_then(decode_u32))
+            .collect::<Option<Vec<u32>>>();
+        match values {
+            // The pc goes last so a state change in cpsr does not realign it
+            Some(values) if values.len() <= REGISTER_COUNT => {
+                let mut order = (0..values.len()).filter(|n| *n != 15).collect::<Vec<usize>>();
+                if values.len() > 15 {
+                    order.push(15);
+                }
+                match order.into_iter().all(|n| self.set_register(n, values[n])) {
+                    true => String::from("OK"),
+                    false => String::from("E22"),
+                }
+            }
+            _ => String::from("E22"),
+        }
+    }
+
+    fn write_register(&mut self, arguments: &str) -> String {
+        let register = arguments.split_once('=').and_then(|(n, value)| {
+            let n = usize::from_str_radix(n, 16).ok()?;
+            Some((n, decode_u32(value)?))
+        });
+        match register {
+            Some((n, value)) if n < REGISTER_COUNT && self.set_register(n, value) => String::from("OK"),
+            _ => String::from("E22"),
+        }
+    }
+
+    fn read_memory(&mut self, arguments: &str) -> String {
+        let Some((address, length)) = parse_address_length(arguments) else {
+            return String::from("E22");
+        };
+        let mapped = (0..length).take_while(|i| is_mapped(address.wrapping_add(*i))).count();
+        let bytes = self
+            .gba
+            .debug_read(address, mapped)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        match bytes.is_empty() && length > 0 {
+            true => String::from("E14"),
+            false => bytes,
+        }
+    }
+
+    fn write_memory(&mut self, arguments: &str) -> String {
+        let Some((range, data)) = arguments.split_once(':') else {
+            return String::from("E22");
+        };
+        let Some((address, length)) = parse_address_length(range) else {
+            return String::from("E22");
+        };
+        let Some(bytes) = decode_hex(data).filter(|bytes| bytes.len() == length as usize) else {
+            return String::from("E22");
+        };
+        if !(0..length).all(|i| is_mapped(address.wrapping_add(i))) {
+            return String::from("E14");
+        }
+        self.gba.debug_write(address, &bytes);
+        String::from("OK")
+    }
+
+    fn resume(&mut self, step: bool, address: &str) -> io::Result<String> {
+        if let Some(address) = decode_address(address) {
+            self.set_register(15, address);
+        }
+
+        let mut reason = match step {
+            true => self.gba.step_instruction(),
+            false => self.gba.run(),
+        };
+        while reason == StopReason::FrameComplete {
+            if self.interrupted()? {
+                self.gba.cancel_run_target();
+                return Ok(format!("S{SIGINT:02x}"));
+            }
+            reason = self.gba.run();
+        }
+
+        Ok(match reason {
+            StopReason::Breakpoint { id, .. } => {
+                let kind = match self.breakpoints.iter().find(|(_, b)| **b == id) {
+                    Some(((1, _, _), _)) => "hwbreak",
+                    _ => "swbreak",
+                };
+                format!("T{SIGTRAP:02x}{kind}:;")
+            }
+            StopReason::Watchpoint { id, address, .. } => {
+                let kind = match self.watchpoints.values().find(|(w, _)| *w == id) {
+                    Some((_, WatchKind::Read)) => "rwatch",
+                    Some((_, WatchKind::Access)) => "awatch",
+                    _ => "watch",
+                };
+                format!("T{SIGTRAP:02x}{kind}:{address:08x};")
+            }
+            _ => format!("S{SIGTRAP:02x}"),
+        })
+    }
+
+    fn insert_breakpoint(&mut self, arguments: &str) -> String {
+        let Some(key) = parse_breakpoint(arguments) else {
+            return String::from("E22");
+        };
+        let (kind, address, length) = key;
+        match kind {
+            0 | 1 => {
+                if !self.breakpoints.contains_key(&key) {
+                    let id = self.gba.debugger_mut().add_breakpoint(Breakpoint::new(address & !0x1));
+                    self.breakpoints.insert(key, id);
+                }
+            }
+            2..=4 => {
+                if !self.watchpoints.contains_key(&key) {
+                    let watch_kind = match kind {
+                        2 => WatchKind::Write,
+                        3 => WatchKind::Read,
+                        _ => WatchKind::Access,
+                    };
+                    let id = self.gba.add_watchpoint(Watchpoint::new(address, length, watch_kind));
+                    self.watchpoints.insert(key, (id, watch_kind));
+                }
+            }
+            _ => return String::new(),
+        }
+        String::from("OK")
+    }
+
+    fn remove_breakpoint(&mut self, arguments: &str) -> String {
+        let Some(key) = parse_breakpoint(arguments) else {
+            return String::from("E22");
+        };
+        if let Some(id) = self.breakpoints.remove(&key) {
+            self.gba.debugger_mut().remove_breakpoint(id);
+        }
+        if let Some((id, _)) = self.watchpoints.remove(&key) {
+            self.gba.remove_watchpoint(id);
+        }
+        String::from("OK")
+    }
+
+    fn clear_breakpoints(&mut self) {
+        for (_, id) in self.breakpoints.drain() {
+            self.gba.debugger_mut().remove_breakpoint(id);
+        }
+        for (_, (id, _)) in self.watchpoints.drain() {
+            self.gba.remove_watchpoint(id);
+        }
+    }
+
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0; 1];
+        let result = match self.stream.read(&mut byte) {
+            Ok(0) => Err(io::Error::from(ErrorKind::ConnectionAborted)),
+            Ok(_) if byte[0] == INTERRUPT => Ok(true),
+            // A late ack or the start of the next packet, which read_packet still has to see
+            Ok(_) => {
+                self.pending.push_back(byte[0]);
+                Ok(false)
+            }
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error),
+        };
+        self.stream.set_nonblocking(false)?;
+        result
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        if let Some(byte) = self.pending.pop_front() {
+            return Ok(Some(byte));
+        }
+        let mut byte = [0; 1];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    fn read_packet(&mut self) -> io::Result<Option<Packet>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(INTERRUPT) => return Ok(Some(Packet::Interrupt)),
+                Some(b'$') => {}
+                // Acks and noise between packets
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut received = [0; 2];
+            for digit in received.iter_mut() {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(byte) => *digit = byte,
+                }
+            }
+
+            let valid = std::str::from_utf8(&received)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                == Some(checksum(&data));
+            if !self.no_ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(Packet::Command(String::from_utf8_lossy(&data).into_owned())));
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${data}#{:02x}", checksum(data.as_bytes()));
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            if self.no_ack {
+                return Ok(());
+            }
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+fn target_xml() -> String {
+    let core = (0..13)
+        .map(|n| format!("r{n}"))
+        .chain(["sp", "lr", "pc"].map(String::from))
+        .enumerate()
+        .map(|(n, name)| {
+            let kind = match n {
+                13 => "data_ptr",
+                15 => "code_ptr",
+                _ => "uint32",
+            };
+            format!(r#"<reg name="{name}" bitsize="32" type="{kind}" regnum="{n}"/>"#)
+        })
+        .collect::<String>();
+    let banked = BANKED_REGISTER_NAMES
+        .iter()
+        .enumerate()
+        .map(|(n, name)| format!(r#"<reg name="{name}" bitsize="32" type="uint32" regnum="{}"/>"#, n + 17))
+        .collect::<String>();
+
+    // The T field tells GDB whether the pc is executing ARM or THUMB code
+    format!(
+        r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd"><target version="1.0"><architecture>armv4t</architecture><feature name="org.gnu.gdb.arm.core">{core}<flags id="cpsr_flags" size="4"><field name="M" start="0" end="4"/><field name="T" start="5" end="5"/><field name="F" start="6" end="6"/><field name="I" start="7" end="7"/><field name="V" start="28" end="28"/><field name="C" start="29" end="29"/><field name="Z" start="30" end="30"/><field name="N" start="31" end="31"/></flags><reg name="cpsr" bitsize="32" type="cpsr_flags" regnum="16"/></feature><feature name="org.ironboyadvance.arm.banked">{banked}</feature></target>"#
+    )
+}
+
+fn read_xfer(document: &str, range: &str) -> String {
+    let Some((offset, length)) = range.split_once(',').and_then(|(offset, length)| {
+        Some((
+            usize::from_str_radix(offset, 16).ok()?,
+            usize::from_str_radix(length, 16).ok()?,
+        ))
+    }) else {
+        return String::from("E22");
+    };
+    let start = offset.min(document.len());
+    let end = offset.saturating_add(length).min(document.len());
+    match end == document.len() {
+        true => format!("l{}", &document[start..end]),
+        false => format!("m{}", &document[start..end]),
+    }
+}
+
+// Reads from unmapped regions panic in the bus
+fn is_mapped(address: u32) -> bool {
+    matches!(address >> 24, 0x00 | 0x02..=0x0F)
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+// Register values are sent as target endian bytes
+fn encode_u32(value: u32) -> String {
+    value.to_le_bytes().iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_u32(value: &str) -> Option<u32> {
+    let bytes: [u8; 4] = decode_hex(value)?.try_into().ok()?;
+    Some(u32::from_le_bytes(bytes))
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if !value.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(value.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn decode_address(value: &str) -> Option<u32> {
+    u32::from_str_radix(value, 16).ok()
+}
+
+fn parse_address_length(arguments: &str) -> Option<(u32, u32)> {
+    let (address, length) = arguments.split_once(',')?;
+    Some((decode_address(address)?, decode_address(length)?))
+}
+
+fn parse_breakpoint(arguments: &str) -> Option<BreakpointKey> {
+    let mut fields = arguments.split(',');
+    let kind = fields.next()?.parse::<u8>().ok()?;
+    let address = decode_address(fields.next()?)?;
+    let length = decode_address(fields.next()?.split(';').next()?)?;
+    Some((kind, address, length))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, VecDeque},
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+        time::Duration,
+    };
+
+    use ironboyadvance_arm7tdmi::assembler::assemble_arm;
+
+    use super::{
+        GdbServer, INTERRUPT, Packet, Session, checksum, decode_u32, encode_u32, parse_breakpoint, read_xfer, target_xml,
+    };
+    use crate::gba::tests::program_gba;
+
+    // Calls a function at 0x080000D8 and idles, assembled at 0x080000C0
+    const PROGRAM: &str = "
+                mov r0, #1
+                bl function
+                mov r2, #0x03000000
+                str r1, [r2]
+        idle:   b idle
+                .word 0
+        function:
+                mov r1, #2
+                bx lr
+    ";
+
+    // Sends raw packet data and returns the acknowledged reply
+    fn exchange(stream: &mut TcpStream, data: &[u8]) -> String {
+        stream.write_all(b"$").unwrap();
+        stream.write_all(data).unwrap();
+        stream.write_all(format!("#{:02x}", checksum(data)).as_bytes()).unwrap();
+        reply(stream)
+    }
+
+    fn reply(stream: &mut TcpStream) -> String {
+        let mut read_byte = || {
+            let mut byte = [0; 1];
+            stream.read_exact(&mut byte).unwrap();
+            byte[0]
+        };
+        while read_byte() != b'$' {}
+        let data = std::iter::from_fn(|| Some(read_byte()))
+            .take_while(|byte| *byte != b'#')
+            .collect::<Vec<u8>>();
+        let received = [read_byte(), read_byte()];
+        assert_eq!(received[..], *format!("{:02x}", checksum(&data)).as_bytes());
+        stream.write_all(b"+").unwrap();
+        String::from_utf8(data).unwrap()
+    }
+
+    fn register(registers: &str, n: usize) -> u32 {
+        decode_u32(&registers[n * 8..n * 8 + 8]).unwrap()
+    }
+
+    #[test]
+    fn packet_encoding() {
+        assert_eq!(checksum(b"qSupported"), 0x37);
+        assert_eq!(encode_u32(0x08000100), "00010008");
+        assert_eq!(decode_u32("00010008"), Some(0x08000100));
+        assert_eq!(parse_breakpoint("2,3000010,4"), Some((2, 0x03000010, 4)));
+    }
+
+    #[test]
+    fn target_description_transfer() {
+        let xml = target_xml();
+        assert!(xml.contains(r#"<reg name="cpsr" bitsize="32" type="cpsr_flags" regnum="16"/>"#));
+        assert!(xml.contains(r#"name="spsr_und" bitsize="32" type="uint32" regnum="36""#));
+
+        let first = read_xfer(&xml, "0,10");
+        assert_eq!(first, format!("m{}", &xml[..0x10]));
+        let last = read_xfer(&xml, &format!("10,{:x}", xml.len()));
+        assert_eq!(last, format!("l{}", &xml[0x10..]));
+    }
+
+    #[test]
+    fn loopback_session() {
+        let mut gba = program_gba("gdb_session", PROGRAM);
+        let server = GdbServer::bind(0).unwrap();
+        let address = server.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+            let mut replies = Vec::new();
+            for data in [
+                &b"?"[..],
+                b"",
+                &[0xFF],
+                b"g",
+                b"m80000c0,4",
+                b"Z0,80000c4,4",
+                b"c",
+                b"g",
+                b"s",
+                b"pf",
+            ] {
+                replies.push(exchange(&mut stream, data));
+            }
+            // A late ack while running is kept for the packet reader instead of eating the interrupt
+            stream.write_all(b"$c#63").unwrap();
+            thread::sleep(Duration::from_millis(50));
+            stream.write_all(&[b'+', INTERRUPT]).unwrap();
+            replies.push(reply(&mut stream));
+            replies.push(exchange(&mut stream, b"?"));
+            stream.write_all(b"$k#6b").unwrap();
+            replies
+        });
+        server.serve(&mut gba).unwrap();
+        let replies = client.join().unwrap();
+
+        assert_eq!(replies[..3], ["S05", "", ""]);
+        assert_eq!(
+            (register(&replies[3], 13), register(&replies[3], 16) & 0x1F),
+            (0x03007F00, 0x1F)
+        );
+        let mov = assemble_arm("mov r0, #1", 0x080000C0).unwrap();
+        assert_eq!(replies[4], encode_u32(mov));
+        assert_eq!(replies[5..7], ["OK", "T05swbreak:;"]);
+        assert_eq!((register(&replies[7], 0), register(&replies[7], 15)), (1, 0x080000C4));
+        assert_eq!(replies[8], "S05");
+        assert_eq!(replies[9], encode_u32(0x080000D8));
+        assert_eq!(replies[10..], ["S02", "S05"]);
+        assert!(gba.debugger_mut().breakpoints().next().is_none());
+    }
+
+    #[test]
+    fn interrupt_poll_keeps_packet_bytes() {
+        let mut gba = program_gba("gdb_poll", PROGRAM);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let mut session = Session {
+            stream,
+            gba: &mut gba,
+            no_ack: true,
+            pending: VecDeque::new(),
+            breakpoints: HashMap::new(),
+            watchpoints: HashMap::new(),
+        };
+
+        client.write_all(b"$?#3f").unwrap();
+        while session.pending.is_empty() {
+            assert!(!session.interrupted().unwrap());
+        }
+        assert!(matches!(session.read_packet().unwrap(), Some(Packet::Command(command)) if command == "?"));
+    }
+}
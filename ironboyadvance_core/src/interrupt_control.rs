@@ -1,6 +1,7 @@
 use std::{cell::RefCell, rc::Rc};
 
 use bitfields::bitfield;
+use ironboyadvance_utils::state::{SaveState, StateError, StateReader, StateWriter};
 
 #[bitfield(u16)]
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -67,3 +68,18 @@ impl InterruptControl {
             && ((self.interrupt_flags.borrow().into_bits() & self.interrupt_enable.into_bits()) != 0)
     }
 }
+
+impl SaveState for InterruptControl {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.interrupt_master_enable);
+        writer.write_u16(self.interrupt_enable.into_bits());
+        writer.write_u16(self.interrupt_flags.borrow().into_bits());
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.interrupt_master_enable = reader.read_bool()?;
+        self.interrupt_enable = Interrupt::from_bits(reader.read_u16()?);
+        *self.interrupt_flags.borrow_mut() = Interrupt::from_bits(reader.read_u16()?);
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use std::{cell::RefCell, rc::Rc};
 
-use ironboyadvance_arm7tdmi::memory::SystemMemoryAccess;
+use ironboyadvance_arm7tdmi::memory::{DebugMemoryAccess, SystemMemoryAccess};
+use ironboyadvance_utils::state::{SaveState, StateError, StateReader, StateWriter};
 
 use crate::{
     interrupt_control::{Interrupt, InterruptControl},
@@ -9,6 +10,7 @@ use crate::{
     system_control::{HaltMode, SystemControl},
 };
 
+const KEYINPUT: u32 = 0x04000130;
 const IE: u32 = 0x04000200;
 const IF: u32 = 0x04000202;
 const WAITCNT: u32 = 0x04000204;
@@ -20,9 +22,13 @@ pub struct IoRegisters {
     cycle_luts: Rc<RefCell<ClockCycleLuts>>,
     interrupt_control: InterruptControl,
     system_control: SystemControl,
+    key_input: u16,
     data: Vec<u8>,
 }
 
+// KEYINPUT bits are active low, with all ten buttons released
+pub const KEYINPUT_RELEASED: u16 = 0x03FF;
+
 impl IoRegisters {
     pub fn new(scheduler: Rc<RefCell<Scheduler>>, cycle_luts: Rc<RefCell<ClockCycleLuts>>) -> Self {
         let interrupt_flags = Rc::new(RefCell::new(Interrupt::from_bits(0)));
@@ -31,6 +37,7 @@ impl IoRegisters {
             cycle_luts,
             interrupt_control: InterruptControl::new(interrupt_flags.clone()),
             system_control: SystemControl::new(),
+            key_input: KEYINPUT_RELEASED,
             data: vec![0; 0x400],
         }
     }
@@ -46,18 +53,26 @@ impl IoRegisters {
     pub fn un_halt(&mut self) {
         self.system_control.set_halt_mode(HaltMode::Running);
     }
+
+    pub fn key_input(&self) -> u16 {
+        self.key_input
+    }
+
+    pub fn set_key_input(&mut self, value: u16) {
+        self.key_input = value & KEYINPUT_RELEASED;
+    }
 }
 
 //TODO: Work on WaitControl
 impl SystemMemoryAccess for IoRegisters {
     fn read_8(&self, address: u32) -> u8 {
-        match address {
-            _ => 0, //TODO: add tracing for this
-        }
+        let value = self.read_16(address & !0x1);
+        (value >> ((address & 0x1) * 8)) as u8
     }
 
     fn read_16(&self, address: u32) -> u16 {
         match address {
+            KEYINPUT => self.key_input,
             IE => self.interrupt_control.interrupt_enable(),
             IF => self.interrupt_control.interrupt_flags(),
             WAITCNT => self.system_control.waitstate_control().into_bits(),
@@ -72,7 +87,8 @@ impl SystemMemoryAccess for IoRegisters {
                 true => todo!("Figure out whuy Stopped is ignored"),
                 false => self.system_control.set_halt_mode(HaltMode::Halted),
             },
-            _ => {} //TODO: add tracing for this
+            // Latched so debug tools can inspect registers that are not emulated yet
+            _ => self.data[(address & 0x3FF) as usize] = value, //TODO: add tracing for this
         }
     }
 
@@ -91,3 +107,70 @@ impl SystemMemoryAccess for IoRegisters {
         }
     }
 }
+
+// Reads have no side effects yet and fall back to the latched value of registers that are not emulated.
+// Writes store the raw value: IF is not acknowledged, HALTCNT does not halt and KEYINPUT can be poked,
+// only WAITCNT still refreshes the derived cycle tables.
+impl DebugMemoryAccess for IoRegisters {
+    fn debug_read_8(&self, address: u32) -> u8 {
+        match address & !0x1 {
+            KEYINPUT | IE | IF | WAITCNT | IME => self.read_8(address),
+            _ => self.data[(address & 0x3FF) as usize],
+        }
+    }
+
+    fn debug_write_8(&mut self, address: u32, value: u8) {
+        let aligned = address & !0x1;
+        let shift = (address & 0x1) * 8;
+        let merged = (self.read_16(aligned) & !(0xFF << shift)) | ((value as u16) << shift);
+        match aligned {
+            KEYINPUT => self.set_key_input(merged),
+            IE => self.interrupt_control.set_interrupt_enable(merged),
+            IF => self.interrupt_control.set_interrupt_flags(merged),
+            WAITCNT => self.write_16(WAITCNT, merged),
+            IME => self.interrupt_control.set_interrupt_master_enable(merged & 0x1 != 0),
+            _ => self.data[(address & 0x3FF) as usize] = value,
+        }
+    }
+}
+
+impl SaveState for IoRegisters {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.interrupt_control.save_state(writer);
+        self.system_control.save_state(writer);
+        writer.write_u16(self.key_input);
+        writer.write_bytes(&self.data);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.interrupt_control.load_state(reader)?;
+        self.system_control.load_state(reader)?;
+        self.key_input = reader.read_u16()?;
+        reader.read_bytes_into(&mut self.data)?;
+        self.cycle_luts
+            .borrow_mut()
+            .update_wait_states(&self.system_control.waitstate_control());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use ironboyadvance_arm7tdmi::memory::DebugMemoryAccess;
+
+    use super::{HALTCNT, IF, IoRegisters};
+    use crate::{scheduler::Scheduler, system_bus::ClockCycleLuts, system_control::HaltMode};
+
+    #[test]
+    fn debug_writes_have_no_side_effects() {
+        let scheduler = Rc::new(RefCell::new(Scheduler::new()));
+        let mut io_registers = IoRegisters::new(scheduler, Rc::new(RefCell::new(ClockCycleLuts::new())));
+        io_registers.debug_write_16(IF, 0x0001);
+        assert_eq!(io_registers.debug_read_16(IF), 0x0001);
+        io_registers.debug_write_8(HALTCNT, 0x00);
+        assert!(io_registers.halt_mode() == HaltMode::Running);
+        assert_eq!(io_registers.debug_read_8(HALTCNT), 0x00);
+    }
+}
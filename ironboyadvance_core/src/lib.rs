@@ -3,12 +3,17 @@ use ppu::CYCLES_PER_FRAME;
 use thiserror::Error;
 
 mod bios;
-mod cartridge;
+pub mod cartridge;
+pub mod debugger;
 pub mod gba;
+pub mod gdb;
 mod interrupt_control;
 mod io_registers;
+pub mod movie;
 pub mod ppu;
+pub mod rewind;
 mod scheduler;
+pub mod symbols;
 mod system_bus;
 mod system_control;
 
@@ -24,4 +29,22 @@ pub enum GbaError {
     IncorrectHeaderLength,
     #[error("Header parsing failed")]
     HeaderParseFailure,
+    #[error("Save state invalid")]
+    InvalidSaveState,
+    #[error("Movie file invalid")]
+    InvalidMovie,
+    #[error("Movie was recorded with a different ROM")]
+    MovieRomMismatch,
+    #[error("Movie starts from a different power on state")]
+    MovieStartMismatch,
+    #[error("Movie desynced at frame {frame}")]
+    MovieDesync { frame: usize },
+    #[error("Invalid breakpoint condition: {0}")]
+    InvalidBreakCondition(String),
+    #[error("ELF file invalid: {0}")]
+    InvalidElf(&'static str),
+    #[error("Unknown symbol: {0}")]
+    UnknownSymbol(String),
+    #[error("Invalid assembly: {0}")]
+    InvalidAssembly(#[from] ironboyadvance_arm7tdmi::assembler::AssemblyError),
 }
@@ -0,0 +1,332 @@
+use std::path::PathBuf;
+
+use ironboyadvance_utils::{
+    read_file,
+    state::{StateError, StateReader, StateWriter},
+};
+
+use crate::{GbaError, gba::GameBoyAdvance};
+
+const MOVIE_MAGIC: &[u8; 4] = b"IBAM";
+const MOVIE_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieStart {
+    PowerOn { skip_bios: bool },
+    SaveState(Vec<u8>),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub frame: u32,
+    pub state_hash: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    rom_hash: u64,
+    start: MovieStart,
+    hash_interval: u32,
+    inputs: Vec<u16>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl Movie {
+    pub fn new(rom_hash: u64, start: MovieStart, hash_interval: u32) -> Self {
+        Movie {
+            rom_hash,
+            start,
+            hash_interval: hash_interval.max(1),
+            inputs: Vec::new(),
+            checkpoints: Vec::new(),
+        }
+    }
+
+    pub fn load(path: PathBuf) -> Result<Movie, GbaError> {
+        let buffer = read_file(&path).map_err(|_| GbaError::FileLoadFailure)?;
+        Movie::from_bytes(&buffer)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Movie, GbaError> {
+        Movie::read(&mut StateReader::new(bytes)).map_err(|_| GbaError::InvalidMovie)
+    }
+
+    fn read(reader: &mut StateReader) -> Result<Movie, StateError> {
+        let mut magic = [0; 4];
+        for byte in magic.iter_mut() {
+            *byte = reader.read_u8()?;
+        }
+        if &magic != MOVIE_MAGIC || reader.read_u32()? != MOVIE_VERSION {
+            return Err(StateError::InvalidValue);
+        }
+
+        let rom_hash = reader.read_u64()?;
+        let start = match reader.read_u8()? {
+            0 => MovieStart::PowerOn {
+                skip_bios: reader.read_bool()?,
+            },
+            1 => MovieStart::SaveState(reader.read_bytes()?),
+            _ => return Err(StateError::InvalidValue),
+        };
+        let hash_interval = reader.read_u32()?;
+
+        let inputs = (0..reader.read_u32()?)
+            .map(|_| reader.read_u16())
+            .collect::<Result<Vec<u16>, StateError>>()?;
+        let checkpoints = (0..reader.read_u32()?)
+            .map(|_| {
+                Ok(Checkpoint {
+                    frame: reader.read_u32()?,
+                    state_hash: reader.read_u64()?,
+                })
+            })
+            .collect::<Result<Vec<Checkpoint>, StateError>>()?;
+
+        match reader.is_empty() {
+            true => Ok(Movie {
+                rom_hash,
+                start,
+                hash_interval,
+                inputs,
+                checkpoints,
+            }),
+            false => Err(StateError::LengthMismatch),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        MOVIE_MAGIC.iter().for_each(|b| writer.write_u8(*b));
+        writer.write_u32(MOVIE_VERSION);
+        writer.write_u64(self.rom_hash);
+        match &self.start {
+            MovieStart::PowerOn { skip_bios } => {
+                writer.write_u8(0);
+                writer.write_bool(*skip_bios);
+            }
+            MovieStart::SaveState(state) => {
+                writer.write_u8(1);
+                writer.write_bytes(state);
+            }
+        }
+        writer.write_u32(self.hash_interval);
+        writer.write_u32(self.inputs.len() as u32);
+        self.inputs.iter().for_each(|input| writer.write_u16(*input));
+        writer.write_u32(self.checkpoints.len() as u32);
+        for checkpoint in self.checkpoints.iter() {
+            writer.write_u32(checkpoint.frame);
+            writer.write_u64(checkpoint.state_hash);
+        }
+        writer.into_inner()
+    }
+
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    pub fn start(&self) -> &MovieStart {
+        &self.start
+    }
+
+    pub fn inputs(&self) -> &[u16] {
+        &self.inputs
+    }
+
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+}
+
+pub struct MovieRecorder {
+    movie: Movie,
+}
+
+impl MovieRecorder {
+    /// Starts recording a freshly constructed machine, playback has to construct it the same way
+    pub fn from_power_on(gba: &GameBoyAdvance, hash_interval: u32) -> Self {
+        let start = MovieStart::PowerOn {
+            skip_bios: gba.skip_bios(),
+        };
+        MovieRecorder {
+            movie: Movie::new(gba.rom_hash(), start, hash_interval),
+        }
+    }
+
+    pub fn from_save_state(gba: &GameBoyAdvance, hash_interval: u32) -> Self {
+        MovieRecorder {
+            movie: Movie::new(gba.rom_hash(), MovieStart::SaveState(gba.save_state()), hash_interval),
+        }
+    }
+
+    pub fn run_frame(&mut self, gba: &mut GameBoyAdvance, key_input: u16) {
+        gba.set_key_input(key_input);
+        self.movie.inputs.push(gba.key_input());
+        gba.run_frame();
+
+        let frame = self.movie.inputs.len() as u32;
+        if frame.is_multiple_of(self.movie.hash_interval) {
+            self.movie.checkpoints.push(Checkpoint {
+                frame,
+                state_hash: gba.state_hash(),
+            });
+        }
+    }
+
+    pub fn finish(self) -> Movie {
+        self.movie
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PlaybackFrame {
+    pub frame: usize,
+    pub frame_hash: u64,
+}
+
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize,
+    next_checkpoint: usize,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        MoviePlayer {
+            movie,
+            frame: 0,
+            next_checkpoint: 0,
+        }
+    }
+
+    /// Checks the movie belongs to the loaded ROM and restores its embedded save state if it has one.
+    /// Movies recorded from power on need a machine that has not run yet and boots the same way.
+    pub fn prepare(&mut self, gba: &mut GameBoyAdvance) -> Result<(), GbaError> {
+        if gba.rom_hash() != self.movie.rom_hash {
+            return Err(GbaError::MovieRomMismatch);
+        }
+        match &self.movie.start {
+            MovieStart::PowerOn { skip_bios } => {
+                if !gba.is_power_on() || gba.skip_bios() != *skip_bios {
+                    return Err(GbaError::MovieStartMismatch);
+                }
+            }
+            MovieStart::SaveState(state) => gba.load_state(state)?,
+        }
+        self.frame = 0;
+        self.next_checkpoint = 0;
+        Ok(())
+    }
+
+    /// Runs the next recorded frame, returning None once every input has been played back
+    pub fn run_frame(&mut self, gba: &mut GameBoyAdvance) -> Option<Result<PlaybackFrame, GbaError>> {
+        let key_input = *self.movie.inputs.get(self.frame)?;
+        gba.set_key_input(key_input);
+        gba.run_frame();
+        self.frame += 1;
+
+        let checkpoint = self.movie.checkpoints.get(self.next_checkpoint).copied();
+        if let Some(checkpoint) = checkpoint.filter(|c| c.frame as usize == self.frame) {
+            self.next_checkpoint += 1;
+            if gba.state_hash() != checkpoint.state_hash {
+                return Some(Err(GbaError::MovieDesync { frame: self.frame }));
+            }
+        }
+
+        Some(Ok(PlaybackFrame {
+            frame: self.frame,
+            frame_hash: gba.frame_hash(),
+        }))
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.inputs.len()
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Checkpoint, Movie, MoviePlayer, MovieRecorder, MovieStart};
+    use crate::{GbaError, gba::tests::program_gba};
+
+    // Shows the held buttons as the backdrop color and sums them into r4
+    const INPUT_PROGRAM: &str = "
+                mov r0, #0x04000000
+                add r1, r0, #0x130
+                mov r2, #0x05000000
+        loop:   ldrh r3, [r1]
+                strh r3, [r2]
+                add r4, r4, r3
+                b loop
+    ";
+
+    #[test]
+    fn movie_round_trip() {
+        let mut movie = Movie::new(0xDEADBEEF, MovieStart::SaveState(vec![1, 2, 3, 4]), 60);
+        movie.inputs = vec![0x3FF, 0x3FE, 0x3F7];
+        movie.checkpoints = vec![Checkpoint {
+            frame: 3,
+            state_hash: 0x1234,
+        }];
+        assert_eq!(Movie::from_bytes(&movie.to_bytes()).unwrap(), movie);
+
+        let movie = Movie::new(7, MovieStart::PowerOn { skip_bios: true }, 1);
+        assert_eq!(Movie::from_bytes(&movie.to_bytes()).unwrap(), movie);
+    }
+
+    #[test]
+    fn truncated_movie_is_rejected() {
+        let movie = Movie::new(7, MovieStart::PowerOn { skip_bios: false }, 1);
+        let bytes = movie.to_bytes();
+        assert!(matches!(
+            Movie::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(GbaError::InvalidMovie)
+        ));
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let mut gba = program_gba("movie_record", INPUT_PROGRAM);
+        let mut recorder = MovieRecorder::from_power_on(&gba, 1);
+        let mut recorded = Vec::new();
+        for frame in 0..16u16 {
+            // Holds A for a few frames, then walks through the other buttons
+            let key_input = match frame {
+                0..4 => 0x3FE,
+                _ => 0x3FF & !(1 << (frame % 10)),
+            };
+            recorder.run_frame(&mut gba, key_input);
+            recorded.push((gba.frame_hash(), gba.state_hash()));
+        }
+        let movie = Movie::from_bytes(&recorder.finish().to_bytes()).unwrap();
+        assert_eq!(movie.checkpoints().len(), 16);
+        assert_ne!(recorded[0].0, recorded[15].0);
+
+        let mut player = MoviePlayer::new(movie.clone());
+        assert!(matches!(player.prepare(&mut gba), Err(GbaError::MovieStartMismatch)));
+
+        let mut gba = program_gba("movie_replay", INPUT_PROGRAM);
+        player.prepare(&mut gba).unwrap();
+        for (frame, (frame_hash, state_hash)) in recorded.into_iter().enumerate() {
+            let playback = player.run_frame(&mut gba).unwrap().unwrap();
+            assert_eq!((playback.frame, playback.frame_hash), (frame + 1, frame_hash));
+            assert_eq!(gba.state_hash(), state_hash);
+        }
+        assert!(player.is_finished() && player.run_frame(&mut gba).is_none());
+    }
+}
@@ -13,3 +13,17 @@ pub const VDRAW_CYCLES: u32 = VDRAW_SCANLINES * CYCLES_PER_SCANLINE;
 pub const VBLANK_CYCLES: u32 = VBLANK_SCANLINES * CYCLES_PER_SCANLINE;
 
 pub const CYCLES_PER_FRAME: usize = VDRAW_CYCLES as usize + VBLANK_CYCLES as usize;
+
+pub mod oam;
+pub mod registers;
+pub mod render;
+
+/// Expands a BGR555 color to 8 bit RGB, replicating the top bits so white stays white
+pub fn bgr555_to_rgb(color: u16) -> [u8; 3] {
+    let expand = |channel: u16| ((channel << 3) | (channel >> 2)) as u8;
+    [
+        expand(color & 0x1F),
+        expand((color >> 5) & 0x1F),
+        expand((color >> 10) & 0x1F),
+    ]
+}
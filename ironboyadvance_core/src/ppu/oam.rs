@@ -0,0 +1,126 @@
+pub const OBJECT_COUNT: usize = 128;
+pub const OBJECT_ATTRIBUTE_BYTES: usize = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ObjectMode {
+    Normal,
+    SemiTransparent,
+    Window,
+    Prohibited,
+}
+
+/// The three attribute halfwords of one OAM entry, the fourth holds affine parameters
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ObjectAttributes {
+    attributes: [u16; 3],
+}
+
+impl ObjectAttributes {
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        ObjectAttributes {
+            attributes: std::array::from_fn(|i| u16::from_le_bytes([bytes[i * 2], bytes[i * 2 + 1]])),
+        }
+    }
+
+    pub fn attributes(&self) -> [u16; 3] {
+        self.attributes
+    }
+
+    pub fn y(&self) -> u32 {
+        (self.attributes[0] & 0xFF) as u32
+    }
+
+    /// Horizontal position, the 9 bit field is signed so sprites can hang off the left edge
+    pub fn x(&self) -> i32 {
+        (((self.attributes[1] & 0x1FF) << 7) as i16 >> 7) as i32
+    }
+
+    pub fn affine(&self) -> bool {
+        self.attributes[0] & (1 << 8) != 0
+    }
+
+    pub fn double_size(&self) -> bool {
+        self.affine() && self.attributes[0] & (1 << 9) != 0
+    }
+
+    pub fn disabled(&self) -> bool {
+        !self.affine() && self.attributes[0] & (1 << 9) != 0
+    }
+
+    pub fn mode(&self) -> ObjectMode {
+        match (self.attributes[0] >> 10) & 0x3 {
+            0 => ObjectMode::Normal,
+            1 => ObjectMode::SemiTransparent,
+            2 => ObjectMode::Window,
+            _ => ObjectMode::Prohibited,
+        }
+    }
+
+    pub fn mosaic(&self) -> bool {
+        self.attributes[0] & (1 << 12) != 0
+    }
+
+    pub fn palette_256(&self) -> bool {
+        self.attributes[0] & (1 << 13) != 0
+    }
+
+    pub fn affine_parameter(&self) -> Option<u32> {
+        self.affine().then_some(((self.attributes[1] >> 9) & 0x1F) as u32)
+    }
+
+    pub fn horizontal_flip(&self) -> bool {
+        !self.affine() && self.attributes[1] & (1 << 12) != 0
+    }
+
+    pub fn vertical_flip(&self) -> bool {
+        !self.affine() && self.attributes[1] & (1 << 13) != 0
+    }
+
+    pub fn tile_number(&self) -> u32 {
+        (self.attributes[2] & 0x3FF) as u32
+    }
+
+    pub fn priority(&self) -> u32 {
+        ((self.attributes[2] >> 10) & 0x3) as u32
+    }
+
+    pub fn palette(&self) -> u32 {
+        (self.attributes[2] >> 12) as u32
+    }
+
+    /// Width and height in pixels from the shape and size fields
+    pub fn dimensions(&self) -> (u32, u32) {
+        let shape = self.attributes[0] >> 14;
+        let size = self.attributes[1] >> 14;
+        match (shape, size) {
+            (0, size) => (8 << size, 8 << size),
+            (1, 0) => (16, 8),
+            (1, 1) => (32, 8),
+            (1, 2) => (32, 16),
+            (1, _) => (64, 32),
+            (2, 0) => (8, 16),
+            (2, 1) => (8, 32),
+            (2, 2) => (16, 32),
+            (2, _) => (32, 64),
+            _ => (8, 8),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ObjectAttributes, ObjectMode};
+
+    #[test]
+    fn attributes() {
+        // 32x16 horizontal sprite at (-8, 40), flipped, tile 5, priority 2, palette 3
+        let bytes = [40, 0b0100_0100, 0xF8, 0b1011_0001, 5, 0b0011_1000];
+        let object = ObjectAttributes::from_bytes(&bytes);
+        assert_eq!(object.y(), 40);
+        assert_eq!(object.x(), -8);
+        assert_eq!(object.dimensions(), (32, 16));
+        assert_eq!(object.mode(), ObjectMode::SemiTransparent);
+        assert!(object.horizontal_flip() && object.vertical_flip() && !object.disabled());
+        assert_eq!((object.tile_number(), object.priority(), object.palette()), (5, 2, 3));
+    }
+}
@@ -0,0 +1,96 @@
+use bitfields::bitfield;
+
+pub const DISPCNT: u32 = 0x04000000;
+pub const BG0CNT: u32 = 0x04000008;
+pub const BG0HOFS: u32 = 0x04000010;
+pub const BG0VOFS: u32 = 0x04000012;
+pub const BG2X: u32 = 0x04000028;
+pub const BG2Y: u32 = 0x0400002C;
+
+/// Address of BGnCNT
+pub const fn background_control_address(background: u32) -> u32 {
+    BG0CNT + background * 2
+}
+
+/// Addresses of BGnHOFS and BGnVOFS
+pub const fn background_offset_addresses(background: u32) -> (u32, u32) {
+    (BG0HOFS + background * 4, BG0VOFS + background * 4)
+}
+
+/// Addresses of the affine reference point BGnX and BGnY, only BG2 and BG3 have one
+pub const fn background_reference_addresses(background: u32) -> (u32, u32) {
+    (BG2X + (background - 2) * 0x10, BG2Y + (background - 2) * 0x10)
+}
+
+#[bitfield(u16)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct DisplayControl {
+    #[bits(3)]
+    bg_mode: u8,
+    cgb_mode: bool,
+    frame_select: bool,
+    hblank_interval_free: bool,
+    obj_one_dimensional_mapping: bool,
+    forced_blank: bool,
+    bg0_enable: bool,
+    bg1_enable: bool,
+    bg2_enable: bool,
+    bg3_enable: bool,
+    obj_enable: bool,
+    window_0_enable: bool,
+    window_1_enable: bool,
+    obj_window_enable: bool,
+}
+
+impl DisplayControl {
+    pub fn background_enabled(&self, background: u32) -> bool {
+        self.into_bits() & (1 << (8 + background)) != 0
+    }
+}
+
+#[bitfield(u16)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct BackgroundControl {
+    #[bits(2)]
+    priority: u8,
+    #[bits(2)]
+    character_base_block: u8,
+    #[bits(2)]
+    _reserved: u8,
+    mosaic: bool,
+    palette_256: bool,
+    #[bits(5)]
+    screen_base_block: u8,
+    display_area_overflow: bool,
+    #[bits(2)]
+    screen_size: u8,
+}
+
+impl BackgroundControl {
+    /// Map size in pixels for a text background
+    pub fn text_size(&self) -> (u32, u32) {
+        match self.screen_size() {
+            0 => (256, 256),
+            1 => (512, 256),
+            2 => (256, 512),
+            _ => (512, 512),
+        }
+    }
+
+    /// Map size in pixels for an affine background, which is always square
+    pub fn affine_size(&self) -> u32 {
+        128 << self.screen_size()
+    }
+}
+
+/// One entry of a text background map
+#[bitfield(u16)]
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct TextMapEntry {
+    #[bits(10)]
+    tile_number: u16,
+    horizontal_flip: bool,
+    vertical_flip: bool,
+    #[bits(4)]
+    palette: u8,
+}
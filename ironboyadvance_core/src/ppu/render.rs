@@ -0,0 +1,351 @@
+//! Composes the visible frame from video memory once the frame completes.
+//! Backgrounds and sprites are sorted by priority, windows, blending and mosaic are not emulated yet.
+
+use super::{
+    HDRAW_PIXELS, VDRAW_SCANLINES,
+    oam::{OBJECT_ATTRIBUTE_BYTES, OBJECT_COUNT, ObjectAttributes, ObjectMode},
+    registers::{
+        BackgroundControl, DISPCNT, DisplayControl, TextMapEntry, background_control_address, background_offset_addresses,
+        background_reference_addresses,
+    },
+};
+
+pub const FRAME_PIXELS: usize = (HDRAW_PIXELS * VDRAW_SCANLINES) as usize;
+
+const WHITE: u16 = 0x7FFF;
+const CHARACTER_BLOCK_BYTES: usize = 0x4000;
+const SCREEN_BLOCK_BYTES: usize = 0x800;
+const OBJ_TILE_BASE: usize = 0x10000;
+const OBJ_PALETTE_BASE: usize = 256;
+const BITMAP_FRAME_BYTES: usize = 0xA000;
+// Layers drawn in front of the backdrop, sprites win ties against backgrounds of the same priority
+const BACKDROP_RANK: u8 = 4 * 5;
+
+/// The memory and registers the PPU reads
+pub struct VideoMemory<'a> {
+    pub vram: &'a [u8],
+    pub palette: &'a [u8],
+    pub oam: &'a [u8],
+    pub io_16: &'a dyn Fn(u32) -> u16,
+}
+
+impl VideoMemory<'_> {
+    fn io_32(&self, address: u32) -> u32 {
+        ((self.io_16)(address + 2) as u32) << 16 | (self.io_16)(address) as u32
+    }
+
+    fn color(&self, index: usize) -> u16 {
+        u16::from_le_bytes([self.palette[index * 2], self.palette[index * 2 + 1]]) & WHITE
+    }
+
+    fn vram_16(&self, offset: usize) -> u16 {
+        match self.vram.get(offset..offset + 2) {
+            Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]),
+            None => 0,
+        }
+    }
+
+    // Palette index of a pixel within the tile starting at `tile_address`, 0 is transparent
+    fn tile_pixel(&self, tile_address: usize, x: u32, y: u32, palette_256: bool) -> usize {
+        match palette_256 {
+            true => self.vram.get(tile_address + (y * 8 + x) as usize).copied().unwrap_or(0) as usize,
+            false => {
+                let byte = self.vram.get(tile_address + (y * 4 + x / 2) as usize).copied().unwrap_or(0);
+                ((byte >> ((x & 0x1) * 4)) & 0xF) as usize
+            }
+        }
+    }
+}
+
+// Color and rank of the frontmost pixel of each column of a scanline
+struct Line {
+    colors: [u16; HDRAW_PIXELS as usize],
+    ranks: [u8; HDRAW_PIXELS as usize],
+}
+
+impl Line {
+    fn draw(&mut self, x: usize, rank: u8, color: u16) {
+        if rank < self.ranks[x] {
+            self.ranks[x] = rank;
+            self.colors[x] = color;
+        }
+    }
+}
+
+/// Renders the frame as BGR555 pixels, row by row
+pub fn render_frame(memory: &VideoMemory, frame: &mut [u16]) {
+    let display_control = DisplayControl::from_bits((memory.io_16)(DISPCNT));
+    if display_control.forced_blank() {
+        frame.fill(WHITE);
+        return;
+    }
+
+    for y in 0..VDRAW_SCANLINES {
+        let mut line = Line {
+            colors: [memory.color(0); HDRAW_PIXELS as usize],
+            ranks: [BACKDROP_RANK; HDRAW_PIXELS as usize],
+        };
+        for background in 0..4 {
+            if display_control.background_enabled(background) {
+                render_background(memory, display_control, background, y, &mut line);
+            }
+        }
+        if display_control.obj_enable() {
+            render_objects(memory, display_control, y, &mut line);
+        }
+        let start = (y * HDRAW_PIXELS) as usize;
+        frame[start..start + HDRAW_PIXELS as usize].copy_from_slice(&line.colors);
+    }
+}
+
+fn render_background(memory: &VideoMemory, display_control: DisplayControl, background: u32, y: u32, line: &mut Line) {
+    let control = BackgroundControl::from_bits((memory.io_16)(background_control_address(background)));
+    let rank = control.priority() * 5 + 1 + background as u8;
+    match (display_control.bg_mode(), background) {
+        (0, _) | (1, 0 | 1) => render_text_line(memory, control, background, y, rank, line),
+        (1, 2) | (2, 2 | 3) => render_affine_line(memory, control, background, y, rank, line),
+        (3..=5, 2) => render_bitmap_line(memory, display_control, y, rank, line),
+        _ => {}
+    }
+}
+
+fn render_text_line(memory: &VideoMemory, control: BackgroundControl, background: u32, y: u32, rank: u8, line: &mut Line) {
+    let (width, height) = control.text_size();
+    let map_base = control.screen_base_block() as usize * SCREEN_BLOCK_BYTES;
+    let tile_base = control.character_base_block() as usize * CHARACTER_BLOCK_BYTES;
+    let palette_256 = control.palette_256();
+    let (horizontal, vertical) = background_offset_addresses(background);
+    let scroll_x = ((memory.io_16)(horizontal) & 0x1FF) as u32;
+    let map_y = (y + ((memory.io_16)(vertical) & 0x1FF) as u32) % height;
+
+    for x in 0..HDRAW_PIXELS {
+        let map_x = (x + scroll_x) % width;
+        let (tile_x, tile_y) = (map_x / 8, map_y / 8);
+        // Maps wider or taller than 256 pixels continue in the following 32x32 screen blocks
+        let block = (tile_x / 32 + (tile_y / 32) * (width / 256)) as usize;
+        let offset = map_base + block * SCREEN_BLOCK_BYTES + (((tile_y % 32) * 32 + tile_x % 32) * 2) as usize;
+        let entry = TextMapEntry::from_bits(memory.vram_16(offset));
+        let tile_address = tile_base + entry.tile_number() as usize * if palette_256 { 64 } else { 32 };
+        let source_x = if entry.horizontal_flip() { 7 - map_x % 8 } else { map_x % 8 };
+        let source_y = if entry.vertical_flip() { 7 - map_y % 8 } else { map_y % 8 };
+        let index = memory.tile_pixel(tile_address, source_x, source_y, palette_256);
+        if index != 0 {
+            let palette_index = match palette_256 {
+                true => index,
+                false => entry.palette() as usize * 16 + index,
+            };
+            line.draw(x as usize, rank, memory.color(palette_index));
+        }
+    }
+}
+
+fn render_affine_line(memory: &VideoMemory, control: BackgroundControl, background: u32, y: u32, rank: u8, line: &mut Line) {
+    let size = control.affine_size() as i32;
+    let map_base = control.screen_base_block() as usize * SCREEN_BLOCK_BYTES;
+    let tile_base = control.character_base_block() as usize * CHARACTER_BLOCK_BYTES;
+    let (reference_x, reference_y) = background_reference_addresses(background);
+    // 28 bit signed reference point and 16 bit signed parameters, both with 8 fractional bits
+    let reference = |address: u32| ((memory.io_32(address) << 4) as i32) >> 4;
+    let parameter = |index: u32| (memory.io_16)(reference_x - 8 + index * 2) as i16 as i32;
+    let (dx, dmx, dy, dmy) = (parameter(0), parameter(1), parameter(2), parameter(3));
+    let origin_x = reference(reference_x) + dmx * y as i32;
+    let origin_y = reference(reference_y) + dmy * y as i32;
+
+    for x in 0..HDRAW_PIXELS {
+        let mut map_x = (origin_x + dx * x as i32) >> 8;
+        let mut map_y = (origin_y + dy * x as i32) >> 8;
+        if control.display_area_overflow() {
+            map_x = map_x.rem_euclid(size);
+            map_y = map_y.rem_euclid(size);
+        } else if !(0..size).contains(&map_x) || !(0..size).contains(&map_y) {
+            continue;
+        }
+        let tile = memory
+            .vram
+            .get(map_base + ((map_y / 8) * (size / 8) + map_x / 8) as usize)
+            .copied();
+        let tile_address = tile_base + tile.unwrap_or(0) as usize * 64;
+        let index = memory.tile_pixel(tile_address, (map_x % 8) as u32, (map_y % 8) as u32, true);
+        if index != 0 {
+            line.draw(x as usize, rank, memory.color(index));
+        }
+    }
+}
+
+// Bitmaps are drawn unscaled, the BIOS sets their affine parameters to the identity
+fn render_bitmap_line(memory: &VideoMemory, display_control: DisplayControl, y: u32, rank: u8, line: &mut Line) {
+    let frame = match display_control.frame_select() {
+        true => BITMAP_FRAME_BYTES,
+        false => 0,
+    };
+    let (width, height) = match display_control.bg_mode() {
+        5 => (160, 128),
+        _ => (HDRAW_PIXELS, VDRAW_SCANLINES),
+    };
+    if y >= height {
+        return;
+    }
+
+    for x in 0..width {
+        let pixel = (y * width + x) as usize;
+        let color = match display_control.bg_mode() {
+            3 => memory.vram_16(pixel * 2) & WHITE,
+            4 => match memory.vram[frame + pixel] {
+                0 => continue,
+                index => memory.color(index as usize),
+            },
+            _ => memory.vram_16(frame + pixel * 2) & WHITE,
+        };
+        line.draw(x as usize, rank, color);
+    }
+}
+
+fn render_objects(memory: &VideoMemory, display_control: DisplayControl, y: u32, line: &mut Line) {
+    // Bitmap modes use the lower half of sprite VRAM for the frame
+    let first_tile = match display_control.bg_mode() >= 3 {
+        true => 512,
+        false => 0,
+    };
+
+    // Lower OAM indices are drawn in front of sprites of the same priority
+    for index in 0..OBJECT_COUNT {
+        let offset = index * OBJECT_ATTRIBUTE_BYTES;
+        let object = ObjectAttributes::from_bytes(&memory.oam[offset..offset + 6]);
+        if object.disabled() || matches!(object.mode(), ObjectMode::Window | ObjectMode::Prohibited) {
+            continue;
+        }
+        if object.tile_number() < first_tile {
+            continue;
+        }
+
+        let (width, height) = object.dimensions();
+        let (bounds_width, bounds_height) = match object.double_size() {
+            true => (width * 2, height * 2),
+            false => (width, height),
+        };
+        // Sprites wrap around the bottom of the 256 line coordinate space
+        let row = y.wrapping_sub(object.y()) & 0xFF;
+        if row >= bounds_height {
+            continue;
+        }
+
+        // Affine parameters are spread across the fourth halfword of four consecutive entries
+        let matrix = object.affine_parameter().map(|group| {
+            let base = group as usize * 4 * OBJECT_ATTRIBUTE_BYTES + 6;
+            std::array::from_fn::<i32, 4, _>(|i| {
+                let offset = base + i * OBJECT_ATTRIBUTE_BYTES;
+                i16::from_le_bytes([memory.oam[offset], memory.oam[offset + 1]]) as i32
+            })
+        });
+
+        let rank = object.priority() as u8 * 5;
+        for column in 0..bounds_width {
+            let x = object.x() + column as i32;
+            if !(0..HDRAW_PIXELS as i32).contains(&x) {
+                continue;
+            }
+            let (source_x, source_y) = match matrix {
+                Some([pa, pb, pc, pd]) => {
+                    let center_x = column as i32 - bounds_width as i32 / 2;
+                    let center_y = row as i32 - bounds_height as i32 / 2;
+                    let source_x = ((pa * center_x + pb * center_y) >> 8) + width as i32 / 2;
+                    let source_y = ((pc * center_x + pd * center_y) >> 8) + height as i32 / 2;
+                    if !(0..width as i32).contains(&source_x) || !(0..height as i32).contains(&source_y) {
+                        continue;
+                    }
+                    (source_x as u32, source_y as u32)
+                }
+                None => (
+                    if object.horizontal_flip() {
+                        width - 1 - column
+                    } else {
+                        column
+                    },
+                    if object.vertical_flip() { height - 1 - row } else { row },
+                ),
+            };
+
+            let palette_256 = object.palette_256();
+            let tile_bytes = if palette_256 { 64 } else { 32 };
+            // One dimensional mapping stores a sprite's tiles back to back, two dimensional in 32 tile rows
+            let row_tiles = match display_control.obj_one_dimensional_mapping() {
+                true => width / 8 * tile_bytes / 32,
+                false => 32,
+            };
+            let tile = object.tile_number() + source_y / 8 * row_tiles + source_x / 8 * tile_bytes / 32;
+            let tile_address = OBJ_TILE_BASE + (tile as usize & 0x3FF) * 32;
+            let palette_index = memory.tile_pixel(tile_address, source_x % 8, source_y % 8, palette_256);
+            if palette_index != 0 {
+                let palette_index = match palette_256 {
+                    true => palette_index,
+                    false => object.palette() as usize * 16 + palette_index,
+                };
+                line.draw(x as usize, rank, memory.color(OBJ_PALETTE_BASE + palette_index));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FRAME_PIXELS, VideoMemory, render_frame};
+    use crate::ppu::HDRAW_PIXELS;
+
+    fn render(io: &[(u32, u16)], vram: &[u8], palette: &[u8], oam: &[u8]) -> Vec<u16> {
+        let io_16 = |address| io.iter().find(|(a, _)| *a == address).map_or(0, |(_, value)| *value);
+        let memory = VideoMemory {
+            vram,
+            palette,
+            oam,
+            io_16: &io_16,
+        };
+        let mut frame = vec![0; FRAME_PIXELS];
+        render_frame(&memory, &mut frame);
+        frame
+    }
+
+    fn pixel(frame: &[u16], x: u32, y: u32) -> u16 {
+        frame[(y * HDRAW_PIXELS + x) as usize]
+    }
+
+    #[test]
+    fn layers() {
+        let mut vram = vec![0; 0x18000];
+        let mut palette = vec![0; 0x400];
+        let mut oam = vec![0; 0x400];
+        palette[..2].copy_from_slice(&0x1234u16.to_le_bytes());
+
+        // Forced blank
+        let frame = render(&[(0x04000000, 0x0080)], &vram, &palette, &oam);
+        assert!(frame.iter().all(|&pixel| pixel == 0x7FFF));
+
+        // Mode 3 bitmaps cover the backdrop, mode 4 shows it through palette index 0
+        vram[(5 * 240 + 10) * 2] = 0x1F;
+        let frame = render(&[(0x04000000, 0x0403)], &vram, &palette, &oam);
+        assert_eq!((pixel(&frame, 10, 5), pixel(&frame, 11, 5)), (0x001F, 0x0000));
+        let frame = render(&[(0x04000000, 0x0404)], &vram, &palette, &oam);
+        assert_eq!(pixel(&frame, 11, 5), 0x1234);
+
+        // Tile 1 of BG0, filled with color 1, at the top left of a map in screen block 8
+        vram.fill(0);
+        vram[0x20..0x40].fill(0x11);
+        vram[0x4000] = 1;
+        palette[2..4].copy_from_slice(&0x03E0u16.to_le_bytes());
+        // The same tile as an 8x8 sprite at (4, 4) using OBJ palette color 1
+        vram[0x10020..0x10040].fill(0x11);
+        palette[0x202..0x204].copy_from_slice(&0x7C00u16.to_le_bytes());
+        oam[..6].copy_from_slice(&[4, 0, 4, 0, 1, 0]);
+        (1..128).for_each(|object| oam[object * 8 + 1] = 0x02);
+        let io = [(0x04000000, 0x1100), (0x04000008, 0x0801)];
+        let frame = render(&io, &vram, &palette, &oam);
+        assert_eq!(pixel(&frame, 0, 0), 0x03E0);
+        assert_eq!(pixel(&frame, 7, 7), 0x7C00);
+        assert_eq!(pixel(&frame, 11, 11), 0x7C00);
+        assert_eq!(pixel(&frame, 12, 12), 0x1234);
+
+        // A sprite behind a background of higher priority
+        oam[5] = 0x0C;
+        let frame = render(&io, &vram, &palette, &oam);
+        assert_eq!((pixel(&frame, 7, 7), pixel(&frame, 8, 8)), (0x03E0, 0x7C00));
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::VecDeque;
+
+use crate::FPS;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RewindConfig {
+    /// Frames emulated between two captured states
+    pub interval_frames: usize,
+    /// Maximum number of captured states kept
+    pub capacity: usize,
+    /// Captured states stored as XOR deltas against each keyframe
+    pub keyframe_interval: usize,
+}
+
+impl RewindConfig {
+    pub fn for_duration(seconds: f32, interval_frames: usize) -> Self {
+        let interval_frames = interval_frames.max(1);
+        RewindConfig {
+            interval_frames,
+            capacity: ((seconds * FPS) as usize / interval_frames).max(1),
+            keyframe_interval: 30,
+        }
+    }
+}
+
+impl Default for RewindConfig {
+    fn default() -> Self {
+        RewindConfig::for_duration(60.0, 2)
+    }
+}
+
+struct Segment {
+    keyframe: Vec<u8>,
+    keyframe_length: usize,
+    deltas: Vec<Vec<u8>>,
+}
+
+impl Segment {
+    fn len(&self) -> usize {
+        1 + self.deltas.len()
+    }
+}
+
+pub struct RewindBuffer {
+    config: RewindConfig,
+    segments: VecDeque<Segment>,
+    frames_since_capture: usize,
+    length: usize,
+}
+
+impl RewindBuffer {
+    pub fn new(config: RewindConfig) -> Self {
+        RewindBuffer {
+            config,
+            segments: VecDeque::new(),
+            frames_since_capture: 0,
+            length: 0,
+        }
+    }
+
+    pub fn config(&self) -> RewindConfig {
+        self.config
+    }
+
+    /// Returns true once every `interval_frames` calls, when a state should be captured
+    pub fn frame_complete(&mut self) -> bool {
+        self.frames_since_capture += 1;
+        if self.frames_since_capture >= self.config.interval_frames {
+            self.frames_since_capture = 0;
+            return true;
+        }
+        false
+    }
+
+    pub fn push(&mut self, state: &[u8]) {
+        let keyframe_interval = self.config.keyframe_interval.max(1);
+        match self.segments.back_mut() {
+            Some(segment) if segment.len() < keyframe_interval && segment.keyframe_length == state.len() => {
+                let keyframe = decompress(&segment.keyframe);
+                let delta = keyframe.iter().zip(state).map(|(a, b)| a ^ b).collect::<Vec<u8>>();
+                segment.deltas.push(compress(&delta));
+            }
+            _ => self.segments.push_back(Segment {
+                keyframe: compress(state),
+                keyframe_length: state.len(),
+                deltas: Vec::new(),
+            }),
+        }
+        self.length += 1;
+
+        // Whole segments are evicted since every delta depends on its keyframe
+        while self.length > self.config.capacity
+            && self.segments.len() > 1
+            && let Some(segment) = self.segments.pop_front()
+        {
+            self.length -= segment.len();
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let segment = self.segments.back_mut()?;
+        let keyframe = decompress(&segment.keyframe);
+        let state = match segment.deltas.pop() {
+            Some(delta) => keyframe.iter().zip(decompress(&delta)).map(|(a, b)| a ^ b).collect(),
+            None => {
+                self.segments.pop_back();
+                keyframe
+            }
+        };
+        self.length -= 1;
+        self.frames_since_capture = 0;
+        Some(state)
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.segments.clear();
+        self.frames_since_capture = 0;
+        self.length = 0;
+    }
+
+    /// Bytes currently held by compressed states
+    pub fn memory_usage(&self) -> usize {
+        self.segments
+            .iter()
+            .map(|s| s.keyframe.len() + s.deltas.iter().map(|d| d.len()).sum::<usize>())
+            .sum()
+    }
+}
+
+// XOR deltas are mostly zero so states are stored as (zero run, literal run, literal bytes) tuples
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let zeros_start = i;
+        while i < data.len() && data[i] == 0 {
+            i += 1;
+        }
+        let literal_start = i;
+        while i < data.len() && !(data[i] == 0 && data.get(i + 1) == Some(&0)) {
+            i += 1;
+        }
+        write_varint(&mut output, literal_start - zeros_start);
+        write_varint(&mut output, i - literal_start);
+        output.extend_from_slice(&data[literal_start..i]);
+    }
+    output
+}
+
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let zeros = read_varint(data, &mut i);
+        let literals = read_varint(data, &mut i);
+        output.resize(output.len() + zeros, 0);
+        output.extend_from_slice(&data[i..i + literals]);
+        i += literals;
+    }
+    output
+}
+
+fn write_varint(output: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        output.push((value as u8 & 0x7F) | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+fn read_varint(data: &[u8], index: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*index];
+        *index += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RewindBuffer, RewindConfig, compress, decompress};
+
+    #[test]
+    fn compression_round_trip() {
+        let mut data = vec![0u8; 4096];
+        data[10] = 1;
+        data[11] = 0;
+        data[12] = 7;
+        data[4000..4010].copy_from_slice(&[0xFF; 10]);
+        let compressed = compress(&data);
+        assert!(compressed.len() < 64);
+        assert_eq!(decompress(&compressed), data);
+        assert_eq!(decompress(&compress(&[])), Vec::<u8>::new());
+        assert_eq!(decompress(&compress(&[1, 2, 3])), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn rewind_pops_in_reverse_order() {
+        let mut rewind = RewindBuffer::new(RewindConfig {
+            interval_frames: 1,
+            capacity: 100,
+            keyframe_interval: 4,
+        });
+        let states = (0..10u8).map(|i| vec![i; 256]).collect::<Vec<_>>();
+        states.iter().for_each(|s| rewind.push(s));
+        assert_eq!(rewind.len(), 10);
+
+        for state in states.iter().rev() {
+            assert_eq!(rewind.pop().as_ref(), Some(state));
+        }
+        assert!(rewind.pop().is_none());
+    }
+
+    #[test]
+    fn rewind_evicts_oldest_segments() {
+        let mut rewind = RewindBuffer::new(RewindConfig {
+            interval_frames: 1,
+            capacity: 8,
+            keyframe_interval: 4,
+        });
+        (0..20u8).for_each(|i| rewind.push(&[i; 16]));
+        assert!(rewind.len() <= 8);
+        assert_eq!(rewind.pop(), Some(vec![19; 16]));
+    }
+
+    #[test]
+    fn frame_interval() {
+        let mut rewind = RewindBuffer::new(RewindConfig {
+            interval_frames: 3,
+            capacity: 8,
+            keyframe_interval: 4,
+        });
+        let captures = (0..9).filter(|_| rewind.frame_complete()).count();
+        assert_eq!(captures, 3);
+    }
+}
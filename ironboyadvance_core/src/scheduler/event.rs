@@ -23,6 +23,27 @@ pub enum EventType {
     Timer(TimerEvent),
 }
 
+impl EventType {
+    pub const fn into_bits(self) -> u16 {
+        match self {
+            EventType::FrameComplete => 0x0000,
+            EventType::Ppu(PpuEvent::None) => 0x0100,
+            EventType::Apu(ApuEvent::None) => 0x0200,
+            EventType::Timer(TimerEvent::None) => 0x0300,
+        }
+    }
+
+    pub const fn from_bits(bits: u16) -> Option<Self> {
+        match bits {
+            0x0000 => Some(EventType::FrameComplete),
+            0x0100 => Some(EventType::Ppu(PpuEvent::None)),
+            0x0200 => Some(EventType::Apu(ApuEvent::None)),
+            0x0300 => Some(EventType::Timer(TimerEvent::None)),
+            _ => None,
+        }
+    }
+}
+
 pub type FutureEvent = (EventType, usize);
 
 #[derive(Debug, Clone, Eq)]
@@ -1,4 +1,5 @@
 use event::{Event, EventType, FutureEvent};
+use ironboyadvance_utils::state::{SaveState, StateError, StateReader, StateWriter};
 use std::collections::BinaryHeap;
 
 pub mod event;
@@ -85,3 +86,28 @@ impl Scheduler {
         self.events.is_empty()
     }
 }
+
+impl SaveState for Scheduler {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u64(self.timestamp as u64);
+        // Sorted so identical machine states always produce identical bytes
+        let events = self.events.clone().into_sorted_vec();
+        writer.write_u32(events.len() as u32);
+        for event in events {
+            writer.write_u16(event.event_type().into_bits());
+            writer.write_u64(event.time() as u64);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.timestamp = reader.read_u64()? as usize;
+        let length = reader.read_u32()?;
+        let mut events = BinaryHeap::new();
+        for _ in 0..length {
+            let event_type = EventType::from_bits(reader.read_u16()?).ok_or(StateError::InvalidValue)?;
+            events.push(Event::new(event_type, reader.read_u64()? as usize));
+        }
+        self.events = events;
+        Ok(())
+    }
+}
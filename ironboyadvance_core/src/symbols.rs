@@ -0,0 +1,43 @@
+use std::path::Path;
+
+use ironboyadvance_arm7tdmi::disassembler::SymbolTable;
+use ironboyadvance_utils::read_file;
+
+use crate::{
+    GbaError,
+    cartridge::elf::{Elf, is_elf},
+};
+
+/// Loads the symbol table of a devkitARM ELF, or a no$gba style `.sym` file for anything else
+pub fn load_symbols(path: &Path) -> Result<SymbolTable, GbaError> {
+    let bytes = read_file(&path.to_path_buf()).map_err(|_| GbaError::FileLoadFailure)?;
+    match is_elf(&bytes) {
+        true => Ok(Elf::parse(&bytes)?.symbols),
+        false => Ok(parse_sym(&String::from_utf8_lossy(&bytes))),
+    }
+}
+
+/// Parses `ADDRESS NAME` lines, skipping `;` comments and the `.arm`, `.thumb`, `.pool` style directives
+pub fn parse_sym(text: &str) -> SymbolTable {
+    let mut symbols = SymbolTable::new();
+    text.lines()
+        .map(|line| line.split(';').next().unwrap_or_default())
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .filter_map(|(address, name)| Some((u32::from_str_radix(address, 16).ok()?, name.trim())))
+        .filter(|(_, name)| !name.is_empty() && !name.starts_with('.'))
+        .for_each(|(address, name)| symbols.insert(address, name));
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_sym;
+
+    #[test]
+    fn parse_no_cash_symbols() {
+        let symbols = parse_sym("; no$gba symbols\n08000000 start\n080000C0 .arm\n08000200 main ; entry\nzzz\n");
+        assert_eq!(symbols.len(), 2);
+        assert_eq!(symbols.get(0x08000200), Some("main"));
+        assert_eq!(symbols.lookup(0x08000210), Some(("main", 0x10)));
+    }
+}
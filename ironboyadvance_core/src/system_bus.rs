@@ -1,13 +1,20 @@
 use std::{cell::RefCell, rc::Rc};
 
 use ironboyadvance_arm7tdmi::memory::{
-    MemoryAccess, MemoryAccessWidth, MemoryInterface, SystemMemoryAccess, decompose_access_pattern,
+    DebugMemoryAccess, MemoryAccess, MemoryAccessWidth, MemoryInterface, SystemMemoryAccess,
+};
+
+use ironboyadvance_utils::{
+    fnv1a_hash,
+    state::{SaveState, StateError, StateReader, StateWriter},
 };
 
 use crate::{
     bios::Bios,
     cartridge::Cartridge,
+    debugger::{StopReason, WatchKind, Watchpoint, WatchpointId},
     io_registers::IoRegisters,
+    ppu::render::{FRAME_PIXELS, VideoMemory, render_frame},
     scheduler::Scheduler,
     system_control::{HaltMode, WaitStateControl},
 };
@@ -150,45 +157,70 @@ pub struct SystemBus {
     pallete_ram: Vec<u8>,
     vram: Vec<u8>,
     oam: Vec<u8>,
+    frame: Vec<u16>,
     cartridge: Cartridge,
     scheduler: Rc<RefCell<Scheduler>>,
     cycle_luts: Rc<RefCell<ClockCycleLuts>>,
+    watchpoints: Vec<(WatchpointId, Watchpoint)>,
+    watchpoint_hit: Option<StopReason>,
 }
 
 impl MemoryInterface for SystemBus {
-    fn load_8(&mut self, address: u32, access: u8) -> u32 {
+    fn load_8(&mut self, address: u32, access: MemoryAccess) -> u32 {
         self.cycle(address, access, MemoryAccessWidth::Byte);
-        self.read_8(address) as u32
+        let value = self.read_8(address) as u32;
+        self.watch(address, 1, WatchKind::Read, value, access);
+        value
     }
 
-    fn load_16(&mut self, address: u32, access: u8) -> u32 {
+    fn load_16(&mut self, address: u32, access: MemoryAccess) -> u32 {
         self.cycle(address, access, MemoryAccessWidth::HalfWord);
-        self.read_16(address) as u32
+        let value = self.read_16(address) as u32;
+        self.watch(address, 2, WatchKind::Read, value, access);
+        value
     }
 
-    fn load_32(&mut self, address: u32, access: u8) -> u32 {
+    fn load_32(&mut self, address: u32, access: MemoryAccess) -> u32 {
         self.cycle(address, access, MemoryAccessWidth::Word);
-        self.read_32(address)
+        let value = self.read_32(address);
+        self.watch(address, 4, WatchKind::Read, value, access);
+        value
     }
 
-    fn store_8(&mut self, address: u32, value: u8, access: u8) {
+    fn store_8(&mut self, address: u32, value: u8, access: MemoryAccess) {
         self.cycle(address, access, MemoryAccessWidth::Byte);
         self.write_8(address, value);
+        self.watch(address, 1, WatchKind::Write, value as u32, access);
     }
 
-    fn store_16(&mut self, address: u32, value: u16, access: u8) {
+    fn store_16(&mut self, address: u32, value: u16, access: MemoryAccess) {
         self.cycle(address, access, MemoryAccessWidth::HalfWord);
         self.write_16(address, value);
+        self.watch(address, 2, WatchKind::Write, value as u32, access);
     }
 
-    fn store_32(&mut self, address: u32, value: u32, access: u8) {
+    fn store_32(&mut self, address: u32, value: u32, access: MemoryAccess) {
         self.cycle(address, access, MemoryAccessWidth::Word);
         self.write_32(address, value);
+        self.watch(address, 4, WatchKind::Write, value, access);
     }
 
     fn idle_cycle(&mut self) {
         self.scheduler.borrow_mut().update(1);
     }
+
+    fn cycle_count(&self) -> u64 {
+        self.scheduler.borrow().timestamp() as u64
+    }
+
+    // Anything the run loop checks between instructions
+    fn yield_requested(&self) -> bool {
+        let scheduler = self.scheduler.borrow();
+        scheduler.timestamp() > scheduler.timestamp_of_next_event()
+            || self.watchpoint_hit.is_some()
+            || self.interrupt_pending()
+            || self.halt_mode() != HaltMode::Running
+    }
 }
 
 impl SystemMemoryAccess for SystemBus {
@@ -227,6 +259,36 @@ impl SystemMemoryAccess for SystemBus {
     }
 }
 
+impl DebugMemoryAccess for SystemBus {
+    fn debug_read_8(&self, address: u32) -> u8 {
+        match address & 0xFF000000 {
+            BIOS_BASE => self.bios.debug_read_8(address),
+            WRAM_BOARD_BASE => self.wram_board[(address & 0x3FFFF) as usize],
+            WRAM_CHIP_BASE => self.wram_chip[(address & 0x7FFF) as usize],
+            IO_REGISTERS_BASE => self.io_registers.debug_read_8(address),
+            PALETTE_RAM_BASE => self.pallete_ram[(address & 0x3FF) as usize],
+            VRAM_BASE => self.vram[(address & 0x17FFF) as usize],
+            OAM_BASE => self.oam[(address & 0x3FF) as usize],
+            ROM_WS0_LO..=SRAM_HI => self.cartridge.debug_read_8(address),
+            _ => 0,
+        }
+    }
+
+    fn debug_write_8(&mut self, address: u32, value: u8) {
+        match address & 0xFF000000 {
+            BIOS_BASE => self.bios.debug_write_8(address, value),
+            WRAM_BOARD_BASE => self.wram_board[(address & 0x3FFFF) as usize] = value,
+            WRAM_CHIP_BASE => self.wram_chip[(address & 0x7FFF) as usize] = value,
+            IO_REGISTERS_BASE => self.io_registers.debug_write_8(address, value),
+            PALETTE_RAM_BASE => self.pallete_ram[(address & 0x3FF) as usize] = value,
+            VRAM_BASE => self.vram[(address & 0x17FFF) as usize] = value,
+            OAM_BASE => self.oam[(address & 0x3FF) as usize] = value,
+            ROM_WS0_LO..=SRAM_HI => self.cartridge.debug_write_8(address, value),
+            _ => {}
+        }
+    }
+}
+
 impl SystemBus {
     pub fn new(cartridge: Cartridge, bios: Bios, scheduler: Rc<RefCell<Scheduler>>) -> Self {
         let cycle_luts = Rc::new(RefCell::new(ClockCycleLuts::new()));
@@ -238,26 +300,24 @@ impl SystemBus {
             pallete_ram: vec![0; 0x400],
             vram: vec![0; 0x18000],
             oam: vec![0; 0x400],
+            frame: vec![0; FRAME_PIXELS],
             cartridge,
             scheduler,
             cycle_luts: cycle_luts,
+            watchpoints: Vec::new(),
+            watchpoint_hit: None,
         }
     }
 
-    pub fn cycle(&mut self, address: u32, access_pattern: u8, width: MemoryAccessWidth) {
-        let access = decompose_access_pattern(access_pattern)[0];
+    pub fn cycle(&mut self, address: u32, access_pattern: MemoryAccess, width: MemoryAccessWidth) {
+        let sequential = access_pattern.contains(MemoryAccess::SEQUENTIAL);
         let index = ((address >> 24) & 0xF) as usize;
-        let cycles = match width {
-            MemoryAccessWidth::Byte | MemoryAccessWidth::HalfWord => match access {
-                MemoryAccess::NonSequential => self.cycle_luts.borrow().n_cycles_16_lut[index],
-                MemoryAccess::Sequential => self.cycle_luts.borrow().s_cycles_16_lut[index],
-                _ => panic!("Should be NonSequential or Sequential"),
-            },
-            MemoryAccessWidth::Word => match access {
-                MemoryAccess::NonSequential => self.cycle_luts.borrow().n_cycles_32_lut[index],
-                MemoryAccess::Sequential => self.cycle_luts.borrow().s_cycles_32_lut[index],
-                _ => panic!("Should be NonSequential or Sequential"),
-            },
+        let luts = self.cycle_luts.borrow();
+        let cycles = match (width, sequential) {
+            (MemoryAccessWidth::Byte | MemoryAccessWidth::HalfWord, false) => luts.n_cycles_16_lut[index],
+            (MemoryAccessWidth::Byte | MemoryAccessWidth::HalfWord, true) => luts.s_cycles_16_lut[index],
+            (MemoryAccessWidth::Word, false) => luts.n_cycles_32_lut[index],
+            (MemoryAccessWidth::Word, true) => luts.s_cycles_32_lut[index],
         };
 
         self.scheduler.borrow_mut().update(cycles);
@@ -274,6 +334,90 @@ impl SystemBus {
     pub fn un_halt(&mut self) {
         self.io_registers.un_halt();
     }
+
+    pub fn key_input(&self) -> u16 {
+        self.io_registers.key_input()
+    }
+
+    pub fn set_key_input(&mut self, value: u16) {
+        self.io_registers.set_key_input(value);
+    }
+
+    pub fn add_watchpoint(&mut self, id: WatchpointId, watchpoint: Watchpoint) {
+        self.watchpoints.push((id, watchpoint));
+    }
+
+    pub fn remove_watchpoint(&mut self, id: WatchpointId) -> bool {
+        let length = self.watchpoints.len();
+        self.watchpoints.retain(|(watchpoint_id, _)| *watchpoint_id != id);
+        self.watchpoints.len() != length
+    }
+
+    pub fn watchpoints(&self) -> &[(WatchpointId, Watchpoint)] {
+        &self.watchpoints
+    }
+
+    pub fn take_watchpoint_hit(&mut self) -> Option<StopReason> {
+        self.watchpoint_hit.take()
+    }
+
+    // Only data accesses are watched, the first hit of an instruction is the one reported
+    fn watch(&mut self, address: u32, width: u32, access: WatchKind, value: u32, access_pattern: MemoryAccess) {
+        if self.watchpoints.is_empty() || self.watchpoint_hit.is_some() || access_pattern.contains(MemoryAccess::INSTRUCTION)
+        {
+            return;
+        }
+        if let Some((id, _)) = self.watchpoints.iter().find(|(_, w)| w.hit(address, width, access)) {
+            self.watchpoint_hit = Some(StopReason::Watchpoint {
+                id: *id,
+                address,
+                access,
+                value,
+            });
+        }
+    }
+
+    pub fn render_frame(&mut self) {
+        let io_16 = |address| self.io_registers.debug_read_16(address);
+        let memory = VideoMemory {
+            vram: &self.vram,
+            palette: &self.pallete_ram,
+            oam: &self.oam,
+            io_16: &io_16,
+        };
+        render_frame(&memory, &mut self.frame);
+    }
+
+    pub fn frame(&self) -> &[u16] {
+        &self.frame
+    }
+
+    pub fn frame_hash(&self) -> u64 {
+        let bytes = self.frame.iter().flat_map(|pixel| pixel.to_le_bytes()).collect::<Vec<u8>>();
+        fnv1a_hash(&bytes)
+    }
+}
+
+impl SaveState for SystemBus {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.wram_board);
+        writer.write_bytes(&self.wram_chip);
+        self.io_registers.save_state(writer);
+        writer.write_bytes(&self.pallete_ram);
+        writer.write_bytes(&self.vram);
+        writer.write_bytes(&self.oam);
+        self.cartridge.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.read_bytes_into(&mut self.wram_board)?;
+        reader.read_bytes_into(&mut self.wram_chip)?;
+        self.io_registers.load_state(reader)?;
+        reader.read_bytes_into(&mut self.pallete_ram)?;
+        reader.read_bytes_into(&mut self.vram)?;
+        reader.read_bytes_into(&mut self.oam)?;
+        self.cartridge.load_state(reader)
+    }
 }
 
 #[cfg(test)]
@@ -1,4 +1,5 @@
 use bitfields::bitfield;
+use ironboyadvance_utils::state::{SaveState, StateError, StateReader, StateWriter};
 
 #[bitfield(u16)]
 #[derive(Copy, Clone, PartialEq, Eq)]
@@ -31,6 +32,21 @@ pub enum HaltMode {
     Stopped,
 }
 
+impl HaltMode {
+    pub const fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0 => Some(HaltMode::Running),
+            1 => Some(HaltMode::Halted),
+            2 => Some(HaltMode::Stopped),
+            _ => None,
+        }
+    }
+
+    pub const fn into_bits(self) -> u8 {
+        self as u8
+    }
+}
+
 pub struct SystemControl {
     waitstate_control: WaitStateControl,
     halt_mode: HaltMode,
@@ -60,3 +76,16 @@ impl SystemControl {
         self.halt_mode
     }
 }
+
+impl SaveState for SystemControl {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.waitstate_control.into_bits());
+        writer.write_u8(self.halt_mode.into_bits());
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.waitstate_control = WaitStateControl::from_bits(reader.read_u16()?);
+        self.halt_mode = HaltMode::from_bits(reader.read_u8()?).ok_or(StateError::InvalidValue)?;
+        Ok(())
+    }
+}
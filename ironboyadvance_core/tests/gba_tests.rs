@@ -0,0 +1,201 @@
+// Runs the jsmolka gba-tests ROMs from the external/gba-tests submodule headlessly.
+// The tests are ignored by default, run them with `cargo test --test gba_tests -- --ignored`
+// once the submodule is checked out. Frame hashes are compared against tests/gba_tests.hashes,
+// ROMs that are only checked by their output are skipped until their hash is recorded there.
+// bios.gba calls into the BIOS, so it only runs when GBA_TESTS_BIOS names a BIOS image.
+// With the `jit` feature every ROM runs a second time with hot blocks compiled to native code.
+
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::Write,
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+};
+
+use ironboyadvance_arm7tdmi::{CpuState, memory::DebugMemoryAccess};
+use ironboyadvance_core::gba::GameBoyAdvance;
+
+const GBA_TESTS_DIRECTORY: &str = "../external/gba-tests";
+const HASHES_FILE: &str = "tests/gba_tests.hashes";
+const MAX_FRAMES: usize = 600;
+// Path of the BIOS image bios.gba runs with
+const BIOS_VARIABLE: &str = "GBA_TESTS_BIOS";
+// Appends the hash of every frame_hash_only ROM without a reference, `GBA_TESTS_RECORD=1 cargo test ...`
+const RECORD_VARIABLE: &str = "GBA_TESTS_RECORD";
+
+// Every test finishes in a branch to itself
+const ARM_IDLE_LOOP: u32 = 0xEAFFFFFE;
+const THUMB_IDLE_LOOP: u16 = 0xE7FE;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Check {
+    // The number of the failing test is left in r12, 0 when every test passed
+    Register,
+    FrameHash,
+    RegisterWithBios,
+}
+
+enum Outcome {
+    Passed,
+    Skipped(String),
+    FailedTest(u32),
+    FrameHashMismatch { expected: u64, actual: u64 },
+    Timeout,
+    Crashed(String),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "passed"),
+            Outcome::Skipped(reason) => write!(f, "skipped, {reason}"),
+            Outcome::FailedTest(test) => write!(f, "failed test {test}"),
+            Outcome::FrameHashMismatch { expected, actual } => {
+                write!(f, "frame hash {actual:016X}, expected {expected:016X}")
+            }
+            Outcome::Timeout => write!(f, "did not finish within {MAX_FRAMES} frames"),
+            Outcome::Crashed(message) => write!(f, "crashed: {message}"),
+        }
+    }
+}
+
+struct RomRun {
+    r12: u32,
+    frame_hash: u64,
+}
+
+fn expected_frame_hash(rom: &str) -> Option<u64> {
+    let hashes = fs::read_to_string(HASHES_FILE).ok()?;
+    hashes
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(name, _)| *name == rom)
+        .and_then(|(_, hash)| u64::from_str_radix(hash.trim().trim_start_matches("0x"), 16).ok())
+}
+
+fn in_idle_loop(gba: &GameBoyAdvance) -> bool {
+    let cpu = gba.cpu();
+    match cpu.cpsr().state() {
+        CpuState::Arm => cpu.debug_read_32(cpu.next_instruction_address()) == ARM_IDLE_LOOP,
+        CpuState::Thumb => cpu.debug_read_16(cpu.next_instruction_address()) == THUMB_IDLE_LOOP,
+    }
+}
+
+fn record_frame_hash(rom: &str, hash: u64) {
+    let mut hashes = OpenOptions::new().append(true).open(HASHES_FILE).unwrap();
+    writeln!(hashes, "{rom} {hash:016X}").unwrap();
+}
+
+fn run_rom(mut gba: GameBoyAdvance, jit: bool) -> Option<RomRun> {
+    #[cfg(feature = "jit")]
+    gba.set_jit(jit);
+    #[cfg(not(feature = "jit"))]
+    assert!(!jit, "built without the jit feature");
+    for _ in 0..MAX_FRAMES {
+        gba.run_frame();
+        if in_idle_loop(&gba) {
+            return Some(RomRun {
+                r12: gba.cpu().register(12),
+                frame_hash: gba.frame_hash(),
+            });
+        }
+    }
+    None
+}
+
+fn run_gba_test(rom: &str, check: Check, jit: bool) {
+    let path = PathBuf::from(GBA_TESTS_DIRECTORY).join(rom);
+    assert!(path.exists(), "{rom} not found, check out the external/gba-tests submodule");
+
+    // Skipping the BIOS intro only skips its boot code, the ROM still calls its software interrupts
+    let gba = match check {
+        Check::RegisterWithBios => match std::env::var(BIOS_VARIABLE) {
+            Ok(bios) => GameBoyAdvance::new(path, PathBuf::from(bios), true),
+            Err(_) => {
+                let reason = format!("it calls BIOS functions, set {BIOS_VARIABLE} to a BIOS image to run it");
+                println!("{rom}: {}", Outcome::Skipped(reason));
+                return;
+            }
+        },
+        _ => GameBoyAdvance::without_bios(path),
+    };
+    let gba = gba.expect("unable to load rom");
+    let checks_r12 = check != Check::FrameHash;
+
+    let outcome = match panic::catch_unwind(AssertUnwindSafe(|| run_rom(gba, jit))) {
+        Err(error) => Outcome::Crashed(
+            error
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| error.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_default(),
+        ),
+        Ok(None) => Outcome::Timeout,
+        Ok(Some(run)) if checks_r12 && run.r12 != 0 => Outcome::FailedTest(run.r12),
+        Ok(Some(run)) => match expected_frame_hash(rom) {
+            Some(expected) if expected != run.frame_hash => Outcome::FrameHashMismatch {
+                expected,
+                actual: run.frame_hash,
+            },
+            Some(_) => Outcome::Passed,
+            None if checks_r12 => Outcome::Passed,
+            // Both runs have to match the same reference, so only the interpreter records it
+            None if !jit && std::env::var_os(RECORD_VARIABLE).is_some() => {
+                record_frame_hash(rom, run.frame_hash);
+                Outcome::Passed
+            }
+            None => Outcome::Skipped(format!(
+                "no reference frame hash recorded for {:016X}, check the output and rerun with {RECORD_VARIABLE}=1",
+                run.frame_hash
+            )),
+        },
+    };
+
+    println!("{rom}: {outcome}");
+    assert!(
+        matches!(outcome, Outcome::Passed | Outcome::Skipped(_)),
+        "{rom} failed: {outcome}"
+    );
+}
+
+macro_rules! gba_test {
+    ($name:ident, $rom:literal) => {
+        gba_test!($name, $rom, Register);
+    };
+    ($name:ident, $rom:literal, frame_hash_only) => {
+        gba_test!($name, $rom, FrameHash);
+    };
+    ($name:ident, $rom:literal, needs_bios) => {
+        gba_test!($name, $rom, RegisterWithBios);
+    };
+    ($name:ident, $rom:literal, $check:ident) => {
+        mod $name {
+            #[test]
+            #[ignore = "requires the external/gba-tests submodule"]
+            fn interpreter() {
+                super::run_gba_test($rom, super::Check::$check, false);
+            }
+
+            #[cfg(feature = "jit")]
+            #[test]
+            #[ignore = "requires the external/gba-tests submodule"]
+            fn jit() {
+                super::run_gba_test($rom, super::Check::$check, true);
+            }
+        }
+    };
+}
+
+gba_test!(arm, "arm/arm.gba");
+gba_test!(thumb, "thumb/thumb.gba");
+gba_test!(memory, "memory/memory.gba");
+gba_test!(bios, "bios/bios.gba", needs_bios);
+gba_test!(save_none, "save/none.gba");
+gba_test!(save_sram, "save/sram.gba");
+gba_test!(save_flash64, "save/flash64.gba");
+gba_test!(save_flash128, "save/flash128.gba");
+gba_test!(ppu_hello, "ppu/hello.gba", frame_hash_only);
+gba_test!(ppu_shades, "ppu/shades.gba", frame_hash_only);
+gba_test!(ppu_stripes, "ppu/stripes.gba", frame_hash_only);
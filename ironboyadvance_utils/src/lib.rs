@@ -4,6 +4,8 @@ use std::{
     path::PathBuf,
 };
 
+pub mod state;
+
 pub fn read_file(filename: &PathBuf) -> io::Result<Vec<u8>> {
     let mut buffer = Vec::new();
     let mut file = File::open(filename)?;
@@ -11,6 +13,13 @@ pub fn read_file(filename: &PathBuf) -> io::Result<Vec<u8>> {
     Ok(buffer)
 }
 
+/// 64 bit FNV-1a, used for ROM identification and state comparisons
+pub fn fnv1a_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF29CE484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001B3)
+    })
+}
+
 pub mod macros {
     #[macro_export]
     macro_rules! get_set {
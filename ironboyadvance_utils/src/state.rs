@@ -0,0 +1,125 @@
+use std::fmt;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StateError {
+    UnexpectedEnd,
+    LengthMismatch,
+    InvalidValue,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::UnexpectedEnd => write!(f, "save state ended unexpectedly"),
+            StateError::LengthMismatch => write!(f, "save state buffer length mismatch"),
+            StateError::InvalidValue => write!(f, "save state contains an invalid value"),
+        }
+    }
+}
+
+pub trait SaveState {
+    fn save_state(&self, writer: &mut StateWriter);
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), StateError>;
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    buffer: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter { buffer: Vec::new() }
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.buffer.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn into_inner(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+pub struct StateReader<'a> {
+    buffer: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        StateReader { buffer, position: 0 }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], StateError> {
+        let end = self.position.checked_add(length).ok_or(StateError::UnexpectedEnd)?;
+        let bytes = self.buffer.get(self.position..end).ok_or(StateError::UnexpectedEnd)?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::InvalidValue),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<Vec<u8>, StateError> {
+        let length = self.read_u32()? as usize;
+        Ok(self.take(length)?.to_vec())
+    }
+
+    /// Reads a length prefixed block written by `write_bytes` into `destination`, which must be the same size
+    pub fn read_bytes_into(&mut self, destination: &mut [u8]) -> Result<(), StateError> {
+        let length = self.read_u32()? as usize;
+        if length != destination.len() {
+            return Err(StateError::LengthMismatch);
+        }
+        destination.copy_from_slice(self.take(length)?);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position == self.buffer.len()
+    }
+}
@@ -0,0 +1,378 @@
+use std::{fs, path::PathBuf};
+
+use eframe::egui::{self, Color32, RichText};
+use ironboyadvance_arm7tdmi::{
+    CpuState,
+    memory::MemoryInterface,
+    trace::{RingBufferTraceSink, format_text_record},
+};
+use ironboyadvance_core::gba::GameBoyAdvance;
+use regex::{Regex, RegexBuilder};
+
+const LINES_BEFORE_CENTER: u32 = 16;
+const LINES_AFTER_CENTER: u32 = 48;
+const HISTORY_CAPACITY: usize = 10_000;
+const HIGHLIGHT: Color32 = Color32::from_rgb(0xFF, 0xD0, 0x40);
+
+pub enum Matcher {
+    Text(String),
+    Regex(Regex),
+}
+
+impl Matcher {
+    /// Plain text searches ignore case, so do regex searches unless the pattern says otherwise
+    pub fn new(pattern: &str, regex: bool) -> Result<Matcher, String> {
+        match regex {
+            true => RegexBuilder::new(pattern)
+                .case_insensitive(true)
+                .build()
+                .map(Matcher::Regex)
+                .map_err(|e| e.to_string()),
+            false => Ok(Matcher::Text(pattern.to_lowercase())),
+        }
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        match self {
+            Matcher::Text(text) => line.to_lowercase().contains(text),
+            Matcher::Regex(regex) => regex.is_match(line),
+        }
+    }
+}
+
+pub struct ListingLine {
+    pub address: u32,
+    /// Symbol naming `address`, shown on its own line above the instruction
+    pub label: Option<String>,
+    pub text: String,
+}
+
+/// Static disassembly of the instructions surrounding `center`, read without side effects
+pub fn listing(gba: &mut GameBoyAdvance, center: u32, state: CpuState) -> Vec<ListingLine> {
+    let width = match state {
+        CpuState::Arm => 4,
+        CpuState::Thumb => 2,
+    };
+    let center = center & !(width - 1);
+    let start = center.saturating_sub(LINES_BEFORE_CENTER * width);
+    let end = center.saturating_add(LINES_AFTER_CENTER * width);
+    (start..end)
+        .step_by(width as usize)
+        .map(|address| {
+            let opcode = match state {
+                CpuState::Arm => format!("{:08X}", u32::from_le_bytes(gba.debug_read(address, 4).try_into().unwrap())),
+                CpuState::Thumb => format!(
+                    "    {:04X}",
+                    u16::from_le_bytes(gba.debug_read(address, 2).try_into().unwrap())
+                ),
+            };
+            ListingLine {
+                address,
+                label: gba.symbols().get(address).map(String::from),
+                text: format!("{address:08X} {opcode} {}", gba.disassemble_at(address, state)),
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum StateSelection {
+    Current,
+    Arm,
+    Thumb,
+}
+
+pub struct DisassemblyView {
+    history: Option<RingBufferTraceSink>,
+    // The ring buffer belongs to this window rather than to `--logs ring`
+    owns_history: bool,
+    history_lines: Vec<String>,
+    // Cycle count the history was last copied at
+    history_cycles: Option<u64>,
+    follow_pc: bool,
+    center: u32,
+    state: StateSelection,
+    goto_input: String,
+    patch_input: String,
+    search_input: String,
+    regex: bool,
+    matches: Vec<usize>,
+    current_match: usize,
+    scroll_to_history: Option<usize>,
+    status: String,
+}
+
+impl DisassemblyView {
+    /// `history` is the ring buffer from `--logs ring`, without one the window can record its own
+    pub fn new(history: Option<RingBufferTraceSink>) -> Self {
+        DisassemblyView {
+            history,
+            owns_history: false,
+            history_lines: Vec::new(),
+            history_cycles: None,
+            follow_pc: true,
+            center: 0,
+            state: StateSelection::Current,
+            goto_input: String::new(),
+            patch_input: String::new(),
+            search_input: String::new(),
+            regex: false,
+            matches: Vec::new(),
+            current_match: 0,
+            scroll_to_history: None,
+            status: String::new(),
+        }
+    }
+
+    fn refresh_history(&mut self, gba: &GameBoyAdvance) {
+        let cycles = gba.cpu().cycle_count();
+        if self.history_cycles == Some(cycles) {
+            return;
+        }
+        self.history_cycles = Some(cycles);
+        self.history_lines = match &self.history {
+            Some(history) => history.records().iter().map(format_text_record).collect(),
+            None => Vec::new(),
+        };
+        self.update_matches();
+    }
+
+    fn set_recording(&mut self, gba: &mut GameBoyAdvance, record: bool) {
+        match record {
+            true => {
+                let sink = RingBufferTraceSink::new(HISTORY_CAPACITY);
+                gba.set_trace_sink(Some(Box::new(sink.clone())));
+                self.history = Some(sink);
+            }
+            false => {
+                gba.set_trace_sink(None);
+                self.history = None;
+            }
+        }
+        self.owns_history = record;
+        self.history_cycles = None;
+    }
+
+    fn update_matches(&mut self) {
+        self.matches.clear();
+        if self.search_input.is_empty() {
+            return;
+        }
+        match Matcher::new(&self.search_input, self.regex) {
+            Ok(matcher) => {
+                self.matches = (0..self.history_lines.len())
+                    .filter(|i| matcher.is_match(&self.history_lines[*i]))
+                    .collect();
+                self.current_match = self.current_match.min(self.matches.len().saturating_sub(1));
+                self.status = format!("{} matches", self.matches.len());
+            }
+            Err(error) => self.status = error,
+        }
+    }
+
+    // A new search starts from the most recent match
+    fn search(&mut self) {
+        self.update_matches();
+        self.current_match = self.matches.len().saturating_sub(1);
+        self.scroll_to_history = self.matches.last().copied();
+    }
+
+    fn step_match(&mut self, forward: bool) {
+        if self.matches.is_empty() {
+            return;
+        }
+        self.current_match = match forward {
+            true => (self.current_match + 1) % self.matches.len(),
+            false => (self.current_match + self.matches.len() - 1) % self.matches.len(),
+        };
+        self.scroll_to_history = Some(self.matches[self.current_match]);
+        self.status = format!("match {} of {}", self.current_match + 1, self.matches.len());
+    }
+
+    fn export(&mut self, gba: &GameBoyAdvance, lines: &[ListingLine]) {
+        let path = PathBuf::from(format!("disassembly_frame{}.txt", gba.frame_count()));
+        let mut contents = String::from("; disassembly\n");
+        lines.iter().for_each(|line| {
+            if let Some(label) = &line.label {
+                contents.push_str(&format!("{label}:\n"));
+            }
+            contents.push_str(&line.text);
+            contents.push('\n');
+        });
+        contents.push_str("; history\n");
+        self.history_lines.iter().for_each(|line| {
+            contents.push_str(line);
+            contents.push('\n');
+        });
+        self.status = match fs::write(&path, contents) {
+            Ok(()) => format!("saved {}", path.display()),
+            Err(error) => format!("unable to save {}: {error}", path.display()),
+        };
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, gba: &mut GameBoyAdvance, open: &mut bool) {
+        self.refresh_history(gba);
+        let pc = gba.cpu().next_instruction_address();
+        if self.follow_pc {
+            self.center = pc;
+        }
+        let state = match self.state {
+            StateSelection::Current => gba.cpu().cpsr().state(),
+            StateSelection::Arm => CpuState::Arm,
+            StateSelection::Thumb => CpuState::Thumb,
+        };
+        let lines = listing(gba, self.center, state);
+        let matcher = Matcher::new(&self.search_input, self.regex)
+            .ok()
+            .filter(|_| !self.search_input.is_empty());
+
+        egui::Window::new("Disassembly")
+            .open(open)
+            .default_width(760.0)
+            .show(ctx, |ui| {
+                self.toolbar(ui, gba, &lines, state);
+                ui.separator();
+
+                ui.label(RichText::new("Disassembly").strong());
+                egui::ScrollArea::vertical()
+                    .id_salt("listing")
+                    .max_height(260.0)
+                    .show(ui, |ui| {
+                        for line in &lines {
+                            if let Some(label) = &line.label {
+                                ui.label(RichText::new(format!("{label}:")).monospace().strong());
+                            }
+                            let marker = if line.address == pc { "▶" } else { " " };
+                            let mut text = RichText::new(format!("{marker} {}", line.text)).monospace();
+                            if matcher.as_ref().is_some_and(|m| m.is_match(&line.text)) {
+                                text = text.color(HIGHLIGHT);
+                            }
+                            let label = ui.label(text);
+                            if line.address == self.center && self.follow_pc {
+                                label.scroll_to_me(Some(egui::Align::Center));
+                            }
+                        }
+                    });
+
+                ui.separator();
+                self.history(ui, gba);
+            });
+    }
+
+    fn toolbar(&mut self, ui: &mut egui::Ui, gba: &mut GameBoyAdvance, lines: &[ListingLine], state: CpuState) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.follow_pc, "Follow PC");
+            ui.label("Go to");
+            let response = ui.add(egui::TextEdit::singleline(&mut self.goto_input).desired_width(80.0));
+            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                let input = self.goto_input.trim();
+                match u32::from_str_radix(input.trim_start_matches("0x"), 16).or_else(|_| gba.resolve_symbol(input)) {
+                    Ok(address) => {
+                        self.center = address;
+                        self.follow_pc = false;
+                    }
+                    Err(_) => self.status = format!("invalid address or symbol: {}", self.goto_input),
+                }
+            }
+            egui::ComboBox::from_id_salt("disassembly_state")
+                .selected_text(match self.state {
+                    StateSelection::Current => "CPSR state",
+                    StateSelection::Arm => "ARM",
+                    StateSelection::Thumb => "THUMB",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.state, StateSelection::Current, "CPSR state");
+                    ui.selectable_value(&mut self.state, StateSelection::Arm, "ARM");
+                    ui.selectable_value(&mut self.state, StateSelection::Thumb, "THUMB");
+                });
+            if ui.button("Export").clicked() {
+                self.export(gba, lines);
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Patch");
+            let response = ui.add(egui::TextEdit::singleline(&mut self.patch_input).hint_text("instruction"));
+            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                // Written over the instruction at the center of the listing
+                let address = match state {
+                    CpuState::Arm => self.center & !0x3,
+                    CpuState::Thumb => self.center & !0x1,
+                };
+                self.status = match gba.patch(address, &self.patch_input, state) {
+                    Ok(length) => format!("patched {length} bytes at {address:08X}"),
+                    Err(error) => error.to_string(),
+                };
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Search");
+            let response = ui.add(egui::TextEdit::singleline(&mut self.search_input).hint_text("text or regex"));
+            let regex_changed = ui.checkbox(&mut self.regex, "Regex").changed();
+            if response.changed() || regex_changed {
+                self.search();
+            }
+            if ui.button("Previous").clicked() {
+                self.step_match(false);
+            }
+            if ui.button("Next").clicked() {
+                self.step_match(true);
+            }
+            ui.label(&self.status);
+        });
+    }
+
+    fn history(&mut self, ui: &mut egui::Ui, gba: &mut GameBoyAdvance) {
+        ui.horizontal(|ui| {
+            ui.label(RichText::new("History").strong());
+            let mut recording = self.owns_history;
+            if self.history.is_some() && !self.owns_history {
+                ui.label(format!("{} instructions from --logs ring", self.history_lines.len()));
+            } else if self.owns_history || !gba.cpu().is_tracing() {
+                if ui.checkbox(&mut recording, "Record").changed() {
+                    self.set_recording(gba, recording);
+                }
+                ui.label(format!("{} instructions", self.history_lines.len()));
+            } else {
+                ui.label("Another trace log is active, use --logs ring to browse history here");
+            }
+        });
+
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        let mut scroll_area = egui::ScrollArea::both()
+            .id_salt("history")
+            .auto_shrink(false)
+            .stick_to_bottom(true);
+        if let Some(row) = self.scroll_to_history.take() {
+            let offset = row as f32 * (row_height + ui.spacing().item_spacing.y);
+            scroll_area = scroll_area.vertical_scroll_offset(offset);
+        }
+        let current = self.matches.get(self.current_match).copied();
+        scroll_area.show_rows(ui, row_height, self.history_lines.len(), |ui, rows| {
+            for row in rows {
+                let mut text = RichText::new(&self.history_lines[row]).monospace();
+                if current == Some(row) {
+                    text = text.background_color(ui.visuals().selection.bg_fill);
+                } else if self.matches.binary_search(&row).is_ok() {
+                    text = text.color(HIGHLIGHT);
+                }
+                ui.label(text);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Matcher;
+
+    #[test]
+    fn text_and_regex_search() {
+        let line = "      123456 08000100 ARM   E3A0C000 MOV r12, #0x0";
+        assert!(Matcher::new("mov R12", false).unwrap().is_match(line));
+        assert!(!Matcher::new("ldr", false).unwrap().is_match(line));
+        assert!(Matcher::new(r"^\s*\d+ 080001[0-9A-F]{2}", true).unwrap().is_match(line));
+        assert!(Matcher::new("(", true).is_err());
+    }
+}
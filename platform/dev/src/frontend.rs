@@ -0,0 +1,98 @@
+use std::time::Instant;
+
+use eframe::egui;
+use ironboyadvance_arm7tdmi::trace::RingBufferTraceSink;
+use ironboyadvance_core::gba::GameBoyAdvance;
+
+use crate::{FRAME_DURATION, disassembly_view::DisassemblyView, memory_viewer::MemoryViewer, vram_viewer::VramViewer};
+
+/// Debug windows opened at startup
+pub struct FrontendOptions {
+    pub memory: bool,
+    pub vram: bool,
+    pub disassembly: bool,
+    /// Ring buffer from `--logs ring`, browsed by the disassembly window
+    pub history: Option<RingBufferTraceSink>,
+}
+
+/// Runs the emulator inside an egui window so the debug viewers can inspect it live
+pub struct DebugFrontend {
+    gba: GameBoyAdvance,
+    paused: bool,
+    next_frame: Instant,
+    memory_viewer: Option<(MemoryViewer, bool)>,
+    vram_viewer: Option<(VramViewer, bool)>,
+    disassembly_view: Option<(DisassemblyView, bool)>,
+}
+
+impl DebugFrontend {
+    pub fn new(gba: GameBoyAdvance, options: FrontendOptions) -> Self {
+        DebugFrontend {
+            gba,
+            paused: false,
+            next_frame: Instant::now(),
+            memory_viewer: options.memory.then(|| (MemoryViewer::new(), true)),
+            vram_viewer: options.vram.then(|| (VramViewer::new(), true)),
+            disassembly_view: options.disassembly.then(|| (DisassemblyView::new(options.history), true)),
+        }
+    }
+
+    pub fn run(self) -> eframe::Result {
+        eframe::run_native(
+            "Iron Boy Advance",
+            eframe::NativeOptions::default(),
+            Box::new(|_| Ok(Box::new(self))),
+        )
+    }
+
+    fn controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            let label = if self.paused { "Resume" } else { "Pause" };
+            if ui.button(label).clicked() {
+                self.paused = !self.paused;
+            }
+            if ui.add_enabled(self.paused, egui::Button::new("Step frame")).clicked() {
+                self.gba.run_frame();
+            }
+            if ui.add_enabled(self.paused, egui::Button::new("Step instruction")).clicked() {
+                self.gba.step_instruction();
+            }
+            if let Some((_, open)) = self.memory_viewer.as_mut() {
+                ui.toggle_value(open, "Memory");
+            }
+            if let Some((_, open)) = self.vram_viewer.as_mut() {
+                ui.toggle_value(open, "VRAM");
+            }
+            if let Some((_, open)) = self.disassembly_view.as_mut() {
+                ui.toggle_value(open, "Disassembly");
+            }
+            ui.label(format!("Frame {}", self.gba.frame_count()));
+        });
+    }
+}
+
+impl eframe::App for DebugFrontend {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if !self.paused && Instant::now() >= self.next_frame {
+            self.gba.run_frame();
+            self.next_frame = Instant::now() + FRAME_DURATION;
+        }
+
+        egui::TopBottomPanel::top("controls").show(ctx, |ui| self.controls(ui));
+        egui::CentralPanel::default().show(ctx, |_| {});
+
+        if let Some((viewer, open)) = self.memory_viewer.as_mut() {
+            viewer.show(ctx, &mut self.gba, open);
+        }
+        if let Some((viewer, open)) = self.vram_viewer.as_mut() {
+            viewer.show(ctx, &self.gba, open);
+        }
+        if let Some((view, open)) = self.disassembly_view.as_mut() {
+            view.show(ctx, &mut self.gba, open);
+        }
+
+        if !self.paused {
+            ctx.request_repaint_after(self.next_frame.saturating_duration_since(Instant::now()));
+        }
+    }
+}
@@ -1,43 +1,363 @@
-use ironboyadvance_core::{FPS, gba::GameBoyAdvance};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    process::ExitCode,
+};
 
-use clap::{ArgAction, Parser};
+use ironboyadvance_arm7tdmi::{
+    disassembler::{DisassemblyOptions, DisassemblyStyle, SymbolTable},
+    profiler::function_name,
+    trace::{BinaryTraceSink, RingBufferTraceSink, TextTraceSink, TraceSink},
+};
+use ironboyadvance_core::{
+    FPS,
+    cartridge::{
+        elf::{Elf, is_elf},
+        header::Header,
+    },
+    gba::GameBoyAdvance,
+    gdb::GdbServer,
+    symbols::load_symbols,
+};
+
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use frontend::{DebugFrontend, FrontendOptions};
+use trace_format::{ForeignFormat, ForeignTraceSink};
+
+mod disassembly_view;
+mod frontend;
+mod memory_viewer;
+mod rom_disassembly;
+mod trace_diff;
+mod trace_format;
+mod vram_render;
+mod vram_viewer;
 
 const FRAME_DURATION_NANOS: f32 = 1_000_000_000.0 / FPS;
 const FRAME_DURATION: std::time::Duration = std::time::Duration::from_nanos(FRAME_DURATION_NANOS as u64);
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    /// One line per instruction with disassembly and registers
+    Text,
+    /// Compact fixed size records without disassembly
+    Binary,
+    /// Keeps the most recent instructions in memory and dumps them if emulation panics
+    Ring,
+    /// mGBA compatible trace log
+    Mgba,
+    /// NanoBoyAdvance compatible trace log
+    Nba,
+}
+
 #[derive(Parser)]
 #[command(name = "Iron Boy Advance")]
 #[command(about = "CLI for Iron Boy Advance", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct DeveloperCli {
-    #[arg(short, long, help = "Rom file to be loaded")]
-    rom: String,
-    #[arg(short, long, help = "Bios file to be loaded")]
-    bios: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[arg(short, long, required = true, help = "Rom file to be loaded")]
+    rom: Option<String>,
+    #[arg(short, long, required = true, help = "Bios file to be loaded")]
+    bios: Option<String>,
     #[arg(short, long, action = ArgAction::SetTrue, required = false, help = "Skips bios")]
     skip_bios: bool,
-    #[arg(short, long, action = ArgAction::SetTrue, required = false, help = "Opens log viewer window")]
-    logs: bool,
+    #[arg(long, action = ArgAction::SetTrue, help = "Runs blocks of instructions decoded once instead of decoding every instruction")]
+    block_cache: bool,
+    #[cfg(feature = "jit")]
+    #[arg(long, action = ArgAction::SetTrue, help = "Compiles hot blocks to native code")]
+    jit: bool,
+    #[arg(short, long, value_enum, help = "Traces executed instructions in the given format")]
+    logs: Option<LogFormat>,
+    #[arg(long, help = "File the trace is written to, stdout for text logs when omitted")]
+    log_file: Option<PathBuf>,
+    #[arg(long, default_value_t = 10_000, help = "Instructions kept by the ring buffer log")]
+    log_capacity: usize,
+    #[arg(
+        long,
+        help = "ELF or no$gba .sym file with symbols, defaults to a .elf or .sym next to the rom"
+    )]
+    symbols: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Runs headless with the profiler and writes folded stacks for flamegraphs to this file"
+    )]
+    profile: Option<PathBuf>,
+    #[arg(long, default_value_t = 600, help = "Frames run by --profile")]
+    profile_frames: u64,
+    #[arg(long, help = "Waits for GDB to attach on this localhost port before running")]
+    gdb: Option<u16>,
     #[arg(short, long, action = ArgAction::SetTrue, required = false, help = "Opens memory viewer window")]
     memory: bool,
     #[arg(short, long, action = ArgAction::SetTrue, required = false, help = "Opens vram viewer window")]
     vram: bool,
+    #[arg(short, long, action = ArgAction::SetTrue, required = false, help = "Opens disassembler window")]
+    disassembly: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compares a binary trace from `--logs binary` against another emulator's log
+    TraceDiff {
+        #[arg(help = "Binary trace recorded by Iron Boy Advance")]
+        ours: PathBuf,
+        #[arg(help = "Reference log recorded by another emulator")]
+        reference: PathBuf,
+        #[arg(short, long, value_enum, help = "Format of the reference log")]
+        format: ForeignFormat,
+        #[arg(long, value_parser = parse_address, help = "Skips both traces to the first instruction at this address")]
+        start: Option<u32>,
+    },
+    /// Disassembles a ROM by following branches from the header entry point
+    Disasm {
+        #[arg(help = "Rom file to be disassembled")]
+        rom: PathBuf,
+        #[arg(short, long, help = "File the listing is written to, stdout when omitted")]
+        output: Option<PathBuf>,
+        #[arg(long, action = ArgAction::SetTrue, help = "Prints GNU style mnemonics")]
+        gnu: bool,
+        #[arg(long, help = "ELF or no$gba .sym file naming functions in the listing")]
+        symbols: Option<PathBuf>,
+    },
+}
+
+fn parse_address(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value.trim_start_matches("0x"), 16).map_err(|e| e.to_string())
+}
+
+fn trace_diff(ours: PathBuf, reference: PathBuf, format: ForeignFormat, start: Option<u32>) -> ExitCode {
+    let traces = trace_diff::load_ours(&ours).and_then(|ours| {
+        let reference = trace_diff::load_reference(&reference, format)?;
+        Ok((ours, reference))
+    });
+    let (ours, reference) = match traces {
+        Ok(traces) => traces,
+        Err(error) => {
+            eprintln!("{error}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let ours = trace_diff::align(&ours, start);
+    let reference = trace_diff::align(&reference, start);
+    match trace_diff::first_divergence(ours, reference) {
+        Some(divergence) => {
+            println!("{}", trace_diff::report(&divergence, ours));
+            ExitCode::FAILURE
+        }
+        None => {
+            println!("{} instructions match", reference.len());
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+fn disasm(rom: PathBuf, symbols: Option<PathBuf>, output: Option<PathBuf>, gnu: bool) -> ExitCode {
+    let image = std::fs::read(&rom)
+        .map_err(|error| error.to_string())
+        .and_then(|bytes| match is_elf(&bytes) {
+            true => Elf::parse(&bytes)
+                .and_then(|elf| Ok((elf.rom_image()?, elf.symbols)))
+                .map_err(|error| error.to_string()),
+            false => Ok((bytes, SymbolTable::new())),
+        })
+        .and_then(|(bytes, mut table)| {
+            if let Some(path) = &symbols {
+                let loaded = load_symbols(path).map_err(|error| error.to_string())?;
+                table.extend(loaded.iter().map(|(address, name)| (address, name.to_string())));
+            }
+            let header = Header::load(&bytes).map_err(|error| error.to_string())?;
+            Ok((bytes, table, header))
+        });
+    let (bytes, symbols, header) = match image {
+        Ok(image) => image,
+        Err(error) => {
+            eprintln!("unable to load {}: {error}", rom.display());
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let analysis = rom_disassembly::explore(&bytes, header.rom_entry_point());
+    let options = DisassemblyOptions {
+        style: if gnu {
+            DisassemblyStyle::Gnu
+        } else {
+            DisassemblyStyle::Native
+        },
+        symbols: Some(&symbols),
+    };
+    let mut writer = log_writer(&output);
+    let result = writeln!(
+        writer,
+        "; {} ({}), entry point {:08X}, {} instructions, {} literals",
+        header.game_title().trim_end_matches('\0'),
+        header.game_code(),
+        header.rom_entry_point(),
+        analysis.code.len(),
+        analysis.data.len()
+    )
+    .and_then(|_| writer.write_all(rom_disassembly::listing(&bytes, &analysis, &options).as_bytes()))
+    .and_then(|_| writer.flush());
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("unable to write listing: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+// Emulates `frames` as fast as possible and reports where the cycles went
+fn profile(mut gba: GameBoyAdvance, output: PathBuf, frames: u64) -> ExitCode {
+    gba.start_profiling();
+    (0..frames).for_each(|_| gba.run_frame());
+    let profiler = gba.stop_profiling().unwrap();
+
+    if let Err(error) = std::fs::write(&output, profiler.folded(gba.symbols())) {
+        eprintln!("unable to write {}: {error}", output.display());
+        return ExitCode::FAILURE;
+    }
+    let total = profiler.total_cycles().max(1);
+    println!(
+        "{} cycles over {frames} frames, folded stacks written to {}",
+        total,
+        output.display()
+    );
+    println!(
+        "{:>12} {:>7} {:>12} {:>7} {:>8}  function",
+        "exclusive", "%", "inclusive", "%", "calls"
+    );
+    for function in profiler.functions().iter().take(30) {
+        println!(
+            "{:>12} {:>6.2}% {:>12} {:>6.2}% {:>8}  {}",
+            function.exclusive,
+            function.exclusive as f64 * 100.0 / total as f64,
+            function.inclusive,
+            function.inclusive as f64 * 100.0 / total as f64,
+            function.calls,
+            function_name(gba.symbols(), function.function)
+        );
+    }
+    ExitCode::SUCCESS
+}
+
+fn log_writer(log_file: &Option<PathBuf>) -> Box<dyn Write> {
+    match log_file {
+        Some(path) => Box::new(BufWriter::new(File::create(path).expect("unable to create log file"))),
+        None => Box::new(BufWriter::new(io::stdout())),
+    }
 }
 
-fn main() {
+fn main() -> ExitCode {
     let cli = DeveloperCli::parse();
-    let show_logs = cli.logs;
-    let _show_memory = cli.memory;
-    let _show_vram = cli.vram;
+    match cli.command {
+        Some(Command::TraceDiff {
+            ours,
+            reference,
+            format,
+            start,
+        }) => return trace_diff(ours, reference, format, start),
+        Some(Command::Disasm {
+            rom,
+            output,
+            gnu,
+            symbols,
+        }) => return disasm(rom, symbols, output, gnu),
+        None => {}
+    }
+
+    let rom = PathBuf::from(cli.rom.unwrap());
+    let mut game_boy_advance = GameBoyAdvance::new(rom.clone(), cli.bios.unwrap().into(), cli.skip_bios).unwrap();
+    let symbols = cli.symbols.or_else(|| {
+        ["elf", "sym"]
+            .into_iter()
+            .map(|extension| rom.with_extension(extension))
+            .find(|path| path.exists() && *path != rom)
+    });
+    if cli.block_cache {
+        game_boy_advance.set_block_cache(true);
+    }
+    #[cfg(feature = "jit")]
+    if cli.jit {
+        game_boy_advance.set_jit(true);
+    }
+    if let Some(path) = symbols {
+        match game_boy_advance.load_symbols(&path) {
+            Ok(count) => println!("Loaded {count} symbols from {}", path.display()),
+            Err(error) => eprintln!("unable to load symbols from {}: {error}", path.display()),
+        }
+    }
 
-    //TODO: build out the windows
-    let mut game_boy_advance = GameBoyAdvance::new(cli.rom.into(), cli.bios.into(), show_logs, cli.skip_bios).unwrap();
-    let mut overshoot = 0;
-    'game: loop {
-        let frame_start_time = std::time::Instant::now();
-        overshoot = game_boy_advance.run(overshoot);
+    let mut ring_buffer = None;
+    let trace_sink: Option<Box<dyn TraceSink>> = match cli.logs {
+        None => None,
+        Some(LogFormat::Text) => Some(Box::new(TextTraceSink::new(log_writer(&cli.log_file)))),
+        Some(LogFormat::Binary) => {
+            let path = cli.log_file.clone().unwrap_or_else(|| PathBuf::from("trace.bin"));
+            Some(Box::new(BinaryTraceSink::new(log_writer(&Some(path)))))
+        }
+        Some(LogFormat::Mgba) => Some(Box::new(ForeignTraceSink::new(
+            ForeignFormat::Mgba,
+            log_writer(&cli.log_file),
+        ))),
+        Some(LogFormat::Nba) => Some(Box::new(ForeignTraceSink::new(
+            ForeignFormat::NanoBoyAdvance,
+            log_writer(&cli.log_file),
+        ))),
+        Some(LogFormat::Ring) => {
+            let sink = RingBufferTraceSink::new(cli.log_capacity);
+            ring_buffer = Some(sink.clone());
+            Some(Box::new(sink))
+        }
+    };
+    game_boy_advance.set_trace_sink(trace_sink);
+
+    if let Some(port) = cli.gdb {
+        let server = GdbServer::bind(port).expect("unable to bind gdb server");
+        println!("Waiting for gdb on {}", server.local_addr().unwrap());
+        if let Err(error) = server.serve(&mut game_boy_advance) {
+            eprintln!("gdb session ended: {error}");
+        }
+    }
+
+    if let Some(output) = cli.profile {
+        return profile(game_boy_advance, output, cli.profile_frames);
+    }
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        if cli.memory || cli.vram || cli.disassembly {
+            let options = FrontendOptions {
+                memory: cli.memory,
+                vram: cli.vram,
+                disassembly: cli.disassembly,
+                history: ring_buffer.clone(),
+            };
+            if let Err(error) = DebugFrontend::new(game_boy_advance, options).run() {
+                eprintln!("unable to open debug window: {error}");
+            }
+            return;
+        }
+        loop {
+            let frame_start_time = std::time::Instant::now();
+            game_boy_advance.run_frame();
+
+            while frame_start_time.elapsed().as_micros() < FRAME_DURATION.as_micros() {
+                std::hint::spin_loop();
+            }
+        }
+    }));
 
-        while frame_start_time.elapsed().as_micros() < FRAME_DURATION.as_micros() {
-            std::hint::spin_loop();
+    if let Err(error) = result {
+        // Dumps the instructions leading up to the panic before unwinding further
+        if let Some(ring_buffer) = ring_buffer {
+            let mut sink = TextTraceSink::new(log_writer(&cli.log_file));
+            ring_buffer.records().iter().for_each(|record| sink.trace(record));
+            sink.flush();
         }
+        panic::resume_unwind(error);
     }
+    ExitCode::SUCCESS
 }
@@ -0,0 +1,341 @@
+use eframe::egui::{self, RichText};
+use ironboyadvance_core::{debugger::MemoryRegion, gba::GameBoyAdvance};
+
+const BYTES_PER_ROW: u32 = 16;
+const MAX_SEARCH_RESULTS: usize = 256;
+const SEARCH_CHUNK_BYTES: usize = 0x10000;
+// The ROM region covers all three wait state mirrors, only the first one is browsed and searched
+const ROM_VIEW_BYTES: u32 = 0x200_0000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ViewType {
+    U8,
+    U16,
+    U32,
+    Ascii,
+}
+
+impl ViewType {
+    fn width(self) -> u32 {
+        match self {
+            ViewType::U8 | ViewType::Ascii => 1,
+            ViewType::U16 => 2,
+            ViewType::U32 => 4,
+        }
+    }
+}
+
+/// First address and length of the part of `region` the viewer shows
+pub fn view_range(region: MemoryRegion) -> (u32, u32) {
+    let range = region.range();
+    let length = range.end() - range.start() + 1;
+    match region {
+        MemoryRegion::Rom => (*range.start(), ROM_VIEW_BYTES),
+        _ => (*range.start(), length),
+    }
+}
+
+/// Region holding `address` and where the viewer shows it, ROM mirrors fold onto the first one
+pub fn view_address(address: u32) -> Option<(MemoryRegion, u32)> {
+    let region = MemoryRegion::ALL
+        .into_iter()
+        .find(|region| region.range().contains(&address))?;
+    let (start, length) = view_range(region);
+    Some((region, start + (address - start) % length))
+}
+
+fn parse_number(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse::<u64>(),
+    }
+    .map_err(|_| format!("invalid number: {value}"))
+}
+
+/// Parses a search pattern: `"text"`, a typed value such as `u16:0x1234` or `u32:100`,
+/// or hex bytes such as `DE AD BE EF`
+pub fn parse_pattern(input: &str) -> Result<Vec<u8>, String> {
+    let input = input.trim();
+    if let Some(text) = input.strip_prefix('"') {
+        let text = text.strip_suffix('"').ok_or("unterminated string")?;
+        return match text.is_empty() {
+            true => Err(String::from("empty pattern")),
+            false => Ok(text.as_bytes().to_vec()),
+        };
+    }
+
+    let typed = [("u8:", 1), ("u16:", 2), ("u32:", 4)]
+        .into_iter()
+        .find_map(|(prefix, width)| input.strip_prefix(prefix).map(|value| (value, width)));
+    if let Some((value, width)) = typed {
+        let value = parse_number(value)?;
+        if width < 8 && value >> (width * 8) != 0 {
+            return Err(format!("{value:#X} does not fit in {} bits", width * 8));
+        }
+        return Ok(value.to_le_bytes()[..width].to_vec());
+    }
+
+    let digits = input.split_whitespace().collect::<String>();
+    if let Some(invalid) = digits.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(format!("invalid hex digit: {invalid}"));
+    }
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return Err(String::from("expected an even number of hex digits"));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| format!("invalid hex byte: {}", &digits[i..i + 2])))
+        .collect()
+}
+
+/// Addresses in `[start, start + length)` where `pattern` begins, up to `MAX_SEARCH_RESULTS`
+pub fn search(gba: &GameBoyAdvance, start: u32, length: u32, pattern: &[u8]) -> Vec<u32> {
+    let mut results = Vec::new();
+    let mut offset = 0;
+    while offset < length as usize && results.len() < MAX_SEARCH_RESULTS {
+        // Chunks overlap by the pattern length so matches across a boundary are found
+        let chunk_length = SEARCH_CHUNK_BYTES.min(length as usize - offset);
+        let read_length = (chunk_length + pattern.len() - 1).min(length as usize - offset);
+        let chunk = gba.debug_read(start + offset as u32, read_length);
+        results.extend(
+            chunk
+                .windows(pattern.len())
+                .take(chunk_length)
+                .enumerate()
+                .filter(|(_, window)| *window == pattern)
+                .map(|(i, _)| start + (offset + i) as u32),
+        );
+        offset += chunk_length;
+    }
+    results.truncate(MAX_SEARCH_RESULTS);
+    results
+}
+
+pub struct MemoryViewer {
+    region: MemoryRegion,
+    view_type: ViewType,
+    goto_input: String,
+    scroll_to: Option<u32>,
+    selected: Option<u32>,
+    edit_input: String,
+    search_input: String,
+    search_results: Vec<u32>,
+    status: String,
+}
+
+impl MemoryViewer {
+    pub fn new() -> Self {
+        MemoryViewer {
+            region: MemoryRegion::WramBoard,
+            view_type: ViewType::U8,
+            goto_input: String::new(),
+            scroll_to: None,
+            selected: None,
+            edit_input: String::new(),
+            search_input: String::new(),
+            search_results: Vec::new(),
+            status: String::new(),
+        }
+    }
+
+    fn jump(&mut self, address: u32) {
+        match view_address(address) {
+            Some((region, address)) => {
+                self.region = region;
+                self.scroll_to = Some(address);
+                self.select(address);
+                self.status.clear();
+            }
+            None => self.status = format!("{address:08X} is not mapped"),
+        }
+    }
+
+    fn select(&mut self, address: u32) {
+        let address = address & !(self.view_type.width() - 1);
+        self.selected = Some(address);
+        self.edit_input.clear();
+    }
+
+    fn cell_text(&self, gba: &GameBoyAdvance, address: u32) -> String {
+        let bytes = gba.debug_read(address, self.view_type.width() as usize);
+        match self.view_type {
+            ViewType::U8 => format!("{:02X}", bytes[0]),
+            ViewType::U16 => format!("{:04X}", u16::from_le_bytes([bytes[0], bytes[1]])),
+            ViewType::U32 => format!("{:08X}", u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+            ViewType::Ascii => match bytes[0] {
+                0x20..=0x7E => (bytes[0] as char).to_string(),
+                _ => String::from("."),
+            },
+        }
+    }
+
+    fn write_selected(&mut self, gba: &mut GameBoyAdvance) {
+        let Some(address) = self.selected else {
+            return;
+        };
+        let width = self.view_type.width() as usize;
+        let bytes = match self.view_type {
+            ViewType::Ascii => self.edit_input.bytes().take(1).collect::<Vec<u8>>(),
+            _ => match u64::from_str_radix(self.edit_input.trim().trim_start_matches("0x"), 16) {
+                Ok(value) if value >> (width * 8) == 0 => value.to_le_bytes()[..width].to_vec(),
+                _ => {
+                    self.status = format!("invalid value: {}", self.edit_input);
+                    return;
+                }
+            },
+        };
+        gba.debug_write(address, &bytes);
+        self.status = format!("wrote {} at {address:08X}", self.edit_input.trim());
+        self.edit_input.clear();
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, gba: &mut GameBoyAdvance, open: &mut bool) {
+        egui::Window::new("Memory").open(open).default_width(620.0).show(ctx, |ui| {
+            self.toolbar(ui, gba);
+            ui.separator();
+            self.grid(ui, gba);
+        });
+    }
+
+    fn toolbar(&mut self, ui: &mut egui::Ui, gba: &mut GameBoyAdvance) {
+        ui.horizontal(|ui| {
+            let previous = self.region;
+            egui::ComboBox::from_id_salt("memory_region")
+                .selected_text(self.region.name())
+                .show_ui(ui, |ui| {
+                    for region in MemoryRegion::ALL {
+                        ui.selectable_value(&mut self.region, region, region.name());
+                    }
+                });
+            if self.region != previous {
+                self.scroll_to = Some(view_range(self.region).0);
+            }
+
+            ui.label("Go to");
+            let response = ui.add(egui::TextEdit::singleline(&mut self.goto_input).desired_width(80.0));
+            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                match parse_number(&format!("0x{}", self.goto_input.trim().trim_start_matches("0x"))) {
+                    Ok(address) if address <= u32::MAX as u64 => self.jump(address as u32),
+                    _ => self.status = format!("invalid address: {}", self.goto_input),
+                }
+            }
+
+            for (view_type, label) in [
+                (ViewType::U8, "u8"),
+                (ViewType::U16, "u16"),
+                (ViewType::U32, "u32"),
+                (ViewType::Ascii, "ASCII"),
+            ] {
+                if ui.radio_value(&mut self.view_type, view_type, label).changed()
+                    && let Some(address) = self.selected
+                {
+                    self.select(address);
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Search");
+            let response =
+                ui.add(egui::TextEdit::singleline(&mut self.search_input).hint_text("DE AD, u16:0x1234, \"text\""));
+            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                match parse_pattern(&self.search_input) {
+                    Ok(pattern) => {
+                        let (start, length) = view_range(self.region);
+                        self.search_results = search(gba, start, length, &pattern);
+                        self.status = format!("{} matches in {}", self.search_results.len(), self.region.name());
+                    }
+                    Err(error) => self.status = error,
+                }
+            }
+            if !self.search_results.is_empty() {
+                let mut jump_to = None;
+                egui::ComboBox::from_id_salt("memory_search_results")
+                    .selected_text("Results")
+                    .show_ui(ui, |ui| {
+                        for address in &self.search_results {
+                            if ui.selectable_label(false, format!("{address:08X}")).clicked() {
+                                jump_to = Some(*address);
+                            }
+                        }
+                    });
+                if let Some(address) = jump_to {
+                    self.jump(address);
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            match self.selected {
+                Some(address) => {
+                    ui.label(format!("{address:08X} = {}", self.cell_text(gba, address)));
+                    let response = ui.add(egui::TextEdit::singleline(&mut self.edit_input).desired_width(80.0));
+                    if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        self.write_selected(gba);
+                    }
+                }
+                None => {
+                    ui.label("Click a value to edit it");
+                }
+            }
+            ui.label(&self.status);
+        });
+    }
+
+    fn grid(&mut self, ui: &mut egui::Ui, gba: &GameBoyAdvance) {
+        let (start, length) = view_range(self.region);
+        let row_height = ui.spacing().interact_size.y;
+        let rows = (length / BYTES_PER_ROW) as usize;
+
+        let mut scroll_area = egui::ScrollArea::vertical().id_salt(self.region.name()).auto_shrink(false);
+        if let Some(address) = self.scroll_to.take() {
+            let row = (address - start) / BYTES_PER_ROW;
+            scroll_area = scroll_area.vertical_scroll_offset(row as f32 * (row_height + ui.spacing().item_spacing.y));
+        }
+
+        let width = self.view_type.width();
+        scroll_area.show_rows(ui, row_height, rows, |ui, visible_rows| {
+            for row in visible_rows {
+                let row_address = start + row as u32 * BYTES_PER_ROW;
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new(format!("{row_address:08X}")).monospace().weak());
+                    for address in (row_address..row_address + BYTES_PER_ROW).step_by(width as usize) {
+                        let text = RichText::new(self.cell_text(gba, address)).monospace();
+                        if ui.selectable_label(self.selected == Some(address), text).clicked() {
+                            self.select(address);
+                        }
+                    }
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ironboyadvance_core::debugger::MemoryRegion;
+
+    use super::{parse_pattern, view_address};
+
+    #[test]
+    fn search_patterns() {
+        assert_eq!(parse_pattern("DE AD be ef"), Ok(vec![0xDE, 0xAD, 0xBE, 0xEF]));
+        assert_eq!(parse_pattern("u16:0x1234"), Ok(vec![0x34, 0x12]));
+        assert_eq!(parse_pattern("u32:1"), Ok(vec![1, 0, 0, 0]));
+        assert_eq!(parse_pattern("\"AGB\""), Ok(b"AGB".to_vec()));
+        assert!(parse_pattern("u8:0x100").is_err());
+        assert!(parse_pattern("ABC").is_err());
+        assert!(parse_pattern("aé").is_err());
+        assert!(parse_pattern("é0").is_err());
+    }
+
+    #[test]
+    fn rom_mirrors() {
+        assert_eq!(view_address(0x0800_0010), Some((MemoryRegion::Rom, 0x0800_0010)));
+        assert_eq!(view_address(0x0A00_0010), Some((MemoryRegion::Rom, 0x0800_0010)));
+        assert_eq!(view_address(0x0C00_0000), Some((MemoryRegion::Rom, 0x0800_0000)));
+        assert_eq!(view_address(0x0300_7FFC), Some((MemoryRegion::WramChip, 0x0300_7FFC)));
+        assert_eq!(view_address(0x1000_0000), None);
+    }
+}
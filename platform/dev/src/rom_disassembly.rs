@@ -0,0 +1,242 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use ironboyadvance_arm7tdmi::{
+    CpuState,
+    disassembler::{
+        DisassemblyOptions, Flow, SymbolTable, analyze_arm, analyze_thumb, disassemble_arm_with, disassemble_thumb_pair,
+        thumb_long_branch_target,
+    },
+};
+
+pub const ROM_BASE: u32 = 0x0800_0000;
+
+/// Code and data found by following every reachable branch from the entry point
+#[derive(Debug, Default)]
+pub struct Analysis {
+    /// Instruction addresses and the state they execute in
+    pub code: BTreeMap<u32, CpuState>,
+    /// Word aligned literal pool entries
+    pub data: BTreeSet<u32>,
+    calls: BTreeSet<u32>,
+    jumps: BTreeSet<u32>,
+    entry: u32,
+}
+
+impl Analysis {
+    /// `start` for the entry point, `sub_` for call targets and `loc_` for other branch targets
+    pub fn labels(&self) -> SymbolTable {
+        let mut labels = SymbolTable::new();
+        self.jumps
+            .iter()
+            .for_each(|address| labels.insert(*address, format!("loc_{address:08X}")));
+        self.calls
+            .iter()
+            .for_each(|address| labels.insert(*address, format!("sub_{address:08X}")));
+        labels.insert(self.entry, "start");
+        labels
+    }
+}
+
+fn read_16(rom: &[u8], address: u32) -> Option<u16> {
+    let offset = address.checked_sub(ROM_BASE)? as usize;
+    rom.get(offset..offset + 2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_32(rom: &[u8], address: u32) -> Option<u32> {
+    let offset = address.checked_sub(ROM_BASE)? as usize;
+    rom.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn state_of(address: u32) -> (u32, CpuState) {
+    match address & 1 {
+        1 => (address & !0x1, CpuState::Thumb),
+        _ => (address & !0x3, CpuState::Arm),
+    }
+}
+
+/// Recursive traversal of `rom` starting from the branch at its first word and from `entry`
+pub fn explore(rom: &[u8], entry: u32) -> Analysis {
+    let mut analysis = Analysis {
+        entry,
+        ..Analysis::default()
+    };
+    let mut queue = vec![(entry, CpuState::Arm), (ROM_BASE, CpuState::Arm)];
+    while let Some((address, state)) = queue.pop() {
+        explore_block(rom, address, state, &mut analysis, &mut queue);
+    }
+    analysis
+}
+
+// Decodes straight line code until something that does not fall through, queueing every branch target
+fn explore_block(rom: &[u8], start: u32, state: CpuState, analysis: &mut Analysis, queue: &mut Vec<(u32, CpuState)>) {
+    // Addresses loaded into registers by pc relative loads, so `BX` through them can be followed
+    let mut constants = HashMap::new();
+    let mut address = start;
+    loop {
+        if analysis.code.contains_key(&address) || analysis.data.contains(&(address & !0x3)) {
+            return;
+        }
+        let (info, width) = match state {
+            CpuState::Arm => match read_32(rom, address) {
+                Some(opcode) => (analyze_arm(opcode, address), 4),
+                None => return,
+            },
+            CpuState::Thumb => {
+                let Some(opcode) = read_16(rom, address) else {
+                    return;
+                };
+                let next = read_16(rom, address + 2).unwrap_or(0);
+                if let Some(target) = thumb_long_branch_target(opcode, next, address) {
+                    analysis.code.insert(address, state);
+                    analysis.code.insert(address + 2, state);
+                    analysis.calls.insert(target);
+                    queue.push((target, CpuState::Thumb));
+                    constants.clear();
+                    address += 4;
+                    continue;
+                }
+                (analyze_thumb(opcode, address), 2)
+            }
+        };
+        analysis.code.insert(address, state);
+
+        if let Some(pc_relative) = info.pc_relative {
+            let value = match pc_relative.load {
+                true => read_32(rom, pc_relative.address).inspect(|_| {
+                    analysis.data.insert(pc_relative.address & !0x3);
+                }),
+                false => Some(pc_relative.address),
+            };
+            match value {
+                Some(value) => constants.insert(pc_relative.register, value),
+                None => constants.remove(&pc_relative.register),
+            };
+        }
+
+        match info.flow {
+            Flow::Branch { target, link, .. } => {
+                match link {
+                    true => analysis.calls.insert(target),
+                    false => analysis.jumps.insert(target),
+                };
+                queue.push((target, state));
+                if link {
+                    constants.clear();
+                }
+            }
+            Flow::Exchange { register, .. } => {
+                if let Some(value) = constants.get(&register) {
+                    let (target, target_state) = state_of(*value);
+                    analysis.jumps.insert(target);
+                    queue.push((target, target_state));
+                }
+            }
+            _ => {}
+        }
+
+        if !info.flow.falls_through() {
+            return;
+        }
+        address += width;
+    }
+}
+
+/// Annotated listing of the whole ROM, with unexplored areas summarised as ranges
+pub fn listing(rom: &[u8], analysis: &Analysis, options: &DisassemblyOptions) -> String {
+    // Loaded symbols take precedence over the generated labels
+    let mut labels = analysis.labels();
+    if let Some(symbols) = options.symbols {
+        labels.extend(symbols.iter().map(|(address, name)| (address, name.to_string())));
+    }
+    let options = DisassemblyOptions {
+        symbols: Some(&labels),
+        ..*options
+    };
+    let end = ROM_BASE + rom.len() as u32;
+    let mut lines = Vec::new();
+    let mut address = ROM_BASE;
+    while address < end {
+        if let Some(label) = labels.get(address) {
+            lines.push(String::new());
+            lines.push(format!("{label}:"));
+        }
+
+        match analysis.code.get(&address) {
+            Some(CpuState::Arm) => {
+                let opcode = read_32(rom, address).unwrap_or(0);
+                let text = disassemble_arm_with(opcode, address, &options);
+                lines.push(format!("{address:08X} {opcode:08X}  {text}"));
+                address += 4;
+            }
+            Some(CpuState::Thumb) => {
+                let opcode = read_16(rom, address).unwrap_or(0);
+                let next = read_16(rom, address + 2).unwrap_or(0);
+                let text = disassemble_thumb_pair(opcode, next, address, &options);
+                match thumb_long_branch_target(opcode, next, address) {
+                    Some(_) => {
+                        lines.push(format!("{address:08X} {opcode:04X}{next:04X}  {text}"));
+                        address += 4;
+                    }
+                    None => {
+                        lines.push(format!("{address:08X} {opcode:04X}      {text}"));
+                        address += 2;
+                    }
+                }
+            }
+            None if analysis.data.contains(&address) => {
+                let value = read_32(rom, address).unwrap_or(0);
+                let target = labels
+                    .get(value & !0x1)
+                    .map(|label| format!("\t; {label}"))
+                    .unwrap_or_default();
+                lines.push(format!("{address:08X} {value:08X}  .word 0x{value:08X}{target}"));
+                address += 4;
+            }
+            None => {
+                let next_code = analysis.code.range(address..).next().map(|(next, _)| *next);
+                let next_data = analysis.data.range(address..).next().copied();
+                let gap_end = [next_code, next_data].into_iter().flatten().min().unwrap_or(end);
+                lines.push(format!(
+                    "; unexplored {address:08X}-{:08X} ({} bytes)",
+                    gap_end - 1,
+                    gap_end - address
+                ));
+                address = gap_end;
+            }
+        }
+    }
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_branches_and_state_changes() {
+        let mut rom = vec![0; 0x100];
+        let mut put = |offset: usize, bytes: &[u8]| rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+        put(0x00, &0xEA00002Eu32.to_le_bytes()); // B 0x080000C0
+        put(0xC0, &0xE59F0004u32.to_le_bytes()); // LDR r0,[0x080000CC]
+        put(0xC4, &0xE12FFF10u32.to_le_bytes()); // BX r0
+        put(0xCC, &0x080000D1u32.to_le_bytes()); // literal, THUMB code at 0x080000D0
+        put(0xD0, &[0x00, 0xF0, 0x02, 0xF8]); // BL 0x080000D8
+        put(0xD4, &0xE7FEu16.to_le_bytes()); // B 0x080000D4
+        put(0xD8, &0x4770u16.to_le_bytes()); // BX lr
+
+        let analysis = explore(&rom, 0x080000C0);
+        assert_eq!(analysis.code.get(&0x080000C4), Some(&CpuState::Arm));
+        assert_eq!(analysis.code.get(&0x080000D0), Some(&CpuState::Thumb));
+        assert_eq!(analysis.code.get(&0x080000D8), Some(&CpuState::Thumb));
+        assert!(!analysis.code.contains_key(&0x080000C8));
+        assert!(analysis.data.contains(&0x080000CC));
+
+        let listing = listing(&rom, &analysis, &DisassemblyOptions::default());
+        assert!(listing.contains("080000D0 F000F802  BL 0x080000D8 <sub_080000D8>"));
+        assert!(listing.contains(".word 0x080000D1\t; loc_080000D0"));
+        assert!(listing.contains("; unexplored 08000004-080000BF (188 bytes)"));
+    }
+}
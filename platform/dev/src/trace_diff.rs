@@ -0,0 +1,144 @@
+use std::{
+    fs::{self, File},
+    io::BufReader,
+    path::Path,
+};
+
+use ironboyadvance_arm7tdmi::trace::read_binary_trace;
+
+use crate::trace_format::{ForeignFormat, TraceState, parse_line};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Divergence {
+    State {
+        index: usize,
+        ours: TraceState,
+        reference: TraceState,
+    },
+    OursEnded {
+        index: usize,
+    },
+}
+
+pub fn load_ours(path: &Path) -> Result<Vec<TraceState>, String> {
+    let file = File::open(path).map_err(|e| format!("unable to open {}: {e}", path.display()))?;
+    let records = read_binary_trace(BufReader::new(file)).map_err(|e| format!("unable to read {}: {e}", path.display()))?;
+    Ok(records.iter().map(TraceState::from).collect())
+}
+
+pub fn load_reference(path: &Path, format: ForeignFormat) -> Result<Vec<TraceState>, String> {
+    let log = fs::read_to_string(path).map_err(|e| format!("unable to read {}: {e}", path.display()))?;
+    Ok(log.lines().filter_map(|line| parse_line(format, line)).collect())
+}
+
+/// Drops everything before the first instruction executed at `pc`
+pub fn align(trace: &[TraceState], pc: Option<u32>) -> &[TraceState] {
+    match pc {
+        Some(pc) => trace.iter().position(|s| s.pc == pc).map_or(&[], |i| &trace[i..]),
+        None => trace,
+    }
+}
+
+// r15 is skipped, emulators disagree on how far ahead the prefetched pc is and `pc` covers it
+fn matches(ours: &TraceState, reference: &TraceState) -> bool {
+    ours.pc == reference.pc
+        && ours.opcode == reference.opcode
+        && ours.cpsr == reference.cpsr
+        && ours.registers[..15] == reference.registers[..15]
+}
+
+/// The reference may stop before our trace, only running out of our own records is a divergence
+pub fn first_divergence(ours: &[TraceState], reference: &[TraceState]) -> Option<Divergence> {
+    for (index, reference) in reference.iter().enumerate() {
+        match ours.get(index) {
+            None => return Some(Divergence::OursEnded { index }),
+            Some(ours) if !matches(ours, reference) => {
+                return Some(Divergence::State {
+                    index,
+                    ours: *ours,
+                    reference: *reference,
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    None
+}
+
+pub fn report(divergence: &Divergence, ours: &[TraceState]) -> String {
+    let (index, ours_state, reference) = match divergence {
+        Divergence::OursEnded { index } => return format!("our trace ended after {index} matching instructions"),
+        Divergence::State { index, ours, reference } => (*index, ours, reference),
+    };
+
+    let mut lines = vec![format!("first divergence at instruction {index}")];
+    if let Some(previous) = index.checked_sub(1).and_then(|i| ours.get(i)) {
+        lines.push(format!(
+            "last matching instruction: {:08X}: {:08X}",
+            previous.pc, previous.opcode
+        ));
+    }
+    lines.push(format!("{:>6} {:>10} {:>10}", "", "ours", "reference"));
+
+    let mut row = |name: String, ours: u32, reference: u32| {
+        let marker = if ours != reference { " <--" } else { "" };
+        lines.push(format!("{name:>6} {ours:>10X} {reference:>10X}{marker}"));
+    };
+    row(String::from("pc"), ours_state.pc, reference.pc);
+    row(String::from("opcode"), ours_state.opcode, reference.opcode);
+    for i in 0..15 {
+        row(format!("r{i}"), ours_state.registers[i], reference.registers[i]);
+    }
+    row(String::from("cpsr"), ours_state.cpsr, reference.cpsr);
+
+    let flags = [("N", 31), ("Z", 30), ("C", 29), ("V", 28), ("I", 7), ("F", 6), ("T", 5)]
+        .into_iter()
+        .filter(|(_, bit)| (ours_state.cpsr ^ reference.cpsr) & (1 << bit) != 0)
+        .map(|(flag, bit)| format!("{flag}: {} -> {}", reference.cpsr >> bit & 1, ours_state.cpsr >> bit & 1))
+        .collect::<Vec<String>>();
+    if !flags.is_empty() {
+        lines.push(format!("cpsr flags (reference -> ours): {}", flags.join(", ")));
+    }
+    if ours_state.cpsr & 0x1F != reference.cpsr & 0x1F {
+        lines.push(format!(
+            "cpsr mode (reference -> ours): {:05b} -> {:05b}",
+            reference.cpsr & 0x1F,
+            ours_state.cpsr & 0x1F
+        ));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Divergence, first_divergence};
+    use crate::trace_format::TraceState;
+
+    fn state(pc: u32) -> TraceState {
+        TraceState {
+            pc,
+            opcode: 0xE1A00000,
+            registers: [0; 16],
+            cpsr: 0x1F,
+        }
+    }
+
+    #[test]
+    fn reports_first_mismatch() {
+        let reference = vec![state(0), state(4), state(8)];
+        let mut ours = reference.clone();
+        assert_eq!(first_divergence(&ours, &reference), None);
+
+        ours[1].registers[3] = 1;
+        ours[2].cpsr = 0;
+        assert!(matches!(
+            first_divergence(&ours, &reference),
+            Some(Divergence::State { index: 1, .. })
+        ));
+        assert_eq!(first_divergence(&ours[..1], &reference[..1]), None);
+        assert_eq!(
+            first_divergence(&reference[..2], &reference),
+            Some(Divergence::OursEnded { index: 2 })
+        );
+    }
+}
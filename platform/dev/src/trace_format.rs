@@ -0,0 +1,164 @@
+use std::io::Write;
+
+use clap::ValueEnum;
+use ironboyadvance_arm7tdmi::{
+    CpuState,
+    trace::{TraceRecord, TraceSink},
+};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ForeignFormat {
+    /// mGBA debugger trace: r0-r15, `cpsr: XXXXXXXX | XXXXXXXX:  disassembly`
+    Mgba,
+    /// NanoBoyAdvance trace: `r0: XXXXXXXX ... r15: XXXXXXXX cpsr: XXXXXXXX | XXXXXXXX: opcode`
+    #[value(name = "nba")]
+    NanoBoyAdvance,
+}
+
+/// CPU state of one instruction as far as other emulators' logs describe it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TraceState {
+    /// Address of the executed instruction
+    pub pc: u32,
+    pub opcode: u32,
+    pub registers: [u32; 16],
+    pub cpsr: u32,
+}
+
+impl From<&TraceRecord> for TraceState {
+    fn from(record: &TraceRecord) -> Self {
+        TraceState {
+            pc: record.pc,
+            opcode: record.opcode,
+            registers: record.registers,
+            cpsr: record.cpsr,
+        }
+    }
+}
+
+pub fn format_record(format: ForeignFormat, record: &TraceRecord) -> String {
+    let opcode = match record.state {
+        CpuState::Arm => format!("{:08X}", record.opcode),
+        CpuState::Thumb => format!("{:04X}", record.opcode),
+    };
+    match format {
+        ForeignFormat::Mgba => {
+            let registers = record
+                .registers
+                .iter()
+                .map(|r| format!("{r:08X}"))
+                .collect::<Vec<String>>()
+                .join(" ");
+            format!(
+                "{registers} cpsr: {:08X} | {opcode}:  {}",
+                record.cpsr,
+                record.disassembly.to_lowercase()
+            )
+        }
+        ForeignFormat::NanoBoyAdvance => {
+            let registers = record
+                .registers
+                .iter()
+                .enumerate()
+                .map(|(i, r)| format!("r{i}: {r:08x}"))
+                .collect::<Vec<String>>()
+                .join(" ");
+            format!(
+                "{registers} cpsr: {:08x} | {:08x}: {}",
+                record.cpsr,
+                record.pc,
+                opcode.to_lowercase()
+            )
+        }
+    }
+}
+
+/// Parses one log line, returning None for lines that are not instruction traces
+pub fn parse_line(format: ForeignFormat, line: &str) -> Option<TraceState> {
+    let (state, instruction) = line.split_once('|')?;
+    let (registers, cpsr) = state.split_once("cpsr:")?;
+    let cpsr = parse_hex(cpsr.trim())?;
+
+    let values = match format {
+        ForeignFormat::Mgba => registers.split_whitespace().map(parse_hex).collect::<Option<Vec<u32>>>()?,
+        ForeignFormat::NanoBoyAdvance => registers
+            .split_whitespace()
+            .filter(|token| !token.ends_with(':'))
+            .map(parse_hex)
+            .collect::<Option<Vec<u32>>>()?,
+    };
+    let registers: [u32; 16] = values.try_into().ok()?;
+
+    let mut instruction = instruction.split_whitespace();
+    let (pc, opcode) = match format {
+        // mGBA only prints the opcode, the executed address is derived from the prefetched r15
+        ForeignFormat::Mgba => {
+            let opcode = instruction.next()?.trim_end_matches(':');
+            let pipeline_offset = if opcode.len() > 4 { 8 } else { 4 };
+            (registers[15].wrapping_sub(pipeline_offset), parse_hex(opcode)?)
+        }
+        ForeignFormat::NanoBoyAdvance => (
+            parse_hex(instruction.next()?.trim_end_matches(':'))?,
+            parse_hex(instruction.next()?)?,
+        ),
+    };
+
+    Some(TraceState {
+        pc,
+        opcode,
+        registers,
+        cpsr,
+    })
+}
+
+fn parse_hex(value: &str) -> Option<u32> {
+    u32::from_str_radix(value.trim_start_matches("0x"), 16).ok()
+}
+
+pub struct ForeignTraceSink<W: Write> {
+    format: ForeignFormat,
+    writer: W,
+}
+
+impl<W: Write> ForeignTraceSink<W> {
+    pub fn new(format: ForeignFormat, writer: W) -> Self {
+        ForeignTraceSink { format, writer }
+    }
+}
+
+impl<W: Write> TraceSink for ForeignTraceSink<W> {
+    fn trace(&mut self, record: &TraceRecord) {
+        let _ = writeln!(self.writer, "{}", format_record(self.format, record));
+    }
+
+    fn flush(&mut self) {
+        let _ = self.writer.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ironboyadvance_arm7tdmi::{CpuState, trace::TraceRecord};
+
+    use super::{ForeignFormat, TraceState, format_record, parse_line};
+
+    #[test]
+    fn exported_lines_parse_back() {
+        let mut registers = std::array::from_fn(|i| i as u32 * 0x01010101);
+        registers[15] = 0x08000108;
+        let record = TraceRecord {
+            pc: 0x08000100,
+            state: CpuState::Arm,
+            opcode: 0xE3A0C000,
+            disassembly: String::from("MOV r12, #0x0"),
+            registers,
+            cpsr: 0x6000001F,
+            cycles: 0,
+        };
+        for format in [ForeignFormat::Mgba, ForeignFormat::NanoBoyAdvance] {
+            let line = format_record(format, &record);
+            assert_eq!(parse_line(format, &line), Some(TraceState::from(&record)), "{line}");
+        }
+        assert_eq!(parse_line(ForeignFormat::Mgba, "Loading ROM"), None);
+    }
+}
@@ -0,0 +1,398 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+};
+
+use ironboyadvance_core::{
+    debugger::MemoryRegion,
+    gba::GameBoyAdvance,
+    ppu::{
+        HDRAW_PIXELS, VDRAW_SCANLINES, bgr555_to_rgb,
+        oam::{OBJECT_ATTRIBUTE_BYTES, OBJECT_COUNT, ObjectAttributes},
+        registers::{
+            BackgroundControl, DISPCNT, DisplayControl, TextMapEntry, background_control_address,
+            background_offset_addresses, background_reference_addresses,
+        },
+    },
+};
+
+pub const CHARACTER_BLOCK_BYTES: usize = 0x4000;
+pub const CHARACTER_BLOCKS: u32 = 6;
+const SCREEN_BLOCK_BYTES: usize = 0x800;
+const OBJ_TILE_BASE: usize = 0x10000;
+const OBJ_PALETTE_BASE: usize = 256;
+const BITMAP_FRAME_BYTES: usize = 0xA000;
+const TILES_PER_ROW: u32 = 16;
+const PALETTE_CELL_PIXELS: u32 = 8;
+const VIEWPORT_COLOR: [u8; 4] = [0xFF, 0x30, 0x30, 0xFF];
+
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    fn new(width: u32, height: u32) -> Self {
+        RgbaImage {
+            width,
+            height,
+            pixels: vec![0; (width * height * 4) as usize],
+        }
+    }
+
+    fn set(&mut self, x: u32, y: u32, rgba: [u8; 4]) {
+        let offset = ((y * self.width + x) * 4) as usize;
+        self.pixels[offset..offset + 4].copy_from_slice(&rgba);
+    }
+
+    /// Outlines a rectangle that wraps around the image edges like a scrolled background does
+    fn outline_wrapped(&mut self, x: u32, y: u32, width: u32, height: u32, rgba: [u8; 4]) {
+        for i in 0..width {
+            self.set((x + i) % self.width, y % self.height, rgba);
+            self.set((x + i) % self.width, (y + height - 1) % self.height, rgba);
+        }
+        for i in 0..height {
+            self.set(x % self.width, (y + i) % self.height, rgba);
+            self.set((x + width - 1) % self.width, (y + i) % self.height, rgba);
+        }
+    }
+
+    pub fn save_png(&self, path: &Path) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&self.pixels).map_err(io::Error::other)
+    }
+}
+
+/// Copy of the memory the PPU reads, taken through side-effect-free debug reads
+pub struct VideoMemory {
+    pub vram: Vec<u8>,
+    pub palette: Vec<u8>,
+    pub oam: Vec<u8>,
+    io: Vec<u8>,
+}
+
+fn region_bytes(gba: &GameBoyAdvance, region: MemoryRegion) -> Vec<u8> {
+    let range = region.range();
+    gba.debug_read(*range.start(), (range.end() - range.start() + 1) as usize)
+}
+
+impl VideoMemory {
+    pub fn read(gba: &GameBoyAdvance) -> Self {
+        VideoMemory {
+            vram: region_bytes(gba, MemoryRegion::Vram),
+            palette: region_bytes(gba, MemoryRegion::PaletteRam),
+            oam: region_bytes(gba, MemoryRegion::Oam),
+            io: region_bytes(gba, MemoryRegion::IoRegisters),
+        }
+    }
+
+    fn io_16(&self, address: u32) -> u16 {
+        let offset = (address - DISPCNT) as usize;
+        u16::from_le_bytes([self.io[offset], self.io[offset + 1]])
+    }
+
+    fn io_32(&self, address: u32) -> u32 {
+        (self.io_16(address + 2) as u32) << 16 | self.io_16(address) as u32
+    }
+
+    pub fn display_control(&self) -> DisplayControl {
+        DisplayControl::from_bits(self.io_16(DISPCNT))
+    }
+
+    pub fn background_control(&self, background: u32) -> BackgroundControl {
+        BackgroundControl::from_bits(self.io_16(background_control_address(background)))
+    }
+
+    /// Raw BGR555 value of one of the 512 palette entries, BG colors first
+    pub fn color(&self, index: usize) -> u16 {
+        u16::from_le_bytes([self.palette[index * 2], self.palette[index * 2 + 1]])
+    }
+
+    fn opaque(&self, index: usize) -> [u8; 4] {
+        let [r, g, b] = bgr555_to_rgb(self.color(index));
+        [r, g, b, 0xFF]
+    }
+
+    pub fn object(&self, index: usize) -> ObjectAttributes {
+        let offset = index * OBJECT_ATTRIBUTE_BYTES;
+        ObjectAttributes::from_bytes(&self.oam[offset..offset + 6])
+    }
+
+    pub fn objects(&self) -> impl Iterator<Item = (usize, ObjectAttributes)> + '_ {
+        (0..OBJECT_COUNT).map(|index| (index, self.object(index)))
+    }
+
+    // Palette index of a pixel within the tile starting at `tile_address`
+    fn tile_pixel(&self, tile_address: usize, x: u32, y: u32, palette_256: bool) -> usize {
+        match palette_256 {
+            true => self.vram.get(tile_address + (y * 8 + x) as usize).copied().unwrap_or(0) as usize,
+            false => {
+                let byte = self.vram.get(tile_address + (y * 4 + x / 2) as usize).copied().unwrap_or(0);
+                ((byte >> ((x & 0x1) * 4)) & 0xF) as usize
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BackgroundKind {
+    Text,
+    Affine,
+    Bitmap,
+}
+
+/// How `background` is drawn in the current video mode, None when the mode does not use it
+pub fn background_kind(memory: &VideoMemory, background: u32) -> Option<BackgroundKind> {
+    match (memory.display_control().bg_mode(), background) {
+        (0, _) | (1, 0 | 1) => Some(BackgroundKind::Text),
+        (1, 2) | (2, 2 | 3) => Some(BackgroundKind::Affine),
+        (3..=5, 2) => Some(BackgroundKind::Bitmap),
+        _ => None,
+    }
+}
+
+/// Tiles of one 16KB character block, blocks 4 and 5 hold sprite tiles and use the OBJ palettes
+pub fn render_tiles(memory: &VideoMemory, character_block: u32, palette_256: bool, palette: u32) -> RgbaImage {
+    let tile_bytes = if palette_256 { 64 } else { 32 };
+    let tiles = (CHARACTER_BLOCK_BYTES / tile_bytes) as u32;
+    let palette_base = match character_block >= 4 {
+        true => OBJ_PALETTE_BASE,
+        false => 0,
+    } + if palette_256 { 0 } else { palette as usize * 16 };
+
+    let mut image = RgbaImage::new(TILES_PER_ROW * 8, tiles / TILES_PER_ROW * 8);
+    for tile in 0..tiles {
+        let tile_address = character_block as usize * CHARACTER_BLOCK_BYTES + tile as usize * tile_bytes;
+        for y in 0..8 {
+            for x in 0..8 {
+                let index = memory.tile_pixel(tile_address, x, y, palette_256);
+                image.set(
+                    (tile % TILES_PER_ROW) * 8 + x,
+                    (tile / TILES_PER_ROW) * 8 + y,
+                    memory.opaque(palette_base + index),
+                );
+            }
+        }
+    }
+    image
+}
+
+fn render_text_background(memory: &VideoMemory, control: BackgroundControl) -> RgbaImage {
+    let (width, height) = control.text_size();
+    let map_base = control.screen_base_block() as usize * SCREEN_BLOCK_BYTES;
+    let tile_base = control.character_base_block() as usize * CHARACTER_BLOCK_BYTES;
+    let palette_256 = control.palette_256();
+
+    let mut image = RgbaImage::new(width, height);
+    for tile_y in 0..height / 8 {
+        for tile_x in 0..width / 8 {
+            // Maps wider or taller than 256 pixels continue in the following 32x32 screen blocks
+            let block = (tile_x / 32 + (tile_y / 32) * (width / 256)) as usize;
+            let offset = map_base + block * SCREEN_BLOCK_BYTES + (((tile_y % 32) * 32 + tile_x % 32) * 2) as usize;
+            let entry = TextMapEntry::from_bits(u16::from_le_bytes([memory.vram[offset], memory.vram[offset + 1]]));
+            let tile_address = tile_base + entry.tile_number() as usize * if palette_256 { 64 } else { 32 };
+
+            for y in 0..8 {
+                for x in 0..8 {
+                    let source_x = if entry.horizontal_flip() { 7 - x } else { x };
+                    let source_y = if entry.vertical_flip() { 7 - y } else { y };
+                    let index = memory.tile_pixel(tile_address, source_x, source_y, palette_256);
+                    if index != 0 {
+                        let palette_index = if palette_256 {
+                            index
+                        } else {
+                            entry.palette() as usize * 16 + index
+                        };
+                        image.set(tile_x * 8 + x, tile_y * 8 + y, memory.opaque(palette_index));
+                    }
+                }
+            }
+        }
+    }
+    image
+}
+
+fn render_affine_background(memory: &VideoMemory, control: BackgroundControl) -> RgbaImage {
+    let size = control.affine_size();
+    let map_base = control.screen_base_block() as usize * SCREEN_BLOCK_BYTES;
+    let tile_base = control.character_base_block() as usize * CHARACTER_BLOCK_BYTES;
+
+    let mut image = RgbaImage::new(size, size);
+    for tile_y in 0..size / 8 {
+        for tile_x in 0..size / 8 {
+            let tile = memory.vram[map_base + (tile_y * size / 8 + tile_x) as usize] as usize;
+            for y in 0..8 {
+                for x in 0..8 {
+                    let index = memory.tile_pixel(tile_base + tile * 64, x, y, true);
+                    if index != 0 {
+                        image.set(tile_x * 8 + x, tile_y * 8 + y, memory.opaque(index));
+                    }
+                }
+            }
+        }
+    }
+    image
+}
+
+fn render_bitmap_background(memory: &VideoMemory, display_control: DisplayControl) -> RgbaImage {
+    let frame = match display_control.frame_select() {
+        true => BITMAP_FRAME_BYTES,
+        false => 0,
+    };
+    let (width, height) = match display_control.bg_mode() {
+        5 => (160, 128),
+        _ => (HDRAW_PIXELS, VDRAW_SCANLINES),
+    };
+
+    let mut image = RgbaImage::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = (y * width + x) as usize;
+            let rgba = match display_control.bg_mode() {
+                3 => {
+                    let offset = pixel * 2;
+                    let [r, g, b] = bgr555_to_rgb(u16::from_le_bytes([memory.vram[offset], memory.vram[offset + 1]]));
+                    [r, g, b, 0xFF]
+                }
+                4 => memory.opaque(memory.vram[frame + pixel] as usize),
+                _ => {
+                    let offset = frame + pixel * 2;
+                    let [r, g, b] = bgr555_to_rgb(u16::from_le_bytes([memory.vram[offset], memory.vram[offset + 1]]));
+                    [r, g, b, 0xFF]
+                }
+            };
+            image.set(x, y, rgba);
+        }
+    }
+    image
+}
+
+/// Whole background layer, optionally outlining the 240x160 area currently on screen.
+/// Affine layers outline the reference point only, rotation and scaling are not shown.
+pub fn render_background(memory: &VideoMemory, background: u32, show_viewport: bool) -> Option<RgbaImage> {
+    let control = memory.background_control(background);
+    let (mut image, viewport) = match background_kind(memory, background)? {
+        BackgroundKind::Text => {
+            let (horizontal, vertical) = background_offset_addresses(background);
+            let viewport = (
+                (memory.io_16(horizontal) & 0x1FF) as u32,
+                (memory.io_16(vertical) & 0x1FF) as u32,
+            );
+            (render_text_background(memory, control), Some(viewport))
+        }
+        BackgroundKind::Affine => {
+            let (x, y) = background_reference_addresses(background);
+            // 28 bit signed fixed point with 8 fractional bits
+            let integer = |value: u32| (((value << 4) as i32) >> 12) as u32;
+            let viewport = (integer(memory.io_32(x)), integer(memory.io_32(y)));
+            (render_affine_background(memory, control), Some(viewport))
+        }
+        BackgroundKind::Bitmap => (render_bitmap_background(memory, memory.display_control()), None),
+    };
+
+    if let (true, Some((x, y))) = (show_viewport, viewport) {
+        let (x, y) = (x % image.width, y % image.height);
+        let width = HDRAW_PIXELS.min(image.width);
+        let height = VDRAW_SCANLINES.min(image.height);
+        image.outline_wrapped(x, y, width, height, VIEWPORT_COLOR);
+    }
+    Some(image)
+}
+
+/// BG palettes on the left, OBJ palettes on the right, one row per 16 color palette
+pub fn render_palettes(memory: &VideoMemory) -> RgbaImage {
+    let mut image = RgbaImage::new(32 * PALETTE_CELL_PIXELS, 16 * PALETTE_CELL_PIXELS);
+    for index in 0..512 {
+        let (cell_x, cell_y) = palette_cell(index);
+        for y in 0..PALETTE_CELL_PIXELS {
+            for x in 0..PALETTE_CELL_PIXELS {
+                image.set(
+                    cell_x * PALETTE_CELL_PIXELS + x,
+                    cell_y * PALETTE_CELL_PIXELS + y,
+                    memory.opaque(index),
+                );
+            }
+        }
+    }
+    image
+}
+
+fn palette_cell(index: usize) -> (u32, u32) {
+    let bank = (index / 256) as u32;
+    let index = (index % 256) as u32;
+    (bank * 16 + index % 16, index / 16)
+}
+
+/// Palette entry under a pixel of `render_palettes`
+pub fn palette_index_at(x: u32, y: u32) -> Option<usize> {
+    let (cell_x, cell_y) = (x / PALETTE_CELL_PIXELS, y / PALETTE_CELL_PIXELS);
+    (cell_x < 32 && cell_y < 16).then_some((cell_x / 16 * 256 + cell_y * 16 + cell_x % 16) as usize)
+}
+
+/// One sprite drawn unscaled and unrotated, `one_dimensional` picks the OBJ tile mapping
+pub fn render_object(memory: &VideoMemory, object: &ObjectAttributes, one_dimensional: bool) -> RgbaImage {
+    let (width, height) = object.dimensions();
+    let palette_256 = object.palette_256();
+    // Tile numbers count 32 byte units, 256 color tiles take two of them
+    let tile_units = if palette_256 { 2 } else { 1 };
+    let row_units = match one_dimensional {
+        true => width / 8 * tile_units,
+        false => 32,
+    };
+
+    let mut image = RgbaImage::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let source_x = if object.horizontal_flip() { width - 1 - x } else { x };
+            let source_y = if object.vertical_flip() { height - 1 - y } else { y };
+            let tile = (object.tile_number() + (source_y / 8) * row_units + (source_x / 8) * tile_units) & 0x3FF;
+            let index = memory.tile_pixel(OBJ_TILE_BASE + tile as usize * 32, source_x % 8, source_y % 8, palette_256);
+            if index != 0 {
+                let palette_index = match palette_256 {
+                    true => OBJ_PALETTE_BASE + index,
+                    false => OBJ_PALETTE_BASE + object.palette() as usize * 16 + index,
+                };
+                image.set(x, y, memory.opaque(palette_index));
+            }
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use ironboyadvance_core::ppu::registers::BackgroundControl;
+
+    use super::{VideoMemory, palette_index_at, render_text_background};
+
+    #[test]
+    fn text_background_and_palette_layout() {
+        let mut memory = VideoMemory {
+            vram: vec![0; 0x18000],
+            palette: vec![0; 0x400],
+            oam: vec![0; 0x400],
+            io: vec![0; 0x400],
+        };
+        // Palette 1 color 2 is pure red, tile 1 has its top left pixel set to that color
+        memory.palette[(16 + 2) * 2] = 0x1F;
+        memory.vram[32] = 0x02;
+        // Screen block 1 holds a 256x256 map whose second entry is tile 1 with palette 1, flipped horizontally
+        memory.vram[0x800 + 2..0x800 + 4].copy_from_slice(&(1u16 | 1 << 10 | 1 << 12).to_le_bytes());
+
+        let control = BackgroundControl::from_bits(1 << 8);
+        let image = render_text_background(&memory, control);
+        assert_eq!((image.width, image.height), (256, 256));
+        let pixel = |x: usize, y: usize| &image.pixels[(y * 256 + x) * 4..(y * 256 + x) * 4 + 4];
+        assert_eq!(pixel(15, 0), [0xFF, 0, 0, 0xFF]);
+        assert_eq!(pixel(8, 0), [0, 0, 0, 0]);
+
+        assert_eq!(palette_index_at(0, 0), Some(0));
+        assert_eq!(palette_index_at(17 * 8, 2 * 8), Some(256 + 33));
+        assert_eq!(palette_index_at(32 * 8, 0), None);
+    }
+}
@@ -0,0 +1,277 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use eframe::egui::{self, ColorImage, RichText, TextureHandle, TextureOptions, load::SizedTexture};
+use ironboyadvance_core::{
+    gba::GameBoyAdvance,
+    ppu::{bgr555_to_rgb, oam::ObjectAttributes},
+};
+
+use crate::vram_render::{
+    CHARACTER_BLOCKS, RgbaImage, VideoMemory, background_kind, palette_index_at, render_background, render_object,
+    render_palettes, render_tiles,
+};
+
+const IMAGE_SCALE: f32 = 2.0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Tab {
+    Tiles,
+    Backgrounds,
+    Palettes,
+    Oam,
+}
+
+pub struct VramViewer {
+    tab: Tab,
+    character_block: u32,
+    palette_256: bool,
+    palette: u32,
+    background: u32,
+    show_viewport: bool,
+    hide_disabled: bool,
+    textures: HashMap<String, TextureHandle>,
+    status: String,
+}
+
+impl VramViewer {
+    pub fn new() -> Self {
+        VramViewer {
+            tab: Tab::Tiles,
+            character_block: 0,
+            palette_256: false,
+            palette: 0,
+            background: 0,
+            show_viewport: true,
+            hide_disabled: true,
+            textures: HashMap::new(),
+            status: String::new(),
+        }
+    }
+
+    fn texture(&mut self, ctx: &egui::Context, name: &str, image: &RgbaImage) -> SizedTexture {
+        let color_image = ColorImage::from_rgba_unmultiplied([image.width as usize, image.height as usize], &image.pixels);
+        let texture = match self.textures.get_mut(name) {
+            Some(texture) => {
+                texture.set(color_image, TextureOptions::NEAREST);
+                texture
+            }
+            None => self
+                .textures
+                .entry(name.to_string())
+                .or_insert_with(|| ctx.load_texture(name, color_image, TextureOptions::NEAREST)),
+        };
+        SizedTexture::new(texture.id(), texture.size_vec2())
+    }
+
+    // Scaled image that reports which source pixel the pointer is over
+    fn image(&mut self, ui: &mut egui::Ui, name: &str, image: &RgbaImage, scale: f32) -> Option<(u32, u32)> {
+        let texture = self.texture(ui.ctx(), name, image);
+        let response =
+            ui.add(egui::Image::new(SizedTexture::new(texture.id, texture.size * scale)).sense(egui::Sense::hover()));
+        response.hover_pos().map(|position| {
+            let position = (position - response.rect.min) / scale;
+            (position.x as u32, position.y as u32)
+        })
+    }
+
+    fn export_button(&mut self, ui: &mut egui::Ui, name: &str, frame: u64, image: &RgbaImage) {
+        if ui.button("Export PNG").clicked() {
+            let path = PathBuf::from(format!("{name}_frame{frame}.png"));
+            self.status = match image.save_png(&path) {
+                Ok(()) => format!("saved {}", path.display()),
+                Err(error) => format!("unable to save {}: {error}", path.display()),
+            };
+        }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, gba: &GameBoyAdvance, open: &mut bool) {
+        let memory = VideoMemory::read(gba);
+        let frame = gba.frame_count();
+        egui::Window::new("VRAM").open(open).default_width(560.0).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                for (tab, label) in [
+                    (Tab::Tiles, "Tiles"),
+                    (Tab::Backgrounds, "Backgrounds"),
+                    (Tab::Palettes, "Palettes"),
+                    (Tab::Oam, "OAM"),
+                ] {
+                    ui.selectable_value(&mut self.tab, tab, label);
+                }
+            });
+            ui.separator();
+            match self.tab {
+                Tab::Tiles => self.tiles(ui, &memory, frame),
+                Tab::Backgrounds => self.backgrounds(ui, &memory, frame),
+                Tab::Palettes => self.palettes(ui, &memory, frame),
+                Tab::Oam => self.objects(ui, &memory, frame),
+            }
+            ui.label(&self.status);
+        });
+    }
+
+    fn tiles(&mut self, ui: &mut egui::Ui, memory: &VideoMemory, frame: u64) {
+        let image = render_tiles(memory, self.character_block, self.palette_256, self.palette);
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("Block")
+                .selected_text(block_name(self.character_block))
+                .show_ui(ui, |ui| {
+                    for block in 0..CHARACTER_BLOCKS {
+                        ui.selectable_value(&mut self.character_block, block, block_name(block));
+                    }
+                });
+            ui.radio_value(&mut self.palette_256, false, "4bpp");
+            ui.radio_value(&mut self.palette_256, true, "8bpp");
+            ui.add_enabled(
+                !self.palette_256,
+                egui::Slider::new(&mut self.palette, 0..=15).text("Palette"),
+            );
+            self.export_button(ui, &format!("tiles_block{}", self.character_block), frame, &image);
+        });
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            if let Some((x, y)) = self.image(ui, "tiles", &image, IMAGE_SCALE) {
+                let tile = (y / 8) * (image.width / 8) + x / 8;
+                ui.label(format!("Tile {tile} ({tile:#05X})"));
+            }
+        });
+    }
+
+    fn backgrounds(&mut self, ui: &mut egui::Ui, memory: &VideoMemory, frame: u64) {
+        ui.horizontal(|ui| {
+            for background in 0..4 {
+                ui.selectable_value(&mut self.background, background, format!("BG{background}"));
+            }
+            ui.checkbox(&mut self.show_viewport, "Scroll window");
+        });
+
+        let display_control = memory.display_control();
+        let control = memory.background_control(self.background);
+        let Some(image) = render_background(memory, self.background, self.show_viewport) else {
+            ui.label(format!(
+                "BG{} is not used in mode {}",
+                self.background,
+                display_control.bg_mode()
+            ));
+            return;
+        };
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "Mode {} {:?}, {}, priority {}, char block {}, screen block {}, {}x{}",
+                display_control.bg_mode(),
+                background_kind(memory, self.background).unwrap(),
+                if display_control.background_enabled(self.background) {
+                    "enabled"
+                } else {
+                    "disabled"
+                },
+                control.priority(),
+                control.character_base_block(),
+                control.screen_base_block(),
+                image.width,
+                image.height,
+            ));
+            self.export_button(ui, &format!("bg{}", self.background), frame, &image);
+        });
+
+        egui::ScrollArea::both().show(ui, |ui| {
+            if let Some((x, y)) = self.image(ui, "background", &image, IMAGE_SCALE) {
+                ui.label(format!("({x}, {y}), tile ({}, {})", x / 8, y / 8));
+            }
+        });
+    }
+
+    fn palettes(&mut self, ui: &mut egui::Ui, memory: &VideoMemory, frame: u64) {
+        let image = render_palettes(memory);
+        ui.horizontal(|ui| {
+            ui.label("BG palettes left, OBJ palettes right");
+            self.export_button(ui, "palettes", frame, &image);
+        });
+        if let Some(index) = self
+            .image(ui, "palettes", &image, IMAGE_SCALE)
+            .and_then(|(x, y)| palette_index_at(x, y))
+        {
+            let color = memory.color(index);
+            let [r, g, b] = bgr555_to_rgb(color);
+            let (bank, index) = if index < 256 { ("BG", index) } else { ("OBJ", index - 256) };
+            ui.label(format!(
+                "{bank} palette {} color {}: {color:#06X} (R {} G {} B {}) #{r:02X}{g:02X}{b:02X}",
+                index / 16,
+                index % 16,
+                color & 0x1F,
+                (color >> 5) & 0x1F,
+                (color >> 10) & 0x1F,
+            ));
+        }
+    }
+
+    fn objects(&mut self, ui: &mut egui::Ui, memory: &VideoMemory, frame: u64) {
+        let one_dimensional = memory.display_control().obj_one_dimensional_mapping();
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.hide_disabled, "Hide disabled");
+            ui.label(format!("{} mapping", if one_dimensional { "1D" } else { "2D" }));
+        });
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("oam").striped(true).show(ui, |ui| {
+                for header in [
+                    "#", "Sprite", "Position", "Size", "Tile", "Palette", "Priority", "Mode", "Flags", "",
+                ] {
+                    ui.label(RichText::new(header).strong());
+                }
+                ui.end_row();
+
+                for (index, object) in memory.objects() {
+                    if self.hide_disabled && object.disabled() {
+                        continue;
+                    }
+                    let image = render_object(memory, &object, one_dimensional);
+                    let (width, height) = object.dimensions();
+                    ui.label(index.to_string());
+                    self.image(ui, &format!("obj{index}"), &image, IMAGE_SCALE);
+                    ui.label(format!("{}, {}", object.x(), object.y()));
+                    ui.label(format!("{width}x{height}"));
+                    ui.label(object.tile_number().to_string());
+                    ui.label(match object.palette_256() {
+                        true => String::from("256"),
+                        false => object.palette().to_string(),
+                    });
+                    ui.label(object.priority().to_string());
+                    ui.label(format!("{:?}", object.mode()));
+                    ui.label(object_flags(&object));
+                    self.export_button(ui, &format!("obj{index}"), frame, &image);
+                    ui.end_row();
+                }
+            });
+        });
+    }
+}
+
+fn block_name(block: u32) -> String {
+    match block {
+        0..4 => format!("BG {block}"),
+        _ => format!("OBJ {}", block - 4),
+    }
+}
+
+fn object_flags(object: &ObjectAttributes) -> String {
+    let mut flags = Vec::new();
+    if let Some(parameter) = object.affine_parameter() {
+        flags.push(format!("affine {parameter}"));
+    }
+    if object.double_size() {
+        flags.push(String::from("double"));
+    }
+    if object.disabled() {
+        flags.push(String::from("disabled"));
+    }
+    if object.horizontal_flip() {
+        flags.push(String::from("h-flip"));
+    }
+    if object.vertical_flip() {
+        flags.push(String::from("v-flip"));
+    }
+    if object.mosaic() {
+        flags.push(String::from("mosaic"));
+    }
+    flags.join(", ")
+}